@@ -3,14 +3,17 @@
 //! This module provides a simple in-memory cache for DNS records to improve
 //! performance by avoiding repeated database lookups for frequently accessed domains.
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex, OnceLock},
+    hash::Hash,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
     time::{Duration, SystemTime},
 };
 use log::debug;
+use lru::LruCache;
+
+use crate::errors::DnsError;
 
 /// Interval for cleaning up expired cache entries (in seconds).
 pub const CACHE_CLEANUP_INTERVAL: Duration = Duration::from_secs(300);
@@ -18,6 +21,26 @@ pub const CACHE_CLEANUP_INTERVAL: Duration = Duration::from_secs(300);
 /// Global cache instance.
 pub static CACHE: OnceLock<DnsCache> = OnceLock::new();
 
+/// Get the global cache.
+///
+/// # Returns
+/// A `Result` containing the cache, or `DnsError::Cache` if it has not been initialized.
+pub fn global() -> Result<&'static DnsCache, DnsError> {
+    CACHE.get().ok_or_else(|| DnsError::Cache("cache not initialized".into()))
+}
+
+/// Lock one of the cache's maps.
+///
+/// # Arguments
+/// * `map` - The map to lock.
+///
+/// # Returns
+/// A `Result` containing the guard, or `DnsError::Cache` if a panic while
+/// holding the lock left it poisoned.
+fn lock<T>(map: &Mutex<T>) -> Result<MutexGuard<'_, T>, DnsError> {
+    map.lock().map_err(|_| DnsError::Cache("cache lock poisoned".into()))
+}
+
 /// An entry in the DNS cache.
 #[derive(Debug, Clone)]
 pub struct CacheEntry {
@@ -29,16 +52,46 @@ pub struct CacheEntry {
     
     /// Time-to-live in seconds.
     pub ttl: u64,
+
+    /// Number of times this entry has been served.
+    pub hits: u64,
+
+    /// Whether a background refresh is already in flight for this entry.
+    pub prefetching: bool,
+
+    /// Whether this entry is a negative marker (RFC 2308) rather than an address.
+    pub negative: bool,
+}
+
+/// The kind of negative answer a cached marker stands for (RFC 2308).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegativeKind {
+    /// The name does not exist, whatever the type.
+    NxDomain,
+
+    /// The name exists but has no records of the queried type.
+    NoData,
 }
 
 /// Cache for DNS records to improve performance.
 #[derive(Debug, Clone)]
 pub struct DnsCache {
-    /// Map of domain names to cache entries.
-    pub entries: Arc<Mutex<HashMap<String, CacheEntry>>>,
+    /// Cached addresses, keyed by domain name and record type (A or AAAA),
+    /// in least recently used order.
+    pub entries: Arc<Mutex<LruCache<(String, u16), CacheEntry>>>,
+
+    /// NXDOMAIN markers, keyed by domain name.
+    pub nxdomain: Arc<Mutex<LruCache<String, CacheEntry>>>,
+
+    /// NODATA markers, keyed by domain name and query type.
+    pub nodata: Arc<Mutex<LruCache<(String, u16), CacheEntry>>>,
     
     /// List of NS records for zones this server is authoritative for.
-    pub ns_records: Vec<String>,
+    pub ns_records: Arc<Mutex<Vec<String>>>,
+
+    /// Maximum entries kept in each map before the least recently used is
+    /// evicted; 0 means unlimited.
+    pub max_entries: usize,
 }
 
 impl DnsCache {
@@ -51,52 +104,274 @@ impl DnsCache {
     /// A new `DnsCache` instance.
     pub fn new(ns_records: Vec<String>) -> Self {
         Self {
-            entries: Arc::new(Mutex::new(HashMap::new())),
-            ns_records,
+            entries: Arc::new(Mutex::new(LruCache::unbounded())),
+            nxdomain: Arc::new(Mutex::new(LruCache::unbounded())),
+            nodata: Arc::new(Mutex::new(LruCache::unbounded())),
+            ns_records: Arc::new(Mutex::new(ns_records)),
+            max_entries: 0,
+        }
+    }
+
+    /// Cap the number of entries the cache holds.
+    ///
+    /// # Arguments
+    /// * `max_entries` - Maximum entries per map; 0 means unlimited.
+    ///
+    /// # Returns
+    /// The cache with the cap applied.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        let cap = NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MAX);
+        if let Ok(mut entries) = lock(&self.entries) {
+            entries.resize(cap);
+        }
+        if let Ok(mut nxdomain) = lock(&self.nxdomain) {
+            nxdomain.resize(cap);
+        }
+        if let Ok(mut nodata) = lock(&self.nodata) {
+            nodata.resize(cap);
         }
+        self
     }
 
     /// Get a cached IP address for a domain.
     ///
+    /// Negative entries are not returned; see `get_negative`.
+    ///
     /// # Arguments
     /// * `domain` - The domain name to look up.
+    /// * `rtype` - The record type, 1 for A or 28 for AAAA.
     ///
     /// # Returns
-    /// An `Option` containing the IP address and TTL if found and not expired.
-    pub fn get(&self, domain: &str) -> Option<(String, u64)> {
-        let cache = self.entries.lock().unwrap();
-        if let Some(entry) = cache.get(domain) {
-            if entry.inserted.elapsed().map(|d| d.as_secs() <= entry.ttl).unwrap_or(true) {
-                return Some((entry.ip.clone(), entry.ttl));
+    /// A `Result` containing the IP address and its remaining TTL if found
+    /// and not expired, or `DnsError::Cache` if the cache cannot be read.
+    pub fn get(&self, domain: &str, rtype: u16) -> Result<Option<(String, u64)>, DnsError> {
+        let mut cache = lock(&self.entries)?;
+        if let Some(entry) = cache.get_mut(&(domain.to_string(), rtype)) {
+            if let Some(remaining) = remaining_ttl(entry) {
+                entry.hits += 1;
+                return Ok(Some((entry.ip.clone(), remaining)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check whether a popular entry is close enough to expiry to refresh it.
+    ///
+    /// Returns `true` at most once per entry, marking it as being prefetched
+    /// so concurrent queries don't trigger duplicate refreshes.
+    ///
+    /// # Arguments
+    /// * `domain` - The domain name to check.
+    /// * `rtype` - The record type, 1 for A or 28 for AAAA.
+    /// * `threshold_percent` - Refresh when less than this percentage of the TTL remains.
+    /// * `min_hits` - Minimum number of hits before an entry counts as popular.
+    ///
+    /// # Returns
+    /// A `Result` containing whether the caller should refresh the entry.
+    pub fn should_prefetch(&self, domain: &str, rtype: u16, threshold_percent: u64, min_hits: u64) -> Result<bool, DnsError> {
+        let mut cache = lock(&self.entries)?;
+        if let Some(entry) = cache.peek_mut(&(domain.to_string(), rtype)) {
+            if entry.prefetching || entry.hits < min_hits {
+                return Ok(false);
+            }
+            let elapsed = entry.inserted.elapsed().map(|d| d.as_secs()).unwrap_or(0);
+            let remaining = entry.ttl.saturating_sub(elapsed);
+            if remaining * 100 < entry.ttl * threshold_percent {
+                entry.prefetching = true;
+                return Ok(true);
             }
         }
-        None
+        Ok(false)
     }
 
     /// Add or update a domain in the cache.
     ///
+    /// A and AAAA addresses are cached separately, so caching one never
+    /// replaces the other.
+    ///
     /// # Arguments
     /// * `domain` - The domain name to cache.
+    /// * `rtype` - The record type, 1 for A or 28 for AAAA.
     /// * `ip` - The IP address for the domain.
     /// * `ttl` - Time-to-live in seconds.
-    pub fn set(&self, domain: String, ip: String, ttl: u64) {
-        let mut cache = self.entries.lock().unwrap();
-        cache.insert(
-            domain,
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the entry was stored.
+    pub fn set(&self, domain: String, rtype: u16, ip: String, ttl: u64) -> Result<(), DnsError> {
+        let mut cache = lock(&self.entries)?;
+        let key = (domain, rtype);
+        // Keep popularity across refreshes so prefetching continues
+        let hits = cache.peek(&key).map(|e| e.hits).unwrap_or(0);
+        cache.put(
+            key,
             CacheEntry {
                 ip,
                 inserted: SystemTime::now(),
                 ttl,
+                hits,
+                prefetching: false,
+                negative: false,
             },
         );
+        Ok(())
+    }
+
+    /// Check whether a query is answered by a cached negative marker.
+    ///
+    /// An NXDOMAIN marker covers every type for the name; a NODATA marker
+    /// only covers the type it was cached for.
+    ///
+    /// # Arguments
+    /// * `domain` - The domain name to look up.
+    /// * `qtype` - The query type.
+    ///
+    /// # Returns
+    /// A `Result` containing the kind of negative answer and its remaining
+    /// TTL if a marker is present and not expired.
+    pub fn get_negative(&self, domain: &str, qtype: u16) -> Result<Option<(NegativeKind, u64)>, DnsError> {
+        {
+            let mut nxdomain = lock(&self.nxdomain)?;
+            if let Some(entry) = nxdomain.get_mut(domain) {
+                if let Some(remaining) = remaining_ttl(entry) {
+                    return Ok(Some((NegativeKind::NxDomain, remaining)));
+                }
+            }
+        }
+
+        let mut nodata = lock(&self.nodata)?;
+        let Some(entry) = nodata.get_mut(&(domain.to_string(), qtype)) else {
+            return Ok(None);
+        };
+        Ok(remaining_ttl(entry).map(|remaining| (NegativeKind::NoData, remaining)))
+    }
+
+    /// Cache that a domain does not exist.
+    ///
+    /// # Arguments
+    /// * `domain` - The domain name to cache.
+    /// * `ttl` - Negative TTL in seconds, normally the SOA MINIMUM.
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the marker was stored.
+    pub fn set_negative(&self, domain: String, ttl: u64) -> Result<(), DnsError> {
+        let mut nxdomain = lock(&self.nxdomain)?;
+        nxdomain.put(domain, negative_entry(ttl));
+        Ok(())
+    }
+
+    /// Cache that a domain has no records of one type.
+    ///
+    /// # Arguments
+    /// * `domain` - The domain name to cache.
+    /// * `qtype` - The query type without records.
+    /// * `ttl` - Negative TTL in seconds, normally the SOA MINIMUM.
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the marker was stored.
+    pub fn set_nodata(&self, domain: String, qtype: u16, ttl: u64) -> Result<(), DnsError> {
+        let mut nodata = lock(&self.nodata)?;
+        nodata.put((domain, qtype), negative_entry(ttl));
+        Ok(())
+    }
+
+    /// Drop every entry, positive or negative, for names inside a zone.
+    ///
+    /// # Arguments
+    /// * `zone` - The zone apex.
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the entries were dropped.
+    pub fn invalidate_zone(&self, zone: &str) -> Result<(), DnsError> {
+        let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+        let suffix = format!(".{}", zone);
+        let in_zone = |domain: &str| domain == zone || domain.ends_with(&suffix);
+
+        remove_where(&mut *lock(&self.entries)?, |(domain, _), _| in_zone(domain));
+        remove_where(&mut *lock(&self.nxdomain)?, |domain, _| in_zone(domain));
+        remove_where(&mut *lock(&self.nodata)?, |(domain, _), _| in_zone(domain));
+        debug!("Cache invalidated for zone {}", zone);
+        Ok(())
+    }
+
+    /// Drop every cached answer, positive and negative.
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the cache was cleared.
+    pub fn clear(&self) -> Result<(), DnsError> {
+        lock(&self.entries)?.clear();
+        lock(&self.nxdomain)?.clear();
+        lock(&self.nodata)?.clear();
+        debug!("Cache cleared");
+        Ok(())
+    }
+
+    /// Replace the NS records the cache was seeded with.
+    ///
+    /// # Arguments
+    /// * `ns_records` - The new list of NS records.
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the records were replaced.
+    pub fn reseed(&self, ns_records: Vec<String>) -> Result<(), DnsError> {
+        *lock(&self.ns_records)? = ns_records;
+        Ok(())
     }
 
     /// Remove expired entries from the cache.
-    pub fn cleanup(&self) {
-        let mut cache = self.entries.lock().unwrap();
-        cache.retain(|_, entry| {
-            entry.inserted.elapsed().map(|d| d.as_secs() <= entry.ttl).unwrap_or(true)
-        });
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the cleanup ran.
+    pub fn cleanup(&self) -> Result<(), DnsError> {
+        remove_where(&mut *lock(&self.entries)?, |_, entry| remaining_ttl(entry).is_none());
+        remove_where(&mut *lock(&self.nxdomain)?, |_, entry| remaining_ttl(entry).is_none());
+        remove_where(&mut *lock(&self.nodata)?, |_, entry| remaining_ttl(entry).is_none());
         debug!("Cache cleanup completed");
+        Ok(())
+    }
+}
+
+/// Create a marker entry for a negative answer.
+///
+/// # Arguments
+/// * `ttl` - Negative TTL in seconds.
+///
+/// # Returns
+/// A new negative `CacheEntry`.
+fn negative_entry(ttl: u64) -> CacheEntry {
+    CacheEntry {
+        ip: String::new(),
+        inserted: SystemTime::now(),
+        ttl,
+        hits: 0,
+        prefetching: false,
+        negative: true,
     }
+}
+
+/// Remove every entry matching a predicate.
+///
+/// Eviction of the least recently used entry happens on insert, so this is
+/// only needed for expiry and invalidation.
+///
+/// # Arguments
+/// * `map` - The map to prune.
+/// * `remove` - Returns `true` for entries to drop.
+fn remove_where<K: Eq + Hash + Clone>(map: &mut LruCache<K, CacheEntry>, remove: impl Fn(&K, &CacheEntry) -> bool) {
+    let doomed: Vec<K> = map.iter().filter(|(k, entry)| remove(k, entry)).map(|(k, _)| k.clone()).collect();
+    for key in doomed {
+        map.pop(&key);
+    }
+}
+
+/// Work out how long an entry has left to live.
+///
+/// # Arguments
+/// * `entry` - The cache entry.
+///
+/// # Returns
+/// An `Option` containing the remaining TTL in seconds, or `None` if the entry has expired.
+fn remaining_ttl(entry: &CacheEntry) -> Option<u64> {
+    let elapsed = entry.inserted.elapsed().map(|d| d.as_secs()).unwrap_or(0);
+    entry.ttl.checked_sub(elapsed)
 }
\ No newline at end of file
@@ -3,12 +3,14 @@
 //! This module defines the configuration structure and methods to load
 //! configuration from environment variables.
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
-use std::{env, fs, net::SocketAddr};
+use std::{collections::HashMap, env, fs, net::{IpAddr, Ipv6Addr, SocketAddr}, sync::{Arc, RwLock}, time::Duration};
 use log::{error, info};
 
+use crate::dnssec::SigningKey;
 use crate::errors::DnsError;
+use crate::hooks::{AclHook, QueryHook, RpzHook};
+use crate::utils::{ip_in_subnet, parse_cidr};
 
 /// Default TTL for DNS records in seconds.
 pub const DEFAULT_TTL: u64 = 600;
@@ -16,10 +18,386 @@ pub const DEFAULT_TTL: u64 = 600;
 /// Maximum size of DNS packets in bytes.
 pub const MAX_PACKET_SIZE: usize = 4096;
 
+/// EDNS UDP buffer size advertised by default, per DNS flag day 2020.
+pub const DEFAULT_EDNS_BUFFER_SIZE: u16 = 1232;
+
+/// Well-known NAT64 prefix used for DNS64 synthesis (RFC 6052).
+pub const DEFAULT_DNS64_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+/// TTL for answers served from emergency records, kept short so clients
+/// pick up real data quickly once the database recovers.
+pub const EMERGENCY_TTL: u64 = 60;
+
+/// Default cap on TCP connections handled at once.
+pub const DEFAULT_MAX_TCP_CONNECTIONS: usize = 512;
+
+/// How long a TCP client may take to send each part of its query, in milliseconds.
+pub const DEFAULT_TCP_READ_TIMEOUT_MS: u64 = 10_000;
+
+/// Default cap on the CNAME records followed when answering one query.
+pub const DEFAULT_MAX_CHAIN_LENGTH: usize = 8;
+
+/// Default cap on entries in each cache map.
+pub const DEFAULT_CACHE_MAX_ENTRIES: usize = 100_000;
+
+/// Default ECS source prefix length for IPv4 clients (RFC 7871 section 11.1).
+pub const DEFAULT_ECS_PREFIX_V4: u8 = 24;
+
+/// Default ECS source prefix length for IPv6 clients (RFC 7871 section 11.1).
+pub const DEFAULT_ECS_PREFIX_V6: u8 = 56;
+
+/// Default number of UDP responses a rate-limited client may receive in a burst.
+pub const DEFAULT_RRL_BURST: u32 = 20;
+
+/// Default validity period of generated RRSIG records, in seconds (7 days).
+pub const DEFAULT_SIGNATURE_VALIDITY_SECS: u32 = 604_800;
+
+/// Default number of seconds between health check rounds.
+pub const DEFAULT_HEALTH_CHECK_INTERVAL_SECS: u64 = 10;
+
+/// How long a single upstream forwarder attempt may take, in milliseconds.
+pub const DEFAULT_FORWARD_TIMEOUT_MS: u64 = 2000;
+
+/// Default cap on negative cache TTLs, in seconds.
+pub const DEFAULT_NEGATIVE_CACHE_MAX_TTL: u64 = 3600;
+
+/// Default upper bound on served and cached TTLs: the largest TTL RFC 2181 allows.
+pub const DEFAULT_MAX_TTL: u64 = 2_147_483_647;
+
+/// A source consulted when resolving a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolutionSource {
+    /// The in-memory answer cache.
+    Cache,
+
+    /// The SQLite record database.
+    Db,
+
+    /// Records provided statically in the configuration.
+    Static,
+
+    /// Upstream forwarders.
+    Forward,
+}
+
+impl std::str::FromStr for ResolutionSource {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cache" => Ok(Self::Cache),
+            "db" => Ok(Self::Db),
+            "static" => Ok(Self::Static),
+            "forward" => Ok(Self::Forward),
+            other => Err(DnsError::Config(format!("Unknown resolution source: {}", other))),
+        }
+    }
+}
+
+/// Config file keys, the environment variables they stand in for, and the
+/// separator used to join list values.
+pub const FILE_SETTINGS: &[(&str, &str, &str)] = &[
+    ("bind_addr", "DNS_BIND", ","),
+    ("udp_bind", "DNS_UDP_BIND", ","),
+    ("tcp_bind", "DNS_TCP_BIND", ","),
+    ("forwarders", "DNS_FORWARDERS", ","),
+    ("dnssec_key_file", "DNSSEC_KEY_FILE", ","),
+    ("emergency_records", "DNS_EMERGENCY_RECORDS", ","),
+    ("resolution_order", "DNS_RESOLUTION_ORDER", ","),
+    ("answer_order", "DNS_ANSWER_ORDER", ","),
+    ("static_records", "DNS_STATIC_RECORDS", ";"),
+    ("geo_records", "DNS_GEO_RECORDS", ";"),
+    ("axfr_allow", "DNS_AXFR_ALLOW", ","),
+    ("maintenance_ip", "DNS_MAINTENANCE_IP", ","),
+    ("maintenance_pattern", "DNS_MAINTENANCE_PATTERN", ","),
+    ("query_deny", "DNS_QUERY_DENY", ","),
+    ("rpz_block", "DNS_RPZ_BLOCK", ","),
+    ("dns64_prefix", "DNS_DNS64_PREFIX", ","),
+    ("db_path", "DNS_DB_PATH", ","),
+    ("cache_ttl", "DNS_CACHE_TTL", ","),
+    ("enable_ipv6", "DNS_ENABLE_IPV6", ","),
+    ("max_recv_size", "DNS_MAX_RECV_SIZE", ","),
+    ("max_udp_response_size", "DNS_MAX_UDP_RESPONSE_SIZE", ","),
+    ("authoritative", "DNS_AUTHORITATIVE", ","),
+    ("ns_records", "DNS_NS_RECORDS", ","),
+    ("default_domain", "DNS_DEFAULT_DOMAIN", ","),
+    ("default_ip", "DNS_DEFAULT_IP", ","),
+    ("edns_echo_options", "DNS_EDNS_ECHO_OPTIONS", ","),
+    ("edns_buffer_size", "DNS_EDNS_BUFFER_SIZE", ","),
+    ("answer_qu_queries", "DNS_ANSWER_QU", ","),
+    ("rebinding_protection", "DNS_REBIND_PROTECTION", ","),
+    ("rebinding_allowlist", "DNS_REBIND_ALLOWLIST", ","),
+    ("response_jitter_ms", "DNS_RESPONSE_JITTER_MS", ","),
+    ("prefetch_threshold_percent", "DNS_PREFETCH_THRESHOLD", ","),
+    ("prefetch_min_hits", "DNS_PREFETCH_MIN_HITS", ","),
+    ("dns64_enabled", "DNS_DNS64", ","),
+    ("slow_query_ms", "DNS_SLOW_QUERY_MS", ","),
+    ("internal_edns_buffer_size", "DNS_INTERNAL_EDNS_BUFFER_SIZE", ","),
+    ("internal_do_bit", "DNS_INTERNAL_DO_BIT", ","),
+    ("reverse_zones", "DNS_REVERSE_ZONES", ","),
+    ("validate_responses", "DNS_VALIDATE_RESPONSES", ","),
+    ("compress_names", "DNS_COMPRESS_NAMES", ","),
+    ("negative_cache_max_ttl", "DNS_NEGATIVE_CACHE_MAX_TTL", ","),
+    ("allow_recursion", "DNS_ALLOW_RECURSION", ","),
+    ("deny_recursion", "DNS_DENY_RECURSION", ","),
+    ("refused_ede", "DNS_REFUSED_EDE", ","),
+    ("strict_edns_options", "DNS_STRICT_EDNS_OPTIONS", ","),
+    ("forward_timeout_ms", "DNS_FORWARD_TIMEOUT_MS", ","),
+    ("max_tcp_connections", "DNS_MAX_TCP_CONNECTIONS", ","),
+    ("tcp_read_timeout_ms", "DNS_TCP_READ_TIMEOUT_MS", ","),
+    ("minimal_responses", "DNS_MINIMAL_RESPONSES", ","),
+    ("edns_fallback", "DNS_EDNS_FALLBACK", ","),
+    ("dns_cookies", "DNS_COOKIES", ","),
+    ("health_checks", "DNS_HEALTH_CHECKS", ","),
+    ("health_check_interval_secs", "DNS_HEALTH_CHECK_INTERVAL_SECS", ","),
+    ("cache_max_entries", "DNS_CACHE_MAX_ENTRIES", ","),
+    ("metrics_bind", "DNS_METRICS_BIND", ","),
+    ("serial_scheme", "DNS_SERIAL_SCHEME", ","),
+    ("max_chain_length", "DNS_MAX_CHAIN_LENGTH", ","),
+    ("doh_bind", "DNS_DOH_BIND", ","),
+    ("doh_tls_cert", "DNS_DOH_TLS_CERT", ","),
+    ("doh_tls_key", "DNS_DOH_TLS_KEY", ","),
+    ("whoami_name", "DNS_WHOAMI_NAME", ","),
+    ("whoami_address", "DNS_WHOAMI_ADDRESS", ","),
+    ("any_response", "DNS_ANY_RESPONSE", ","),
+    ("ecs_forwarding", "DNS_ECS_FORWARDING", ","),
+    ("ecs_prefix_v4", "DNS_ECS_PREFIX_V4", ","),
+    ("ecs_prefix_v6", "DNS_ECS_PREFIX_V6", ","),
+    ("rrl_rate", "DNS_RRL_RATE", ","),
+    ("rrl_burst", "DNS_RRL_BURST", ","),
+    ("rrl_per_name", "DNS_RRL_PER_NAME", ","),
+    ("rrl_action", "DNS_RRL_ACTION", ","),
+    ("auto_ptr", "DNS_AUTO_PTR", ","),
+    ("dnssec_signing", "DNSSEC_SIGNING", ","),
+    ("dnssec_private_key_file", "DNSSEC_PRIVATE_KEY_FILE", ","),
+    ("dnssec_signature_validity", "DNSSEC_SIGNATURE_VALIDITY_SECS", ","),
+    ("health_bind", "DNS_HEALTH_BIND", ","),
+    ("reload_flush_cache", "DNS_RELOAD_FLUSH_CACHE", ","),
+    ("min_ttl", "DNS_MIN_TTL", ","),
+    ("max_ttl", "DNS_MAX_TTL", ","),
+];
+
+/// How the records of a multi-record A, AAAA or NS answer are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerOrder {
+    /// Start each answer at the next address in turn.
+    RoundRobin,
+
+    /// Start each answer at an address chosen from a hash of the client IP,
+    /// so a client keeps getting the same backend.
+    Sticky,
+}
+
+impl std::str::FromStr for AnswerOrder {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "round-robin" | "roundrobin" => Ok(Self::RoundRobin),
+            "sticky" => Ok(Self::Sticky),
+            other => Err(DnsError::Config(format!("Unknown answer order: {}", other))),
+        }
+    }
+}
+
+/// Whether positive answers carry authority and additional data, after
+/// BIND's `minimal-responses` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MinimalResponses {
+    /// Answer section only.
+    Yes,
+
+    /// Add the zone's NS records and their glue to every positive answer.
+    No,
+
+    /// Add NS records and glue only when the query did not ask for recursion.
+    NoAuthRecursive,
+}
+
+impl std::str::FromStr for MinimalResponses {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "yes" | "true" | "1" => Ok(Self::Yes),
+            "no" | "false" | "0" => Ok(Self::No),
+            "no-auth-recursive" => Ok(Self::NoAuthRecursive),
+            other => Err(DnsError::Config(format!("Unknown minimal responses mode: {}", other))),
+        }
+    }
+}
+
+/// How a zone's SOA serial advances when its records change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialScheme {
+    /// Seconds since the Unix epoch.
+    UnixTime,
+
+    /// `YYYYMMDDnn`: today's date followed by a two-digit change counter.
+    DateCounter,
+
+    /// The previous serial plus one.
+    Increment,
+}
+
+impl std::str::FromStr for SerialScheme {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "unixtime" | "unix" => Ok(Self::UnixTime),
+            "datecounter" | "date" => Ok(Self::DateCounter),
+            "increment" | "counter" => Ok(Self::Increment),
+            other => Err(DnsError::Config(format!("Unknown serial scheme: {}", other))),
+        }
+    }
+}
+
+/// How queries for type ANY are answered at names in our zones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnyResponse {
+    /// A single synthesized HINFO record (RFC 8482 section 4.2).
+    Minimal,
+
+    /// Every record stored at the name.
+    Full,
+}
+
+impl std::str::FromStr for AnyResponse {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "minimal" | "hinfo" => Ok(Self::Minimal),
+            "full" | "all" => Ok(Self::Full),
+            other => Err(DnsError::Config(format!("Unknown ANY response mode: {}", other))),
+        }
+    }
+}
+
+/// What happens to a UDP query from a client over its rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RrlAction {
+    /// Send nothing.
+    Drop,
+
+    /// Send an empty response with TC set, so a real client retries over TCP.
+    Truncate,
+}
+
+impl std::str::FromStr for RrlAction {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "drop" => Ok(Self::Drop),
+            "truncate" | "slip" => Ok(Self::Truncate),
+            other => Err(DnsError::Config(format!("Unknown rate limit action: {}", other))),
+        }
+    }
+}
+
+/// An answer override served to clients from a specific subnet.
+#[derive(Debug, Clone)]
+pub struct GeoRecord {
+    /// The name the override applies to.
+    pub name: String,
+
+    /// Network address of the client subnet.
+    pub network: IpAddr,
+
+    /// Prefix length of the client subnet.
+    pub prefix_len: u8,
+
+    /// The address served to matching clients.
+    pub value: String,
+}
+
+/// A temporary A/AAAA answer pointing clients at a status page.
+#[derive(Debug, Clone)]
+pub struct MaintenanceMode {
+    /// The name the override applies to; `*.name` matches its subdomains and
+    /// `None` matches every name.
+    pub pattern: Option<String>,
+
+    /// The status page address served to matching queries.
+    pub target: IpAddr,
+}
+
+impl MaintenanceMode {
+    /// Check whether a queried name falls under the maintenance pattern.
+    ///
+    /// # Arguments
+    /// * `domain` - The queried domain name.
+    ///
+    /// # Returns
+    /// `true` if queries for the name should get the maintenance address.
+    pub fn matches(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        match self.pattern.as_deref() {
+            None => true,
+            Some(pattern) => match pattern.strip_prefix("*.") {
+                Some(suffix) => domain.ends_with(&format!(".{}", suffix)),
+                None => domain == pattern,
+            },
+        }
+    }
+}
+
+/// A periodic probe deciding whether an address may be served in answers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthCheck {
+    /// Healthy while a TCP connection to the address can be opened.
+    Tcp(SocketAddr),
+
+    /// Healthy while an HTTP GET of `path` answers with a 2xx or 3xx status.
+    Http {
+        /// The backend address and port.
+        addr: SocketAddr,
+        /// The request path, starting with `/`.
+        path: String,
+    },
+}
+
+impl HealthCheck {
+    /// The address whose records the check gates.
+    ///
+    /// # Returns
+    /// The backend IP address.
+    pub fn target(&self) -> IpAddr {
+        match self {
+            Self::Tcp(addr) | Self::Http { addr, .. } => addr.ip(),
+        }
+    }
+}
+
+impl std::str::FromStr for HealthCheck {
+    type Err = DnsError;
+
+    /// Parse `ip:port` as a TCP check or `http://ip:port/path` as an HTTP check.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || DnsError::Config(format!("Invalid health check: {}", s));
+        match s.strip_prefix("http://") {
+            Some(rest) => {
+                let (addr, path) = match rest.find('/') {
+                    Some(i) => (&rest[..i], &rest[i..]),
+                    None => (rest, "/"),
+                };
+                Ok(Self::Http {
+                    addr: addr.parse().map_err(|_| invalid())?,
+                    path: path.to_string(),
+                })
+            }
+            None => Ok(Self::Tcp(s.strip_prefix("tcp://").unwrap_or(s).parse().map_err(|_| invalid())?)),
+        }
+    }
+}
+
 /// Server configuration loaded from environment variables.
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
-    /// Address to bind the DNS server to.
+    /// Address to bind the DNS server to; the default for `udp_bind` and `tcp_bind`.
     pub bind_addr: SocketAddr,
     
     /// Path to the SQLite database file.
@@ -31,8 +409,11 @@ pub struct ServerConfig {
     /// Whether to enable IPv6 support.
     pub enable_ipv6: bool,
     
-    /// Maximum size of DNS packets.
-    pub max_packet_size: usize,
+    /// Size of the UDP receive buffer; must hold the largest EDNS query accepted.
+    pub max_recv_size: usize,
+
+    /// Upper bound on UDP response size, applied on top of the client's EDNS buffer.
+    pub max_udp_response_size: usize,
     
     /// Whether this server is authoritative for its zones.
     pub authoritative: bool,
@@ -54,64 +435,873 @@ pub struct ServerConfig {
     
     /// List of DNSKEY records for DNSSEC.
     pub dnskey_records: Vec<String>,
+
+    /// Names and IPv4 addresses served when the database cannot be read.
+    pub emergency_records: HashMap<String, String>,
+
+    /// EDNS option codes echoed verbatim from query to response.
+    pub edns_echo_options: Vec<u16>,
+
+    /// EDNS UDP buffer size we advertise and the cap on UDP responses.
+    pub edns_buffer_size: u16,
+
+    /// Whether to answer queries with the mDNS QU bit set as normal unicast.
+    pub answer_qu_queries: bool,
+
+    /// Whether to block forwarded answers pointing at private addresses.
+    pub rebinding_protection: bool,
+
+    /// Zones allowed to resolve to private addresses despite rebinding protection.
+    pub rebinding_allowlist: Vec<String>,
+
+    /// Upper bound in milliseconds of a random delay applied before responding (0 disables).
+    pub response_jitter_ms: u64,
+
+    /// Order in which resolution sources are consulted for a query.
+    pub resolution_order: Vec<ResolutionSource>,
+
+    /// Records served from configuration, keyed by name, as (value, ttl, record_type).
+    pub static_records: HashMap<String, Vec<(String, u64, String)>>,
+
+    /// Refresh cached entries in the background once less than this
+    /// percentage of their TTL remains (0 disables prefetching).
+    pub prefetch_threshold_percent: u64,
+
+    /// Minimum number of cache hits before an entry is prefetched.
+    pub prefetch_min_hits: u64,
+
+    /// Whether to synthesize AAAA records from A records (DNS64).
+    pub dns64_enabled: bool,
+
+    /// The /96 NAT64 prefix used for DNS64 synthesis.
+    pub dns64_prefix: Ipv6Addr,
+
+    /// Log queries taking longer than this many milliseconds (0 disables).
+    pub slow_query_ms: u64,
+
+    /// EDNS buffer size advertised in queries the server originates itself.
+    pub internal_edns_buffer_size: u16,
+
+    /// Whether queries the server originates itself set the DO bit.
+    pub internal_do_bit: bool,
+
+    /// Reverse zones (e.g. `2.0.192.in-addr.arpa`) served with a derived SOA and NS set.
+    pub reverse_zones: Vec<String>,
+
+    /// Whether to check every response against its query before sending.
+    /// Defaults to on in debug builds.
+    pub validate_responses: bool,
+
+    /// Per-subnet A/AAAA overrides, classified by ECS or source address.
+    pub geo_records: Vec<GeoRecord>,
+
+    /// Whether to compress names in the answer, authority and additional sections.
+    pub compress_names: bool,
+
+    /// Upper bound on how long nonexistent names are cached (0 disables negative caching).
+    pub negative_cache_max_ttl: u64,
+
+    /// Whether queries for names outside our zones are forwarded upstream.
+    pub allow_recursion: bool,
+
+    /// Client subnets recursion is offered to; empty means every client.
+    pub recursion_allow: Vec<(IpAddr, u8)>,
+
+    /// Client subnets never offered recursion, even inside `recursion_allow`.
+    pub recursion_deny: Vec<(IpAddr, u8)>,
+
+    /// Whether REFUSED responses explain themselves with an Extended DNS Error.
+    pub refused_ede: bool,
+
+    /// Client subnets allowed to transfer zones over AXFR; empty disables transfers.
+    pub axfr_allow: Vec<(IpAddr, u8)>,
+
+    /// Query hooks run around resolution, in order.
+    pub hooks: Vec<Arc<dyn QueryHook>>,
+
+    /// Whether queries whose EDNS option lengths disagree with the OPT RDLENGTH get FORMERR.
+    pub strict_edns_options: bool,
+
+    /// Status page answer that overrides A/AAAA resolution while set.
+    pub maintenance_mode: Option<MaintenanceMode>,
+
+    /// How long a forwarder may take to accept a connection or reply before the next is used.
+    pub forward_timeout: Duration,
+
+    /// How the records of a multi-record A, AAAA or NS answer are ordered.
+    pub answer_order: AnswerOrder,
+
+    /// Maximum TCP connections handled at once; excess connections are closed.
+    pub max_tcp_connections: usize,
+
+    /// How long a TCP client may take to send its length prefix or query before the connection is closed.
+    pub tcp_read_timeout: Duration,
+
+    /// Whether positive answers carry the zone's NS records and glue.
+    pub minimal_responses: MinimalResponses,
+
+    /// Whether a forwarder that times out is retried with a 512-byte EDNS
+    /// buffer and then without EDNS before giving up on it.
+    pub edns_fallback: bool,
+
+    /// Whether to issue DNS cookies and let clients that return a valid one
+    /// receive UDP responses above `edns_buffer_size`.
+    pub dns_cookies: bool,
+
+    /// Probes for backends whose A/AAAA records are withheld while unhealthy.
+    pub health_checks: Vec<HealthCheck>,
+
+    /// Time between rounds of health checks.
+    pub health_check_interval: Duration,
+
+    /// Maximum entries in each cache map before the least recently used is
+    /// evicted; 0 means unlimited.
+    pub cache_max_entries: usize,
+
+    /// Address to serve Prometheus metrics on; `None` disables the endpoint.
+    pub metrics_bind: Option<SocketAddr>,
+
+    /// How zone serials advance when records are updated.
+    pub serial_scheme: SerialScheme,
+
+    /// Maximum CNAME records followed when answering one query.
+    pub max_chain_length: usize,
+
+    /// Address to serve DNS over HTTPS on; `None` disables DoH.
+    pub doh_bind: Option<SocketAddr>,
+
+    /// PEM certificate chain for DoH; without it DoH is served over plain
+    /// HTTP for use behind a TLS-terminating proxy.
+    pub doh_tls_cert: Option<String>,
+
+    /// PEM private key matching `doh_tls_cert`.
+    pub doh_tls_key: Option<String>,
+
+    /// Name answered with the server's own address (A/AAAA) and the
+    /// client's address (TXT); `None` disables the responder.
+    pub whoami_name: Option<String>,
+
+    /// Address the whoami responder reports; when unset, the bind address
+    /// or else the local address used to reach the client.
+    pub whoami_address: Option<IpAddr>,
+
+    /// How ANY queries for names in our zones are answered.
+    pub any_response: AnyResponse,
+
+    /// Whether forwarded queries without an ECS option get one built from
+    /// the client's address; off by default as it reveals clients upstream.
+    pub ecs_forwarding: bool,
+
+    /// Source prefix length sent upstream for IPv4 clients.
+    pub ecs_prefix_v4: u8,
+
+    /// Source prefix length sent upstream for IPv6 clients.
+    pub ecs_prefix_v6: u8,
+
+    /// UDP responses per second allowed to each client (0 disables rate limiting).
+    pub rrl_rate: u32,
+
+    /// Responses a client may receive at once before the rate applies.
+    pub rrl_burst: u32,
+
+    /// Whether clients are limited per queried name rather than overall.
+    pub rrl_per_name: bool,
+
+    /// What to do with queries over the limit.
+    pub rrl_action: RrlAction,
+
+    /// Whether PTR queries without stored PTR records are answered from the
+    /// A/AAAA records pointing at the address.
+    pub auto_ptr: bool,
+
+    /// Key used to sign answers online for DO queries; `None` disables signing.
+    pub signing_key: Option<Arc<SigningKey>>,
+
+    /// How long generated RRSIG records stay valid, in seconds.
+    pub signature_validity: u32,
+
+    /// Address to serve the liveness/readiness probe on; `None` disables the endpoint.
+    pub health_bind: Option<SocketAddr>,
+
+    /// Whether cached answers are dropped when the configuration is reloaded.
+    pub reload_flush_cache: bool,
+
+    /// Address the UDP listener binds to.
+    pub udp_bind: SocketAddr,
+
+    /// Address the TCP listener binds to.
+    pub tcp_bind: SocketAddr,
+
+    /// Lower bound on TTLs written into responses and the cache, in seconds.
+    pub min_ttl: u64,
+
+    /// Upper bound on TTLs written into responses and the cache, in seconds.
+    pub max_ttl: u64,
+}
+
+/// The live server configuration, shared by the listeners.
+///
+/// Each query takes a snapshot with [`SharedConfig::current`], so a reload
+/// swaps the configuration atomically: queries in flight finish with the
+/// configuration they started with and later ones see the new one.
+#[derive(Debug, Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<ServerConfig>>>);
+
+impl SharedConfig {
+    /// Share a configuration.
+    ///
+    /// # Arguments
+    /// * `config` - The initial configuration.
+    ///
+    /// # Returns
+    /// A new `SharedConfig`.
+    pub fn new(config: ServerConfig) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    /// Get the configuration currently in effect.
+    ///
+    /// # Returns
+    /// A snapshot of the configuration.
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Put a new configuration into effect.
+    ///
+    /// # Arguments
+    /// * `config` - The new configuration.
+    pub fn replace(&self, config: ServerConfig) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
 }
 
 impl ServerConfig {
     /// Load server configuration from environment variables.
     ///
+    /// When `DNS_CONFIG_FILE` is set, the named TOML file supplies defaults
+    /// that the environment overrides.
+    ///
     /// # Returns
     /// A `Result` containing either the loaded `ServerConfig` or a `DnsError`.
     pub fn from_env() -> Result<Self, DnsError> {
-        let bind_addr = env::var("DNS_BIND")
+        match env::var("DNS_CONFIG_FILE") {
+            Ok(path) => Self::from_file(&path),
+            Err(_) => Self::load(&HashMap::new(), None),
+        }
+    }
+
+    /// Load server configuration from a TOML file.
+    ///
+    /// Keys are named after the `ServerConfig` fields they set (see
+    /// [`FILE_SETTINGS`]); lists may be given as TOML arrays. Environment
+    /// variables still override values from the file.
+    ///
+    /// # Arguments
+    /// * `path` - Path to the TOML config file.
+    ///
+    /// # Returns
+    /// A `Result` containing either the loaded `ServerConfig` or a `DnsError`.
+    pub fn from_file(path: &str) -> Result<Self, DnsError> {
+        let content = fs::read_to_string(path)?;
+        let table: toml::Table = content.parse()
+            .map_err(|e| DnsError::Config(format!("Invalid config file {}: {}", path, e)))?;
+
+        let mut settings = HashMap::new();
+        for (key, value) in &table {
+            if key == "ds_records" || key == "dnskey_records" {
+                continue;
+            }
+            let (_, env_key, separator) = FILE_SETTINGS.iter()
+                .find(|(name, _, _)| name == key)
+                .ok_or_else(|| DnsError::Config(format!("Unknown setting in {}: {}", path, key)))?;
+            settings.insert(env_key.to_string(), setting_value(key, value, separator)?);
+        }
+
+        let dnskey_records = table.get("dnskey_records")
+            .map(|value| string_list("dnskey_records", value))
+            .transpose()?;
+        let mut config = Self::load(&settings, dnskey_records)?;
+        if let Some(value) = table.get("ds_records") {
+            config.ds_records = string_list("ds_records", value)?;
+        }
+        info!("Loaded configuration from {}", path);
+        Ok(config)
+    }
+
+    /// Build the configuration from environment variables, falling back to
+    /// settings read from a config file.
+    ///
+    /// # Arguments
+    /// * `file` - Settings from a config file, keyed by environment variable name.
+    /// * `file_dnskey_records` - DNSKEY records listed in a config file, which
+    ///   replace those read from `DNSSEC_KEY_FILE`.
+    ///
+    /// # Returns
+    /// A `Result` containing either the loaded `ServerConfig` or a `DnsError`.
+    fn load(file: &HashMap<String, String>, file_dnskey_records: Option<Vec<String>>) -> Result<Self, DnsError> {
+        let var = |key: &str| env::var(key).or_else(|e| file.get(key).cloned().ok_or(e));
+
+        let bind_addr = var("DNS_BIND")
             .unwrap_or_else(|_| "0.0.0.0:53".into())
             .parse()
             .map_err(|_| DnsError::Config("Invalid DNS_BIND address".into()))?;
+        let udp_bind = match var("DNS_UDP_BIND") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse()
+                .map_err(|_| DnsError::Config("Invalid DNS_UDP_BIND address".into()))?,
+            _ => bind_addr,
+        };
+        let tcp_bind = match var("DNS_TCP_BIND") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse()
+                .map_err(|_| DnsError::Config("Invalid DNS_TCP_BIND address".into()))?,
+            _ => bind_addr,
+        };
 
-        let forwarders = env::var("DNS_FORWARDERS")
+        let forwarders = var("DNS_FORWARDERS")
             .unwrap_or_else(|_| "8.8.8.8:53,1.1.1.1:53,9.9.9.9:53".into())
             .split(',')
             .filter_map(|s| s.trim().parse().ok())
             .collect();
 
-        let key_path = env::var("DNSSEC_KEY_FILE").unwrap_or_else(|_| "Kbzo.in.+008+24550.key".to_string());
-        let dnskey_records = match fs::read_to_string(&key_path) {
-            Ok(content) => {
-                info!("Loaded DNSSEC key from {}", key_path);
-                vec![content.trim().to_string()]
-            },
-            Err(e) => {
-                error!("Failed to load DNSSEC key from {}: {}", key_path, e);
-                vec![]
+        // Key files may hold several DNSKEY lines (e.g. KSK and ZSK) plus ';' comments
+        let key_paths = var("DNSSEC_KEY_FILE").unwrap_or_else(|_| "Kbzo.in.+008+24550.key".to_string());
+        let mut dnskey_records = Vec::new();
+        for key_path in key_paths.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match fs::read_to_string(key_path) {
+                Ok(content) => {
+                    info!("Loaded DNSSEC key from {}", key_path);
+                    dnskey_records.extend(content.lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+                        .map(str::to_string));
+                },
+                Err(e) => error!("Failed to load DNSSEC key from {}: {}", key_path, e),
+            }
+        }
+        if let Some(records) = file_dnskey_records {
+            dnskey_records = records;
+        }
+
+        let emergency_records = var("DNS_EMERGENCY_RECORDS")
+            .map(|v| {
+                v.split(',')
+                    .filter_map(|entry| entry.split_once('='))
+                    .map(|(name, ip)| (name.trim().trim_end_matches('.').to_string(), ip.trim().to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let resolution_order = match var("DNS_RESOLUTION_ORDER") {
+            Ok(v) => v.split(',').map(|s| s.parse()).collect::<Result<Vec<_>, _>>()?,
+            Err(_) => vec![
+                ResolutionSource::Cache,
+                ResolutionSource::Db,
+                ResolutionSource::Static,
+                ResolutionSource::Forward,
+            ],
+        };
+
+        let answer_order = match var("DNS_ANSWER_ORDER") {
+            Ok(v) => v.parse()?,
+            Err(_) => AnswerOrder::RoundRobin,
+        };
+
+        let health_checks = var("DNS_HEALTH_CHECKS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<HealthCheck>, _>>()?;
+
+        let metrics_bind = match var("DNS_METRICS_BIND") {
+            Ok(v) if !v.trim().is_empty() => Some(v.trim().parse()
+                .map_err(|_| DnsError::Config("Invalid DNS_METRICS_BIND address".into()))?),
+            _ => None,
+        };
+
+        let health_bind = match var("DNS_HEALTH_BIND") {
+            Ok(v) if !v.trim().is_empty() => Some(v.trim().parse()
+                .map_err(|_| DnsError::Config("Invalid DNS_HEALTH_BIND address".into()))?),
+            _ => None,
+        };
+
+        let doh_bind = match var("DNS_DOH_BIND") {
+            Ok(v) if !v.trim().is_empty() => Some(v.trim().parse()
+                .map_err(|_| DnsError::Config("Invalid DNS_DOH_BIND address".into()))?),
+            _ => None,
+        };
+        let doh_tls_cert = var("DNS_DOH_TLS_CERT").ok().filter(|v| !v.trim().is_empty());
+        let doh_tls_key = var("DNS_DOH_TLS_KEY").ok().filter(|v| !v.trim().is_empty());
+        if doh_tls_cert.is_some() != doh_tls_key.is_some() {
+            return Err(DnsError::Config("DNS_DOH_TLS_CERT and DNS_DOH_TLS_KEY must be set together".into()));
+        }
+
+        let whoami_name = var("DNS_WHOAMI_NAME").ok()
+            .map(|v| v.trim().trim_end_matches('.').to_ascii_lowercase())
+            .filter(|v| !v.is_empty());
+        let whoami_address = match var("DNS_WHOAMI_ADDRESS") {
+            Ok(v) if !v.trim().is_empty() => Some(v.trim().parse()
+                .map_err(|_| DnsError::Config("Invalid DNS_WHOAMI_ADDRESS".into()))?),
+            _ => None,
+        };
+
+        let serial_scheme = match var("DNS_SERIAL_SCHEME") {
+            Ok(v) => v.parse()?,
+            Err(_) => SerialScheme::Increment,
+        };
+        let any_response = match var("DNS_ANY_RESPONSE") {
+            Ok(v) => v.parse()?,
+            Err(_) => AnyResponse::Minimal,
+        };
+        let rrl_action = match var("DNS_RRL_ACTION") {
+            Ok(v) => v.parse()?,
+            Err(_) => RrlAction::Truncate,
+        };
+
+        // Online signing needs the private half of one of the served DNSKEYs
+        let dnssec_signing = var("DNSSEC_SIGNING")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let signing_key = match (dnssec_signing, var("DNSSEC_PRIVATE_KEY_FILE")) {
+            (true, Ok(path)) if !path.trim().is_empty() => Some(Arc::new(SigningKey::load(path.trim(), &dnskey_records)?)),
+            (true, _) => return Err(DnsError::Config("DNSSEC_SIGNING requires DNSSEC_PRIVATE_KEY_FILE".into())),
+            (false, _) => None,
+        };
+        let signature_validity = var("DNSSEC_SIGNATURE_VALIDITY_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_SIGNATURE_VALIDITY_SECS);
+        if signature_validity == 0 {
+            return Err(DnsError::Config("DNSSEC_SIGNATURE_VALIDITY_SECS must be greater than 0".into()));
+        }
+
+        let min_ttl = var("DNS_MIN_TTL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let max_ttl = var("DNS_MAX_TTL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_TTL);
+        if min_ttl > max_ttl {
+            return Err(DnsError::Config(format!("DNS_MIN_TTL ({}) exceeds DNS_MAX_TTL ({})", min_ttl, max_ttl)));
+        }
+
+        let minimal_responses = match var("DNS_MINIMAL_RESPONSES") {
+            Ok(v) => v.parse()?,
+            Err(_) => MinimalResponses::Yes,
+        };
+
+        // Static records: "name TYPE value" entries separated by ';' outside quotes,
+        // so TXT values such as DMARC policies may contain ';'
+        let mut static_records: HashMap<String, Vec<(String, u64, String)>> = HashMap::new();
+        if let Ok(v) = var("DNS_STATIC_RECORDS") {
+            for entry in split_unquoted(&v, ';').into_iter().filter(|e| !e.trim().is_empty()) {
+                let mut parts = entry.trim().splitn(3, char::is_whitespace);
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(name), Some(rtype), Some(value)) => {
+                        static_records
+                            .entry(name.trim_end_matches('.').to_string())
+                            .or_default()
+                            .push((value.trim().to_string(), DEFAULT_TTL, rtype.to_ascii_uppercase()));
+                    }
+                    _ => return Err(DnsError::Config(format!("Invalid static record: {}", entry))),
+                }
+            }
+        }
+
+        // GeoDNS overrides: "name@subnet=address" entries separated by ';'
+        let mut geo_records = Vec::new();
+        if let Ok(v) = var("DNS_GEO_RECORDS") {
+            for entry in v.split(';').filter(|e| !e.trim().is_empty()) {
+                let parsed = entry.trim().split_once('@').and_then(|(name, rest)| {
+                    let (subnet, value) = rest.split_once('=')?;
+                    let (network, prefix_len) = parse_cidr(subnet)?;
+                    value.trim().parse::<IpAddr>().ok()?;
+                    Some(GeoRecord {
+                        name: name.trim().trim_end_matches('.').to_ascii_lowercase(),
+                        network,
+                        prefix_len,
+                        value: value.trim().to_string(),
+                    })
+                });
+                match parsed {
+                    Some(record) => geo_records.push(record),
+                    None => return Err(DnsError::Config(format!("Invalid GeoDNS record: {}", entry))),
+                }
+            }
+        }
+
+        // DNS_ALLOW_RECURSION is a boolean or a list of client subnets
+        let mut allow_recursion = true;
+        let mut recursion_allow = Vec::new();
+        if let Ok(v) = var("DNS_ALLOW_RECURSION") {
+            let v = v.trim();
+            if v == "1" || v.eq_ignore_ascii_case("true") {
+                allow_recursion = true;
+            } else if v.is_empty() || v == "0" || v.eq_ignore_ascii_case("false") {
+                allow_recursion = false;
+            } else {
+                for subnet in v.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                    recursion_allow.push(parse_cidr(subnet)
+                        .ok_or_else(|| DnsError::Config(format!("Invalid DNS_ALLOW_RECURSION subnet: {}", subnet)))?);
+                }
+            }
+        }
+        let mut recursion_deny = Vec::new();
+        if let Ok(v) = var("DNS_DENY_RECURSION") {
+            for subnet in v.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                recursion_deny.push(parse_cidr(subnet)
+                    .ok_or_else(|| DnsError::Config(format!("Invalid DNS_DENY_RECURSION subnet: {}", subnet)))?);
+            }
+        }
+
+        let mut axfr_allow = Vec::new();
+        if let Ok(v) = var("DNS_AXFR_ALLOW") {
+            for subnet in v.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                axfr_allow.push(parse_cidr(subnet)
+                    .ok_or_else(|| DnsError::Config(format!("Invalid DNS_AXFR_ALLOW subnet: {}", subnet)))?);
+            }
+        }
+
+        let maintenance_mode = match var("DNS_MAINTENANCE_IP") {
+            Ok(v) if !v.trim().is_empty() => Some(MaintenanceMode {
+                pattern: var("DNS_MAINTENANCE_PATTERN")
+                    .ok()
+                    .map(|p| p.trim().trim_end_matches('.').to_ascii_lowercase())
+                    .filter(|p| !p.is_empty()),
+                target: v.trim().parse()
+                    .map_err(|_| DnsError::Config(format!("Invalid DNS_MAINTENANCE_IP: {}", v)))?,
+            }),
+            _ => None,
+        };
+
+        // Built-in hooks; embedders can append their own to `hooks`
+        let mut hooks: Vec<Arc<dyn QueryHook>> = Vec::new();
+        if let Ok(v) = var("DNS_QUERY_DENY") {
+            let mut deny = Vec::new();
+            for subnet in v.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                deny.push(parse_cidr(subnet)
+                    .ok_or_else(|| DnsError::Config(format!("Invalid DNS_QUERY_DENY subnet: {}", subnet)))?);
+            }
+            if !deny.is_empty() {
+                hooks.push(Arc::new(AclHook { deny }));
             }
+        }
+        if let Ok(v) = var("DNS_RPZ_BLOCK") {
+            let blocked: Vec<String> = v.split(',')
+                .map(|s| s.trim().trim_end_matches('.').to_ascii_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if !blocked.is_empty() {
+                hooks.push(Arc::new(RpzHook { blocked }));
+            }
+        }
+
+        let dns64_prefix = match var("DNS_DNS64_PREFIX") {
+            Ok(v) => v.trim_end_matches("/96")
+                .parse()
+                .map_err(|_| DnsError::Config("Invalid DNS_DNS64_PREFIX".into()))?,
+            Err(_) => DEFAULT_DNS64_PREFIX,
         };
 
         Ok(Self {
             bind_addr,
-            db_path: env::var("DNS_DB_PATH").unwrap_or_else(|_| "dns.db".into()),
-            cache_ttl: env::var("DNS_CACHE_TTL")
+            db_path: var("DNS_DB_PATH").unwrap_or_else(|_| "dns.db".into()),
+            cache_ttl: var("DNS_CACHE_TTL")
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(DEFAULT_TTL),
-            enable_ipv6: env::var("DNS_ENABLE_IPV6")
+            enable_ipv6: var("DNS_ENABLE_IPV6")
                 .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
                 .unwrap_or(false),
-            max_packet_size: env::var("DNS_MAX_PACKET_SIZE")
+            max_recv_size: var("DNS_MAX_RECV_SIZE")
+                .or_else(|_| var("DNS_MAX_PACKET_SIZE"))
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(MAX_PACKET_SIZE),
-            authoritative: env::var("DNS_AUTHORITATIVE")
+            max_udp_response_size: var("DNS_MAX_UDP_RESPONSE_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(MAX_PACKET_SIZE),
+            authoritative: var("DNS_AUTHORITATIVE")
                 .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
                 .unwrap_or(false),
-            ns_records: env::var("DNS_NS_RECORDS")
+            ns_records: var("DNS_NS_RECORDS")
                 .map(|v| v.split(',').map(|s| s.trim().to_string()).collect())
                 .unwrap_or_else(|_| vec!["ns1.yourdomain.tld.".into(), "ns2.yourdomain.tld.".into()]),
-            default_domain: env::var("DNS_DEFAULT_DOMAIN").unwrap_or_else(|_| "bzo.in".into()),
-            default_ip: env::var("DNS_DEFAULT_IP").unwrap_or_else(|_| "<your-public-ip4-here>".into()),
+            default_domain: var("DNS_DEFAULT_DOMAIN").unwrap_or_else(|_| "bzo.in".into()),
+            default_ip: var("DNS_DEFAULT_IP").unwrap_or_else(|_| "<your-public-ip4-here>".into()),
             ds_records: vec![
                 "yourdomain.tld. IN DS 24550 8 2 1F21CA282945434EE0662805430599CB2A6C479D9F934087150901CE2DA580A0".to_string()
             ],
             dnskey_records,
             forwarders,
+            emergency_records,
+            edns_echo_options: var("DNS_EDNS_ECHO_OPTIONS")
+                .map(|v| v.split(',').filter_map(|s| s.trim().parse().ok()).collect())
+                .unwrap_or_default(),
+            edns_buffer_size: var("DNS_EDNS_BUFFER_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_EDNS_BUFFER_SIZE),
+            answer_qu_queries: var("DNS_ANSWER_QU")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            rebinding_protection: var("DNS_REBIND_PROTECTION")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            rebinding_allowlist: var("DNS_REBIND_ALLOWLIST")
+                .map(|v| {
+                    v.split(',')
+                        .map(|s| s.trim().trim_end_matches('.').to_ascii_lowercase())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            response_jitter_ms: var("DNS_RESPONSE_JITTER_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            resolution_order,
+            static_records,
+            prefetch_threshold_percent: var("DNS_PREFETCH_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10),
+            prefetch_min_hits: var("DNS_PREFETCH_MIN_HITS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            dns64_enabled: var("DNS_DNS64")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            dns64_prefix,
+            slow_query_ms: var("DNS_SLOW_QUERY_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            internal_edns_buffer_size: var("DNS_INTERNAL_EDNS_BUFFER_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_EDNS_BUFFER_SIZE),
+            internal_do_bit: var("DNS_INTERNAL_DO_BIT")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            reverse_zones: var("DNS_REVERSE_ZONES")
+                .map(|v| {
+                    v.split(',')
+                        .map(|s| s.trim().trim_end_matches('.').to_ascii_lowercase())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            validate_responses: var("DNS_VALIDATE_RESPONSES")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(cfg!(debug_assertions)),
+            geo_records,
+            compress_names: var("DNS_COMPRESS_NAMES")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            negative_cache_max_ttl: var("DNS_NEGATIVE_CACHE_MAX_TTL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_NEGATIVE_CACHE_MAX_TTL),
+            allow_recursion,
+            recursion_allow,
+            recursion_deny,
+            refused_ede: var("DNS_REFUSED_EDE")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            axfr_allow,
+            hooks,
+            strict_edns_options: var("DNS_STRICT_EDNS_OPTIONS")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            maintenance_mode,
+            forward_timeout: Duration::from_millis(var("DNS_FORWARD_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_FORWARD_TIMEOUT_MS)),
+            answer_order,
+            max_tcp_connections: var("DNS_MAX_TCP_CONNECTIONS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_TCP_CONNECTIONS),
+            tcp_read_timeout: Duration::from_millis(var("DNS_TCP_READ_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TCP_READ_TIMEOUT_MS)),
+            minimal_responses,
+            edns_fallback: var("DNS_EDNS_FALLBACK")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            dns_cookies: var("DNS_COOKIES")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            health_checks,
+            health_check_interval: Duration::from_secs(var("DNS_HEALTH_CHECK_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL_SECS)),
+            cache_max_entries: var("DNS_CACHE_MAX_ENTRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_CACHE_MAX_ENTRIES),
+            metrics_bind,
+            serial_scheme,
+            max_chain_length: var("DNS_MAX_CHAIN_LENGTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_CHAIN_LENGTH),
+            doh_bind,
+            doh_tls_cert,
+            doh_tls_key,
+            whoami_name,
+            whoami_address,
+            any_response,
+            ecs_forwarding: var("DNS_ECS_FORWARDING")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            ecs_prefix_v4: var("DNS_ECS_PREFIX_V4")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_ECS_PREFIX_V4)
+                .min(32),
+            ecs_prefix_v6: var("DNS_ECS_PREFIX_V6")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_ECS_PREFIX_V6)
+                .min(128),
+            rrl_rate: var("DNS_RRL_RATE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            rrl_burst: var("DNS_RRL_BURST")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_RRL_BURST),
+            rrl_per_name: var("DNS_RRL_PER_NAME")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            rrl_action,
+            auto_ptr: var("DNS_AUTO_PTR")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            signing_key,
+            signature_validity,
+            health_bind,
+            reload_flush_cache: var("DNS_RELOAD_FLUSH_CACHE")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            udp_bind,
+            tcp_bind,
+            min_ttl,
+            max_ttl,
         })
     }
-}
\ No newline at end of file
+
+    /// Bring a TTL within `min_ttl` and `max_ttl`.
+    ///
+    /// # Arguments
+    /// * `ttl` - The TTL from a record or an upstream answer.
+    ///
+    /// # Returns
+    /// The clamped TTL.
+    pub fn clamp_ttl(&self, ttl: u64) -> u64 {
+        ttl.clamp(self.min_ttl, self.max_ttl)
+    }
+
+    /// Check whether queries for names outside our zones can be forwarded.
+    ///
+    /// # Returns
+    /// `true` if recursion is allowed, forwarding is in the resolution
+    /// order and at least one forwarder is configured.
+    pub fn forwarding_enabled(&self) -> bool {
+        self.allow_recursion
+            && !self.forwarders.is_empty()
+            && self.resolution_order.contains(&ResolutionSource::Forward)
+    }
+
+    /// Check whether a client may have queries forwarded on its behalf.
+    ///
+    /// # Arguments
+    /// * `client` - The client's source address.
+    ///
+    /// # Returns
+    /// `true` if recursion is allowed, the client is inside `recursion_allow`
+    /// (or it is empty) and the client is not inside `recursion_deny`.
+    pub fn recursion_allowed_for(&self, client: IpAddr) -> bool {
+        let in_any = |subnets: &[(IpAddr, u8)]| {
+            subnets.iter().any(|(network, prefix_len)| ip_in_subnet(&client, network, *prefix_len))
+        };
+        self.allow_recursion
+            && (self.recursion_allow.is_empty() || in_any(&self.recursion_allow))
+            && !in_any(&self.recursion_deny)
+    }
+}
+
+/// Convert a config file value to the string form of its environment variable.
+///
+/// # Arguments
+/// * `key` - The config file key, for error messages.
+/// * `value` - The TOML value.
+/// * `separator` - Separator used to join array elements.
+///
+/// # Returns
+/// A `Result` containing the setting as a string or a `DnsError`.
+fn setting_value(key: &str, value: &toml::Value, separator: &str) -> Result<String, DnsError> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Array(_) => Ok(string_list(key, value)?.join(separator)),
+        _ => Err(DnsError::Config(format!("Unsupported value for {}", key))),
+    }
+}
+
+/// Read a config file value as a list of strings.
+///
+/// # Arguments
+/// * `key` - The config file key, for error messages.
+/// * `value` - The TOML value, an array or a single string.
+///
+/// # Returns
+/// A `Result` containing the strings or a `DnsError`.
+fn string_list(key: &str, value: &toml::Value) -> Result<Vec<String>, DnsError> {
+    match value {
+        toml::Value::String(s) => Ok(vec![s.clone()]),
+        toml::Value::Array(items) => items.iter()
+            .map(|item| match item {
+                toml::Value::String(s) => Ok(s.clone()),
+                toml::Value::Integer(i) => Ok(i.to_string()),
+                _ => Err(DnsError::Config(format!("Unsupported list element for {}", key))),
+            })
+            .collect(),
+        _ => Err(DnsError::Config(format!("Expected a list for {}", key))),
+    }
+}
+
+/// Split a string on a separator, ignoring separators inside double quotes.
+///
+/// # Arguments
+/// * `s` - The string to split.
+/// * `separator` - The separator character.
+///
+/// # Returns
+/// The pieces of the string, quotes preserved.
+fn split_unquoted(s: &str, separator: char) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == separator && !in_quotes {
+            pieces.push(&s[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    pieces.push(&s[start..]);
+    pieces
+}
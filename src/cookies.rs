@@ -0,0 +1,128 @@
+//! DNS Cookies (RFC 7873).
+//!
+//! This module issues and checks server cookies. A client that returns a
+//! valid server cookie has shown it receives our responses at its source
+//! address, which lets it be sent UDP answers above the non-fragmenting size.
+//! Server cookies use the RFC 9018 layout, hashed with a per-process secret.
+#![allow(dead_code)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rand::Rng;
+
+use crate::utils::extract_edns_options;
+
+/// EDNS option code for COOKIE.
+pub const COOKIE_OPTION: u16 = 10;
+
+/// Length of a client cookie.
+pub const CLIENT_COOKIE_LEN: usize = 8;
+
+/// Length of the server cookies this server issues.
+pub const SERVER_COOKIE_LEN: usize = 16;
+
+/// How long an issued server cookie stays valid, in seconds (RFC 9018 section 4.3).
+pub const COOKIE_LIFETIME: u32 = 3600;
+
+/// How far in the future a cookie timestamp may be, in seconds.
+const COOKIE_CLOCK_SKEW: u32 = 300;
+
+/// Secret mixed into every server cookie hash.
+static SECRET: OnceLock<[u64; 2]> = OnceLock::new();
+
+/// Read the COOKIE option of a query.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+///
+/// # Returns
+/// An `Option` containing the client cookie and the server cookie, if any,
+/// or `None` if the query carries no well-formed COOKIE option.
+pub fn extract_cookie(query: &[u8]) -> Option<([u8; CLIENT_COOKIE_LEN], Vec<u8>)> {
+    let (_, data) = extract_edns_options(query).into_iter().find(|(code, _)| *code == COOKIE_OPTION)?;
+    // Client cookie alone, or followed by an 8 to 32 byte server cookie
+    if data.len() != CLIENT_COOKIE_LEN && !(16..=40).contains(&data.len()) {
+        return None;
+    }
+    let mut client = [0u8; CLIENT_COOKIE_LEN];
+    client.copy_from_slice(&data[..CLIENT_COOKIE_LEN]);
+    Some((client, data[CLIENT_COOKIE_LEN..].to_vec()))
+}
+
+/// Compute the server cookie for a client.
+///
+/// # Arguments
+/// * `client_cookie` - The client cookie from the query.
+/// * `client` - The client address.
+/// * `timestamp` - Seconds since the Unix epoch, truncated to 32 bits.
+///
+/// # Returns
+/// The 16-byte server cookie: version, reserved bytes, timestamp and hash.
+pub fn server_cookie(client_cookie: &[u8; CLIENT_COOKIE_LEN], client: IpAddr, timestamp: u32) -> [u8; SERVER_COOKIE_LEN] {
+    let secret = SECRET.get_or_init(|| {
+        let mut rng = rand::thread_rng();
+        [rng.gen(), rng.gen()]
+    });
+
+    let mut cookie = [0u8; SERVER_COOKIE_LEN];
+    cookie[0] = 1; // Version
+    cookie[4..8].copy_from_slice(&timestamp.to_be_bytes());
+
+    let mut hasher = DefaultHasher::new();
+    secret.hash(&mut hasher);
+    client_cookie.hash(&mut hasher);
+    cookie[..8].hash(&mut hasher);
+    client.hash(&mut hasher);
+    cookie[8..].copy_from_slice(&hasher.finish().to_be_bytes());
+    cookie
+}
+
+/// Check whether a query returns a server cookie we issued to its sender
+/// within the last hour.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `client` - The client address.
+///
+/// # Returns
+/// `true` if the query carries a current, valid server cookie.
+pub fn has_valid_server_cookie(query: &[u8], client: IpAddr) -> bool {
+    let (client_cookie, server) = match extract_cookie(query) {
+        Some(cookie) => cookie,
+        None => return false,
+    };
+    if server.len() != SERVER_COOKIE_LEN || server[0] != 1 {
+        return false;
+    }
+
+    let timestamp = u32::from_be_bytes([server[4], server[5], server[6], server[7]]);
+    let age = now().wrapping_sub(timestamp) as i32;
+    if age > COOKIE_LIFETIME as i32 || age < -(COOKIE_CLOCK_SKEW as i32) {
+        return false;
+    }
+    server_cookie(&client_cookie, client, timestamp)[..] == server[..]
+}
+
+/// Build the COOKIE option data for a response.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `client` - The client address.
+///
+/// # Returns
+/// An `Option` containing the client cookie followed by a fresh server
+/// cookie, or `None` if the query carries no COOKIE option.
+pub fn response_cookie(query: &[u8], client: IpAddr) -> Option<Vec<u8>> {
+    let (client_cookie, _) = extract_cookie(query)?;
+    let mut data = client_cookie.to_vec();
+    data.extend_from_slice(&server_cookie(&client_cookie, client, now()));
+    Some(data)
+}
+
+/// Current time in seconds since the Unix epoch, truncated to 32 bits.
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0)
+}
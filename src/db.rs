@@ -3,10 +3,72 @@
 //! This module provides functions for interacting with the SQLite database
 //! that stores DNS records and zone information.
 
-use rusqlite::{params, Connection};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use chrono::{DateTime, Datelike, Utc};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, OpenFlags, Transaction};
+use log::warn;
 
-use crate::errors::DnsError;
-use crate::config::ServerConfig;
+use crate::cache::CACHE;
+use crate::errors::{DnsError, RecordValidationError};
+use crate::config::{SerialScheme, ServerConfig};
+use crate::utils::validate_dns_name;
+
+/// Maximum number of pooled read-only connections per database.
+pub const DB_POOL_SIZE: u32 = 8;
+
+/// How long a query waits for a free pooled connection.
+pub const DB_POOL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Record types accepted by the `dns_records` table.
+pub const RECORD_TYPES: &[&str] = &[
+    "A", "AAAA", "MX", "TXT", "NS", "CNAME", "PTR", "SOA",
+    "SRV", "CAA", "NAPTR", "DS", "DNSKEY", "RRSIG", "NSEC",
+    "TLSA", "SSHFP", "SVCB", "HTTPS",
+];
+
+/// Read-only connection pools for query-time lookups, keyed by database path.
+static POOLS: OnceLock<Mutex<HashMap<String, Pool<SqliteConnectionManager>>>> = OnceLock::new();
+
+/// Borrow a read-only connection to the database from its shared pool.
+///
+/// The pool for `db_path` is created on first use. Creation opens the
+/// database directly first, so a missing or unreadable database is reported
+/// immediately rather than after the pool's connection timeout.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+///
+/// # Returns
+/// A `Result` containing a pooled connection or a `DnsError`.
+pub fn pooled_connection(db_path: &str) -> Result<PooledConnection<SqliteConnectionManager>, DnsError> {
+    let pool = {
+        let mut pools = POOLS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+        match pools.get(db_path) {
+            Some(pool) => pool.clone(),
+            None => {
+                let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+                Connection::open_with_flags(db_path, flags)?;
+                let manager = SqliteConnectionManager::file(db_path).with_flags(flags);
+                let pool = Pool::builder()
+                    .max_size(DB_POOL_SIZE)
+                    .min_idle(Some(1))
+                    .connection_timeout(DB_POOL_TIMEOUT)
+                    .build(manager)?;
+                pools.insert(db_path.to_string(), pool.clone());
+                pool
+            }
+        }
+    };
+    Ok(pool.get()?)
+}
 
 /// Information about a DNS zone.
 #[derive(Debug, Clone)]
@@ -43,7 +105,7 @@ pub fn init_db(db_path: &str, default_domain: &str, default_ip: &str) -> Result<
             record_type TEXT NOT NULL CHECK(record_type IN (
                 'A','AAAA','MX','TXT','NS','CNAME','PTR','SOA',
                 'SRV','CAA','NAPTR','DS','DNSKEY','RRSIG','NSEC',
-                'TLSA','SSHFP'
+                'TLSA','SSHFP','SVCB','HTTPS'
             )),
             value TEXT NOT NULL,
             ttl INTEGER DEFAULT 3600,
@@ -55,6 +117,7 @@ pub fn init_db(db_path: &str, default_domain: &str, default_ip: &str) -> Result<
     let count: i64 = conn.query_row("SELECT COUNT(*) FROM dns_records", [], |row| row.get(0))?;
 
     if count == 0 && !default_ip.is_empty() {
+        validate_dns_name(default_domain)?;
         let mail_domain = format!("mail.{}", default_domain);
         let ns1 = format!("ns1.{}", default_domain);
         let ns2 = format!("ns2.{}", default_domain);
@@ -83,6 +146,446 @@ pub fn init_db(db_path: &str, default_domain: &str, default_ip: &str) -> Result<
     Ok(())
 }
 
+/// Build an `InvalidValue` error for a record field.
+fn invalid(field: &'static str, reason: impl Into<String>) -> RecordValidationError {
+    RecordValidationError::InvalidValue { field, reason: reason.into() }
+}
+
+/// Check that a field holds a well-formed domain name.
+///
+/// # Arguments
+/// * `field` - The field name reported on failure.
+/// * `name` - The domain name, with or without a trailing dot.
+///
+/// # Returns
+/// A `Result` indicating whether the name is valid.
+fn check_name(field: &'static str, name: &str) -> Result<(), RecordValidationError> {
+    if name.trim_end_matches('.').is_empty() {
+        return Err(invalid(field, "empty name"));
+    }
+    match validate_dns_name(name) {
+        Err(DnsError::Config(reason)) => Err(invalid(field, reason)),
+        Err(other) => Err(invalid(field, other.to_string())),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Parse a numeric or address record field.
+///
+/// # Arguments
+/// * `field` - The field name reported on failure.
+/// * `value` - The field text.
+///
+/// # Returns
+/// A `Result` containing the parsed number.
+fn check_number<T: FromStr>(field: &'static str, value: &str) -> Result<T, RecordValidationError> {
+    value.parse().map_err(|_| invalid(field, format!("cannot parse {:?}", value)))
+}
+
+/// Split a record value into exactly `count` whitespace-separated fields.
+///
+/// # Arguments
+/// * `value` - The record value.
+/// * `count` - The number of fields expected.
+///
+/// # Returns
+/// A `Result` containing the fields.
+fn check_fields(value: &str, count: usize) -> Result<Vec<&str>, RecordValidationError> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() != count {
+        return Err(invalid("value", format!("expected {} fields, got {}", count, fields.len())));
+    }
+    Ok(fields)
+}
+
+/// Validate a record's syntax before it is stored.
+///
+/// # Arguments
+/// * `domain` - The owner name.
+/// * `record_type` - The record type name.
+/// * `value` - The record value in the database's text form.
+/// * `ttl` - The record TTL in seconds.
+///
+/// # Returns
+/// A `Result` naming the field that failed, if any.
+pub fn validate_record(domain: &str, record_type: &str, value: &str, ttl: i64) -> Result<(), RecordValidationError> {
+    check_name("domain", domain)?;
+    let record_type = record_type.to_ascii_uppercase();
+    if !RECORD_TYPES.contains(&record_type.as_str()) {
+        return Err(RecordValidationError::InvalidType(record_type));
+    }
+    if !(0..=i32::MAX as i64).contains(&ttl) {
+        return Err(invalid("ttl", format!("{} is outside 0..=2147483647", ttl)));
+    }
+
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(invalid("value", "empty value"));
+    }
+
+    match record_type.as_str() {
+        "A" => {
+            check_number::<Ipv4Addr>("address", value)?;
+        }
+        "AAAA" => {
+            check_number::<Ipv6Addr>("address", value)?;
+        }
+        "NS" | "CNAME" | "PTR" => check_name("target", value)?,
+        "MX" => {
+            let fields = check_fields(value, 2)?;
+            check_number::<u16>("preference", fields[0])?;
+            check_name("exchange", fields[1])?;
+        }
+        "SRV" => {
+            let fields = check_fields(value, 4)?;
+            check_number::<u16>("priority", fields[0])?;
+            check_number::<u16>("weight", fields[1])?;
+            check_number::<u16>("port", fields[2])?;
+            check_name("target", fields[3])?;
+        }
+        "SOA" => {
+            let fields = check_fields(value, 7)?;
+            check_name("mname", fields[0])?;
+            check_name("rname", fields[1])?;
+            for (field, text) in ["serial", "refresh", "retry", "expire", "minimum"].into_iter().zip(&fields[2..]) {
+                check_number::<u32>(field, text)?;
+            }
+        }
+        "CAA" => {
+            let mut fields = value.splitn(3, char::is_whitespace);
+            check_number::<u8>("flags", fields.next().unwrap_or_default())?;
+            let tag = fields.next().unwrap_or_default();
+            if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(invalid("tag", format!("{:?} is not a valid CAA tag", tag)));
+            }
+            if fields.next().map(str::trim).unwrap_or_default().is_empty() {
+                return Err(invalid("value", "missing CAA value"));
+            }
+        }
+        "TLSA" => {
+            let fields = check_fields(value, 4)?;
+            check_number::<u8>("usage", fields[0])?;
+            check_number::<u8>("selector", fields[1])?;
+            check_number::<u8>("matching_type", fields[2])?;
+            hex::decode(fields[3]).map_err(|e| invalid("certificate_data", e.to_string()))?;
+        }
+        "SSHFP" => {
+            let fields = check_fields(value, 3)?;
+            check_number::<u8>("algorithm", fields[0])?;
+            check_number::<u8>("fp_type", fields[1])?;
+            hex::decode(fields[2]).map_err(|e| invalid("fingerprint", e.to_string()))?;
+        }
+        "NAPTR" => {
+            crate::dns::naptr_rdata(value).map_err(|e| invalid("value", e.to_string()))?;
+        }
+        "SVCB" | "HTTPS" => {
+            let mut fields = value.split_whitespace();
+            check_number::<u16>("priority", fields.next().unwrap_or_default())?;
+            let target = fields.next().unwrap_or_default();
+            if target != "." {
+                check_name("target", target)?;
+            }
+            crate::dns::svcb_rdata(value).map_err(|e| invalid("params", e.to_string()))?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Compare two SOA serials with RFC 1982 serial number arithmetic.
+///
+/// # Arguments
+/// * `a` - The serial to test.
+/// * `b` - The serial to compare against.
+///
+/// # Returns
+/// `true` if `a` is newer than `b`; serials exactly 2^31 apart are not ordered.
+pub fn serial_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Work out the serial that follows `current` under a scheme.
+///
+/// If the scheme's value would not be newer than `current`, for example a
+/// second change within the same second or a `DateCounter` that has used up
+/// the day's 100 changes, `current` plus one is used instead.
+///
+/// # Arguments
+/// * `current` - The zone's current serial.
+/// * `scheme` - How serials advance.
+/// * `now` - The time of the change.
+///
+/// # Returns
+/// The next serial, always newer than `current`.
+pub fn next_serial(current: u32, scheme: SerialScheme, now: DateTime<Utc>) -> u32 {
+    let candidate = match scheme {
+        SerialScheme::UnixTime => now.timestamp() as u32,
+        SerialScheme::DateCounter => (now.year() as u32 * 10000 + now.month() * 100 + now.day()) * 100,
+        SerialScheme::Increment => current.wrapping_add(1),
+    };
+    if serial_newer(candidate, current) {
+        candidate
+    } else {
+        current.wrapping_add(1)
+    }
+}
+
+/// Advance the serial of the zone enclosing a name.
+///
+/// # Arguments
+/// * `tx` - The transaction making the change.
+/// * `domain` - The changed name.
+/// * `scheme` - How serials advance.
+/// * `serial_override` - An explicit new serial, which must be newer than the current one.
+///
+/// # Returns
+/// A `Result` containing the zone's new serial, or `None` if no zone in the
+/// database encloses the name.
+fn advance_zone_serial(
+    tx: &Transaction,
+    domain: &str,
+    scheme: SerialScheme,
+    serial_override: Option<u32>,
+) -> Result<Option<u32>, DnsError> {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let Some((zone, soa)) = (0..labels.len()).find_map(|i| {
+        let zone = labels[i..].join(".");
+        tx.query_row(
+            "SELECT value FROM dns_records WHERE domain = ?1 AND record_type = 'SOA' LIMIT 1",
+            params![zone],
+            |row| row.get::<_, String>(0),
+        ).ok().map(|soa| (zone, soa))
+    }) else {
+        return Ok(None);
+    };
+
+    let mut fields: Vec<String> = soa.split_whitespace().map(str::to_string).collect();
+    let current = check_number::<u32>("serial", fields.get(2).map(String::as_str).unwrap_or(""))?;
+    let serial = match serial_override {
+        Some(serial) if !serial_newer(serial, current) => {
+            return Err(invalid("serial", format!("{} is not newer than the current serial {}", serial, current)).into());
+        }
+        Some(serial) => serial,
+        None => next_serial(current, scheme, Utc::now()),
+    };
+    fields[2] = serial.to_string();
+
+    tx.execute(
+        "UPDATE dns_records SET value = ?1 WHERE domain = ?2 AND record_type = 'SOA'",
+        params![fields.join(" "), zone],
+    )?;
+    Ok(Some(serial))
+}
+
+/// Validate a record and add it to the database.
+///
+/// Besides the checks in [`validate_record`], the record is rejected if an
+/// identical record exists or if it would put a CNAME alongside other
+/// records at the same name. The serial of the enclosing zone advances in
+/// the same transaction.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `domain` - The owner name.
+/// * `record_type` - The record type name.
+/// * `value` - The record value in the database's text form.
+/// * `ttl` - The record TTL in seconds.
+/// * `scheme` - How the zone serial advances.
+/// * `serial_override` - An explicit new zone serial, which must be newer than the current one.
+///
+/// # Returns
+/// A `Result` containing the zone's new serial, or `None` if no zone
+/// encloses the record or the record is itself an SOA; `DnsError::Validation`
+/// names the problem if the record or serial is rejected.
+pub fn insert_record(
+    db_path: &str,
+    domain: &str,
+    record_type: &str,
+    value: &str,
+    ttl: i64,
+    scheme: SerialScheme,
+    serial_override: Option<u32>,
+) -> Result<Option<u32>, DnsError> {
+    validate_record(domain, record_type, value, ttl)?;
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let record_type = record_type.to_ascii_uppercase();
+    let value = value.trim();
+
+    let mut conn = Connection::open(db_path)?;
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare("SELECT record_type, value FROM dns_records WHERE domain = ?1")?;
+        let existing = stmt.query_map(params![domain], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if existing.iter().any(|(rtype, v)| rtype.eq_ignore_ascii_case(&record_type) && v == value) {
+            return Err(RecordValidationError::DuplicateConflict {
+                domain,
+                record_type,
+                value: value.to_string(),
+            }.into());
+        }
+        // DNSSEC records are the only ones allowed alongside a CNAME
+        let is_dnssec = |rtype: &str| rtype.eq_ignore_ascii_case("RRSIG") || rtype.eq_ignore_ascii_case("NSEC");
+        let has_cname = existing.iter().any(|(rtype, _)| rtype.eq_ignore_ascii_case("CNAME"));
+        let has_other = existing.iter().any(|(rtype, _)| !is_dnssec(rtype));
+        if (record_type == "CNAME" && has_other) || (!is_dnssec(&record_type) && record_type != "CNAME" && has_cname) {
+            return Err(RecordValidationError::CnameConflict { domain }.into());
+        }
+    }
+    tx.execute(
+        "INSERT INTO dns_records (domain, record_type, value, ttl) VALUES (?1, ?2, ?3, ?4)",
+        params![domain, record_type, value, ttl],
+    )?;
+    let serial = if record_type == "SOA" {
+        None
+    } else {
+        advance_zone_serial(&tx, &domain, scheme, serial_override)?
+    };
+    tx.commit()?;
+    Ok(serial)
+}
+
+/// Atomically replace every record in a zone with a new set.
+///
+/// The old records are deleted and the new ones inserted in one transaction,
+/// so queries see either the old zone or the new one, never a mix. The SOA
+/// serial always moves forward: the new set's serial is kept if it is newer
+/// than the current one, and otherwise the next serial under `scheme` is
+/// used. A new set without an apex SOA keeps the current SOA with an
+/// advanced serial. Cached answers for the zone are dropped once the
+/// transaction commits.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+/// * `records` - The new (domain, ttl, record_type, value) tuples, in the shape `zone_records_page` returns.
+/// * `scheme` - How the serial advances when the new set's is not newer.
+///
+/// # Returns
+/// A `Result` containing the zone's new serial, or `DnsError::Validation` if any record is invalid.
+pub fn replace_zone(
+    db_path: &str,
+    zone: &str,
+    records: &[(String, u64, String, String)],
+    scheme: SerialScheme,
+) -> Result<u32, DnsError> {
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    let suffix = format!(".{}", zone);
+
+    let mut rows = Vec::with_capacity(records.len());
+    let mut new_soa = None;
+    for (domain, ttl, record_type, value) in records {
+        validate_record(domain, record_type, value, *ttl as i64)?;
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        if domain != zone && !domain.ends_with(&suffix) {
+            return Err(invalid("domain", format!("{} is outside zone {}", domain, zone)).into());
+        }
+        let record_type = record_type.to_ascii_uppercase();
+        if record_type == "SOA" && domain == zone {
+            if new_soa.is_some() {
+                return Err(invalid("value", "zone has more than one SOA").into());
+            }
+            new_soa = Some(rows.len());
+        }
+        rows.push((domain, *ttl as i64, record_type, value.trim().to_string()));
+    }
+
+    let mut conn = Connection::open(db_path)?;
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+
+    let old_soa: Option<(String, i64)> = tx.query_row(
+        "SELECT value, ttl FROM dns_records WHERE domain = ?1 AND record_type = 'SOA' LIMIT 1",
+        params![zone],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).ok();
+    let old_serial = old_soa.as_ref()
+        .and_then(|(soa, _)| soa.split_whitespace().nth(2))
+        .and_then(|serial| serial.parse::<u32>().ok());
+
+    // Carry the current SOA over if the new set has none
+    let index = match (new_soa, old_soa) {
+        (Some(index), _) => index,
+        (None, Some((soa, ttl))) => {
+            rows.push((zone.clone(), ttl, "SOA".to_string(), soa));
+            rows.len() - 1
+        }
+        (None, None) => return Err(invalid("record_type", format!("zone {} has no SOA", zone)).into()),
+    };
+
+    // Serial numbers compare with RFC 1982 arithmetic
+    let mut fields: Vec<String> = rows[index].3.split_whitespace().map(str::to_string).collect();
+    let proposed = check_number::<u32>("serial", &fields[2])?;
+    let serial = match old_serial {
+        Some(old) if !serial_newer(proposed, old) => next_serial(old, scheme, Utc::now()),
+        _ => proposed,
+    };
+    fields[2] = serial.to_string();
+    rows[index].3 = fields.join(" ");
+
+    tx.execute(
+        "DELETE FROM dns_records WHERE domain = ?1 OR domain LIKE '%.' || ?1",
+        params![zone],
+    )?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR REPLACE INTO dns_records (domain, record_type, value, ttl) VALUES (?1, ?2, ?3, ?4)"
+        )?;
+        for (domain, ttl, record_type, value) in &rows {
+            stmt.execute(params![domain, record_type, value, ttl])?;
+        }
+    }
+    tx.commit()?;
+
+    if let Some(cache) = CACHE.get() {
+        if let Err(e) = cache.invalidate_zone(&zone) {
+            warn!("Zone {} replaced but its cached answers were not dropped: {}", zone, e);
+        }
+    }
+    Ok(serial)
+}
+
+/// Import a master file (RFC 1035) as the full contents of its zone.
+///
+/// The zone is named by the file's SOA record, and its records replace the
+/// zone's current ones atomically through [`replace_zone`].
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `path` - Path to the zone file.
+/// * `scheme` - How the serial advances if the file's serial is not newer than the stored one.
+///
+/// # Returns
+/// A `Result` containing the zone name and the number of records imported,
+/// or `DnsError::Config` naming the line of a parse error.
+pub fn import_zone_file(db_path: &str, path: &str, scheme: SerialScheme) -> Result<(String, usize), DnsError> {
+    let text = std::fs::read_to_string(path)?;
+    let records = crate::zonefile::parse(&text, None)?;
+
+    let zone = records.iter()
+        .find(|(_, _, rtype, _)| rtype == "SOA")
+        .map(|(domain, _, _, _)| domain.clone())
+        .ok_or_else(|| DnsError::Config(format!("{} has no SOA record", path)))?;
+    replace_zone(db_path, &zone, &records, scheme)?;
+    Ok((zone, records.len()))
+}
+
+/// Export a zone as a master file (RFC 1035).
+///
+/// The SOA comes first, each record gets its own line with its TTL, and
+/// owners are written relative to `$ORIGIN`; see [`crate::zonefile::export`].
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+///
+/// # Returns
+/// A `Result` containing the zone file text, or an error if the zone has no SOA.
+pub fn export_zone(db_path: &str, zone: &str) -> Result<String, DnsError> {
+    crate::zonefile::export(db_path, zone)
+}
+
 /// Look up DNS records for a domain.
 ///
 /// # Arguments
@@ -92,29 +595,295 @@ pub fn init_db(db_path: &str, default_domain: &str, default_ip: &str) -> Result<
 /// # Returns
 /// A vector of tuples containing (value, ttl, record_type) for each record found.
 pub fn lookup_records(db_path: &str, domain: &str) -> Vec<(String, u64, String)> {
-    let conn = Connection::open(db_path);
-    match conn {
-        Ok(conn) => {
-            match conn.prepare(
-                "SELECT value, ttl, record_type FROM dns_records WHERE domain = ?"
-            ) {
-                Ok(mut stmt) => {
-                    match stmt.query_map(params![domain], |row| {
-                        Ok((
-                            row.get(0).unwrap_or_default(),
-                            row.get(1).unwrap_or_default(),
-                            row.get(2).unwrap_or_default(),
-                        ))
-                    }) {
-                        Ok(rows) => rows.filter_map(Result::ok).collect(),
-                        Err(_) => Vec::new(),
-                    }
-                }
-                Err(_) => Vec::new(),
-            }
+    try_lookup_records(db_path, domain).unwrap_or_default()
+}
+
+/// Look up DNS records for a domain, reporting database failures.
+///
+/// Unlike `lookup_records`, a missing, unreadable or corrupted database is
+/// returned as an error instead of an empty result, so callers can tell
+/// "no such record" apart from "database unavailable".
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `domain` - Domain name to look up.
+///
+/// # Returns
+/// A `Result` containing (value, ttl, record_type) tuples or a `DnsError`.
+pub fn try_lookup_records(db_path: &str, domain: &str) -> Result<Vec<(String, u64, String)>, DnsError> {
+    let conn = pooled_connection(db_path)?;
+    let mut stmt = conn.prepare_cached(
+        "SELECT value, ttl, record_type FROM dns_records WHERE domain = ?"
+    )?;
+    let rows = stmt.query_map(params![domain], |row| {
+        Ok((
+            row.get(0).unwrap_or_default(),
+            row.get(1).unwrap_or_default(),
+            row.get(2).unwrap_or_default(),
+        ))
+    })?;
+
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+/// Find the names whose A or AAAA records point at an address.
+///
+/// Wildcard owners are skipped, as they are not names a client can look up.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `addr` - The address to look for.
+///
+/// # Returns
+/// A `Result` containing (name, ttl) pairs or a `DnsError`.
+pub fn lookup_names_by_address(db_path: &str, addr: IpAddr) -> Result<Vec<(String, u64)>, DnsError> {
+    let record_type = if addr.is_ipv4() { "A" } else { "AAAA" };
+    let conn = pooled_connection(db_path)?;
+    let mut stmt = conn.prepare_cached(
+        "SELECT domain, ttl FROM dns_records
+         WHERE record_type = ?1 AND lower(value) = ?2 AND domain NOT LIKE '*%' ORDER BY domain"
+    )?;
+    let rows = stmt.query_map(params![record_type, addr.to_string()], |row| {
+        Ok((row.get(0).unwrap_or_default(), row.get(1).unwrap_or_default()))
+    })?;
+
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+/// Check whether a name exists in the database, either with records of its
+/// own or as an empty non-terminal above other names.
+///
+/// # Arguments
+/// * `conn` - A database connection.
+/// * `name` - The domain name.
+///
+/// # Returns
+/// A `Result` containing whether the name exists.
+fn name_exists(conn: &Connection, name: &str) -> Result<bool, DnsError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT EXISTS(SELECT 1 FROM dns_records
+         WHERE domain = ?1 OR substr(domain, -length(?1) - 1) = '.' || ?1)"
+    )?;
+    Ok(stmt.query_row(params![name], |row| row.get(0))?)
+}
+
+/// Look up the records a wildcard synthesizes for a name (RFC 4592).
+///
+/// Only the wildcard directly below the closest encloser (the nearest
+/// existing ancestor within `zone`) applies, so a wildcard never reaches past
+/// an existing name. Names that exist themselves, including the zone apex
+/// and empty non-terminals, are never synthesized.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `domain` - The queried name, which has no records of its own.
+/// * `zone` - The apex of the closest enclosing zone.
+///
+/// # Returns
+/// A `Result` containing the wildcard's (value, ttl, record_type) tuples,
+/// empty if no wildcard applies.
+pub fn lookup_records_wildcard(db_path: &str, domain: &str, zone: &str) -> Result<Vec<(String, u64, String)>, DnsError> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    if !domain.ends_with(&format!(".{}", zone)) {
+        return Ok(Vec::new());
+    }
+
+    let conn = pooled_connection(db_path)?;
+    if name_exists(&conn, &domain)? {
+        return Ok(Vec::new());
+    }
+
+    // Walk up to the closest encloser; the apex always encloses
+    let mut encloser = domain.as_str();
+    loop {
+        encloser = match encloser.split_once('.') {
+            Some((_, parent)) => parent,
+            None => return Ok(Vec::new()),
+        };
+        if encloser == zone || name_exists(&conn, encloser)? {
+            break;
         }
-        Err(_) => Vec::new(),
     }
+    drop(conn);
+
+    try_lookup_records(db_path, &format!("*.{}", encloser))
+}
+
+/// List the owner names inside a zone with the record types each one holds.
+///
+/// Stored RRSIG and NSEC records are left out, as the server generates its
+/// own. Names are lowercased and returned in no particular order; callers
+/// needing DNSSEC canonical order sort them with `dnssec::canonical_cmp`.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+///
+/// # Returns
+/// A `Result` containing (name, record types) pairs or a `DnsError`.
+pub fn zone_owner_types(db_path: &str, zone: &str) -> Result<Vec<(String, Vec<String>)>, DnsError> {
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    let conn = pooled_connection(db_path)?;
+    let mut stmt = conn.prepare_cached(
+        "SELECT lower(domain), group_concat(DISTINCT upper(record_type)) FROM dns_records
+         WHERE (lower(domain) = ?1 OR lower(domain) LIKE '%.' || ?1)
+           AND upper(record_type) NOT IN ('RRSIG', 'NSEC')
+         GROUP BY lower(domain)"
+    )?;
+    let rows = stmt.query_map(params![zone], |row| {
+        let types: String = row.get(1).unwrap_or_default();
+        Ok((row.get(0).unwrap_or_default(), types.split(',').map(str::to_string).collect()))
+    })?;
+
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+/// Read one page of the records inside a zone, in primary-key order.
+///
+/// Callers page through a zone by passing the last row of the previous page
+/// as `after`, so a whole zone never has to be held in memory.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+/// * `after` - The (domain, record_type, value) key of the last row already read.
+/// * `limit` - Maximum number of rows to return.
+///
+/// # Returns
+/// A `Result` containing (domain, ttl, record_type, value) tuples or a `DnsError`.
+pub fn zone_records_page(
+    db_path: &str,
+    zone: &str,
+    after: Option<&(String, String, String)>,
+    limit: usize,
+) -> Result<Vec<(String, u64, String, String)>, DnsError> {
+    let conn = pooled_connection(db_path)?;
+    let mut stmt = conn.prepare_cached(
+        "SELECT domain, ttl, record_type, value FROM dns_records
+         WHERE (domain = ?1 OR domain LIKE '%.' || ?1)
+           AND (domain, record_type, value) > (?2, ?3, ?4)
+         ORDER BY domain, record_type, value
+         LIMIT ?5"
+    )?;
+    let (domain, rtype, value) = after.cloned().unwrap_or_default();
+    let rows = stmt.query_map(params![zone, domain, rtype, value, limit as i64], |row| {
+        Ok((
+            row.get(0)?,
+            row.get::<_, i64>(1).unwrap_or_default() as u64,
+            row.get(2)?,
+            row.get(3)?,
+        ))
+    })?;
+
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+/// Summary of a zone's SOA and contents, as shown to operators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneSummary {
+    /// The zone apex.
+    pub name: String,
+
+    /// Current SOA serial.
+    pub serial: u32,
+
+    /// Number of records in the zone, including the SOA.
+    pub record_count: u64,
+
+    /// TTL of the apex SOA, used as the zone's default TTL.
+    pub default_ttl: u64,
+
+    /// SOA primary name server.
+    pub mname: String,
+
+    /// SOA responsible mailbox.
+    pub rname: String,
+
+    /// SOA refresh interval in seconds.
+    pub refresh: u32,
+
+    /// SOA retry interval in seconds.
+    pub retry: u32,
+
+    /// SOA expire interval in seconds.
+    pub expire: u32,
+
+    /// SOA minimum (negative caching) TTL in seconds.
+    pub minimum: u32,
+}
+
+/// Read the apex SOA value and TTL of a zone.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+///
+/// # Returns
+/// A `Result` containing the SOA value and TTL, or `None` if the zone has no SOA.
+fn zone_soa(db_path: &str, zone: &str) -> Result<Option<(String, u64)>, DnsError> {
+    let conn = pooled_connection(db_path)?;
+    let mut stmt = conn.prepare_cached(
+        "SELECT value, ttl FROM dns_records WHERE domain = ?1 AND record_type = 'SOA' LIMIT 1"
+    )?;
+    let mut rows = stmt.query_map(params![zone], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1).unwrap_or_default() as u64))
+    })?;
+    Ok(rows.next().transpose()?)
+}
+
+/// Get the current SOA serial of a zone.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+///
+/// # Returns
+/// A `Result` containing the serial, or `None` if the zone has no valid SOA.
+pub fn current_serial(db_path: &str, zone: &str) -> Result<Option<u32>, DnsError> {
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    Ok(zone_soa(db_path, &zone)?
+        .and_then(|(soa, _)| soa.split_whitespace().nth(2).and_then(|serial| serial.parse().ok())))
+}
+
+/// Summarize a zone's serial, record count, default TTL and SOA fields.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+///
+/// # Returns
+/// A `Result` containing the summary, or `None` if the zone has no valid SOA.
+pub fn zone_summary(db_path: &str, zone: &str) -> Result<Option<ZoneSummary>, DnsError> {
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    let Some((soa, default_ttl)) = zone_soa(db_path, &zone)? else {
+        return Ok(None);
+    };
+    let fields: Vec<&str> = soa.split_whitespace().collect();
+    if fields.len() < 7 {
+        return Ok(None);
+    }
+    let numbers = fields[2..7].iter()
+        .map(|field| field.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let conn = pooled_connection(db_path)?;
+    let record_count: i64 = conn.prepare_cached(
+        "SELECT COUNT(*) FROM dns_records WHERE domain = ?1 OR domain LIKE '%.' || ?1"
+    )?.query_row(params![zone], |row| row.get(0))?;
+
+    Ok(Some(ZoneSummary {
+        mname: fields[0].to_string(),
+        rname: fields[1].to_string(),
+        name: zone,
+        serial: numbers[0],
+        record_count: record_count as u64,
+        default_ttl,
+        refresh: numbers[1],
+        retry: numbers[2],
+        expire: numbers[3],
+        minimum: numbers[4],
+    }))
 }
 
 /// Get information about all zones for which this server is authoritative.
@@ -127,46 +896,44 @@ pub fn lookup_records(db_path: &str, domain: &str) -> Vec<(String, u64, String)>
 pub fn get_authoritative_zones(db_path: &str) -> Vec<ZoneInfo> {
     let mut zones = Vec::new();
 
-    if let Ok(conn) = Connection::open(db_path) {
+    if let Ok(conn) = pooled_connection(db_path) {
         // Find all domains with NS records (these are zones)
-        if let Ok(mut stmt) = conn.prepare(
+        if let Ok(mut stmt) = conn.prepare_cached(
             "SELECT DISTINCT domain FROM dns_records WHERE record_type = 'NS'"
         ) {
             if let Ok(rows) = stmt.query_map([], |row| {
-                Ok(row.get::<_, String>(0)?)
+                row.get::<_, String>(0)
             }) {
-                for domain_result in rows {
-                    if let Ok(domain) = domain_result {
-                        let mut zone_info = ZoneInfo {
-                            name: domain.clone(),
-                            ns_records: Vec::new(),
-                            soa_record: None,
-                        };
-
-                        // Get NS records for this zone
-                        if let Ok(mut ns_stmt) = conn.prepare(
-                            "SELECT value FROM dns_records WHERE domain = ? AND record_type = 'NS'"
-                        ) {
-                            if let Ok(ns_rows) = ns_stmt.query_map([&domain], |row| {
-                                Ok(row.get::<_, String>(0)?)
-                            }) {
-                                zone_info.ns_records = ns_rows.filter_map(Result::ok).collect();
-                            }
-                        }
+                for domain in rows.flatten() {
+                    let mut zone_info = ZoneInfo {
+                        name: domain.clone(),
+                        ns_records: Vec::new(),
+                        soa_record: None,
+                    };
 
-                        // Get SOA record if exists
-                        if let Ok(mut soa_stmt) = conn.prepare(
-                            "SELECT value FROM dns_records WHERE domain = ? AND record_type = 'SOA' LIMIT 1"
-                        ) {
-                            if let Ok(mut soa_rows) = soa_stmt.query_map([&domain], |row| {
-                                Ok(row.get::<_, String>(0)?)
-                            }) {
-                                zone_info.soa_record = soa_rows.next().and_then(|r| r.ok());
-                            }
+                    // Get NS records for this zone
+                    if let Ok(mut ns_stmt) = conn.prepare_cached(
+                        "SELECT value FROM dns_records WHERE domain = ? AND record_type = 'NS'"
+                    ) {
+                        if let Ok(ns_rows) = ns_stmt.query_map([&domain], |row| {
+                            row.get::<_, String>(0)
+                        }) {
+                            zone_info.ns_records = ns_rows.filter_map(Result::ok).collect();
                         }
+                    }
 
-                        zones.push(zone_info);
+                    // Get SOA record if exists
+                    if let Ok(mut soa_stmt) = conn.prepare_cached(
+                        "SELECT value FROM dns_records WHERE domain = ? AND record_type = 'SOA' LIMIT 1"
+                    ) {
+                        if let Ok(mut soa_rows) = soa_stmt.query_map([&domain], |row| {
+                            row.get::<_, String>(0)
+                        }) {
+                            zone_info.soa_record = soa_rows.next().and_then(|r| r.ok());
+                        }
                     }
+
+                    zones.push(zone_info);
                 }
             }
         }
@@ -191,6 +958,40 @@ pub fn get_authoritative_zones(db_path: &str) -> Vec<ZoneInfo> {
     zones
 }
 
+/// Get all zones this server answers for, including configured reverse zones.
+///
+/// Reverse zones listed in `config.reverse_zones` that have no NS/SOA data in
+/// the database get an SOA and NS set derived from the server's own name
+/// servers, so negative answers for them carry a proper authority section.
+///
+/// # Arguments
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A vector of `ZoneInfo` structs containing information about each zone.
+pub fn get_zones(config: &ServerConfig) -> Vec<ZoneInfo> {
+    let mut zones = get_authoritative_zones(&config.db_path);
+
+    for reverse_zone in &config.reverse_zones {
+        if zones.iter().any(|z| z.name.eq_ignore_ascii_case(reverse_zone)) {
+            continue;
+        }
+        let primary_ns = config.ns_records.first()
+            .cloned()
+            .unwrap_or_else(|| format!("ns1.{}", config.default_domain));
+        zones.push(ZoneInfo {
+            name: reverse_zone.clone(),
+            ns_records: config.ns_records.clone(),
+            soa_record: Some(format!(
+                "{} hostmaster.{} 1 10800 3600 604800 86400",
+                primary_ns, config.default_domain
+            )),
+        });
+    }
+
+    zones
+}
+
 /// Find the closest parent zone for a given domain.
 ///
 /// # Arguments
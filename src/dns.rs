@@ -2,20 +2,60 @@
 //!
 //! This module provides functions for building and parsing DNS messages.
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io;
-use log::info;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
 use tokio::net::{TcpStream, UdpSocket};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use base64::Engine;
 
+use crate::dnssec::{canonical_cmp, nsec_rdata, sign_rrset, SigningKey};
 use crate::errors::DnsError;
-use crate::config::{ServerConfig, DEFAULT_TTL};
-use crate::utils::{encode_dns_name, extract_domain, extract_query_type, has_opt_record, extract_edns_payload_size, extract_do_bit, parse_sig_time};
-use crate::db::{lookup_records, get_authoritative_zones, find_closest_parent_zone};
-use crate::cache::CACHE;
+use crate::config::{AnswerOrder, AnyResponse, GeoRecord, MinimalResponses, ResolutionSource, ServerConfig, DEFAULT_EDNS_BUFFER_SIZE, DEFAULT_TTL, EMERGENCY_TTL};
+use crate::utils::{encode_dns_name, NameCompressor, extract_domain, extract_query_type, has_opt_record, extract_edns_payload_size, extract_do_bit, extract_edns_options, extract_client_subnet, encode_client_subnet, find_opt_record, ip_in_subnet, is_private_address, parse_reverse_name, parse_sig_time, question_end, random_below, recursion_desired, read_name, skip_name, synthesize_dns64, to_punycode, txt_character_strings, validate_dns_name, validate_name_length};
+use crate::db::{try_lookup_records, lookup_records_wildcard, lookup_names_by_address, zone_owner_types, zone_records_page, get_zones, find_closest_parent_zone, ZoneInfo};
+use crate::cache::{self, DnsCache, NegativeKind};
+use crate::health::retain_healthy;
+use crate::metrics::{record_cache, record_forward};
+use crate::cookies::{has_valid_server_cookie, response_cookie, COOKIE_OPTION};
+use crate::hooks::{HookAction, ParsedQuery, ResponseBuilder};
+
+/// Size at which zone transfer messages are flushed to the client.
+pub const AXFR_MESSAGE_SIZE: usize = 16384;
+
+/// Number of records read from the database at a time during a zone transfer.
+pub const AXFR_PAGE_SIZE: usize = 256;
+
+/// EDE INFO-CODE for a query refused because we are not authoritative and
+/// will not recurse (RFC 8914 section 4.21).
+pub const EDE_NOT_AUTHORITATIVE: u16 = 20;
+
+/// EDE INFO-CODE for an answer that failed DNSSEC validation (RFC 8914 section 4.7).
+pub const EDE_DNSSEC_BOGUS: u16 = 6;
+
+/// EDE INFO-CODE for an answer whose signatures have expired (RFC 8914 section 4.8).
+pub const EDE_SIGNATURE_EXPIRED: u16 = 7;
+
+/// Counter used to rotate multi-address answers between queries.
+static ROUND_ROBIN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// EDNS buffer size (or `None` for no EDNS) each forwarder last answered at
+/// after falling back.
+static EDNS_WORKING_SIZE: OnceLock<Mutex<HashMap<SocketAddr, Option<u16>>>> = OnceLock::new();
+
+/// EDNS buffer size retried when a forwarder does not answer a larger one.
+pub const EDNS_FALLBACK_SIZE: u16 = 512;
+
+/// SOA serial, refresh, retry, expire and minimum used in negative responses
+/// when the zone's stored SOA cannot be parsed.
+const DEFAULT_SOA_TIMERS: [u32; 5] = [1, 10800, 3600, 604800, 86400];
 
 /// Encode a RRSIG record.
 ///
@@ -31,9 +71,9 @@ pub fn encode_rrsig_rr(
 ) -> Result<Vec<u8>, DnsError> {
     // Example: "bzo.in. 3600 IN RRSIG DNSKEY 8 2 3600 20250601000000 20240501000000 24550 bzo.in. Q3N9z2n...base64..."
     let parts: Vec<&str> = rrsig_record.split_whitespace().collect();
-    if parts.len() < 10 {
-        return Err(DnsError::Config(format!(
-            "Malformed RRSIG record - expected at least 10 parts, got {}",
+    if parts.len() < 13 {
+        return Err(DnsError::Dnssec(format!(
+            "Malformed RRSIG record - expected at least 13 parts, got {}",
             parts.len()
         )));
     }
@@ -44,7 +84,7 @@ pub fn encode_rrsig_rr(
         "A" => 1u16,
         "NS" => 2u16,
         "SOA" => 6u16,
-        _ => return Err(DnsError::Config(format!("Unsupported type_covered: {}", parts[4]))),
+        _ => return Err(DnsError::Dnssec(format!("Unsupported type_covered: {}", parts[4]))),
     };
     let algorithm = parts[5].parse::<u8>()?;
     let labels = parts[6].parse::<u8>()?;
@@ -91,205 +131,2908 @@ pub fn encode_rrsig_rr(
     Ok(rr)
 }
 
-/// Forward a DNS query to upstream resolvers using UDP.
+/// Write the owner name of an answer record.
+///
+/// Records owned by the queried name (including answers synthesized from a
+/// wildcard or DNAME) use a compression pointer to the question at offset 12.
+/// Any other owner, such as the target of a chased CNAME, goes through the
+/// message's compressor, so only the suffix it shares with earlier names is
+/// replaced by a pointer.
 ///
 /// # Arguments
-/// * `forwarder` - The upstream resolver to forward to.
-/// * `query` - The DNS query to forward.
+/// * `response` - The response being built.
+/// * `owner` - The owner name of the record.
+/// * `query` - The DNS query.
+/// * `names` - The compressor for the response.
 ///
 /// # Returns
-/// A `Result` containing the response or an error.
-pub async fn forward_request_udp(forwarder: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
-    let socket = UdpSocket::bind("0.0.0.0:0").await?;
-    socket.send_to(query, forwarder).await?;
-
-    let mut buf = vec![0u8; 4096];
-    let (size, _) = socket.recv_from(&mut buf).await?;
-    Ok(buf[..size].to_vec())
+/// A `Result` that is `DnsError::Config` if the owner is not a valid name.
+pub fn write_owner_name(response: &mut Vec<u8>, owner: &str, query: &[u8], names: &mut NameCompressor) -> Result<(), DnsError> {
+    let qname = extract_domain(query)
+        .map(|q| if q.is_ascii() { q } else { to_punycode(&q).unwrap_or(q) })
+        .unwrap_or_default();
+    if owner.trim_end_matches('.').eq_ignore_ascii_case(qname.trim_end_matches('.')) {
+        response.extend_from_slice(&[0xc0, 0x0c]);
+        Ok(())
+    } else {
+        names.write_name(response, owner)
+    }
 }
 
-/// Forward a DNS query to upstream resolvers using TCP.
+/// Append a complete record of class IN, compressing its names through `names`.
 ///
 /// # Arguments
-/// * `forwarder` - The upstream resolver to forward to.
-/// * `query` - The DNS query to forward.
+/// * `response` - The response being built.
+/// * `owner` - The owner name of the record.
+/// * `rtype` - The record type.
+/// * `ttl` - Time-to-live in seconds.
+/// * `value` - The stored record value.
+/// * `query` - The DNS query.
+/// * `names` - The compressor for the response.
 ///
 /// # Returns
-/// A `Result` containing the response or an error.
-pub async fn forward_request_tcp(forwarder: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
-    // Connect to the forwarder using TCP
-    let mut stream = TcpStream::connect(forwarder).await?;
+/// A `Result` indicating success, or an error for malformed values.
+pub fn write_record(
+    response: &mut Vec<u8>,
+    owner: &str,
+    rtype: u16,
+    ttl: u64,
+    value: &str,
+    query: &[u8],
+    names: &mut NameCompressor,
+) -> Result<(), DnsError> {
+    write_owner_name(response, owner, query, names)?;
+    response.extend_from_slice(&rtype.to_be_bytes());
+    response.extend_from_slice(&[0x00, 0x01]); // Class IN
+    response.extend_from_slice(&(ttl as u32).to_be_bytes());
+    write_rdata(response, rtype, value, names)
+}
 
-    // Write the query with a 2-byte length prefix (per DNS over TCP)
-    let query_len = query.len() as u16;
-    stream.write_all(&query_len.to_be_bytes()).await?;
-    stream.write_all(query).await?;
+/// Fill in a record's RDLENGTH once its RDATA has been written.
+///
+/// # Arguments
+/// * `response` - The response being built.
+/// * `rdlength_pos` - Offset of the two-byte RDLENGTH placeholder.
+pub fn set_rdlength(response: &mut [u8], rdlength_pos: usize) {
+    let rdlength = (response.len() - rdlength_pos - 2) as u16;
+    response[rdlength_pos..rdlength_pos + 2].copy_from_slice(&rdlength.to_be_bytes());
+}
 
-    // Read the 2-byte length prefix of the response
-    let mut len_buf = [0u8; 2];
-    stream.read_exact(&mut len_buf).await?;
-    let resp_len = u16::from_be_bytes(len_buf) as usize;
+/// Append an OPT pseudo-record answering the query's EDNS settings.
+///
+/// The DO bit is copied from the query and `payload_size` is advertised as
+/// our own UDP buffer size. EDNS options from the query are echoed verbatim
+/// only when their code appears in `echo_options`; all others are dropped.
+///
+/// # Arguments
+/// * `response` - The response being built.
+/// * `query` - The DNS query.
+/// * `payload_size` - The UDP payload size to advertise.
+/// * `echo_options` - EDNS option codes to copy into the response.
+pub fn append_opt_record(response: &mut Vec<u8>, query: &[u8], payload_size: u16, echo_options: &[u16]) {
+    let do_bit = extract_do_bit(query);
 
-    // Read the response
-    let mut resp_buf = vec![0u8; resp_len];
-    stream.read_exact(&mut resp_buf).await?;
+    let mut rdata = Vec::new();
+    for (code, data) in extract_edns_options(query) {
+        if echo_options.contains(&code) {
+            rdata.extend_from_slice(&code.to_be_bytes());
+            rdata.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            rdata.extend_from_slice(&data);
+        }
+    }
+
+    response.extend_from_slice(&[0x00]); // Root domain
+    response.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
+    response.extend_from_slice(&payload_size.to_be_bytes()); // UDP payload size
+    response.extend_from_slice(&[0x00]); // Extended RCODE
+    response.extend_from_slice(&[0x00]); // EDNS version
+
+    if do_bit {
+        response.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
+    } else {
+        response.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
+    }
 
-    Ok(resp_buf)
+    response.extend_from_slice(&(rdata.len() as u16).to_be_bytes()); // RDATA length
+    response.extend_from_slice(&rdata);
 }
 
-/// Forward a DNS query to upstream resolvers.
+/// Echo the query's EDNS Client Subnet option in a response with the given scope.
+///
+/// The option is updated in place if the response already echoes it, and
+/// appended to the response's OPT record otherwise (RFC 7871).
 ///
 /// # Arguments
-/// * `query` - The DNS query to forward.
-/// * `forwarders` - List of upstream resolvers to try.
+/// * `response` - The response to modify.
+/// * `query` - The DNS query.
+/// * `scope` - The SCOPE PREFIX-LENGTH for the answer.
+pub fn set_ecs_scope(response: &mut Vec<u8>, query: &[u8], scope: u8) {
+    let ecs = match extract_edns_options(query).into_iter().find(|(code, _)| *code == 8) {
+        Some((_, data)) if data.len() >= 4 => data,
+        _ => return,
+    };
+    let rdlength_pos = match find_opt_record(response).and_then(|start| skip_name(response, start)) {
+        Some(pos) if pos + 10 <= response.len() => pos + 8,
+        _ => return,
+    };
+    let rdlength = u16::from_be_bytes([response[rdlength_pos], response[rdlength_pos + 1]]) as usize;
+    let rdata_start = rdlength_pos + 2;
+    let rdata_end = (rdata_start + rdlength).min(response.len());
+
+    // Update an echoed ECS option if there is one
+    let mut pos = rdata_start;
+    while pos + 4 <= rdata_end {
+        let code = u16::from_be_bytes([response[pos], response[pos + 1]]);
+        let len = u16::from_be_bytes([response[pos + 2], response[pos + 3]]) as usize;
+        if code == 8 && len >= 4 && pos + 4 + len <= rdata_end {
+            response[pos + 7] = scope;
+            return;
+        }
+        pos += 4 + len;
+    }
+
+    // Otherwise append one to the OPT record
+    let mut data = ecs;
+    data[3] = scope;
+    append_edns_option(response, 8, &data);
+}
+
+/// Append an EDNS option to the OPT record of a response.
 ///
-/// # Returns
-/// An `Option` containing the response if successful.
-pub async fn forward_to_resolvers(query: &[u8], forwarders: &[SocketAddr]) -> Option<Vec<u8>> {
-    for &forwarder in forwarders {
-        info!("Forwarding query to resolver: {}", forwarder);
-        if let Ok(resp) = forward_request_udp(forwarder, query).await {
-            info!("Received response from resolver: {}", forwarder);
-            return Some(resp);
+/// Does nothing if the response has no OPT record.
+///
+/// # Arguments
+/// * `response` - The response to modify.
+/// * `code` - The option code.
+/// * `data` - The option data.
+pub fn append_edns_option(response: &mut Vec<u8>, code: u16, data: &[u8]) {
+    let rdlength_pos = match find_opt_record(response).and_then(|start| skip_name(response, start)) {
+        Some(pos) if pos + 10 <= response.len() => pos + 8,
+        _ => return,
+    };
+    let rdlength = u16::from_be_bytes([response[rdlength_pos], response[rdlength_pos + 1]]) as usize;
+    let rdata_end = (rdlength_pos + 2 + rdlength).min(response.len());
+
+    let mut option = Vec::with_capacity(4 + data.len());
+    option.extend_from_slice(&code.to_be_bytes());
+    option.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    option.extend_from_slice(data);
+    let new_rdlength = (rdlength + option.len()) as u16;
+    response.splice(rdata_end..rdata_end, option);
+    response[rdlength_pos..rdlength_pos + 2].copy_from_slice(&new_rdlength.to_be_bytes());
+}
+
+/// Remove every EDNS option with the given code from a packet's OPT record.
+///
+/// # Arguments
+/// * `packet` - The packet to modify.
+/// * `code` - The option code.
+pub fn remove_edns_option(packet: &mut Vec<u8>, code: u16) {
+    let rdlength_pos = match find_opt_record(packet).and_then(|start| skip_name(packet, start)) {
+        Some(pos) if pos + 10 <= packet.len() => pos + 8,
+        _ => return,
+    };
+    let rdlength = u16::from_be_bytes([packet[rdlength_pos], packet[rdlength_pos + 1]]) as usize;
+    let rdata_end = (rdlength_pos + 2 + rdlength).min(packet.len());
+
+    let mut pos = rdlength_pos + 2;
+    let mut end = rdata_end;
+    while pos + 4 <= end {
+        let len = u16::from_be_bytes([packet[pos + 2], packet[pos + 3]]) as usize;
+        let option_end = (pos + 4 + len).min(end);
+        if u16::from_be_bytes([packet[pos], packet[pos + 1]]) == code {
+            packet.drain(pos..option_end);
+            end -= option_end - pos;
+        } else {
+            pos = option_end;
         }
     }
-    None
+    let new_rdlength = (rdlength - (rdata_end - end)) as u16;
+    packet[rdlength_pos..rdlength_pos + 2].copy_from_slice(&new_rdlength.to_be_bytes());
 }
 
-/// Forward a DNS query to upstream resolvers using TCP.
+/// Append an Extended DNS Error option (RFC 8914) to a response's OPT record.
 ///
 /// # Arguments
-/// * `query` - The DNS query to forward.
-/// * `forwarders` - List of upstream resolvers to try.
+/// * `response` - The response to modify.
+/// * `info_code` - The EDE INFO-CODE.
+/// * `extra_text` - Human-readable explanation for operators.
+pub fn append_extended_error(response: &mut Vec<u8>, info_code: u16, extra_text: &str) {
+    let mut data = info_code.to_be_bytes().to_vec();
+    data.extend_from_slice(extra_text.as_bytes());
+    append_edns_option(response, 15, &data);
+}
+
+/// Check that a response is well-formed and answers the given query.
+///
+/// The response must echo the query's transaction ID and question section
+/// exactly, have QR set, and contain exactly as many records as its header
+/// counts claim. This catches builder bugs before they reach clients.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `response` - The response about to be sent.
 ///
 /// # Returns
-/// An `Option` containing the response if successful.
-pub async fn forward_to_resolvers_tcp(query: &[u8], forwarders: &[SocketAddr]) -> Option<Vec<u8>> {
-    for &forwarder in forwarders {
-        if let Ok(resp) = forward_request_tcp(forwarder, query).await {
-            return Some(resp);
+/// A `Result` indicating whether the response is valid.
+pub fn validate_response(query: &[u8], response: &[u8]) -> Result<(), DnsError> {
+    if response.len() < 12 || query.len() < 12 {
+        return Err(DnsError::Protocol("Response shorter than a DNS header".into()));
+    }
+    if response[0..2] != query[0..2] {
+        return Err(DnsError::Protocol("Response transaction ID does not match query".into()));
+    }
+    if response[2] & 0x80 == 0 {
+        return Err(DnsError::Protocol("Response does not have QR set".into()));
+    }
+    if response[4..6] != query[4..6] {
+        return Err(DnsError::Protocol("Response QDCOUNT does not match query".into()));
+    }
+
+    // Question sections must match byte for byte
+    let qdcount = u16::from_be_bytes([query[4], query[5]]);
+    let question_end = (0..qdcount)
+        .try_fold(12, |pos, _| skip_name(query, pos).map(|p| p + 4))
+        .filter(|&end| end <= query.len())
+        .ok_or_else(|| DnsError::Protocol("Query question section is malformed".into()))?;
+    if response.len() < question_end || response[12..question_end] != query[12..question_end] {
+        return Err(DnsError::Protocol("Response question section does not match query".into()));
+    }
+
+    // Walk every record the header claims and make sure nothing is missing or left over
+    let records = u16::from_be_bytes([response[6], response[7]]) as u32
+        + u16::from_be_bytes([response[8], response[9]]) as u32
+        + u16::from_be_bytes([response[10], response[11]]) as u32;
+    let mut pos = question_end;
+    for i in 0..records {
+        pos = skip_name(response, pos)
+            .filter(|&p| p + 10 <= response.len())
+            .ok_or_else(|| DnsError::Protocol(format!("Response record {} of {} is missing", i + 1, records)))?;
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        pos += 10 + rdlength;
+        if pos > response.len() {
+            return Err(DnsError::Protocol(format!("Response record {} of {} is truncated", i + 1, records)));
         }
     }
-    None
+    if pos != response.len() {
+        return Err(DnsError::Protocol(format!(
+            "Response has {} bytes beyond the records its header counts",
+            response.len() - pos
+        )));
+    }
+
+    Ok(())
 }
 
-/// Send a DNS response over TCP.
+/// Compute the largest UDP response the client can accept.
+///
+/// Clients without EDNS are limited to 512 bytes. Otherwise the client's
+/// advertised buffer is honoured, capped at our own EDNS buffer size so we
+/// never send responses large enough to fragment, and at
+/// `config.max_udp_response_size`.
 ///
 /// # Arguments
-/// * `stream` - The TCP stream to send the response on.
-/// * `response` - The DNS response to send.
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
 ///
 /// # Returns
-/// A `Result` indicating success or failure.
-pub async fn send_tcp_response(stream: &mut TcpStream, response: &[u8]) -> io::Result<()> {
-    stream.write_all(&(response.len() as u16).to_be_bytes()).await?;
-    stream.write_all(response).await
+/// The maximum response size in bytes.
+pub fn udp_response_limit(query: &[u8], config: &ServerConfig) -> usize {
+    match extract_edns_payload_size(query) {
+        Some(size) => (size.max(512).min(config.edns_buffer_size.max(512)) as usize)
+            .min(config.max_udp_response_size.max(512)),
+        None => 512,
+    }
 }
 
-/// Generate a DNS response for a query.
+/// Compute the largest UDP response a particular client can accept.
+///
+/// Responses normally stay within [`udp_response_limit`] so they are not
+/// fragmented. A client that returns a valid server cookie has shown it
+/// receives our responses at its address, so it gets its full advertised
+/// buffer, still capped at `config.max_udp_response_size`.
 ///
 /// # Arguments
 /// * `query` - The DNS query.
-/// * `domain` - The domain name from the query.
+/// * `src` - The client address.
 /// * `config` - The server configuration.
 ///
 /// # Returns
-/// A `Result` containing the response or an error.
-pub async fn generate_dns_response(
-    query: &[u8],
-    domain: String,
-    config: &ServerConfig,
-) -> Result<Vec<u8>, DnsError> {
-    let query_type = extract_query_type(query).unwrap_or(1);
-
-    // Handle DNSKEY queries first
-    if query_type == 48 {
-        if let Some(dnskey) = config.dnskey_records.first() {
-            return build_dnskey_response(query, dnskey, 3600, config);
+/// The maximum response size in bytes.
+pub fn udp_response_limit_for(query: &[u8], src: IpAddr, config: &ServerConfig) -> usize {
+    match extract_edns_payload_size(query) {
+        Some(size) if config.dns_cookies && has_valid_server_cookie(query, src) => {
+            (size.max(512) as usize).min(config.max_udp_response_size.max(512))
         }
-        return build_nxdomain_response(query, config.authoritative)
-            .ok_or(DnsError::Protocol("No DNSKEY record".into()));
+        _ => udp_response_limit(query, config),
     }
+}
 
-    // Handle DS queries (type 43)
-    if query_type == 43 {
-        if let Some(ds) = config.ds_records.first() {
-            return build_ds_response(query, ds, 3600, config);
+/// Truncate a response to its header and question section and set TC.
+///
+/// The client is expected to retry over TCP. An OPT record is re-added if
+/// the query carried one.
+///
+/// # Arguments
+/// * `response` - The full response.
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// The truncated response.
+pub fn truncate_response(response: &[u8], query: &[u8], config: &ServerConfig) -> Vec<u8> {
+    if response.len() < 12 {
+        return response.to_vec();
+    }
+
+    // Find the end of the question section
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let question_end = (0..qdcount)
+        .try_fold(12, |pos, _| skip_name(response, pos).map(|p| p + 4))
+        .filter(|&end| end <= response.len());
+
+    let mut truncated = match question_end {
+        Some(end) => response[..end].to_vec(),
+        None => {
+            let mut header = response[..12].to_vec();
+            header[4..6].fill(0); // QDCOUNT
+            header
         }
-        return build_nxdomain_response(query, config.authoritative)
-            .ok_or(DnsError::Protocol("NXDOMAIN".into()));
+    };
+
+    truncated[2] |= 0x02; // Set TC
+    truncated[6..12].fill(0); // ANCOUNT, NSCOUNT, ARCOUNT
+
+    if has_opt_record(query) {
+        append_opt_record(&mut truncated, query, config.edns_buffer_size, &config.edns_echo_options);
+        truncated[11] = 1; // ARCOUNT
     }
 
-    // Check cache for A/AAAA queries
-    if query_type == 1 || query_type == 28 {
-        if let Some((ip, ttl)) = CACHE.get().unwrap().get(&domain) {
-            return build_dns_response(query, &ip, ttl, config);
+    truncated
+}
+
+/// Build a query originated by the server itself.
+///
+/// Queries the server sends on its own behalf (rather than forwarding a
+/// client's query) should be built here so they all carry the same EDNS
+/// buffer size and DO bit, taken from `config.internal_edns_buffer_size`
+/// and `config.internal_do_bit`.
+///
+/// # Arguments
+/// * `name` - The domain name to query.
+/// * `qtype` - The query type.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// The DNS query in wire format, with a random transaction ID and RD set.
+pub fn build_query(name: &str, qtype: u16, config: &ServerConfig) -> Vec<u8> {
+    let mut query = Vec::with_capacity(64);
+
+    // Random transaction ID
+    let id = random_below(u16::MAX as u64 + 1) as u16;
+    query.extend_from_slice(&id.to_be_bytes());
+
+    // Flags: RD = 1
+    query.extend_from_slice(&[0x01, 0x00]);
+
+    // QDCOUNT = 1, ANCOUNT = 0, NSCOUNT = 0, ARCOUNT = 1 (OPT)
+    query.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+    // Question
+    query.extend_from_slice(&encode_dns_name(name));
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&[0x00, 0x01]); // Class IN
+
+    // OPT record
+    query.extend_from_slice(&[0x00]); // Root domain
+    query.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
+    query.extend_from_slice(&config.internal_edns_buffer_size.to_be_bytes()); // UDP payload size
+    query.extend_from_slice(&[0x00]); // Extended RCODE
+    query.extend_from_slice(&[0x00]); // EDNS version
+    if config.internal_do_bit {
+        query.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
+    } else {
+        query.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
+    }
+    query.extend_from_slice(&[0x00, 0x00]); // RDATA length
+
+    query
+}
+
+/// Copy a query for forwarding with a fresh random transaction ID.
+///
+/// # Arguments
+/// * `query` - The client's DNS query.
+///
+/// # Returns
+/// A `Result` containing the rewritten query and the ID it now carries.
+fn with_upstream_id(query: &[u8]) -> io::Result<(Vec<u8>, [u8; 2])> {
+    if query.len() < 12 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "query too short to forward"));
+    }
+
+    let id = (random_below(0x10000) as u16).to_be_bytes();
+    let mut upstream = query.to_vec();
+    upstream[0..2].copy_from_slice(&id);
+    Ok((upstream, id))
+}
+
+/// Read the name and QTYPE/QCLASS bytes of a packet's first question.
+///
+/// # Arguments
+/// * `packet` - The DNS packet.
+///
+/// # Returns
+/// An `Option` containing the question name and its four QTYPE/QCLASS bytes.
+fn read_question(packet: &[u8]) -> Option<(String, &[u8])> {
+    let (name, end) = read_name(packet, 12)?;
+    packet.get(end..end + 4).map(|fields| (name, fields))
+}
+
+/// Check that a response echoes the question of a query.
+///
+/// Names are compared case-insensitively; QTYPE and QCLASS must match exactly.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `response` - The DNS response.
+///
+/// # Returns
+/// `true` if the response carries a single question identical to the query's.
+fn question_matches(query: &[u8], response: &[u8]) -> bool {
+    if response.len() < 12 || response[4..6] != [0, 1] {
+        return false;
+    }
+
+    match (read_question(query), read_question(response)) {
+        (Some((query_name, query_fields)), Some((response_name, response_fields))) => {
+            query_name.eq_ignore_ascii_case(&response_name) && query_fields == response_fields
         }
+        _ => false,
     }
+}
 
-    // Lookup records in database
-    let records = lookup_records(&config.db_path, &domain);
-    let requested_type = match query_type {
-        1 => "A",
-        2 => "NS",
-        5 => "CNAME",
-        6 => "SOA",
-        12 => "PTR",
-        15 => "MX",
-        16 => "TXT",
-        28 => "AAAA",
-        _ => "",
-    };
+/// Check an upstream response against the ID and question we sent and put
+/// the client's original ID back.
+///
+/// # Arguments
+/// * `response` - The upstream response.
+/// * `sent_id` - The transaction ID used for the upstream query.
+/// * `query` - The client's DNS query.
+///
+/// # Returns
+/// A `Result` containing the response, or an error if the ID or question do not match.
+fn restore_client_id(mut response: Vec<u8>, sent_id: [u8; 2], query: &[u8]) -> io::Result<Vec<u8>> {
+    if response.len() < 12 || response[0..2] != sent_id {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "upstream response ID mismatch"));
+    }
+    if !question_matches(query, &response) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "upstream response question mismatch"));
+    }
 
-    // Try exact match first
-    if let Some((value, ttl, _)) = records.iter()
-        .find(|(_, _, rtype)| rtype == requested_type)
-        .cloned()
-    {
-        return match requested_type {
-            "SOA" => build_soa_response(query, &value, ttl, domain, config),
-            "NS" => build_ns_response(query, &records, ttl, domain, config),
-            "MX" | "TXT" | "CNAME" | "PTR" => {
-                build_generic_record_response(query, &value, ttl, domain, query_type, config)
-            },
-            "A" | "AAAA" => {
-                CACHE.get().unwrap().set(domain.clone(), value.clone(), ttl);
-                build_dns_response(query, &value, ttl, config)
-            },
-            _ => Err(DnsError::Protocol("Unsupported record type".into()))
-        };
+    response[0..2].copy_from_slice(&query[0..2]);
+    Ok(response)
+}
+
+/// Run an upstream I/O step, failing with `TimedOut` if it takes longer than `timeout`.
+///
+/// # Arguments
+/// * `timeout` - Upper bound on the step.
+/// * `step` - The I/O future.
+///
+/// # Returns
+/// The step's result, or a timeout error.
+async fn with_timeout<T>(timeout: Duration, step: impl std::future::Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(timeout, step).await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "upstream timed out")))
+}
+
+/// Forward a DNS query to upstream resolvers using UDP.
+///
+/// The query is sent under a fresh random transaction ID; the client's ID is
+/// restored on the returned response. The receive buffer matches the EDNS
+/// buffer size the query advertises (512 bytes without EDNS); a reply that
+/// fills it may have been cut short, so the query is repeated over TCP.
+///
+/// # Arguments
+/// * `forwarder` - The upstream resolver to forward to.
+/// * `query` - The DNS query to forward.
+/// * `timeout` - How long to wait for the reply.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub async fn forward_request_udp(forwarder: SocketAddr, query: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let (upstream, sent_id) = with_upstream_id(query)?;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.send_to(&upstream, forwarder).await?;
+
+    let advertised = if has_opt_record(query) { extract_edns_payload_size(query) } else { None };
+    let mut buf = vec![0u8; advertised.unwrap_or(512).max(512) as usize];
+    let (size, _) = with_timeout(timeout, socket.recv_from(&mut buf)).await?;
+    if size == buf.len() {
+        debug!("Reply from {} filled the {} byte buffer, retrying over TCP", forwarder, size);
+        return forward_request_tcp(forwarder, query, timeout).await;
     }
+    restore_client_id(buf[..size].to_vec(), sent_id, query)
+}
 
-    // Fallback for A/AAAA queries
-    if query_type == 1 || query_type == 28 {
-        if let Some((ip, ttl, _)) = records.iter()
-            .find(|(_, _, rtype)| rtype == "A")
-            .cloned()
-        {
-            CACHE.get().unwrap().set(domain.clone(), ip.clone(), ttl);
-            return build_dns_response(query, &ip, ttl, config);
+/// Rewrite the EDNS buffer size a query advertises.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `size` - The buffer size to advertise, or `None` to remove the OPT record.
+///
+/// # Returns
+/// The rewritten query; unchanged if it carries no OPT record.
+fn with_edns_buffer(query: &[u8], size: Option<u16>) -> Vec<u8> {
+    let mut rewritten = query.to_vec();
+    let Some(opt) = find_opt_record(query) else {
+        return rewritten;
+    };
+    // OPT owner is the root name, so TYPE follows at opt + 1
+    if opt + 11 > query.len() {
+        return rewritten;
+    }
+
+    match size {
+        Some(size) => rewritten[opt + 3..opt + 5].copy_from_slice(&size.to_be_bytes()),
+        None => {
+            let rdlength = u16::from_be_bytes([query[opt + 9], query[opt + 10]]) as usize;
+            let end = (opt + 11 + rdlength).min(query.len());
+            rewritten.drain(opt..end);
+            let arcount = u16::from_be_bytes([query[10], query[11]]).saturating_sub(1);
+            rewritten[10..12].copy_from_slice(&arcount.to_be_bytes());
         }
     }
+    rewritten
+}
+
+/// Add an EDNS Client Subnet option for the client to a query being forwarded.
+///
+/// A query without EDNS gains an OPT record advertising our own buffer size.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `client` - The client's source address.
+/// * `config` - The server configuration; gives the source prefix lengths.
+///
+/// # Returns
+/// The rewritten query.
+fn with_client_subnet(query: &[u8], client: IpAddr, config: &ServerConfig) -> Vec<u8> {
+    let mut rewritten = query.to_vec();
+    if !has_opt_record(query) {
+        append_opt_record(&mut rewritten, query, config.edns_buffer_size, &[]);
+        let arcount = u16::from_be_bytes([query[10], query[11]]).saturating_add(1);
+        rewritten[10..12].copy_from_slice(&arcount.to_be_bytes());
+    }
+
+    let source_prefix = if client.is_ipv6() { config.ecs_prefix_v6 } else { config.ecs_prefix_v4 };
+    append_edns_option(&mut rewritten, 8, &encode_client_subnet(client, source_prefix));
+    rewritten
+}
+
+/// Forward a DNS query over UDP, shrinking the EDNS buffer on timeouts.
+///
+/// Some firewalls drop large EDNS responses, so a forwarder that does not
+/// answer is retried with a 512-byte buffer and then without EDNS. The size
+/// that worked is remembered per forwarder and used as the starting point
+/// for later queries.
+///
+/// # Arguments
+/// * `forwarder` - The upstream resolver to forward to.
+/// * `query` - The DNS query to forward.
+/// * `timeout` - How long to wait for each reply.
+///
+/// # Returns
+/// A `Result` containing the response, or the last attempt's error.
+pub async fn forward_request_udp_with_fallback(forwarder: SocketAddr, query: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let advertised = if has_opt_record(query) { extract_edns_payload_size(query) } else { None };
+    let steps: Vec<Option<u16>> = match advertised {
+        Some(size) if size > EDNS_FALLBACK_SIZE => vec![Some(size), Some(EDNS_FALLBACK_SIZE), None],
+        Some(size) => vec![Some(size), None],
+        None => vec![None],
+    };
+
+    // Start from the largest size this forwarder is known to answer
+    let sizes = EDNS_WORKING_SIZE.get_or_init(|| Mutex::new(HashMap::new()));
+    let known = sizes.lock().unwrap().get(&forwarder).copied();
+    let start = known
+        .and_then(|known| steps.iter().position(|step| *step <= known))
+        .unwrap_or(0);
+
+    let mut last_error = io::Error::new(io::ErrorKind::TimedOut, "upstream timed out");
+    for (index, step) in steps.iter().enumerate().skip(start) {
+        let attempt = if index == 0 { query.to_vec() } else { with_edns_buffer(query, *step) };
+        match forward_request_udp(forwarder, &attempt, timeout).await {
+            Ok(response) => {
+                if index > 0 && known != Some(*step) {
+                    info!("Forwarder {} answers with EDNS buffer {:?}", forwarder, step);
+                    sizes.lock().unwrap().insert(forwarder, *step);
+                }
+                return Ok(response);
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                debug!("Forwarder {} timed out with EDNS buffer {:?}", forwarder, step);
+                last_error = e;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error)
+}
+
+/// Forward a DNS query to upstream resolvers using TCP.
+///
+/// Like [`forward_request_udp`], the query is sent under a fresh random
+/// transaction ID and the client's ID is restored on the response.
+///
+/// # Arguments
+/// * `forwarder` - The upstream resolver to forward to.
+/// * `query` - The DNS query to forward.
+/// * `timeout` - How long to wait for the connection and for each read.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub async fn forward_request_tcp(forwarder: SocketAddr, query: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let (upstream, sent_id) = with_upstream_id(query)?;
+
+    // Connect to the forwarder using TCP
+    let mut stream = with_timeout(timeout, TcpStream::connect(forwarder)).await?;
+
+    // Write the query with a 2-byte length prefix (per DNS over TCP)
+    let query_len = upstream.len() as u16;
+    stream.write_all(&query_len.to_be_bytes()).await?;
+    stream.write_all(&upstream).await?;
+
+    // Read the 2-byte length prefix of the response
+    let mut len_buf = [0u8; 2];
+    with_timeout(timeout, stream.read_exact(&mut len_buf)).await?;
+    let resp_len = u16::from_be_bytes(len_buf) as usize;
+
+    // Read the response
+    let mut resp_buf = vec![0u8; resp_len];
+    with_timeout(timeout, stream.read_exact(&mut resp_buf)).await?;
+
+    restore_client_id(resp_buf, sent_id, query)
+}
+
+/// Query every forwarder at once and return the first valid response.
+///
+/// Each attempt's I/O is bounded by `timeout`. When several answers are already
+/// waiting, the forwarder listed first wins; the remaining attempts are
+/// cancelled.
+///
+/// # Arguments
+/// * `query` - The DNS query to forward.
+/// * `forwarders` - List of upstream resolvers, in order of preference.
+/// * `timeout` - Upper bound on each attempt.
+/// * `transport` - Whether to ask the forwarders over UDP or TCP.
+/// * `edns_fallback` - Whether UDP attempts retry with smaller EDNS buffers on timeout.
+///
+/// # Returns
+/// An `Option` containing the response if any forwarder answered.
+async fn race_forwarders(
+    query: &[u8],
+    forwarders: &[SocketAddr],
+    timeout: Duration,
+    transport: Transport,
+    edns_fallback: bool,
+) -> Option<Vec<u8>> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let attempts: Vec<_> = forwarders.iter().copied().enumerate().map(|(index, forwarder)| {
+        let query = query.to_vec();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            debug!("Forwarding query to resolver: {} ({:?})", forwarder, transport);
+            let result = match transport {
+                Transport::Udp if edns_fallback => forward_request_udp_with_fallback(forwarder, &query, timeout).await,
+                Transport::Udp => forward_request_udp(forwarder, &query, timeout).await,
+                Transport::Tcp | Transport::Https => forward_request_tcp(forwarder, &query, timeout).await,
+            };
+            let _ = tx.send((index, forwarder, result));
+        })
+    }).collect();
+    drop(tx);
+
+    let mut best: Option<(usize, Vec<u8>)> = None;
+    while let Some((index, forwarder, result)) = rx.recv().await {
+        match result {
+            Ok(resp) => {
+                info!("Received response from resolver: {}", forwarder);
+                best = Some((index, resp));
+                break;
+            }
+            Err(e) => debug!("Resolver {} failed: {}", forwarder, e),
+        }
+    }
+
+    // Prefer an earlier forwarder whose answer arrived alongside the winner
+    while let Ok((index, _, result)) = rx.try_recv() {
+        let preferred = matches!(&best, Some((best_index, _)) if index < *best_index);
+        if let (true, Ok(resp)) = (preferred, result) {
+            best = Some((index, resp));
+        }
+    }
+
+    for attempt in attempts {
+        attempt.abort();
+    }
+    best.map(|(_, resp)| resp)
+}
+
+/// Forward a DNS query to upstream resolvers.
+///
+/// # Arguments
+/// * `query` - The DNS query to forward.
+/// * `forwarders` - List of upstream resolvers to try.
+/// * `timeout` - Upper bound on each forwarder attempt.
+/// * `edns_fallback` - Whether to retry with smaller EDNS buffers on timeout.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+pub async fn forward_to_resolvers(
+    query: &[u8],
+    forwarders: &[SocketAddr],
+    timeout: Duration,
+    edns_fallback: bool,
+) -> Option<Vec<u8>> {
+    race_forwarders(query, forwarders, timeout, Transport::Udp, edns_fallback).await
+}
+
+/// Forward a DNS query to upstream resolvers using TCP.
+///
+/// # Arguments
+/// * `query` - The DNS query to forward.
+/// * `forwarders` - List of upstream resolvers to try.
+/// * `timeout` - Upper bound on each forwarder attempt.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+pub async fn forward_to_resolvers_tcp(query: &[u8], forwarders: &[SocketAddr], timeout: Duration) -> Option<Vec<u8>> {
+    race_forwarders(query, forwarders, timeout, Transport::Tcp, false).await
+}
+
+/// Send a DNS response over TCP.
+///
+/// # Arguments
+/// * `stream` - The TCP stream to send the response on.
+/// * `response` - The DNS response to send.
+///
+/// # Returns
+/// A `Result` indicating success or failure.
+pub async fn send_tcp_response(stream: &mut TcpStream, response: &[u8]) -> io::Result<()> {
+    stream.write_all(&(response.len() as u16).to_be_bytes()).await?;
+    stream.write_all(response).await
+}
+
+/// Writes a long answer over TCP as a series of length-prefixed messages.
+///
+/// Records are buffered only until the current message reaches
+/// `max_message_size`, then flushed, so memory use stays bounded no matter
+/// how many records are sent. Only the first message carries the question.
+pub struct TcpMessageWriter<'a, W: AsyncWrite + Unpin> {
+    /// The stream messages are written to.
+    stream: &'a mut W,
+
+    /// The message currently being filled.
+    buffer: Vec<u8>,
+
+    /// Header and question of the first message.
+    first_header: Vec<u8>,
+
+    /// Header of subsequent messages (no question).
+    next_header: Vec<u8>,
+
+    /// Number of records in the current message.
+    count: u16,
+
+    /// Size at which the current message is flushed.
+    max_message_size: usize,
+}
+
+impl<'a, W: AsyncWrite + Unpin> TcpMessageWriter<'a, W> {
+    /// Create a writer answering `query`.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to write to.
+    /// * `query` - The DNS query being answered.
+    /// * `max_message_size` - Flush a message once it would exceed this size.
+    ///
+    /// # Returns
+    /// A `Result` containing the writer, or an error if the query is malformed.
+    pub fn new(stream: &'a mut W, query: &[u8], max_message_size: usize) -> Result<Self, DnsError> {
+        let question_end = skip_name(query, 12)
+            .map(|pos| pos + 4)
+            .filter(|pos| *pos <= query.len())
+            .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+
+        // ID, QR=1 AA=1, RCODE=0, counts filled in on flush
+        let mut next_header = Vec::with_capacity(12);
+        next_header.extend_from_slice(&query[..2]);
+        next_header.extend_from_slice(&[0x84, 0x00]);
+        next_header.extend_from_slice(&[0x00; 8]);
+
+        let mut first_header = next_header.clone();
+        first_header[5] = 1; // QDCOUNT
+        first_header.extend_from_slice(&query[12..question_end]);
+
+        Ok(Self {
+            stream,
+            buffer: first_header.clone(),
+            first_header,
+            next_header,
+            count: 0,
+            max_message_size: max_message_size.clamp(512, u16::MAX as usize),
+        })
+    }
+
+    /// Add an encoded resource record, flushing the current message first if it is full.
+    ///
+    /// # Arguments
+    /// * `record` - The encoded resource record.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or failure.
+    pub async fn push_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.count > 0 && (self.buffer.len() + record.len() > self.max_message_size || self.count == u16::MAX) {
+            self.flush().await?;
+        }
+        self.buffer.extend_from_slice(record);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Flush any buffered records.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or failure.
+    pub async fn finish(mut self) -> io::Result<()> {
+        if self.count > 0 {
+            self.flush().await?;
+        }
+        self.stream.flush().await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.buffer[6..8].copy_from_slice(&self.count.to_be_bytes()); // ANCOUNT
+        self.stream.write_all(&(self.buffer.len() as u16).to_be_bytes()).await?;
+        self.stream.write_all(&self.buffer).await?;
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&self.next_header);
+        self.count = 0;
+        Ok(())
+    }
+}
+
+/// Encode a complete resource record without name compression.
+///
+/// # Arguments
+/// * `owner` - The owner name.
+/// * `rtype` - The record type name, e.g. `"MX"`.
+/// * `ttl` - Time-to-live in seconds.
+/// * `value` - The stored record value.
+///
+/// # Returns
+/// A `Result` containing the encoded record or an error.
+pub fn encode_record(owner: &str, rtype: &str, ttl: u64, value: &str) -> Result<Vec<u8>, DnsError> {
+    let code = query_type_code(rtype)
+        .ok_or_else(|| DnsError::Protocol(format!("Unsupported record type: {}", rtype)))?;
+    validate_name_length(owner)?;
+    let mut rr = encode_dns_name(owner);
+    rr.extend_from_slice(&code.to_be_bytes());
+    rr.extend_from_slice(&[0x00, 0x01]); // Class IN
+    rr.extend_from_slice(&(ttl as u32).to_be_bytes());
+    write_rdata(&mut rr, code, value, &mut NameCompressor::new(false))?;
+    Ok(rr)
+}
+
+/// Stream a zone transfer (AXFR, RFC 5936) to a TCP client.
+///
+/// The zone's SOA opens and closes the transfer. Records are read from the
+/// database a page at a time and written through a `TcpMessageWriter`, so
+/// the zone is never held in memory as a whole.
+///
+/// # Arguments
+/// * `stream` - The client stream.
+/// * `query` - The AXFR query.
+/// * `zone` - The zone being transferred.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` indicating success or failure.
+pub async fn transfer_zone<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    query: &[u8],
+    zone: &ZoneInfo,
+    config: &ServerConfig,
+) -> Result<(), DnsError> {
+    let soa = zone.soa_record.as_deref()
+        .ok_or_else(|| DnsError::Protocol(format!("Zone {} has no SOA", zone.name)))?;
+    let soa_rr = encode_record(&zone.name, "SOA", DEFAULT_TTL, soa)?;
+
+    let mut writer = TcpMessageWriter::new(stream, query, AXFR_MESSAGE_SIZE)?;
+    writer.push_record(&soa_rr).await?;
+
+    let mut sent = 0usize;
+    let mut last: Option<(String, String, String)> = None;
+    loop {
+        let page = zone_records_page(&config.db_path, &zone.name, last.as_ref(), AXFR_PAGE_SIZE)?;
+        let Some((domain, _, rtype, value)) = page.last().cloned() else {
+            break;
+        };
+        last = Some((domain, rtype, value));
+
+        for (domain, ttl, rtype, value) in page {
+            // The apex SOA is sent only at the start and end
+            if rtype.eq_ignore_ascii_case("SOA") && domain.eq_ignore_ascii_case(&zone.name) {
+                continue;
+            }
+            match encode_record(&domain, &rtype, ttl, &value) {
+                Ok(rr) => {
+                    writer.push_record(&rr).await?;
+                    sent += 1;
+                }
+                Err(e) => debug!("Skipping {} {} in transfer of {}: {}", domain, rtype, zone.name, e),
+            }
+        }
+    }
+
+    writer.push_record(&soa_rr).await?;
+    writer.finish().await?;
+    info!("Transferred zone {} ({} records)", zone.name, sent);
+    Ok(())
+}
+
+/// The transport a query arrived on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// DNS over UDP; responses are bounded and may be truncated with TC.
+    Udp,
+
+    /// DNS over TCP; responses carry the full answer and never set TC.
+    Tcp,
+
+    /// DNS over HTTPS (RFC 8484); like TCP, responses are never truncated.
+    Https,
+}
+
+/// Time spent in each resolution phase of a query.
+#[derive(Debug, Clone, Default)]
+pub struct QueryTimings {
+    /// Time spent consulting the cache.
+    pub cache: Duration,
+
+    /// Time spent querying the database.
+    pub db: Duration,
+
+    /// Time spent waiting on upstream forwarders.
+    pub forward: Duration,
+}
+
+/// Per-query information supplied by the handlers and filled in during resolution.
+#[derive(Debug, Clone)]
+pub struct QueryContext {
+    /// The client address the query came from.
+    pub src: SocketAddr,
+
+    /// The transport the query arrived on.
+    pub transport: Transport,
+
+    /// Phase timings recorded while resolving the query.
+    pub timings: QueryTimings,
+
+    /// ECS scope prefix length of the answer; 0 when it does not depend on the client subnet.
+    pub ecs_scope: u8,
+
+    /// Whether the response was relayed from an upstream forwarder.
+    pub forwarded: bool,
+
+    /// Whether the answer was made up by the server, like the whoami
+    /// answers, rather than read from stored or forwarded records.
+    pub synthesized: bool,
+}
+
+impl QueryContext {
+    /// Create a context for a query received from `src` over `transport`.
+    ///
+    /// # Arguments
+    /// * `src` - The client address.
+    /// * `transport` - The transport the query arrived on.
+    ///
+    /// # Returns
+    /// A new `QueryContext` with zeroed timings.
+    pub fn new(src: SocketAddr, transport: Transport) -> Self {
+        Self {
+            src,
+            transport,
+            timings: QueryTimings::default(),
+            ecs_scope: 0,
+            forwarded: false,
+            synthesized: false,
+        }
+    }
+}
+
+/// Generate a DNS response for a query.
+///
+/// UDP responses larger than the client can accept are truncated with the
+/// TC bit set; TCP responses are always returned in full.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `config` - The server configuration.
+/// * `ctx` - The query context; phase timings are recorded into it.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub async fn generate_dns_response(
+    query: &[u8],
+    domain: String,
+    config: &ServerConfig,
+    ctx: &mut QueryContext,
+) -> Result<Vec<u8>, DnsError> {
+    // Let hooks rewrite, answer or refuse the query first
+    let mut parsed = ParsedQuery::parse(query, ctx.src, ctx.transport)?;
+    for hook in &config.hooks {
+        match hook.pre(&mut parsed).await {
+            HookAction::Continue => {}
+            HookAction::ShortCircuit(response) => return Ok(response),
+            HookAction::Refuse => {
+                return build_refused_response(query, config, None)
+                    .ok_or(DnsError::Protocol("REFUSED".into()));
+            }
+            HookAction::Nxdomain => {
+                return build_nxdomain_response(query, false, config)
+                    .ok_or(DnsError::Protocol("NXDOMAIN".into()));
+            }
+        }
+    }
+
+    let mut response = if parsed.is_rewritten() {
+        debug!("Hooks rewrote query for {} to {} type {}", domain, parsed.name, parsed.qtype);
+        let rewritten = parsed.to_query()?;
+        let response = resolve_query(&rewritten, parsed.name.clone(), config, ctx).await?;
+        restore_question(&response, query, &parsed.name)
+            .ok_or_else(|| DnsError::Protocol("Malformed response to rewritten query".into()))?
+    } else {
+        resolve_query(query, domain.clone(), config, ctx).await?
+    };
+
+    // Positive authoritative answers may carry the zone's NS set and glue
+    if !ctx.forwarded {
+        if let Some(full) = add_authority_data(&response, query, config, ctx.transport) {
+            response = full;
+        }
+    }
+
+    if !config.hooks.is_empty() {
+        let mut builder = ResponseBuilder { query: parsed, response };
+        for hook in &config.hooks {
+            hook.post(&mut builder).await;
+        }
+        response = builder.response;
+    }
+
+    // Prove negative answers from signed zones with NSEC records
+    if extract_do_bit(query) && !ctx.forwarded {
+        if let Some(with_nsec) = add_denial_records(&response, &domain, config) {
+            response = with_nsec;
+        }
+    }
+
+    // Keep absurd TTLs from the database or upstream within the configured
+    // bounds; synthesized answers pick their own TTLs on purpose
+    if !ctx.synthesized {
+        clamp_ttls(&mut response, config);
+    }
+
+    // Sign our own answers for validating clients, failing closed
+    if let Some(key) = &config.signing_key {
+        if extract_do_bit(query) && !ctx.forwarded && key.covers(&domain) {
+            response = signed_or_servfail(&response, query, &domain, key, config)?;
+        }
+    }
+
+    // Hand the client a fresh server cookie
+    let cookie = if config.dns_cookies { response_cookie(query, ctx.src.ip()) } else { None };
+    if let Some(cookie) = &cookie {
+        append_edns_option(&mut response, COOKIE_OPTION, cookie);
+    }
+
+    // Truncate oversized UDP responses so the client retries over TCP
+    if ctx.transport == Transport::Udp && response.len() > udp_response_limit_for(query, ctx.src.ip(), config) {
+        debug!("Truncating {} byte UDP response", response.len());
+        response = truncate_response(&response, query, config);
+        if let Some(cookie) = &cookie {
+            append_edns_option(&mut response, COOKIE_OPTION, cookie);
+        }
+    }
+
+    // Forwarded responses carry the upstream's own ECS scope
+    if !ctx.forwarded {
+        set_ecs_scope(&mut response, query, ctx.ecs_scope);
+    }
+
+    Ok(response)
+}
+
+/// Add the enclosing zone's NS records to the authority section of a
+/// positive answer from one of our zones, with glue for in-zone name servers in the
+/// additional section, as `config.minimal_responses` allows.
+///
+/// Over UDP the data is left out rather than making the answer truncate.
+///
+/// # Arguments
+/// * `response` - The answer.
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
+/// * `transport` - The transport the query arrived on.
+///
+/// # Returns
+/// An `Option` containing the extended answer, or `None` to send it unchanged.
+fn add_authority_data(response: &[u8], query: &[u8], config: &ServerConfig, transport: Transport) -> Option<Vec<u8>> {
+    let include = match config.minimal_responses {
+        MinimalResponses::Yes => false,
+        MinimalResponses::No => true,
+        MinimalResponses::NoAuthRecursive => !recursion_desired(query),
+    };
+    if !include || response.len() < 12 {
+        return None;
+    }
+
+    // Only positive answers without an authority section
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+    let nscount = u16::from_be_bytes([response[8], response[9]]);
+    if response[3] & 0x0F != 0 || ancount == 0 || nscount != 0 {
+        return None;
+    }
+
+    let domain = extract_domain(query)?.trim_end_matches('.').to_ascii_lowercase();
+    let zone = find_closest_parent_zone(&domain, &get_zones(config))?;
+    if zone.ns_records.is_empty() || (extract_query_type(query) == Some(2) && domain == zone.name) {
+        return None;
+    }
+
+    // Authority goes after the answers, glue ahead of any OPT record
+    let mut pos = skip_name(response, 12)? + 4;
+    for _ in 0..ancount {
+        pos = skip_name(response, pos)?;
+        let rdlength = u16::from_be_bytes([*response.get(pos + 8)?, *response.get(pos + 9)?]) as usize;
+        pos += 10 + rdlength;
+    }
+    if pos > response.len() {
+        return None;
+    }
+
+    let mut full = response[..pos].to_vec();
+    let mut names = NameCompressor::new(config.compress_names);
+    names.seed_question(&full);
+    let mut glue = Vec::new();
+    for ns in &zone.ns_records {
+        write_record(&mut full, &zone.name, 2, DEFAULT_TTL, ns, query, &mut names).ok()?;
+        for rr in glue_records(ns, &zone.name, config) {
+            if !glue.contains(&rr) {
+                glue.push(rr);
+            }
+        }
+    }
+    for (owner, rtype, ttl, value) in &glue {
+        write_record(&mut full, owner, *rtype, *ttl, value, query, &mut names).ok()?;
+    }
+    full.extend_from_slice(&response[pos..]);
+    full[8..10].copy_from_slice(&(zone.ns_records.len() as u16).to_be_bytes());
+    let arcount = u16::from_be_bytes([response[10], response[11]]) + glue.len() as u16;
+    full[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+    if transport == Transport::Udp && full.len() > udp_response_limit(query, config) {
+        return None;
+    }
+    Some(full)
+}
+
+/// Encode the A and AAAA glue records for a name server.
+///
+/// Glue is only needed, and only trusted by resolvers, for name servers
+/// inside the zone they serve (in-bailiwick), so other targets get none.
+///
+/// # Arguments
+/// * `target` - The name server's name.
+/// * `bailiwick` - The zone the name server serves.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// The glue records as (owner, type, ttl, value), empty if the target is
+/// out of bailiwick or has no addresses.
+fn glue_records(target: &str, bailiwick: &str, config: &ServerConfig) -> Vec<(String, u16, u64, String)> {
+    let target = target.trim_end_matches('.').to_ascii_lowercase();
+    let bailiwick = bailiwick.trim_end_matches('.').to_ascii_lowercase();
+    if target != bailiwick && !target.ends_with(&format!(".{}", bailiwick)) {
+        return Vec::new();
+    }
+
+    try_lookup_records(&config.db_path, &target).unwrap_or_default()
+        .into_iter()
+        .filter_map(|(value, ttl, rtype)| match rtype.as_str() {
+            "A" => Some((target.clone(), 1, ttl, value)),
+            "AAAA" => Some((target.clone(), 28, ttl, value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Resolve a query through the configured sources without applying transport limits.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `config` - The server configuration.
+/// * `ctx` - The query context; phase timings are recorded into it.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+async fn resolve_query(
+    query: &[u8],
+    domain: String,
+    config: &ServerConfig,
+    ctx: &mut QueryContext,
+) -> Result<Vec<u8>, DnsError> {
+    let query_type = extract_query_type(query).unwrap_or(1);
+
+    // Records are stored in punycode; normalize raw Unicode QNAMEs to match
+    let domain = if domain.is_ascii() { domain } else { to_punycode(&domain)? };
+
+    if config.whoami_name.as_deref() == Some(domain.as_str()) {
+        ctx.synthesized = true;
+        return whoami_response(query, query_type, &domain, ctx.src.ip(), ctx.transport, config);
+    }
+
+    // Handle DNSKEY queries first
+    if query_type == 48 {
+        if !config.dnskey_records.is_empty() {
+            return build_dnskey_response(query, &config.dnskey_records, 3600, config);
+        }
+        return build_nxdomain_response(query, config.authoritative, config)
+            .ok_or(DnsError::Protocol("No DNSKEY record".into()));
+    }
+
+    // Handle DS queries (type 43), preferring per-zone records from the database
+    if query_type == 43 {
+        let started = Instant::now();
+        let db_ds = match try_lookup_records(&config.db_path, &domain) {
+            Ok(records) => records,
+            Err(e) => {
+                warn!("DS lookup for {} failed: {}", domain, e);
+                Vec::new()
+            }
+        };
+        ctx.timings.db += started.elapsed();
+
+        let (values, ttls): (Vec<String>, Vec<u64>) = db_ds.into_iter()
+            .filter(|(_, _, rtype)| rtype.eq_ignore_ascii_case("DS"))
+            .map(|(value, ttl, _)| (value, ttl))
+            .unzip();
+        if let Some(ttl) = ttls.iter().min() {
+            return build_ds_response(query, &values, *ttl, config);
+        }
+        if !config.ds_records.is_empty() {
+            return build_ds_response(query, &config.ds_records, 3600, config);
+        }
+        return build_nxdomain_response(query, config.authoritative, config)
+            .ok_or(DnsError::Protocol("NXDOMAIN".into()));
+    }
+
+    // Maintenance mode points addresses at the status page; SOA/NS still resolve normally
+    if query_type == 1 || query_type == 28 {
+        if let Some(maintenance) = config.maintenance_mode.as_ref().filter(|m| m.matches(&domain)) {
+            debug!("Maintenance answer for {}: {}", domain, maintenance.target);
+            if maintenance.target.is_ipv6() == (query_type == 28) {
+                return build_dns_response(query, &maintenance.target.to_string(), EMERGENCY_TTL, config);
+            }
+            return build_nodata_response(query, config.authoritative, config)
+                .ok_or(DnsError::Protocol("NODATA".into()));
+        }
+    }
+
+    // Static GeoDNS overrides take precedence over every other source
+    if query_type == 1 || query_type == 28 {
+        if let Some(geo) = geo_override(query, &domain, query_type, ctx.src.ip(), config) {
+            debug!("GeoDNS override for {} from {}: {}", domain, ctx.src, geo.value);
+            ctx.ecs_scope = extract_client_subnet(query).map(|(_, source_prefix)| source_prefix).unwrap_or(0);
+            return build_dns_response(query, &geo.value, DEFAULT_TTL, config);
+        }
+    }
+
+    // ANY at our own names gets a minimal or full answer (RFC 8482)
+    if query_type == 255 {
+        if let Some(response) = any_response(query, &domain, ctx, config) {
+            return response;
+        }
+    }
+
+    // Consult each resolution source in the configured order
+    let mut records = Vec::new();
+    let mut negative_checked = false;
+    for source in &config.resolution_order {
+        match source {
+            ResolutionSource::Cache => {
+                if config.negative_cache_max_ttl > 0 {
+                    let started = Instant::now();
+                    let negative = with_cache(|cache| cache.get_negative(&domain, query_type)).flatten();
+                    ctx.timings.cache += started.elapsed();
+                    if negative.is_some() {
+                        record_cache(true);
+                    }
+                    match negative {
+                        Some((NegativeKind::NxDomain, _)) => {
+                            debug!("Negative cache hit for {}", domain);
+                            return build_nxdomain_response(query, config.authoritative, config)
+                                .ok_or(DnsError::Protocol("NXDOMAIN".into()));
+                        }
+                        Some((NegativeKind::NoData, _)) => {
+                            debug!("NODATA cache hit for {} type {}", domain, query_type);
+                            return build_nodata_response(query, config.authoritative, config)
+                                .ok_or(DnsError::Protocol("NODATA".into()));
+                        }
+                        None => {}
+                    }
+                }
+                if query_type == 1 || query_type == 28 {
+                    let started = Instant::now();
+                    let cached = with_cache(|cache| cache.get(&domain, query_type)).flatten();
+                    ctx.timings.cache += started.elapsed();
+                    if let Some((ip, ttl)) = cached {
+                        record_cache(true);
+                        if config.prefetch_threshold_percent > 0
+                            && with_cache(|cache| {
+                                cache.should_prefetch(&domain, query_type, config.prefetch_threshold_percent, config.prefetch_min_hits)
+                            }).unwrap_or(false)
+                        {
+                            prefetch_record(domain.clone(), query_type, config.clone());
+                        }
+                        return build_dns_response(query, &ip, ttl, config);
+                    }
+                }
+                record_cache(false);
+            }
+            ResolutionSource::Db => {
+                let started = Instant::now();
+                let lookup = try_lookup_records(&config.db_path, &domain);
+                ctx.timings.db += started.elapsed();
+                let db_records = match lookup {
+                    Ok(records) => records,
+                    Err(e) => {
+                        error!("EMERGENCY MODE: database {} unavailable: {}", config.db_path, e);
+                        if matches!(query_type, 1 | 28) {
+                            let emergency = config.emergency_records.get(&domain)
+                                .and_then(|ip| ip.parse::<IpAddr>().ok().map(|addr| (ip, addr)));
+                            if let Some((ip, addr)) = emergency {
+                                // The other address family has no data rather than failing
+                                if addr.is_ipv6() != (query_type == 28) {
+                                    return build_nodata_response(query, config.authoritative, config)
+                                        .ok_or(DnsError::Protocol("NODATA".into()));
+                                }
+                                error!("EMERGENCY MODE: serving emergency record {} -> {}", domain, ip);
+                                return build_dns_response(query, ip, EMERGENCY_TTL, config);
+                            }
+                        }
+                        return Err(e);
+                    }
+                };
+
+                // Names without records of their own may be covered by a wildcard
+                let db_records = if db_records.is_empty() {
+                    let started = Instant::now();
+                    let wildcard = match find_closest_parent_zone(&domain, &get_zones(config)) {
+                        Some(zone) => lookup_records_wildcard(&config.db_path, &domain, &zone.name)
+                            .unwrap_or_else(|e| {
+                                warn!("Wildcard lookup for {} failed: {}", domain, e);
+                                Vec::new()
+                            }),
+                        None => Vec::new(),
+                    };
+                    ctx.timings.db += started.elapsed();
+                    if !wildcard.is_empty() {
+                        debug!("Answering {} from a wildcard", domain);
+                    }
+                    wildcard
+                } else {
+                    db_records
+                };
+
+                if let Some(response) = answer_from_records(query, query_type, &domain, &db_records, ctx.src.ip(), config) {
+                    return response;
+                }
+
+                // A CNAME stands in for every other type at its name (RFC 1034 section 3.6.2)
+                if query_type != 5 {
+                    if let Some(response) = chase_cname(query, query_type, &domain, &db_records, ctx, config).await {
+                        return response;
+                    }
+                }
+
+                // PTR answers can be derived from the forward records
+                if query_type == 12 && config.auto_ptr {
+                    let started = Instant::now();
+                    let derived = auto_ptr_response(query, &domain, config);
+                    ctx.timings.db += started.elapsed();
+                    if let Some(response) = derived {
+                        return response;
+                    }
+                }
+
+                // CAA is inherited from the closest ancestor within the zone (RFC 8659)
+                if query_type == 257 {
+                    let started = Instant::now();
+                    let inherited = inherited_caa(&domain, config);
+                    ctx.timings.db += started.elapsed();
+                    if let Some(rrset) = inherited {
+                        return build_generic_rrset_response(query, &rrset, domain, query_type, config);
+                    }
+                }
+                records.extend(db_records);
+            }
+            ResolutionSource::Static => {
+                if let Some(static_records) = config.static_records.get(&domain) {
+                    if let Some(response) = answer_from_records(query, query_type, &domain, static_records, ctx.src.ip(), config) {
+                        return response;
+                    }
+                    records.extend(static_records.iter().cloned());
+                }
+            }
+            ResolutionSource::Forward => {
+                // Never forward names inside zones we are authoritative for
+                let started = Instant::now();
+                let zones = get_zones(config);
+                ctx.timings.db += started.elapsed();
+                if !negative_checked {
+                    negative_checked = true;
+                    if let Some(response) = authoritative_negative_response(query, &domain, &records, &zones, config) {
+                        return response;
+                    }
+                }
+                if !config.allow_recursion {
+                    debug!("Refusing recursive query for {}: recursion disabled", domain);
+                    let ede = config.refused_ede
+                        .then_some((EDE_NOT_AUTHORITATIVE, "recursion is disabled on this server"));
+                    return build_refused_response(query, config, ede)
+                        .ok_or(DnsError::Protocol("REFUSED".into()));
+                }
+                if !config.recursion_allowed_for(ctx.src.ip()) {
+                    debug!("Refusing recursive query for {} from {}: client not allowed recursion", domain, ctx.src);
+                    let ede = config.refused_ede
+                        .then_some((EDE_NOT_AUTHORITATIVE, "recursion is not available to this client"));
+                    return build_refused_response(query, config, ede)
+                        .ok_or(DnsError::Protocol("REFUSED".into()));
+                }
+                if !recursion_desired(query) {
+                    debug!("Refusing non-recursive query for {}: not authoritative", domain);
+                    let ede = config.refused_ede
+                        .then_some((EDE_NOT_AUTHORITATIVE, "recursion was not requested"));
+                    return build_refused_response(query, config, ede)
+                        .ok_or(DnsError::Protocol("REFUSED".into()));
+                }
+                let started = Instant::now();
+                let forwarded = forward_query(query, &domain, &zones, config, ctx.transport, ctx.src.ip()).await;
+                ctx.timings.forward += started.elapsed();
+                record_forward(forwarded.is_some());
+                if let Some(response) = forwarded {
+                    ctx.forwarded = true;
+                    return response;
+                }
+            }
+        }
+    }
+
+    if !negative_checked {
+        let zones = get_zones(config);
+        if let Some(response) = authoritative_negative_response(query, &domain, &records, &zones, config) {
+            return response;
+        }
+    }
+
+    Err(DnsError::Protocol("Failed to resolve domain".into()))
+}
+
+/// Answer an ANY query for a name in one of our zones.
+///
+/// With `AnyResponse::Minimal` the answer is a single synthesized HINFO
+/// record (RFC 8482 section 4.2); with `AnyResponse::Full` it holds every
+/// stored record of a supported type at the name.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `ctx` - The query context; database time is recorded into it.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the response result, or `None` if the name is
+/// outside our zones or has no records, leaving it to the usual sources.
+fn any_response(
+    query: &[u8],
+    domain: &str,
+    ctx: &mut QueryContext,
+    config: &ServerConfig,
+) -> Option<Result<Vec<u8>, DnsError>> {
+    let started = Instant::now();
+    let zone = find_closest_parent_zone(domain, &get_zones(config))?;
+    let mut records = match try_lookup_records(&config.db_path, domain) {
+        Ok(records) if records.is_empty() => lookup_records_wildcard(&config.db_path, domain, &zone.name)
+            .unwrap_or_else(|e| {
+                warn!("Wildcard lookup for {} failed: {}", domain, e);
+                Vec::new()
+            }),
+        Ok(records) => records,
+        Err(e) => return Some(Err(e)),
+    };
+    ctx.timings.db += started.elapsed();
+    if let Some(static_records) = config.static_records.get(domain) {
+        records.extend(static_records.iter().cloned());
+    }
+
+    let ttl = records.iter().map(|(_, ttl, _)| *ttl).min()?;
+    let answers: Vec<(String, u16, u64, String)> = match config.any_response {
+        AnyResponse::Minimal => vec![(domain.to_string(), 13, ttl, "\"RFC8482\" \"\"".to_string())],
+        AnyResponse::Full => {
+            let mut answers: Vec<_> = records.into_iter()
+                .filter_map(|(value, ttl, rtype)| {
+                    query_type_code(&rtype).map(|code| (domain.to_string(), code, ttl, value))
+                })
+                .collect();
+            // Keep each RRset together
+            answers.sort_by_key(|(_, code, _, _)| *code);
+            answers
+        }
+    };
+    if answers.is_empty() {
+        return None;
+    }
+    debug!("ANY answer for {} with {} record(s)", domain, answers.len());
+    Some(build_chain_response(query, &answers, config))
+}
+
+/// Derive a PTR answer from the A or AAAA records pointing at an address.
+///
+/// Every name whose forward record points at the address is returned.
+/// Reverse names with enough labels for a whole address that do not parse
+/// as one are answered NXDOMAIN; shorter names, such as a reverse zone
+/// apex, are left to the usual sources.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The reverse name from the query.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the response result, or `None` if the name is not
+/// a complete reverse name or no forward record points at the address.
+fn auto_ptr_response(query: &[u8], domain: &str, config: &ServerConfig) -> Option<Result<Vec<u8>, DnsError>> {
+    let name = domain.to_ascii_lowercase();
+    let (labels, address_labels) = if let Some(labels) = name.strip_suffix(".in-addr.arpa") {
+        (labels, 4)
+    } else {
+        (name.strip_suffix(".ip6.arpa")?, 32)
+    };
+    if labels.split('.').count() < address_labels {
+        return None;
+    }
+
+    let Some(addr) = parse_reverse_name(domain) else {
+        debug!("Malformed reverse name {}", domain);
+        return Some(build_nxdomain_response(query, config.authoritative, config)
+            .ok_or(DnsError::Protocol("NXDOMAIN".into())));
+    };
+    let names = match lookup_names_by_address(&config.db_path, addr) {
+        Ok(names) => names,
+        Err(e) => return Some(Err(e)),
+    };
+    if names.is_empty() {
+        return None;
+    }
+
+    debug!("Derived PTR for {} from {} forward record(s)", addr, names.len());
+    let answers: Vec<(String, u16, u64, String)> = names.into_iter()
+        .map(|(name, ttl)| (domain.to_string(), 12, ttl, name))
+        .collect();
+    Some(build_chain_response(query, &answers, config))
+}
+
+/// Answer the whoami name: A/AAAA with the server's address, TXT with the client's.
+///
+/// Answers carry a zero TTL so they always reflect the server that replied.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `query_type` - The query type.
+/// * `domain` - The whoami name.
+/// * `client` - The client's source address.
+/// * `transport` - The transport the query arrived on.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response; other types, or an address type the
+/// server has no address for, get NODATA.
+fn whoami_response(
+    query: &[u8],
+    query_type: u16,
+    domain: &str,
+    client: IpAddr,
+    transport: Transport,
+    config: &ServerConfig,
+) -> Result<Vec<u8>, DnsError> {
+    match query_type {
+        1 | 28 => {
+            if let Some(addr) = server_address(client, transport, config).filter(|addr| addr.is_ipv6() == (query_type == 28)) {
+                return build_dns_response(query, &addr.to_string(), 0, config);
+            }
+        }
+        16 => return build_generic_record_response(query, &client.to_string(), 0, domain.to_string(), 16, config),
+        _ => {}
+    }
+    build_nodata_response(query, config.authoritative, config)
+        .ok_or(DnsError::Protocol("NODATA".into()))
+}
+
+/// Work out the address the whoami responder reports for this server.
+///
+/// # Arguments
+/// * `client` - The client's source address.
+/// * `transport` - The transport the query arrived on.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// `config.whoami_address` if set, else the transport's bind address if it
+/// is specific, else the local address the system would use to reach the client.
+fn server_address(client: IpAddr, transport: Transport, config: &ServerConfig) -> Option<IpAddr> {
+    if let Some(addr) = config.whoami_address {
+        return Some(addr);
+    }
+    let bind = match transport {
+        Transport::Udp => config.udp_bind,
+        Transport::Tcp => config.tcp_bind,
+        Transport::Https => config.doh_bind.unwrap_or(config.tcp_bind),
+    };
+    if !bind.ip().is_unspecified() {
+        return Some(bind.ip());
+    }
+
+    // Connecting a UDP socket picks the route without sending anything
+    let unspecified: IpAddr = if client.is_ipv6() { Ipv6Addr::UNSPECIFIED.into() } else { Ipv4Addr::UNSPECIFIED.into() };
+    let socket = std::net::UdpSocket::bind((unspecified, 0)).ok()?;
+    socket.connect((client, 53)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Find the GeoDNS override for a client, preferring the most specific subnet.
+///
+/// The client is classified by its EDNS Client Subnet address when present,
+/// otherwise by its source address.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `query_type` - The query type (A or AAAA).
+/// * `src` - The source address of the query.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the matching override.
+fn geo_override<'a>(
+    query: &[u8],
+    domain: &str,
+    query_type: u16,
+    src: IpAddr,
+    config: &'a ServerConfig,
+) -> Option<&'a GeoRecord> {
+    if config.geo_records.is_empty() {
+        return None;
+    }
+    let client = extract_client_subnet(query).map(|(addr, _)| addr).unwrap_or(src);
+    let domain = domain.to_ascii_lowercase();
+
+    config.geo_records.iter()
+        .filter(|geo| geo.name == domain)
+        .filter(|geo| geo.value.parse::<IpAddr>().map(|ip| ip.is_ipv6() == (query_type == 28)).unwrap_or(false))
+        .filter(|geo| ip_in_subnet(&client, &geo.network, geo.prefix_len))
+        .max_by_key(|geo| geo.prefix_len)
+}
+
+/// Put a query's original question back on the response to its rewritten form.
+///
+/// Every record is re-encoded with its names written in full, so no
+/// compression pointer refers into the replaced question. Records owned by
+/// the rewritten name are given the original name.
+///
+/// # Arguments
+/// * `response` - The response to the rewritten query.
+/// * `query` - The original query.
+/// * `rewritten_name` - The name the query was rewritten to.
+///
+/// # Returns
+/// An `Option` containing the rebuilt response, or `None` if either packet is malformed.
+pub fn restore_question(response: &[u8], query: &[u8], rewritten_name: &str) -> Option<Vec<u8>> {
+    let original_name = extract_domain(query)?;
+    let question_end = skip_name(query, 12)? + 4;
+    let mut pos = skip_name(response, 12)? + 4;
+    if question_end > query.len() || pos > response.len() {
+        return None;
+    }
+
+    let mut rebuilt = Vec::with_capacity(response.len());
+    rebuilt.extend_from_slice(&response[..12]);
+    rebuilt[4..6].copy_from_slice(&[0x00, 0x01]); // QDCOUNT
+    rebuilt.extend_from_slice(&query[12..question_end]);
+
+    let counts = [6, 8, 10].map(|i| u16::from_be_bytes([response[i], response[i + 1]]) as u32);
+    for _ in 0..counts.iter().sum::<u32>() {
+        let (owner, after_owner) = read_name(response, pos)?;
+        if after_owner + 10 > response.len() {
+            return None;
+        }
+        let rtype = u16::from_be_bytes([response[after_owner], response[after_owner + 1]]);
+        let rdlength = u16::from_be_bytes([response[after_owner + 8], response[after_owner + 9]]) as usize;
+        let rdata_start = after_owner + 10;
+        let rdata_end = rdata_start + rdlength;
+        if rdata_end > response.len() {
+            return None;
+        }
+
+        let owner = if owner.eq_ignore_ascii_case(rewritten_name.trim_end_matches('.')) { original_name.clone() } else { owner };
+        rebuilt.extend_from_slice(&encode_dns_name(&owner));
+        rebuilt.extend_from_slice(&response[after_owner..after_owner + 8]); // TYPE, CLASS, TTL
+
+        // Expand names inside RDATA that may have been compressed
+        let rdlength_pos = rebuilt.len();
+        rebuilt.extend_from_slice(&[0x00, 0x00]);
+        rebuilt.extend_from_slice(&expand_rdata(response, rtype, rdata_start, rdata_end, false)?);
+        set_rdlength(&mut rebuilt, rdlength_pos);
+        pos = rdata_end;
+    }
+
+    Some(rebuilt)
+}
+
+/// Copy a record's RDATA with any compressed domain names expanded.
+///
+/// Names are recognised in the RDATA of NS, CNAME, PTR, MX, SOA and SRV
+/// records; other types are copied as they are.
+///
+/// # Arguments
+/// * `packet` - The message holding the record.
+/// * `rtype` - The record type.
+/// * `rdata_start` - Offset of the RDATA.
+/// * `rdata_end` - Offset just past the RDATA.
+/// * `lowercase` - Whether to lowercase the names, as DNSSEC canonical form requires.
+///
+/// # Returns
+/// An `Option` containing the RDATA, or `None` if it is malformed.
+fn expand_rdata(packet: &[u8], rtype: u16, rdata_start: usize, rdata_end: usize, lowercase: bool) -> Option<Vec<u8>> {
+    let name = |pos: usize| -> Option<(Vec<u8>, usize)> {
+        let (name, next) = read_name(packet, pos)?;
+        let name = if lowercase { name.to_ascii_lowercase() } else { name };
+        Some((encode_dns_name(&name), next))
+    };
+
+    let mut rdata = Vec::with_capacity(rdata_end.saturating_sub(rdata_start));
+    match rtype {
+        2 | 5 | 12 => rdata.extend_from_slice(&name(rdata_start)?.0),
+        15 if rdata_end > rdata_start + 2 => {
+            rdata.extend_from_slice(packet.get(rdata_start..rdata_start + 2)?);
+            rdata.extend_from_slice(&name(rdata_start + 2)?.0);
+        }
+        33 if rdata_end > rdata_start + 6 => {
+            rdata.extend_from_slice(packet.get(rdata_start..rdata_start + 6)?);
+            rdata.extend_from_slice(&name(rdata_start + 6)?.0);
+        }
+        6 => {
+            let (mname, next) = name(rdata_start)?;
+            let (rname, next) = name(next)?;
+            rdata.extend_from_slice(&mname);
+            rdata.extend_from_slice(&rname);
+            rdata.extend_from_slice(packet.get(next..rdata_end)?);
+        }
+        _ => rdata.extend_from_slice(packet.get(rdata_start..rdata_end)?),
+    }
+    Some(rdata)
+}
+
+/// Add NSEC records proving a negative answer to its authority section.
+///
+/// Only zones with a configured DNSKEY are covered. For NXDOMAIN the NSEC
+/// records cover the queried name and the wildcard at its closest encloser;
+/// for NODATA the NSEC owned by (or covering) the queried name shows the
+/// queried type is absent.
+///
+/// # Arguments
+/// * `response` - The negative response.
+/// * `domain` - The domain name from the query.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the extended response, or `None` to send it unchanged.
+fn add_denial_records(response: &[u8], domain: &str, config: &ServerConfig) -> Option<Vec<u8>> {
+    let rcode = *response.get(3)? & 0x0F;
+    if !config.authoritative || response[6..8] != [0, 0] || (rcode != 0 && rcode != 3) {
+        return None;
+    }
+    let ttl = if rcode == 3 { negative_ttl(response)? } else { nodata_ttl(response)? };
+
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let zone = find_closest_parent_zone(&domain, &get_zones(config))?;
+    let signed_zone = config.dnskey_records.iter().any(|record| {
+        record.split_whitespace().next()
+            .is_some_and(|owner| owner.trim_end_matches('.').eq_ignore_ascii_case(&zone.name))
+    });
+    if !signed_zone {
+        return None;
+    }
+
+    let mut names: Vec<(String, Vec<u16>)> = zone_owner_types(&config.db_path, &zone.name).ok()?
+        .into_iter()
+        .map(|(name, types)| {
+            let mut codes: Vec<u16> = types.iter().filter_map(|t| query_type_code(t)).collect();
+            codes.push(47); // NSEC
+            if config.signing_key.is_some() {
+                codes.push(46); // RRSIG
+            }
+            if name == zone.name {
+                codes.push(48); // DNSKEY
+            }
+            (name, codes)
+        })
+        .collect();
+    names.sort_by(|a, b| canonical_cmp(&a.0, &b.0));
+    if names.is_empty() {
+        return None;
+    }
+
+    // The NSEC owned by a name, or the one whose span covers it
+    let nsec_for = |name: &str| -> usize {
+        names.iter()
+            .rposition(|(owner, _)| canonical_cmp(owner, name) != std::cmp::Ordering::Greater)
+            .unwrap_or(names.len() - 1)
+    };
+    let mut proofs = vec![nsec_for(&domain)];
+    if rcode == 3 {
+        // The closest encloser is the nearest ancestor that exists, even as an empty non-terminal
+        let mut encloser = domain.as_str();
+        while let Some((_, parent)) = encloser.split_once('.') {
+            encloser = parent;
+            let suffix = format!(".{}", encloser);
+            if encloser == zone.name || names.iter().any(|(owner, _)| owner == encloser || owner.ends_with(&suffix)) {
+                break;
+            }
+        }
+        let wildcard = nsec_for(&format!("*.{}", encloser));
+        if !proofs.contains(&wildcard) {
+            proofs.push(wildcard);
+        }
+    }
+
+    // The authority section ends where the additional section starts
+    let mut pos = skip_name(response, 12)? + 4;
+    let nscount = u16::from_be_bytes([response[8], response[9]]);
+    for _ in 0..nscount {
+        pos = skip_name(response, pos)?;
+        let rdlength = u16::from_be_bytes([*response.get(pos + 8)?, *response.get(pos + 9)?]) as usize;
+        pos += 10 + rdlength;
+    }
+    if pos > response.len() {
+        return None;
+    }
+
+    let mut extended = response[..pos].to_vec();
+    for &index in &proofs {
+        let (owner, types) = &names[index];
+        let next = &names[(index + 1) % names.len()].0;
+        let rdata = nsec_rdata(next, types);
+        extended.extend_from_slice(&encode_dns_name(owner));
+        extended.extend_from_slice(&47u16.to_be_bytes());
+        extended.extend_from_slice(&[0x00, 0x01]); // Class IN
+        extended.extend_from_slice(&(ttl as u32).to_be_bytes());
+        extended.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        extended.extend_from_slice(&rdata);
+    }
+    extended.extend_from_slice(&response[pos..]);
+    extended[8..10].copy_from_slice(&(nscount + proofs.len() as u16).to_be_bytes());
+    Some(extended)
+}
+
+/// An RRset collected for signing: owner, type, class, TTL and canonical RDATAs.
+type SigningRrset = (String, u16, [u8; 2], u32, Vec<Vec<u8>>);
+
+/// Sign the answer and authority sections of one of our responses.
+///
+/// Every RRset in those sections owned by a name in the signing key's zone
+/// gets an RRSIG, except delegation NS sets, which the parent does not sign.
+/// The message is rebuilt without name compression so the signed records
+/// appear in the same form they were signed in.
+///
+/// # Arguments
+/// * `response` - The response to sign.
+/// * `key` - The signing key.
+/// * `validity` - How long the signatures stay valid, in seconds.
+///
+/// # Returns
+/// A `Result` containing the signed response, or an error if it could not
+/// be parsed or signed.
+fn sign_response(response: &[u8], key: &SigningKey, validity: u32) -> Result<Vec<u8>, DnsError> {
+    let malformed = || DnsError::Protocol("Malformed response to sign".into());
+    let question_end = skip_name(response, 12).ok_or_else(malformed)? + 4;
+    if response.len() < 12 || question_end > response.len() {
+        return Err(malformed());
+    }
+
+    // Allow for clock skew between us and validators
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0);
+    let inception = now.saturating_sub(3600);
+    let expiration = now.saturating_add(validity);
+
+    let mut signed = Vec::with_capacity(response.len() * 2);
+    signed.extend_from_slice(&response[..question_end]);
+
+    let mut pos = question_end;
+    for (section, count_pos) in [6, 8, 10].into_iter().enumerate() {
+        let count = u16::from_be_bytes([response[count_pos], response[count_pos + 1]]);
+        // RRsets to sign, in order of appearance
+        let mut rrsets: Vec<SigningRrset> = Vec::new();
+        let mut written = 0u16;
+
+        for _ in 0..count {
+            let (owner, after_owner) = read_name(response, pos).ok_or_else(malformed)?;
+            let fixed = response.get(after_owner..after_owner + 10).ok_or_else(malformed)?;
+            let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+            let class = [fixed[2], fixed[3]];
+            let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+            let rdata_start = after_owner + 10;
+            let rdata_end = rdata_start + u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+            if rdata_end > response.len() {
+                return Err(malformed());
+            }
+
+            signed.extend_from_slice(&encode_dns_name(&owner));
+            signed.extend_from_slice(&fixed[..8]);
+            let rdlength_pos = signed.len();
+            signed.extend_from_slice(&[0x00, 0x00]);
+            signed.extend_from_slice(&expand_rdata(response, rtype, rdata_start, rdata_end, false).ok_or_else(malformed)?);
+            set_rdlength(&mut signed, rdlength_pos);
+            written += 1;
+            pos = rdata_end;
+
+            let owner = owner.to_ascii_lowercase();
+            let delegation = rtype == 2 && owner != key.signer;
+            if section == 2 || rtype == 46 || rtype == 41 || delegation || !key.covers(&owner) {
+                continue;
+            }
+            let rdata = expand_rdata(response, rtype, rdata_start, rdata_end, true).ok_or_else(malformed)?;
+            match rrsets.iter_mut().find(|set| set.0 == owner && set.1 == rtype && set.2 == class) {
+                Some(set) => {
+                    set.3 = set.3.min(ttl);
+                    set.4.push(rdata);
+                }
+                None => rrsets.push((owner, rtype, class, ttl, vec![rdata])),
+            }
+        }
+
+        for (owner, rtype, class, ttl, rdatas) in rrsets {
+            let rrsig = sign_rrset(key, &owner, rtype, ttl, &rdatas, inception, expiration)?;
+            signed.extend_from_slice(&encode_dns_name(&owner));
+            signed.extend_from_slice(&46u16.to_be_bytes());
+            signed.extend_from_slice(&class);
+            signed.extend_from_slice(&ttl.to_be_bytes());
+            signed.extend_from_slice(&(rrsig.len() as u16).to_be_bytes());
+            signed.extend_from_slice(&rrsig);
+            written += 1;
+        }
+        signed[count_pos..count_pos + 2].copy_from_slice(&written.to_be_bytes());
+    }
+
+    Ok(signed)
+}
+
+/// Sign a response for a validating client, failing closed.
+///
+/// An answer that cannot be signed is never sent unsigned, as a validator
+/// would reject it as a downgrade. It is replaced by SERVFAIL with an
+/// Extended DNS Error: "Signature Expired" when the configured validity
+/// leaves no time for the signature to be valid, "DNSSEC Bogus" for any
+/// other signing failure.
+///
+/// # Arguments
+/// * `response` - The response to sign.
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `key` - The signing key.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the signed response or the SERVFAIL replacing it.
+fn signed_or_servfail(
+    response: &[u8],
+    query: &[u8],
+    domain: &str,
+    key: &SigningKey,
+    config: &ServerConfig,
+) -> Result<Vec<u8>, DnsError> {
+    let (info_code, reason) = if config.signature_validity == 0 {
+        (EDE_SIGNATURE_EXPIRED, "signature validity is zero".to_string())
+    } else {
+        match sign_response(response, key, config.signature_validity) {
+            Ok(signed) => return Ok(signed),
+            Err(e) => (EDE_DNSSEC_BOGUS, e.to_string()),
+        }
+    };
+
+    error!("Failed to sign response for {}: {}", domain, reason);
+    let mut servfail = build_servfail_response(query, config, true)
+        .ok_or(DnsError::Protocol("SERVFAIL".into()))?;
+    if has_opt_record(query) {
+        append_extended_error(&mut servfail, info_code, "Signing failed");
+    }
+    Ok(servfail)
+}
+
+/// Run an operation against the shared cache.
+///
+/// A cache that is missing or unusable is logged and skipped, so queries
+/// fall through to the database and forwarders instead of failing.
+///
+/// # Arguments
+/// * `op` - The cache operation.
+///
+/// # Returns
+/// An `Option` containing the operation's result, or `None` if the cache was skipped.
+fn with_cache<T>(op: impl FnOnce(&DnsCache) -> Result<T, DnsError>) -> Option<T> {
+    match cache::global().and_then(op) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Skipping cache: {}", e);
+            None
+        }
+    }
+}
+
+/// Refresh a cached A or AAAA record in the background.
+///
+/// The current cache entry keeps being served until the refreshed record
+/// replaces it.
+///
+/// # Arguments
+/// * `domain` - The domain name to refresh.
+/// * `query_type` - The record type to refresh, 1 for A or 28 for AAAA.
+/// * `config` - The server configuration.
+fn prefetch_record(domain: String, query_type: u16, config: ServerConfig) {
+    tokio::spawn(async move {
+        debug!("Prefetching {} before cache expiry", domain);
+        let type_name = query_type_name(query_type);
+        let records = try_lookup_records(&config.db_path, &domain).unwrap_or_default();
+        let fresh = records.iter()
+            .chain(config.static_records.get(&domain).into_iter().flatten())
+            .find(|(_, _, rtype)| rtype == type_name)
+            .cloned();
+
+        match fresh {
+            Some((ip, ttl, _)) => {
+                with_cache(|cache| cache.set(domain, query_type, ip, config.clamp_ttl(ttl)));
+            }
+            None => debug!("Prefetch found no {} record for {}", type_name, domain),
+        }
+    });
+}
+
+/// Map a query type to the record type name stored in the database.
+///
+/// # Arguments
+/// * `query_type` - The query type.
+///
+/// # Returns
+/// The record type name, or an empty string for unsupported types.
+pub fn query_type_name(query_type: u16) -> &'static str {
+    match query_type {
+        1 => "A",
+        2 => "NS",
+        5 => "CNAME",
+        6 => "SOA",
+        12 => "PTR",
+        15 => "MX",
+        16 => "TXT",
+        28 => "AAAA",
+        33 => "SRV",
+        35 => "NAPTR",
+        44 => "SSHFP",
+        52 => "TLSA",
+        64 => "SVCB",
+        65 => "HTTPS",
+        257 => "CAA",
+        255 => "ANY",
+        _ => "",
+    }
+}
+
+/// Map a record type name stored in the database to its type code.
+///
+/// # Arguments
+/// * `name` - The record type name, e.g. `"MX"`.
+///
+/// # Returns
+/// An `Option` containing the type code for supported types.
+pub fn query_type_code(name: &str) -> Option<u16> {
+    [1, 2, 5, 6, 12, 15, 16, 28, 33, 35, 44, 52, 64, 65, 257]
+        .into_iter()
+        .find(|code| query_type_name(*code).eq_ignore_ascii_case(name))
+}
+
+/// Build an answer from a set of records for the queried name.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `query_type` - The query type.
+/// * `domain` - The domain name from the query.
+/// * `records` - The (value, ttl, record_type) records known for the domain.
+/// * `src` - The client's source address.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the response result, or `None` if no record answers the query.
+fn answer_from_records(
+    query: &[u8],
+    query_type: u16,
+    domain: &str,
+    records: &[(String, u64, String)],
+    src: IpAddr,
+    config: &ServerConfig,
+) -> Option<Result<Vec<u8>, DnsError>> {
+    let requested_type = query_type_name(query_type);
+    let domain = domain.to_string();
+
+    // Try exact match first
+    if let Some((value, ttl, _)) = records.iter()
+        .find(|(_, _, rtype)| rtype == requested_type)
+        .cloned()
+    {
+        return Some(match requested_type {
+            "SOA" => build_soa_response(query, &value, ttl, domain, config),
+            "NS" => {
+                let mut servers: Vec<(String, u64)> = records.iter()
+                    .filter(|(_, _, rtype)| rtype == "NS")
+                    .map(|(value, ttl, _)| (value.clone(), *ttl))
+                    .collect();
+                order_rrset(&mut servers, query, src, config);
+                let ordered: Vec<(String, u64, String)> = servers.into_iter()
+                    .map(|(value, ttl)| (value, ttl, "NS".to_string()))
+                    .collect();
+                build_ns_response(query, &ordered, domain, config)
+            },
+            "MX" | "TXT" | "CNAME" | "PTR" | "NAPTR" | "SSHFP" | "TLSA" => {
+                build_generic_record_response(query, &value, ttl, domain, query_type, config)
+            },
+            "SRV" | "CAA" => {
+                let rrset: Vec<(String, u64)> = records.iter()
+                    .filter(|(_, _, rtype)| rtype == requested_type)
+                    .map(|(value, ttl, _)| (value.clone(), *ttl))
+                    .collect();
+                build_generic_rrset_response(query, &rrset, domain, query_type, config)
+            },
+            "SVCB" | "HTTPS" => {
+                let values: Vec<String> = records.iter()
+                    .filter(|(_, _, rtype)| rtype == requested_type)
+                    .map(|(value, _, _)| value.clone())
+                    .collect();
+                build_svcb_response(query, &values, ttl, query_type, config)
+            },
+            "A" | "AAAA" => {
+                let mut addresses: Vec<(String, u64)> = records.iter()
+                    .filter(|(_, _, rtype)| rtype == requested_type)
+                    .map(|(value, ttl, _)| (value.clone(), *ttl))
+                    .collect();
+
+                // The cache holds a single address, so only single-address names are cached
+                if addresses.len() == 1 {
+                    with_cache(|cache| cache.set(domain.clone(), query_type, value.clone(), config.clamp_ttl(ttl)));
+                } else {
+                    retain_healthy(&mut addresses);
+                    order_rrset(&mut addresses, query, src, config);
+                }
+                build_multi_address_response(query, &addresses, config)
+            },
+            _ => Err(DnsError::Protocol("Unsupported record type".into()))
+        });
+    }
+
+    // DNS64: synthesize an AAAA from the A record when no AAAA exists
+    if query_type == 28 && config.dns64_enabled {
+        if let Some((v4, ttl)) = records.iter()
+            .filter(|(_, _, rtype)| rtype == "A")
+            .find_map(|(value, ttl, _)| value.parse::<Ipv4Addr>().ok().map(|v4| (v4, *ttl)))
+        {
+            let synthesized = synthesize_dns64(&config.dns64_prefix, &v4);
+            debug!("DNS64 synthesized {} for {} from {}", synthesized, domain, v4);
+            return Some(build_dns_response(query, &synthesized.to_string(), ttl, config));
+        }
+    }
+
+    None
+}
+
+/// Answer a query at a CNAME by following the chain to its target.
+///
+/// Targets inside our zones are looked up in the database. When the chain
+/// leaves our zones, an A or AAAA query is forwarded upstream for the
+/// target if forwarding is enabled, the client set RD and the client is
+/// allowed recursion, and the upstream answer is appended; otherwise the
+/// chain so far is returned for the client to follow. At most
+/// `config.max_chain_length` CNAME records are followed, and a chain that
+/// loops back on itself stops at the repeated name.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `query_type` - The query type.
+/// * `domain` - The domain name from the query.
+/// * `records` - The records found for the domain.
+/// * `ctx` - The query context; forwarding time is recorded into it.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the response result, or `None` if the domain has no CNAME.
+async fn chase_cname(
+    query: &[u8],
+    query_type: u16,
+    domain: &str,
+    records: &[(String, u64, String)],
+    ctx: &mut QueryContext,
+    config: &ServerConfig,
+) -> Option<Result<Vec<u8>, DnsError>> {
+    let requested_type = query_type_name(query_type);
+    let zones = get_zones(config);
+    let mut chain: Vec<(String, u16, u64, String)> = Vec::new();
+    let mut owner = domain.to_string();
+    let mut records = records.to_vec();
+
+    loop {
+        if let Some((target, ttl, _)) = records.iter().find(|(_, _, rtype)| rtype == "CNAME") {
+            let target = target.trim_end_matches('.').to_ascii_lowercase();
+            if chain.len() >= config.max_chain_length {
+                debug!("CNAME chain from {} is longer than {} records", domain, config.max_chain_length);
+                break;
+            }
+            let looped = target == domain || chain.iter().any(|(name, ..)| *name == target);
+            chain.push((owner, 5, *ttl, target.clone()));
+            if looped {
+                warn!("CNAME loop at {} while answering {}", target, domain);
+                break;
+            }
+            owner = target;
+        } else {
+            // The end of the chain inside our zones
+            chain.extend(records.iter()
+                .filter(|(_, _, rtype)| rtype == requested_type)
+                .map(|(value, ttl, _)| (owner.clone(), query_type, *ttl, value.clone())));
+            break;
+        }
+
+        let Some(zone) = find_closest_parent_zone(&owner, &zones) else {
+            if matches!(query_type, 1 | 28) && recursion_desired(query) && config.recursion_allowed_for(ctx.src.ip()) {
+                chain.extend(forward_chain_target(&owner, query_type, ctx, config).await);
+            }
+            break;
+        };
+        records = match try_lookup_records(&config.db_path, &owner) {
+            Ok(found) if found.is_empty() => lookup_records_wildcard(&config.db_path, &owner, &zone.name).unwrap_or_default(),
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Lookup of CNAME target {} failed: {}", owner, e);
+                break;
+            }
+        };
+    }
+
+    if chain.is_empty() {
+        return None;
+    }
+    debug!("Answering {} with a chain of {} records", domain, chain.len());
+    Some(build_chain_response(query, &chain, config))
+}
+
+/// Resolve the external target of a CNAME chain through the forwarders.
+///
+/// # Arguments
+/// * `target` - The name the chain points at.
+/// * `query_type` - The query type, A or AAAA.
+/// * `ctx` - The query context; forwarding time is recorded into it.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// The CNAME and address records of the upstream answer, as (owner, type, ttl, value);
+/// empty if forwarding is disabled or no forwarder answered.
+async fn forward_chain_target(
+    target: &str,
+    query_type: u16,
+    ctx: &mut QueryContext,
+    config: &ServerConfig,
+) -> Vec<(String, u16, u64, String)> {
+    if !config.forwarding_enabled() {
+        return Vec::new();
+    }
+
+    let upstream_query = build_query(target, query_type, config);
+    let started = Instant::now();
+    let response = match forward_to_resolvers(&upstream_query, &config.forwarders, config.forward_timeout, config.edns_fallback).await {
+        Some(response) if response.len() > 2 && response[2] & 0x02 != 0 => {
+            forward_to_resolvers_tcp(&upstream_query, &config.forwarders, config.forward_timeout).await
+        }
+        response => response,
+    };
+    ctx.timings.forward += started.elapsed();
+    record_forward(response.is_some());
+
+    let Some(response) = response else {
+        debug!("No forwarder answered for CNAME target {}", target);
+        return Vec::new();
+    };
+    if config.rebinding_protection && is_rebinding_response(target, &response, config) {
+        warn!("Blocked forwarded answer for CNAME target {} pointing at a private address", target);
+        return Vec::new();
+    }
+
+    extract_answer_records(&response)
+        .into_iter()
+        .filter(|(_, rtype, _, _)| *rtype == 5 || *rtype == query_type)
+        .collect()
+}
+
+/// Order a multi-record RRset following `config.answer_order`.
+///
+/// # Arguments
+/// * `rrset` - The (value, ttl) pairs to order in place.
+/// * `query` - The DNS query.
+/// * `src` - The client address, used when no ECS option is present.
+/// * `config` - The server configuration.
+fn order_rrset(rrset: &mut [(String, u64)], query: &[u8], src: IpAddr, config: &ServerConfig) {
+    match config.answer_order {
+        AnswerOrder::RoundRobin => rotate_addresses(rrset),
+        AnswerOrder::Sticky => {
+            let client = extract_client_subnet(query).map(|(addr, _)| addr).unwrap_or(src);
+            rotate_addresses_for_client(rrset, client);
+        }
+    }
+}
+
+/// Rotate an address set so successive queries start at a different address.
+///
+/// # Arguments
+/// * `addresses` - The addresses to rotate in place.
+fn rotate_addresses(addresses: &mut [(String, u64)]) {
+    if addresses.len() > 1 {
+        let offset = ROUND_ROBIN_COUNTER.fetch_add(1, Ordering::Relaxed) % addresses.len();
+        addresses.rotate_left(offset);
+    }
+}
+
+/// Rotate an address set so it starts at an address chosen by the client IP.
+///
+/// A given client always gets the same first address for the same set.
+///
+/// # Arguments
+/// * `addresses` - The addresses to rotate in place.
+/// * `client` - The client address, from ECS or the query source.
+fn rotate_addresses_for_client(addresses: &mut [(String, u64)], client: IpAddr) {
+    if addresses.len() > 1 {
+        // Order the set first so the choice does not depend on storage order
+        addresses.sort();
+        let mut hasher = DefaultHasher::new();
+        client.hash(&mut hasher);
+        let offset = (hasher.finish() % addresses.len() as u64) as usize;
+        addresses.rotate_left(offset);
+    }
+}
+
+/// Find the CAA records a name inherits by walking up towards its zone apex.
+///
+/// The walk stops at the apex of the enclosing zone, so CAA is never
+/// inherited from outside a zone we serve.
+///
+/// # Arguments
+/// * `domain` - The queried name, which has no CAA record of its own.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the closest ancestor's CAA RRset as (value, ttl) pairs.
+fn inherited_caa(domain: &str, config: &ServerConfig) -> Option<Vec<(String, u64)>> {
+    let zones = get_zones(config);
+    let zone = find_closest_parent_zone(domain, &zones)?;
+
+    let mut name = domain;
+    while !name.eq_ignore_ascii_case(&zone.name) {
+        name = name.split_once('.')?.1;
+        let found: Vec<(String, u64)> = try_lookup_records(&config.db_path, name).ok()?
+            .into_iter()
+            .chain(config.static_records.get(name).into_iter().flatten().cloned())
+            .filter(|(_, _, rtype)| rtype == "CAA")
+            .map(|(value, ttl, _)| (value, ttl))
+            .collect();
+        if !found.is_empty() {
+            debug!("CAA for {} inherited from {}", domain, name);
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+/// Build the negative answer for a name inside a zone we are authoritative for.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `records` - The records found locally for the domain.
+/// * `zones` - The zones this server is authoritative for.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the response result, or `None` if the name is outside our zones.
+fn authoritative_negative_response(
+    query: &[u8],
+    domain: &str,
+    records: &[(String, u64, String)],
+    zones: &[ZoneInfo],
+    config: &ServerConfig,
+) -> Option<Result<Vec<u8>, DnsError>> {
+    if !config.authoritative {
+        return None;
+    }
+    let zone = find_closest_parent_zone(domain, zones)?;
+
+    // A zone holding nothing but its apex NS set, with no SOA, is delegated
+    // to us but not actually configured here
+    let lame = zone.soa_record.is_none()
+        && zone_owner_types(&config.db_path, &zone.name)
+            .map(|owners| owners.iter().all(|(owner, types)| {
+                owner.eq_ignore_ascii_case(&zone.name) && types.iter().all(|t| t == "NS")
+            }))
+            .unwrap_or(false);
+    if lame {
+        warn!(
+            "Lame delegation for zone {}: NS records present but no SOA or data, answering SERVFAIL for {}",
+            zone.name, domain
+        );
+        return Some(build_servfail_response(query, config, true)
+            .ok_or(DnsError::Protocol("SERVFAIL".into())));
+    }
+
+    let minimum = zone.soa_record.as_deref()
+        .and_then(|soa| soa.split_whitespace().nth(6))
+        .and_then(|m| m.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_TTL);
+
+    // A name that has records, just not of the queried type, exists (NODATA)
+    if !records.is_empty() {
+        if let Some(qtype) = extract_query_type(query) {
+            cache_negative(domain, Some(qtype), minimum, config);
+        }
+        return Some(build_nodata_response(query, true, config)
+            .ok_or(DnsError::Protocol("NODATA".into())));
+    }
+
+    // The name has no records at all, so it is cached as nonexistent
+    cache_negative(domain, None, minimum, config);
+
+    Some(build_nxdomain_response(query, true, config)
+        .ok_or(DnsError::Protocol("NXDOMAIN".into())))
+}
+
+/// Cache a negative answer, capping its TTL at `config.negative_cache_max_ttl`.
+///
+/// # Arguments
+/// * `domain` - The domain name from the query.
+/// * `nodata_type` - The query type for a NODATA answer, or `None` if the name does not exist.
+/// * `ttl` - The negative TTL derived from the zone's SOA.
+/// * `config` - The server configuration.
+fn cache_negative(domain: &str, nodata_type: Option<u16>, ttl: u64, config: &ServerConfig) {
+    let ttl = config.clamp_ttl(ttl).min(config.negative_cache_max_ttl);
+    if ttl == 0 {
+        return;
+    }
+    match nodata_type {
+        Some(qtype) => {
+            debug!("Caching absence of type {} at {} for {}s", qtype, domain, ttl);
+            with_cache(|cache| cache.set_nodata(domain.to_string(), qtype, ttl));
+        }
+        None => {
+            debug!("Caching nonexistence of {} for {}s", domain, ttl);
+            with_cache(|cache| cache.set_negative(domain.to_string(), ttl));
+        }
+    }
+}
+
+/// Clamp the TTL of every record in a response to `config.min_ttl` and
+/// `config.max_ttl`.
+///
+/// The OPT record is left alone, as its TTL field carries EDNS flags. A
+/// response that cannot be parsed is left unchanged from that point on.
+///
+/// # Arguments
+/// * `response` - The response to modify.
+/// * `config` - The server configuration.
+pub fn clamp_ttls(response: &mut [u8], config: &ServerConfig) {
+    if response.len() < 12 {
+        return;
+    }
+
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let records = [6, 8, 10].into_iter()
+        .map(|pos| u16::from_be_bytes([response[pos], response[pos + 1]]) as u32)
+        .sum::<u32>();
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        match skip_name(response, pos) {
+            Some(end) => pos = end + 4,
+            None => return,
+        }
+    }
+    for _ in 0..records {
+        pos = match skip_name(response, pos) {
+            Some(end) if end + 10 <= response.len() => end,
+            _ => return,
+        };
+        let rtype = u16::from_be_bytes([response[pos], response[pos + 1]]);
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        if rtype != 41 {
+            let ttl = u32::from_be_bytes([response[pos + 4], response[pos + 5], response[pos + 6], response[pos + 7]]);
+            let clamped = config.clamp_ttl(ttl as u64).min(u32::MAX as u64) as u32;
+            response[pos + 4..pos + 8].copy_from_slice(&clamped.to_be_bytes());
+        }
+        pos += 10 + rdlength;
+    }
+}
+
+/// Derive the negative TTL of an NXDOMAIN response (RFC 2308 section 5).
+///
+/// # Arguments
+/// * `response` - The DNS response.
+///
+/// # Returns
+/// An `Option` containing the lesser of the authority SOA's TTL and MINIMUM,
+/// or `None` if the response is not NXDOMAIN or carries no SOA.
+pub fn negative_ttl(response: &[u8]) -> Option<u64> {
+    if response.len() < 12 || response[3] & 0x0F != 3 {
+        return None;
+    }
+    authority_soa_ttl(response)
+}
+
+/// Derive the negative TTL of a NODATA response (RFC 2308 section 5).
+///
+/// # Arguments
+/// * `response` - The DNS response.
+///
+/// # Returns
+/// An `Option` containing the lesser of the authority SOA's TTL and MINIMUM,
+/// or `None` if the response has answers, an error code, or no SOA.
+pub fn nodata_ttl(response: &[u8]) -> Option<u64> {
+    if response.len() < 12 || response[3] & 0x0F != 0 || response[6] != 0 || response[7] != 0 {
+        return None;
+    }
+    authority_soa_ttl(response)
+}
+
+/// Find the SOA in the authority section of a response and take its negative TTL.
+///
+/// # Arguments
+/// * `response` - The DNS response.
+///
+/// # Returns
+/// An `Option` containing the lesser of the SOA's TTL and MINIMUM, or `None` if there is no SOA.
+fn authority_soa_ttl(response: &[u8]) -> Option<u64> {
+    if response.len() < 12 {
+        return None;
+    }
+
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+    let nscount = u16::from_be_bytes([response[8], response[9]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = skip_name(response, pos)? + 4;
+    }
+    for i in 0..(ancount as u32 + nscount as u32) {
+        pos = skip_name(response, pos)?;
+        if pos + 10 > response.len() {
+            return None;
+        }
+        let rtype = u16::from_be_bytes([response[pos], response[pos + 1]]);
+        let ttl = u32::from_be_bytes([response[pos + 4], response[pos + 5], response[pos + 6], response[pos + 7]]);
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        let rdata_end = pos + 10 + rdlength;
+        if rdata_end > response.len() {
+            return None;
+        }
+        if i >= ancount as u32 && rtype == 6 && rdlength >= 20 {
+            let minimum = u32::from_be_bytes([
+                response[rdata_end - 4], response[rdata_end - 3], response[rdata_end - 2], response[rdata_end - 1],
+            ]);
+            return Some(ttl.min(minimum) as u64);
+        }
+        pos = rdata_end;
+    }
+
+    None
+}
+
+/// Forward a query upstream and vet the answer.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `zones` - The zones this server is authoritative for.
+/// * `config` - The server configuration.
+/// * `transport` - The transport the query arrived on.
+/// * `client` - The client's source address, sent upstream as ECS when
+///   `config.ecs_forwarding` is on and the query carries none.
+///
+/// # Returns
+/// An `Option` containing the response result, or `None` if every forwarder failed.
+async fn forward_query(
+    query: &[u8],
+    domain: &str,
+    zones: &[ZoneInfo],
+    config: &ServerConfig,
+    transport: Transport,
+    client: IpAddr,
+) -> Option<Result<Vec<u8>, DnsError>> {
+    // A client's own ECS option is passed through as sent
+    let injected = config.ecs_forwarding && extract_client_subnet(query).is_none();
+    let upstream = if injected { with_client_subnet(query, client, config) } else { query.to_vec() };
+
+    // TCP clients can take the full answer, so ask upstream over TCP first
+    let mut response = match transport {
+        Transport::Udp => match forward_to_resolvers(&upstream, &config.forwarders, config.forward_timeout, config.edns_fallback).await {
+            Some(response) => response,
+            None => forward_to_resolvers_tcp(&upstream, &config.forwarders, config.forward_timeout).await?,
+        },
+        Transport::Tcp | Transport::Https => match forward_to_resolvers_tcp(&upstream, &config.forwarders, config.forward_timeout).await {
+            Some(response) => response,
+            None => forward_to_resolvers(&upstream, &config.forwarders, config.forward_timeout, config.edns_fallback).await?,
+        },
+    };
+
+    // Clients that sent no ECS (or no EDNS at all) must not get it back
+    if injected {
+        if has_opt_record(query) {
+            remove_edns_option(&mut response, 8);
+        } else {
+            response = with_edns_buffer(&response, None);
+        }
+    }
+
+    // Refuse to hand out private addresses for public names (DNS rebinding)
+    if config.rebinding_protection
+        && find_closest_parent_zone(domain, zones).is_none()
+        && is_rebinding_response(domain, &response, config)
+    {
+        warn!("Blocked forwarded answer for {} pointing at a private address", domain);
+        return Some(build_nxdomain_response(query, false, config)
+            .ok_or(DnsError::Protocol("NXDOMAIN".into())));
+    }
+
+    if let Some(ttl) = negative_ttl(&response) {
+        cache_negative(domain, None, ttl, config);
+    } else if let Some(ttl) = nodata_ttl(&response) {
+        cache_negative(domain, extract_query_type(query), ttl, config);
+    } else if extract_client_subnet(&upstream).is_none() {
+        // Answers tailored to a client subnet are not shared with other clients
+        cache_forwarded_addresses(&response, domain, zones, config);
+    }
+
+    Some(Ok(response))
+}
+
+/// Cache the A and AAAA records of a forwarded answer so repeat queries are
+/// answered locally.
+///
+/// Only complete NOERROR answers are cached, and only for the queried name
+/// and the names on its CNAME chain; other records an upstream volunteers
+/// are not trusted, nor are names inside our own zones. The cache holds one
+/// address per name and type, so, as for our own records, RRsets of several
+/// addresses are left out.
+///
+/// # Arguments
+/// * `response` - The upstream response.
+/// * `domain` - The domain name from the query.
+/// * `zones` - The zones this server is authoritative for.
+/// * `config` - The server configuration.
+fn cache_forwarded_addresses(response: &[u8], domain: &str, zones: &[ZoneInfo], config: &ServerConfig) {
+    if response.len() < 12 || response[2] & 0x02 != 0 || response[3] & 0x0F != 0 {
+        return;
+    }
+
+    let records = extract_answer_records(response);
+
+    // Follow the CNAME chain from the queried name
+    let mut chain = vec![domain.to_ascii_lowercase()];
+    while chain.len() <= records.len() {
+        let last = &chain[chain.len() - 1];
+        let next = records.iter()
+            .find(|(owner, rtype, _, _)| *rtype == 5 && owner.eq_ignore_ascii_case(last))
+            .map(|(_, _, _, target)| target.to_ascii_lowercase());
+        match next {
+            Some(target) if !chain.contains(&target) => chain.push(target),
+            _ => break,
+        }
+    }
+
+    let mut addresses: HashMap<(String, u16), Vec<(String, u64)>> = HashMap::new();
+    for (owner, rtype, ttl, value) in records {
+        let owner = owner.to_ascii_lowercase();
+        if (rtype == 1 || rtype == 28) && chain.contains(&owner) && find_closest_parent_zone(&owner, zones).is_none() {
+            addresses.entry((owner, rtype)).or_default().push((value, ttl));
+        }
+    }
+
+    for ((owner, rtype), found) in addresses {
+        let [(address, ttl)] = found.as_slice() else {
+            continue;
+        };
+        let ttl = config.clamp_ttl(*ttl);
+        if ttl > 0 {
+            debug!("Caching forwarded {} -> {} for {}s", owner, address, ttl);
+            with_cache(|cache| cache.set(owner, rtype, address.clone(), ttl));
+        }
+    }
+}
+
+/// Extract the A and AAAA addresses from the answer section of a response.
+///
+/// # Arguments
+/// * `response` - The DNS response.
+///
+/// # Returns
+/// A vector of the addresses found in the answer section.
+pub fn extract_answer_addresses(response: &[u8]) -> Vec<IpAddr> {
+    let mut addresses = Vec::new();
+    if response.len() < 12 {
+        return addresses;
+    }
+
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = match skip_name(response, pos) {
+            Some(p) => p + 4,
+            None => return addresses,
+        };
+    }
+
+    for _ in 0..ancount {
+        pos = match skip_name(response, pos) {
+            Some(p) => p,
+            None => break,
+        };
+        if pos + 10 > response.len() {
+            break;
+        }
+        let rtype = u16::from_be_bytes([response[pos], response[pos + 1]]);
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        let rdata = pos + 10;
+        if rdata + rdlength > response.len() {
+            break;
+        }
+
+        match (rtype, rdlength) {
+            (1, 4) => {
+                let octets: [u8; 4] = response[rdata..rdata + 4].try_into().unwrap();
+                addresses.push(IpAddr::V4(Ipv4Addr::from(octets)));
+            }
+            (28, 16) => {
+                let octets: [u8; 16] = response[rdata..rdata + 16].try_into().unwrap();
+                addresses.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+
+        pos = rdata + rdlength;
+    }
+
+    addresses
+}
+
+/// Extract the CNAME, A and AAAA records from the answer section of a response.
+///
+/// # Arguments
+/// * `response` - The DNS response.
+///
+/// # Returns
+/// A vector of (owner, type, ttl, value) tuples, with values in the database's text form.
+pub fn extract_answer_records(response: &[u8]) -> Vec<(String, u16, u64, String)> {
+    let mut records = Vec::new();
+    if response.len() < 12 {
+        return records;
+    }
+
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = match skip_name(response, pos) {
+            Some(p) => p + 4,
+            None => return records,
+        };
+    }
+
+    for _ in 0..ancount {
+        let (owner, next) = match read_name(response, pos) {
+            Some(name) => name,
+            None => break,
+        };
+        pos = next;
+        if pos + 10 > response.len() {
+            break;
+        }
+        let rtype = u16::from_be_bytes([response[pos], response[pos + 1]]);
+        let ttl = u32::from_be_bytes([response[pos + 4], response[pos + 5], response[pos + 6], response[pos + 7]]) as u64;
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        let rdata = pos + 10;
+        if rdata + rdlength > response.len() {
+            break;
+        }
+
+        let value = match (rtype, rdlength) {
+            (1, 4) => {
+                let octets: [u8; 4] = response[rdata..rdata + 4].try_into().unwrap();
+                Some(Ipv4Addr::from(octets).to_string())
+            }
+            (28, 16) => {
+                let octets: [u8; 16] = response[rdata..rdata + 16].try_into().unwrap();
+                Some(Ipv6Addr::from(octets).to_string())
+            }
+            (5, _) => read_name(response, rdata).map(|(target, _)| target),
+            _ => None,
+        };
+        if let Some(value) = value {
+            records.push((owner, rtype, ttl, value));
+        }
 
-    // If we're authoritative for this domain, return NXDOMAIN
-    let zones = get_authoritative_zones(&config.db_path);
-    if config.authoritative && find_closest_parent_zone(&domain, &zones).is_some() {
-        return build_nxdomain_response(query, true)
-            .ok_or(DnsError::Protocol("NXDOMAIN".into()));
+        pos = rdata + rdlength;
     }
 
-    // Forward to upstream resolvers
-    if let Some(response) = forward_to_resolvers(query, &config.forwarders).await {
-        Ok(response)
-    } else if let Some(response) = forward_to_resolvers_tcp(query, &config.forwarders).await {
-        Ok(response)
-    } else {
-        Err(DnsError::Protocol("Failed to resolve domain".into()))
-    }
+    records
+}
+
+/// Check whether a forwarded response looks like a DNS rebinding attempt.
+///
+/// A response is suspicious when it answers with a private, loopback or
+/// link-local address for a name outside the configured allowlist.
+///
+/// # Arguments
+/// * `domain` - The queried domain name.
+/// * `response` - The forwarded DNS response.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// `true` if the response should be blocked.
+pub fn is_rebinding_response(domain: &str, response: &[u8], config: &ServerConfig) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let allowed = config.rebinding_allowlist.iter().any(|zone| {
+        domain == *zone || domain.ends_with(&format!(".{}", zone))
+    });
+
+    !allowed && extract_answer_addresses(response).iter().any(is_private_address)
 }
 
 /// Build a DNS response for an A or AAAA record.
 ///
+/// The record type follows the address: IPv6 addresses produce a 16-byte
+/// AAAA answer and IPv4 addresses a 4-byte A answer. An address whose family
+/// does not match the query type is rejected.
+///
 /// # Arguments
 /// * `query` - The DNS query.
 /// * `ip` - The IP address for the response.
@@ -303,6 +3046,26 @@ pub fn build_dns_response(
     ip: &str,
     ttl: u64,
     config: &ServerConfig,
+) -> Result<Vec<u8>, DnsError> {
+    build_multi_address_response(query, &[(ip.to_string(), ttl)], config)
+}
+
+/// Build a DNS response carrying one A or AAAA answer per address.
+///
+/// Answers are emitted in the order given, each using a pointer to the
+/// question name.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `addresses` - The addresses to answer with, as (address, ttl).
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub fn build_multi_address_response(
+    query: &[u8],
+    addresses: &[(String, u64)],
+    config: &ServerConfig,
 ) -> Result<Vec<u8>, DnsError> {
     let mut response = Vec::with_capacity(512);
 
@@ -318,7 +3081,7 @@ pub fn build_dns_response(
     // RA = 1 (recursion available)
     // Z = 0
     // RCODE = 0 (no error)
-    let flags1 = 0x80 | (query[2] & 0x01); // Set QR and preserve RD
+    let flags1 = 0x80 | recursion_desired(query) as u8; // Set QR and preserve RD
     let flags2 = 0x80; // Set RA
 
     response.extend_from_slice(&[
@@ -329,86 +3092,74 @@ pub fn build_dns_response(
     // Copy QDCOUNT from query
     response.extend_from_slice(&query[4..6]);
 
-    // Set ANCOUNT to 1
-    response.extend_from_slice(&[0x00, 0x01]);
-
-    // Set NSCOUNT (2 if authoritative, else 0)
-    response.extend_from_slice(&[0x00, if config.authoritative { 0x02 } else { 0x00 }]);
+    // Set ANCOUNT to the number of addresses
+    response.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
 
-    // Set ARCOUNT to 0
+    // Set NSCOUNT to 0
     response.extend_from_slice(&[0x00, 0x00]);
 
-    // Copy question section from query
-    let qname_end = query[12..].iter().position(|&b| b == 0)
-        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))? + 13;
-    response.extend_from_slice(&query[12..qname_end + 4]);
+    // Set ARCOUNT to 1 if EDNS is present, 0 otherwise
+    let has_edns = has_opt_record(query);
+    response.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
 
-    // Add answer section
-    // Name pointer to question
-    response.extend_from_slice(&[0xc0, 0x0c]);
+    // Copy question section from query
+    let question_end = question_end(query)
+        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+    response.extend_from_slice(&query[12..question_end]);
 
-    // Type A (0x0001)
-    response.extend_from_slice(&[0x00, 0x01]);
+    let query_type = extract_query_type(query).unwrap_or(1);
+    for (ip, ttl) in addresses {
+        // Parse IP address; the address family must match the query type
+        let (rtype, octets): (u16, Vec<u8>) = match ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(v4)) if query_type != 28 => (1, v4.octets().to_vec()),
+            Ok(IpAddr::V6(v6)) if query_type != 1 => (28, v6.octets().to_vec()),
+            _ => {
+                return Err(DnsError::Protocol(format!(
+                    "Invalid {} address: {}", query_type_name(query_type), ip
+                )));
+            }
+        };
 
-    // Class IN (0x0001)
-    response.extend_from_slice(&[0x00, 0x01]);
+        // Name pointer to question
+        response.extend_from_slice(&[0xc0, 0x0c]);
 
-    // TTL
-    response.extend_from_slice(&(ttl as u32).to_be_bytes());
+        // Type A (0x0001) or AAAA (0x001C)
+        response.extend_from_slice(&rtype.to_be_bytes());
 
-    // Parse IP address
-    let octets: Vec<u8> = ip.split('.')
-        .filter_map(|s| s.parse::<u8>().ok())
-        .collect();
+        // Class IN (0x0001)
+        response.extend_from_slice(&[0x00, 0x01]);
 
-    if octets.len() != 4 {
-        return Err(DnsError::Protocol(format!("Invalid IPv4 address: {}", ip)));
-    }
+        // TTL
+        response.extend_from_slice(&(*ttl as u32).to_be_bytes());
 
-    // RDLENGTH (4 for IPv4)
-    response.extend_from_slice(&[0x00, 0x04]);
+        // RDLENGTH (4 for IPv4, 16 for IPv6)
+        response.extend_from_slice(&(octets.len() as u16).to_be_bytes());
 
-    // RDATA (IP address)
-    response.extend_from_slice(&octets);
+        // RDATA (IP address)
+        response.extend_from_slice(&octets);
+    }
 
     // Add EDNS record if present in query
-    if has_opt_record(query) {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
-        let do_bit = extract_do_bit(query);
-
-        // Add OPT record
-        response.extend_from_slice(&[0x00]); // Root domain
-        response.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        response.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size
-        response.extend_from_slice(&[0x00]); // Extended RCODE
-        response.extend_from_slice(&[0x00]); // EDNS version
-
-        if do_bit {
-            response.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            response.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
-        }
-
-        response.extend_from_slice(&[0x00, 0x00]); // RDATA length
+    if has_edns {
+        append_opt_record(&mut response, query, config.edns_buffer_size, &config.edns_echo_options);
     }
 
     Ok(response)
 }
 
-/// Build a DNS response for a DS record.
+/// Build an answer from records that may differ in owner and type, such as
+/// a CNAME chain and the records at its end.
 ///
 /// # Arguments
 /// * `query` - The DNS query.
-/// * `ds_record` - The DS record string.
-/// * `ttl` - Time-to-live in seconds.
+/// * `chain` - The answer records in order, as (owner, type, ttl, value).
 /// * `config` - The server configuration.
 ///
 /// # Returns
 /// A `Result` containing the response or an error.
-pub fn build_ds_response(
+fn build_chain_response(
     query: &[u8],
-    ds_record: &str,
-    ttl: u64,
+    chain: &[(String, u16, u64, String)],
     config: &ServerConfig,
 ) -> Result<Vec<u8>, DnsError> {
     let mut response = Vec::with_capacity(512);
@@ -416,17 +3167,18 @@ pub fn build_ds_response(
     // Copy transaction ID and question from query
     response.extend_from_slice(&query[..2]);
 
-    // Set flags
-    let flags1 = 0x84; // QR=1, AA=1, RD=0
-    let flags2 = 0x00; // RA=0, Z=0, RCODE=0
-
-    response.extend_from_slice(&[flags1, flags2]);
+    // Set flags: QR, AA if authoritative, RD copied from the query, RA
+    let flags1 = 0x80 | recursion_desired(query) as u8;
+    response.extend_from_slice(&[
+        if config.authoritative { flags1 | 0x04 } else { flags1 },
+        0x80,
+    ]);
 
     // Copy QDCOUNT from query
     response.extend_from_slice(&query[4..6]);
 
-    // Set ANCOUNT to 1
-    response.extend_from_slice(&[0x00, 0x01]);
+    // One answer per record in the chain
+    response.extend_from_slice(&(chain.len() as u16).to_be_bytes());
 
     // Set NSCOUNT to 0
     response.extend_from_slice(&[0x00, 0x00]);
@@ -436,86 +3188,45 @@ pub fn build_ds_response(
     response.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
 
     // Copy question section from query
-    let qname_end = query[12..].iter().position(|&b| b == 0)
-        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))? + 13;
-    response.extend_from_slice(&query[12..qname_end + 4]);
-
-    // Parse DS record
-    // Format: "yourdomain.tld. IN DS 24550 8 2 1F21CA282945434EE0662805430599CB2A6C479D9F934087150901CE2DA580A0"
-    let parts: Vec<&str> = ds_record.split_whitespace().collect();
-    if parts.len() < 7 {
-        return Err(DnsError::Config(format!("Invalid DS record format: {}", ds_record)));
-    }
-
-    let key_tag = parts[3].parse::<u16>()
-        .map_err(|_| DnsError::Config(format!("Invalid key tag: {}", parts[3])))?;
-    let algorithm = parts[4].parse::<u8>()
-        .map_err(|_| DnsError::Config(format!("Invalid algorithm: {}", parts[4])))?;
-    let digest_type = parts[5].parse::<u8>()
-        .map_err(|_| DnsError::Config(format!("Invalid digest type: {}", parts[5])))?;
-    let digest = hex::decode(parts[6])
-        .map_err(|_| DnsError::Config(format!("Invalid digest: {}", parts[6])))?;
-
-    // Add answer section
-    // Name pointer to question
-    response.extend_from_slice(&[0xc0, 0x0c]);
-
-    // Type DS (0x002B)
-    response.extend_from_slice(&[0x00, 0x2B]);
-
-    // Class IN (0x0001)
-    response.extend_from_slice(&[0x00, 0x01]);
-
-    // TTL
-    response.extend_from_slice(&(ttl as u32).to_be_bytes());
+    let question_end = question_end(query)
+        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+    response.extend_from_slice(&query[12..question_end]);
 
-    // RDLENGTH
-    let rdlength = 4 + digest.len(); // 2 + 1 + 1 + digest.len()
-    response.extend_from_slice(&(rdlength as u16).to_be_bytes());
+    let mut names = NameCompressor::new(config.compress_names);
+    names.seed_question(&response);
+    for (owner, rtype, ttl, value) in chain {
+        write_owner_name(&mut response, owner, query, &mut names)?;
+        response.extend_from_slice(&rtype.to_be_bytes());
 
-    // RDATA
-    response.extend_from_slice(&key_tag.to_be_bytes());
-    response.push(algorithm);
-    response.push(digest_type);
-    response.extend_from_slice(&digest);
+        // Class IN (0x0001)
+        response.extend_from_slice(&[0x00, 0x01]);
+        response.extend_from_slice(&(*ttl as u32).to_be_bytes());
+        write_rdata(&mut response, *rtype, value, &mut names)?;
+    }
 
     // Add EDNS record if present in query
     if has_edns {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
-        let do_bit = extract_do_bit(query);
-
-        // Add OPT record
-        response.extend_from_slice(&[0x00]); // Root domain
-        response.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        response.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size
-        response.extend_from_slice(&[0x00]); // Extended RCODE
-        response.extend_from_slice(&[0x00]); // EDNS version
-
-        if do_bit {
-            response.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            response.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
-        }
-
-        response.extend_from_slice(&[0x00, 0x00]); // RDATA length
+        append_opt_record(&mut response, query, config.edns_buffer_size, &config.edns_echo_options);
     }
 
     Ok(response)
 }
 
-/// Build a DNS response for a DNSKEY record.
+/// Build an answer holding a whole DS or DNSKEY RRset at the queried name.
 ///
 /// # Arguments
 /// * `query` - The DNS query.
-/// * `dnskey_record` - The DNSKEY record string.
+/// * `rtype` - The record type code of the set.
+/// * `rdatas` - The encoded RDATA of each record in the set.
 /// * `ttl` - Time-to-live in seconds.
 /// * `config` - The server configuration.
 ///
 /// # Returns
 /// A `Result` containing the response or an error.
-pub fn build_dnskey_response(
+fn build_rrset_response(
     query: &[u8],
-    dnskey_record: &str,
+    rtype: u16,
+    rdatas: &[Vec<u8>],
     ttl: u64,
     config: &ServerConfig,
 ) -> Result<Vec<u8>, DnsError> {
@@ -533,34 +3244,98 @@ pub fn build_dnskey_response(
     // Copy QDCOUNT from query
     response.extend_from_slice(&query[4..6]);
 
-    // Set ANCOUNT to 1
-    response.extend_from_slice(&[0x00, 0x01]);
+    // One answer per record in the set
+    response.extend_from_slice(&(rdatas.len() as u16).to_be_bytes());
 
     // Set NSCOUNT to 0
     response.extend_from_slice(&[0x00, 0x00]);
 
-    // Set ARCOUNT to 1 if EDNS is present, 0 otherwise
+    // Set ARCOUNT to 1 if EDNS is present
     let has_edns = has_opt_record(query);
     response.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
 
     // Copy question section from query
-    let qname_end = query[12..].iter().position(|&b| b == 0)
-        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))? + 13;
-    response.extend_from_slice(&query[12..qname_end + 4]);
+    let question_end = question_end(query)
+        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+    response.extend_from_slice(&query[12..question_end]);
+
+    for rdata in rdatas {
+        // Name pointer to question
+        response.extend_from_slice(&[0xc0, 0x0c]);
+        response.extend_from_slice(&rtype.to_be_bytes());
+
+        // Class IN (0x0001)
+        response.extend_from_slice(&[0x00, 0x01]);
+        response.extend_from_slice(&(ttl as u32).to_be_bytes());
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(rdata);
+    }
+
+    // Add EDNS record if present in query
+    if has_edns {
+        append_opt_record(&mut response, query, config.edns_buffer_size, &config.edns_echo_options);
+    }
+
+    Ok(response)
+}
+
+/// Encode the RDATA of a DS record.
+///
+/// Accepts both the config form with owner, class and type in front and the
+/// bare RDATA form stored in the database.
+///
+/// # Arguments
+/// * `ds_record` - The DS record string, e.g.
+///   `"yourdomain.tld. IN DS 24550 8 2 1F21CA28..."` or `"24550 8 2 1F21CA28..."`.
+///
+/// # Returns
+/// A `Result` containing the RDATA or an error.
+fn ds_rdata(ds_record: &str) -> Result<Vec<u8>, DnsError> {
+    let parts: Vec<&str> = ds_record.split_whitespace().collect();
+    let start = parts.iter().position(|p| p.eq_ignore_ascii_case("DS")).map_or(0, |i| i + 1);
+    let parts = &parts[start..];
+    if parts.len() < 4 {
+        return Err(DnsError::Dnssec(format!("Invalid DS record format: {}", ds_record)));
+    }
+
+    let key_tag = parts[0].parse::<u16>()
+        .map_err(|_| DnsError::Dnssec(format!("Invalid key tag: {}", parts[0])))?;
+    let algorithm = parts[1].parse::<u8>()
+        .map_err(|_| DnsError::Dnssec(format!("Invalid algorithm: {}", parts[1])))?;
+    let digest_type = parts[2].parse::<u8>()
+        .map_err(|_| DnsError::Dnssec(format!("Invalid digest type: {}", parts[2])))?;
+    let digest_hex = parts[3..].concat();
+    let digest = hex::decode(&digest_hex)
+        .map_err(|e| DnsError::Hex(format!("DS digest {}: {}", digest_hex, e)))?;
+
+    let mut rdata = Vec::with_capacity(4 + digest.len());
+    rdata.extend_from_slice(&key_tag.to_be_bytes());
+    rdata.push(algorithm);
+    rdata.push(digest_type);
+    rdata.extend_from_slice(&digest);
+    Ok(rdata)
+}
 
-    // Parse DNSKEY record
-    // Format: "yourdomain.tld. IN DNSKEY 256 3 8 AwEAAb/xrM..."
+/// Encode the RDATA of a DNSKEY record.
+///
+/// # Arguments
+/// * `dnskey_record` - The DNSKEY record string, e.g.
+///   `"yourdomain.tld. IN DNSKEY 256 3 8 AwEAAb/xrM..."`.
+///
+/// # Returns
+/// A `Result` containing the RDATA or an error.
+fn dnskey_rdata(dnskey_record: &str) -> Result<Vec<u8>, DnsError> {
     let parts: Vec<&str> = dnskey_record.split_whitespace().collect();
     if parts.len() < 7 {
-        return Err(DnsError::Config(format!("Invalid DNSKEY record format: {}", dnskey_record)));
+        return Err(DnsError::Dnssec(format!("Invalid DNSKEY record format: {}", dnskey_record)));
     }
 
     let flags = parts[3].parse::<u16>()
-        .map_err(|_| DnsError::Config(format!("Invalid flags: {}", parts[3])))?;
+        .map_err(|_| DnsError::Dnssec(format!("Invalid flags: {}", parts[3])))?;
     let protocol = parts[4].parse::<u8>()
-        .map_err(|_| DnsError::Config(format!("Invalid protocol: {}", parts[4])))?;
+        .map_err(|_| DnsError::Dnssec(format!("Invalid protocol: {}", parts[4])))?;
     let algorithm = parts[5].parse::<u8>()
-        .map_err(|_| DnsError::Config(format!("Invalid algorithm: {}", parts[5])))?;
+        .map_err(|_| DnsError::Dnssec(format!("Invalid algorithm: {}", parts[5])))?;
     let public_key = parts[6..].join("");
 
     // Decode base64 public key
@@ -568,51 +3343,223 @@ pub fn build_dnskey_response(
         .decode(&public_key)
         .map_err(|e| DnsError::Base64(e.to_string()))?;
 
-    // Add answer section
-    // Name pointer to question
-    response.extend_from_slice(&[0xc0, 0x0c]);
+    let mut rdata = Vec::with_capacity(4 + key_data.len());
+    rdata.extend_from_slice(&flags.to_be_bytes());
+    rdata.push(protocol);
+    rdata.push(algorithm);
+    rdata.extend_from_slice(&key_data);
+    Ok(rdata)
+}
+
+/// Build a DNS response for a DS RRset.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `ds_records` - The DS record strings; each becomes one answer record.
+/// * `ttl` - Time-to-live in seconds.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub fn build_ds_response(
+    query: &[u8],
+    ds_records: &[String],
+    ttl: u64,
+    config: &ServerConfig,
+) -> Result<Vec<u8>, DnsError> {
+    let rdatas = ds_records.iter()
+        .map(|record| ds_rdata(record))
+        .collect::<Result<Vec<_>, _>>()?;
+    build_rrset_response(query, 43, &rdatas, ttl, config)
+}
 
-    // Type DNSKEY (0x0030)
-    response.extend_from_slice(&[0x00, 0x30]);
+/// Build a DNS response for a DNSKEY RRset.
+///
+/// Validating resolvers expect the complete set, e.g. both the KSK and ZSK.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `dnskey_records` - The DNSKEY record strings; each becomes one answer record.
+/// * `ttl` - Time-to-live in seconds.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub fn build_dnskey_response(
+    query: &[u8],
+    dnskey_records: &[String],
+    ttl: u64,
+    config: &ServerConfig,
+) -> Result<Vec<u8>, DnsError> {
+    let rdatas = dnskey_records.iter()
+        .map(|record| dnskey_rdata(record))
+        .collect::<Result<Vec<_>, _>>()?;
+    build_rrset_response(query, 48, &rdatas, ttl, config)
+}
 
-    // Class IN (0x0001)
-    response.extend_from_slice(&[0x00, 0x01]);
+/// Encode the RDATA of a NAPTR record (RFC 3403).
+///
+/// # Arguments
+/// * `value` - The record value, e.g.
+///   `100 10 "U" "E2U+sip" "!^.*$!sip:info@example.com!" .`.
+///
+/// # Returns
+/// A `Result` containing the RDATA, or `DnsError::Config` naming the value.
+pub fn naptr_rdata(value: &str) -> Result<Vec<u8>, DnsError> {
+    let invalid = || DnsError::Config(format!("Invalid NAPTR record format: {}", value));
+    let mut parts = value.trim().splitn(3, char::is_whitespace);
+    let (order, preference, rest) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(order), Some(preference), Some(rest)) if rest.trim_start().starts_with('"') => {
+            (order, preference, rest)
+        }
+        _ => return Err(invalid()),
+    };
 
-    // TTL
-    response.extend_from_slice(&(ttl as u32).to_be_bytes());
+    let order = order.parse::<u16>()
+        .map_err(|_| DnsError::Config(format!("Invalid NAPTR order: {}", value)))?;
+    let preference = preference.parse::<u16>()
+        .map_err(|_| DnsError::Config(format!("Invalid NAPTR preference: {}", value)))?;
+
+    // Flags, service and regexp, then the replacement as a bare word
+    let fields = txt_character_strings(rest);
+    let [flags, service, regexp, replacement] = fields.as_slice() else {
+        return Err(invalid());
+    };
+    if replacement != "." {
+        validate_dns_name(replacement)
+            .map_err(|_| DnsError::Config(format!("Invalid NAPTR replacement: {}", value)))?;
+    }
 
-    // RDLENGTH
-    let rdlength = 4 + key_data.len(); // 2 + 1 + 1 + key_data.len()
-    response.extend_from_slice(&(rdlength as u16).to_be_bytes());
+    let mut rdata = Vec::with_capacity(64);
+    rdata.extend_from_slice(&order.to_be_bytes());
+    rdata.extend_from_slice(&preference.to_be_bytes());
+    for string in [flags, service, regexp] {
+        rdata.push(string.len() as u8);
+        rdata.extend_from_slice(string.as_bytes());
+    }
 
-    // RDATA
-    response.extend_from_slice(&flags.to_be_bytes());
-    response.push(protocol);
-    response.push(algorithm);
-    response.extend_from_slice(&key_data);
+    // The replacement must not be compressed (RFC 3403 section 4.1)
+    rdata.extend_from_slice(&encode_dns_name(replacement));
+    Ok(rdata)
+}
 
-    // Add EDNS record if present in query
-    if has_edns {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
-        let do_bit = extract_do_bit(query);
-
-        // Add OPT record
-        response.extend_from_slice(&[0x00]); // Root domain
-        response.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        response.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size
-        response.extend_from_slice(&[0x00]); // Extended RCODE
-        response.extend_from_slice(&[0x00]); // EDNS version
-
-        if do_bit {
-            response.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            response.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
+/// Encode one SvcParam of a SVCB or HTTPS record.
+///
+/// # Arguments
+/// * `key` - The parameter name, e.g. `"alpn"`.
+/// * `text` - The parameter value in presentation format, e.g. `"h2,h3"`.
+///
+/// # Returns
+/// A `Result` containing the SvcParamKey and its wire-format value.
+fn svc_param(key: &str, text: &str) -> Result<(u16, Vec<u8>), DnsError> {
+    let invalid = || DnsError::Config(format!("Invalid SVCB parameter: {}={}", key, text));
+    let mut data = Vec::new();
+
+    let code = match key.to_ascii_lowercase().as_str() {
+        "alpn" => {
+            for id in text.split(',') {
+                if id.is_empty() || id.len() > 255 {
+                    return Err(invalid());
+                }
+                data.push(id.len() as u8);
+                data.extend_from_slice(id.as_bytes());
+            }
+            1
+        }
+        "port" => {
+            let port = text.parse::<u16>().map_err(|_| invalid())?;
+            data.extend_from_slice(&port.to_be_bytes());
+            3
+        }
+        "ipv4hint" => {
+            for address in text.split(',') {
+                let v4 = address.parse::<Ipv4Addr>().map_err(|_| invalid())?;
+                data.extend_from_slice(&v4.octets());
+            }
+            4
+        }
+        "ipv6hint" => {
+            for address in text.split(',') {
+                let v6 = address.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+                data.extend_from_slice(&v6.octets());
+            }
+            6
+        }
+        _ => return Err(DnsError::Config(format!("Unsupported SVCB parameter: {}", key))),
+    };
+    Ok((code, data))
+}
+
+/// Encode the RDATA of a SVCB or HTTPS record (RFC 9460).
+///
+/// The `alpn`, `port`, `ipv4hint` and `ipv6hint` parameters are supported and
+/// are written in ascending key order, as the RFC requires.
+///
+/// # Arguments
+/// * `value` - The record value, e.g. `"1 . alpn=h2,h3 ipv4hint=192.0.2.1"`.
+///
+/// # Returns
+/// A `Result` containing the RDATA or an error.
+pub fn svcb_rdata(value: &str) -> Result<Vec<u8>, DnsError> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() < 2 {
+        return Err(DnsError::Config(format!("Invalid SVCB record format: {}", value)));
+    }
+
+    let priority = parts[0].parse::<u16>()
+        .map_err(|_| DnsError::Config(format!("Invalid SVCB priority: {}", parts[0])))?;
+    let target = parts[1];
+    if target != "." {
+        validate_dns_name(target)?;
+    }
+    if priority == 0 && parts.len() > 2 {
+        return Err(DnsError::Config(format!("SVCB AliasMode record takes no parameters: {}", value)));
+    }
+
+    let mut params = BTreeMap::new();
+    for param in &parts[2..] {
+        let (key, text) = param.split_once('=').unwrap_or((param, ""));
+        let (code, data) = svc_param(key, text.trim_matches('"'))?;
+        if params.insert(code, data).is_some() {
+            return Err(DnsError::Config(format!("Duplicate SVCB parameter: {}", key)));
         }
+    }
 
-        response.extend_from_slice(&[0x00, 0x00]); // RDATA length
+    // The target name must not be compressed (RFC 9460 section 2.2)
+    let mut rdata = priority.to_be_bytes().to_vec();
+    rdata.extend_from_slice(&encode_dns_name(target));
+    for (code, data) in params {
+        rdata.extend_from_slice(&code.to_be_bytes());
+        rdata.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&data);
     }
+    Ok(rdata)
+}
 
-    Ok(response)
+/// Build a DNS response for a SVCB or HTTPS RRset.
+///
+/// Clients choose among the records by SvcPriority, so the whole set is returned.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `values` - The record values; each becomes one answer record.
+/// * `ttl` - Time-to-live in seconds.
+/// * `query_type` - The query type, 64 (SVCB) or 65 (HTTPS).
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub fn build_svcb_response(
+    query: &[u8],
+    values: &[String],
+    ttl: u64,
+    query_type: u16,
+    config: &ServerConfig,
+) -> Result<Vec<u8>, DnsError> {
+    let rdatas = values.iter()
+        .map(|value| svcb_rdata(value))
+        .collect::<Result<Vec<_>, _>>()?;
+    build_rrset_response(query, query_type, &rdatas, ttl, config)
 }
 
 /// Build a DNS response for a SOA record.
@@ -658,9 +3605,9 @@ pub fn build_soa_response(
     response.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
 
     // Copy question section from query
-    let qname_end = query[12..].iter().position(|&b| b == 0)
-        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))? + 13;
-    response.extend_from_slice(&query[12..qname_end + 4]);
+    let question_end = question_end(query)
+        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+    response.extend_from_slice(&query[12..question_end]);
 
     // Parse SOA record
     // Format: "ns1.example.com. hostmaster.example.com. 1 10800 3600 604800 86400"
@@ -683,8 +3630,10 @@ pub fn build_soa_response(
         .map_err(|_| DnsError::Config(format!("Invalid minimum: {}", parts[6])))?;
 
     // Add answer section
-    // Name pointer to question
-    response.extend_from_slice(&[0xc0, 0x0c]);
+    // Owner name (pointer to question when it is the queried name)
+    let mut names = NameCompressor::new(config.compress_names);
+    names.seed_question(&response);
+    write_owner_name(&mut response, &domain, query, &mut names)?;
 
     // Type SOA (0x0006)
     response.extend_from_slice(&[0x00, 0x06]);
@@ -695,42 +3644,23 @@ pub fn build_soa_response(
     // TTL
     response.extend_from_slice(&(ttl as u32).to_be_bytes());
 
-    // Encode MNAME and RNAME
-    let mname_wire = encode_dns_name(mname);
-    let rname_wire = encode_dns_name(rname);
-
-    // RDLENGTH
-    let rdlength = mname_wire.len() + rname_wire.len() + 20; // 5 32-bit integers
-    response.extend_from_slice(&(rdlength as u16).to_be_bytes());
+    // RDLENGTH placeholder, filled in once the compressed names are written
+    let rdlength_pos = response.len();
+    response.extend_from_slice(&[0x00, 0x00]);
 
     // RDATA
-    response.extend_from_slice(&mname_wire);
-    response.extend_from_slice(&rname_wire);
+    names.write_name(&mut response, mname)?;
+    names.write_name(&mut response, rname)?;
     response.extend_from_slice(&serial.to_be_bytes());
     response.extend_from_slice(&refresh.to_be_bytes());
     response.extend_from_slice(&retry.to_be_bytes());
     response.extend_from_slice(&expire.to_be_bytes());
     response.extend_from_slice(&minimum.to_be_bytes());
+    set_rdlength(&mut response, rdlength_pos);
 
     // Add EDNS record if present in query
     if has_edns {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
-        let do_bit = extract_do_bit(query);
-
-        // Add OPT record
-        response.extend_from_slice(&[0x00]); // Root domain
-        response.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        response.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size
-        response.extend_from_slice(&[0x00]); // Extended RCODE
-        response.extend_from_slice(&[0x00]); // EDNS version
-
-        if do_bit {
-            response.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            response.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
-        }
-
-        response.extend_from_slice(&[0x00, 0x00]); // RDATA length
+        append_opt_record(&mut response, query, config.edns_buffer_size, &config.edns_echo_options);
     }
 
     Ok(response)
@@ -738,10 +3668,12 @@ pub fn build_soa_response(
 
 /// Build a DNS response for NS records.
 ///
+/// Name servers inside the queried zone get their A/AAAA records as glue in
+/// the additional section, ahead of any OPT record.
+///
 /// # Arguments
 /// * `query` - The DNS query.
 /// * `records` - The DNS records for the domain.
-/// * `ttl` - Time-to-live in seconds.
 /// * `domain` - The domain name from the query.
 /// * `config` - The server configuration.
 ///
@@ -750,7 +3682,6 @@ pub fn build_soa_response(
 pub fn build_ns_response(
     query: &[u8],
     records: &[(String, u64, String)],
-    ttl: u64,
     domain: String,
     config: &ServerConfig,
 ) -> Result<Vec<u8>, DnsError> {
@@ -779,19 +3710,22 @@ pub fn build_ns_response(
     // Set NSCOUNT to 0
     response.extend_from_slice(&[0x00, 0x00]);
 
-    // Set ARCOUNT to 1 if EDNS is present, 0 otherwise
+    // ARCOUNT is set once the glue is known
     let has_edns = has_opt_record(query);
-    response.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
+    response.extend_from_slice(&[0x00, 0x00]);
 
     // Copy question section from query
-    let qname_end = query[12..].iter().position(|&b| b == 0)
-        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))? + 13;
-    response.extend_from_slice(&query[12..qname_end + 4]);
+    let question_end = question_end(query)
+        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+    response.extend_from_slice(&query[12..question_end]);
 
     // Add answer section for each NS record
+    let mut names = NameCompressor::new(config.compress_names);
+    names.seed_question(&response);
+    let mut glue = Vec::new();
     for (ns_value, ns_ttl, _) in ns_records {
-        // Name pointer to question
-        response.extend_from_slice(&[0xc0, 0x0c]);
+        // Owner name (pointer to question when it is the queried name)
+        write_owner_name(&mut response, &domain, query, &mut names)?;
 
         // Type NS (0x0002)
         response.extend_from_slice(&[0x00, 0x02]);
@@ -802,41 +3736,37 @@ pub fn build_ns_response(
         // TTL
         response.extend_from_slice(&(*ns_ttl as u32).to_be_bytes());
 
-        // Encode NS name
-        let ns_data = encode_dns_name(ns_value);
-
-        // RDLENGTH
-        response.extend_from_slice(&(ns_data.len() as u16).to_be_bytes());
+        // RDLENGTH placeholder
+        let rdlength_pos = response.len();
+        response.extend_from_slice(&[0x00, 0x00]);
 
         // RDATA (NS name)
-        response.extend_from_slice(&ns_data);
+        names.write_name(&mut response, ns_value)?;
+        set_rdlength(&mut response, rdlength_pos);
+
+        for rr in glue_records(ns_value, &domain, config) {
+            if !glue.contains(&rr) {
+                glue.push(rr);
+            }
+        }
+    }
+
+    // Glue goes in the additional section, before the OPT record
+    for (owner, rtype, ttl, value) in &glue {
+        write_record(&mut response, owner, *rtype, *ttl, value, query, &mut names)?;
     }
+    let arcount = glue.len() as u16 + has_edns as u16;
+    response[10..12].copy_from_slice(&arcount.to_be_bytes());
 
     // Add EDNS record if present in query
     if has_edns {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
-        let do_bit = extract_do_bit(query);
-
-        // Add OPT record
-        response.extend_from_slice(&[0x00]); // Root domain
-        response.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        response.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size
-        response.extend_from_slice(&[0x00]); // Extended RCODE
-        response.extend_from_slice(&[0x00]); // EDNS version
-
-        if do_bit {
-            response.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            response.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
-        }
-
-        response.extend_from_slice(&[0x00, 0x00]); // RDATA length
+        append_opt_record(&mut response, query, config.edns_buffer_size, &config.edns_echo_options);
     }
 
     Ok(response)
 }
 
-/// Build a DNS response for generic record types (MX, TXT, CNAME, PTR).
+/// Build a DNS response for generic record types (MX, TXT, CNAME, PTR, SRV, NAPTR, SSHFP, TLSA, CAA).
 ///
 /// # Arguments
 /// * `query` - The DNS query.
@@ -848,10 +3778,31 @@ pub fn build_ns_response(
 ///
 /// # Returns
 /// A `Result` containing the response or an error.
-pub fn build_generic_record_response(
+pub fn build_generic_record_response(
+    query: &[u8],
+    value: &str,
+    ttl: u64,
+    domain: String,
+    query_type: u16,
+    config: &ServerConfig,
+) -> Result<Vec<u8>, DnsError> {
+    build_generic_rrset_response(query, &[(value.to_string(), ttl)], domain, query_type, config)
+}
+
+/// Build a DNS response answering with every record of a generic RRset.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `rrset` - The (value, ttl) records of the set.
+/// * `domain` - The owner name of the records.
+/// * `query_type` - The query type.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response or an error.
+pub fn build_generic_rrset_response(
     query: &[u8],
-    value: &str,
-    ttl: u64,
+    rrset: &[(String, u64)],
     domain: String,
     query_type: u16,
     config: &ServerConfig,
@@ -870,8 +3821,8 @@ pub fn build_generic_record_response(
     // Copy QDCOUNT from query
     response.extend_from_slice(&query[4..6]);
 
-    // Set ANCOUNT to 1
-    response.extend_from_slice(&[0x00, 0x01]);
+    // Set ANCOUNT to the size of the RRset
+    response.extend_from_slice(&(rrset.len() as u16).to_be_bytes());
 
     // Set NSCOUNT to 0
     response.extend_from_slice(&[0x00, 0x00]);
@@ -881,25 +3832,108 @@ pub fn build_generic_record_response(
     response.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
 
     // Copy question section from query
-    let qname_end = query[12..].iter().position(|&b| b == 0)
-        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))? + 13;
-    response.extend_from_slice(&query[12..qname_end + 4]);
+    let question_end = question_end(query)
+        .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+    response.extend_from_slice(&query[12..question_end]);
 
     // Add answer section
-    // Name pointer to question
-    response.extend_from_slice(&[0xc0, 0x0c]);
+    let mut names = NameCompressor::new(config.compress_names);
+    names.seed_question(&response);
+    for (value, ttl) in rrset {
+        // Owner name (pointer to question when it is the queried name)
+        write_owner_name(&mut response, &domain, query, &mut names)?;
 
-    // Type
-    response.extend_from_slice(&query_type.to_be_bytes());
+        // Type
+        response.extend_from_slice(&query_type.to_be_bytes());
 
-    // Class IN (0x0001)
-    response.extend_from_slice(&[0x00, 0x01]);
+        // Class IN (0x0001)
+        response.extend_from_slice(&[0x00, 0x01]);
 
-    // TTL
-    response.extend_from_slice(&(ttl as u32).to_be_bytes());
+        // TTL
+        response.extend_from_slice(&(*ttl as u32).to_be_bytes());
+
+        // RDATA depends on record type
+        write_rdata(&mut response, query_type, value, &mut names)?;
+    }
+
+    // Add EDNS record if present in query
+    if has_edns {
+        append_opt_record(&mut response, query, config.edns_buffer_size, &config.edns_echo_options);
+    }
+
+    Ok(response)
+}
+
+/// Write the RDLENGTH and RDATA of a record from its stored value.
+///
+/// Names inside the RDATA of NS, CNAME, SOA, PTR and MX records are
+/// compressed through `names`; other types are written uncompressed.
+///
+/// # Arguments
+/// * `packet` - The message being built.
+/// * `rtype` - The record type.
+/// * `value` - The stored record value.
+/// * `names` - The name compressor for the message.
+///
+/// # Returns
+/// A `Result` indicating success, or an error for malformed values.
+pub fn write_rdata(
+    packet: &mut Vec<u8>,
+    rtype: u16,
+    value: &str,
+    names: &mut NameCompressor,
+) -> Result<(), DnsError> {
+    match rtype {
+        // A record
+        1 => {
+            let v4: Ipv4Addr = value.trim().parse()
+                .map_err(|_| DnsError::Config(format!("Invalid A record: {}", value)))?;
+            packet.extend_from_slice(&4u16.to_be_bytes());
+            packet.extend_from_slice(&v4.octets());
+        },
+
+        // AAAA record
+        28 => {
+            let v6: Ipv6Addr = value.trim().parse()
+                .map_err(|_| DnsError::Config(format!("Invalid AAAA record: {}", value)))?;
+            packet.extend_from_slice(&16u16.to_be_bytes());
+            packet.extend_from_slice(&v6.octets());
+        },
+
+        // SOA record
+        6 => {
+            // Parse SOA record: "ns1.example.com. hostmaster.example.com. 1 10800 3600 604800 86400"
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            if parts.len() < 7 {
+                return Err(DnsError::Config(format!("Invalid SOA record format: {}", value)));
+            }
+
+            // RDLENGTH placeholder
+            let rdlength_pos = packet.len();
+            packet.extend_from_slice(&[0x00, 0x00]);
+
+            // RDATA
+            names.write_name(packet, parts[0])?;
+            names.write_name(packet, parts[1])?;
+            for field in &parts[2..7] {
+                let n = field.parse::<u32>()
+                    .map_err(|_| DnsError::Config(format!("Invalid SOA field: {}", field)))?;
+                packet.extend_from_slice(&n.to_be_bytes());
+            }
+            set_rdlength(packet, rdlength_pos);
+        },
+
+        // NS record
+        2 => {
+            // RDLENGTH placeholder
+            let rdlength_pos = packet.len();
+            packet.extend_from_slice(&[0x00, 0x00]);
+
+            // RDATA
+            names.write_name(packet, value)?;
+            set_rdlength(packet, rdlength_pos);
+        },
 
-    // RDATA depends on record type
-    match query_type {
         // MX record
         15 => {
             // Parse MX record: "10 mail.example.com."
@@ -912,69 +3946,175 @@ pub fn build_generic_record_response(
                 .map_err(|_| DnsError::Config(format!("Invalid MX preference: {}", parts[0])))?;
             let exchange = parts[1];
 
-            // Encode exchange name
-            let exchange_wire = encode_dns_name(exchange);
+            // RDLENGTH placeholder
+            let rdlength_pos = packet.len();
+            packet.extend_from_slice(&[0x00, 0x00]);
+
+            // RDATA
+            packet.extend_from_slice(&preference.to_be_bytes());
+            names.write_name(packet, exchange)?;
+            set_rdlength(packet, rdlength_pos);
+        },
+
+        // TXT record, and HINFO whose CPU and OS are two character-strings
+        16 | 13 => {
+            // RDLENGTH, filled in once the strings are written
+            let rdlength_pos = packet.len();
+            packet.extend_from_slice(&[0x00, 0x00]);
+
+            // RDATA: one length-prefixed character-string per chunk
+            for string in txt_character_strings(value) {
+                packet.push(string.len() as u8);
+                packet.extend_from_slice(string.as_bytes());
+            }
+            set_rdlength(packet, rdlength_pos);
+        },
+
+        // SRV record
+        33 => {
+            // Parse SRV record: "10 60 5060 sipserver.example.com."
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            if parts.len() < 4 {
+                return Err(DnsError::Config(format!("Invalid SRV record format: {}", value)));
+            }
+
+            let priority = parts[0].parse::<u16>()
+                .map_err(|_| DnsError::Config(format!("Invalid SRV priority: {}", parts[0])))?;
+            let weight = parts[1].parse::<u16>()
+                .map_err(|_| DnsError::Config(format!("Invalid SRV weight: {}", parts[1])))?;
+            let port = parts[2].parse::<u16>()
+                .map_err(|_| DnsError::Config(format!("Invalid SRV port: {}", parts[2])))?;
+
+            // The SRV target must not be compressed (RFC 2782)
+            validate_name_length(parts[3])?;
+            let target_wire = encode_dns_name(parts[3]);
 
             // RDLENGTH
-            let rdlength = 2 + exchange_wire.len(); // preference + exchange
-            response.extend_from_slice(&(rdlength as u16).to_be_bytes());
+            let rdlength = 6 + target_wire.len(); // priority + weight + port + target
+            packet.extend_from_slice(&(rdlength as u16).to_be_bytes());
 
             // RDATA
-            response.extend_from_slice(&preference.to_be_bytes());
-            response.extend_from_slice(&exchange_wire);
+            packet.extend_from_slice(&priority.to_be_bytes());
+            packet.extend_from_slice(&weight.to_be_bytes());
+            packet.extend_from_slice(&port.to_be_bytes());
+            packet.extend_from_slice(&target_wire);
         },
 
-        // TXT record
-        16 => {
-            // Remove quotes if present
-            let txt_value = value.trim_matches('"');
+        // SSHFP record
+        44 => {
+            // Parse SSHFP record: "4 2 <hex fingerprint>"
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            if parts.len() < 3 {
+                return Err(DnsError::Config(format!("Invalid SSHFP record format: {}", value)));
+            }
+
+            let algorithm = parts[0].parse::<u8>()
+                .map_err(|_| DnsError::Config(format!("Invalid SSHFP algorithm: {}", parts[0])))?;
+            let fp_type = parts[1].parse::<u8>()
+                .map_err(|_| DnsError::Config(format!("Invalid SSHFP fingerprint type: {}", parts[1])))?;
+
+            // The fingerprint may be split across whitespace
+            let fingerprint = hex::decode(parts[2..].concat())
+                .map_err(|e| DnsError::Hex(format!("SSHFP fingerprint {}: {}", parts[2..].concat(), e)))?;
 
             // RDLENGTH
-            let rdlength = txt_value.len() + 1; // length byte + text
-            response.extend_from_slice(&(rdlength as u16).to_be_bytes());
+            let rdlength = 2 + fingerprint.len(); // algorithm + fingerprint type + fingerprint
+            packet.extend_from_slice(&(rdlength as u16).to_be_bytes());
 
             // RDATA
-            response.push(txt_value.len() as u8);
-            response.extend_from_slice(txt_value.as_bytes());
+            packet.push(algorithm);
+            packet.push(fp_type);
+            packet.extend_from_slice(&fingerprint);
         },
 
-        // CNAME or PTR record
-        5 | 12 => {
-            // Encode target name
-            let target_wire = encode_dns_name(value);
+        // TLSA record
+        52 => {
+            // Parse TLSA record: "3 1 1 <hex certificate association data>"
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            if parts.len() < 4 {
+                return Err(DnsError::Config(format!("Invalid TLSA record format: {}", value)));
+            }
+
+            let usage = parts[0].parse::<u8>()
+                .map_err(|_| DnsError::Config(format!("Invalid TLSA usage: {}", parts[0])))?;
+            let selector = parts[1].parse::<u8>()
+                .map_err(|_| DnsError::Config(format!("Invalid TLSA selector: {}", parts[1])))?;
+            let matching_type = parts[2].parse::<u8>()
+                .map_err(|_| DnsError::Config(format!("Invalid TLSA matching type: {}", parts[2])))?;
+
+            // Association data may be split across whitespace
+            let data = hex::decode(parts[3..].concat())
+                .map_err(|e| DnsError::Hex(format!("TLSA association data {}: {}", parts[3..].concat(), e)))?;
 
             // RDLENGTH
-            response.extend_from_slice(&(target_wire.len() as u16).to_be_bytes());
+            let rdlength = 3 + data.len(); // usage + selector + matching type + data
+            packet.extend_from_slice(&(rdlength as u16).to_be_bytes());
 
             // RDATA
-            response.extend_from_slice(&target_wire);
+            packet.push(usage);
+            packet.push(selector);
+            packet.push(matching_type);
+            packet.extend_from_slice(&data);
         },
 
-        _ => return Err(DnsError::Protocol(format!("Unsupported record type: {}", query_type))),
-    }
+        // NAPTR record
+        35 => {
+            let rdata = naptr_rdata(value)?;
+            packet.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&rdata);
+        },
 
-    // Add EDNS record if present in query
-    if has_edns {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
-        let do_bit = extract_do_bit(query);
-
-        // Add OPT record
-        response.extend_from_slice(&[0x00]); // Root domain
-        response.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        response.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size
-        response.extend_from_slice(&[0x00]); // Extended RCODE
-        response.extend_from_slice(&[0x00]); // EDNS version
-
-        if do_bit {
-            response.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            response.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
-        }
+        // SVCB or HTTPS record
+        64 | 65 => {
+            let rdata = svcb_rdata(value)?;
+            packet.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&rdata);
+        },
+
+        // CAA record
+        257 => {
+            // Parse CAA record: 0 issue "letsencrypt.org"
+            let mut parts = value.trim().splitn(3, char::is_whitespace);
+            let (flags, tag, caa_value) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(flags), Some(tag), Some(caa_value)) if !tag.is_empty() => (flags, tag, caa_value),
+                _ => return Err(DnsError::Config(format!("Invalid CAA record format: {}", value))),
+            };
+
+            let flags = flags.parse::<u8>()
+                .map_err(|_| DnsError::Config(format!("Invalid CAA flags: {}", flags)))?;
+            if tag.len() > 255 {
+                return Err(DnsError::Config(format!("Invalid CAA tag: {}", tag)));
+            }
+
+            // Remove quotes if present
+            let caa_value = caa_value.trim().trim_matches('"');
+
+            // RDLENGTH
+            let rdlength = 2 + tag.len() + caa_value.len(); // flags + tag length + tag + value
+            packet.extend_from_slice(&(rdlength as u16).to_be_bytes());
+
+            // RDATA
+            packet.push(flags);
+            packet.push(tag.len() as u8);
+            packet.extend_from_slice(tag.as_bytes());
+            packet.extend_from_slice(caa_value.as_bytes());
+        },
+
+        // CNAME or PTR record
+        5 | 12 => {
+            // RDLENGTH placeholder
+            let rdlength_pos = packet.len();
+            packet.extend_from_slice(&[0x00, 0x00]);
+
+            // RDATA
+            names.write_name(packet, value)?;
+            set_rdlength(packet, rdlength_pos);
+        },
 
-        response.extend_from_slice(&[0x00, 0x00]); // RDATA length
+        _ => return Err(DnsError::Protocol(format!("Unsupported record type: {}", rtype))),
     }
 
-    Ok(response)
+    Ok(())
 }
 
 /// Build a DNS response for a "not implemented" error.
@@ -1005,7 +4145,7 @@ pub fn build_not_implemented_response(query: &[u8], authoritative: bool) -> Opti
     // Z = 0
     // RCODE = 4 (not implemented)
     let opcode = query[2] & 0x78; // Extract OPCODE
-    let rd = query[2] & 0x01; // Extract RD
+    let rd = recursion_desired(query) as u8; // Extract RD
     let flags1 = 0x80 | opcode | rd; // QR=1, OPCODE=opcode, RD=rd
     let flags2 = 0x84; // RA=1, RCODE=4 (not implemented)
 
@@ -1017,8 +4157,11 @@ pub fn build_not_implemented_response(query: &[u8], authoritative: bool) -> Opti
     // Copy QDCOUNT from query
     resp.extend_from_slice(&query[4..6]);
 
-    // Set ANCOUNT, NSCOUNT, ARCOUNT to 0
-    resp.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    // Check for EDNS
+    let has_edns = has_opt_record(query);
+
+    // Set ANCOUNT and NSCOUNT to 0, ARCOUNT to 1 if EDNS
+    resp.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, if has_edns { 0x01 } else { 0x00 }]);
 
     // Copy question section from query
     let mut pos = 12;
@@ -1044,26 +4187,159 @@ pub fn build_not_implemented_response(query: &[u8], authoritative: bool) -> Opti
     // Copy question section
     resp.extend_from_slice(&query[12..pos]);
 
-    // Check for EDNS
-    let has_edns = has_opt_record(query);
     if has_edns {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
-        let do_bit = extract_do_bit(query);
-
-        // Add OPT record
-        resp.extend_from_slice(&[0x00]); // Root domain
-        resp.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        resp.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size
-        resp.extend_from_slice(&[0x00]); // Extended RCODE
-        resp.extend_from_slice(&[0x00]); // EDNS version
-
-        if do_bit {
-            resp.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            resp.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
+        append_opt_record(&mut resp, query, DEFAULT_EDNS_BUFFER_SIZE, &[]);
+    }
+
+    Some(resp)
+}
+
+/// Build a DNS response for a "format error" (FORMERR).
+///
+/// No OPT record is included since a malformed OPT is a common cause.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+pub fn build_formerr_response(query: &[u8]) -> Option<Vec<u8>> {
+    if query.len() < 12 {
+        return None;
+    }
+
+    let mut resp = Vec::with_capacity(512);
+
+    // Copy transaction ID from query
+    resp.extend_from_slice(&query[0..2]);
+
+    // Set flags
+    // QR = 1 (response)
+    // OPCODE = copy from query
+    // RD = copy from query
+    // RA = 1 (recursion available)
+    // RCODE = 1 (format error)
+    let opcode = query[2] & 0x78; // Extract OPCODE
+    let rd = recursion_desired(query) as u8; // Extract RD
+    resp.extend_from_slice(&[0x80 | opcode | rd, 0x81]);
+
+    // Echo the question if it can be parsed, otherwise send the bare header
+    match question_end(query) {
+        Some(end) => {
+            resp.extend_from_slice(&[0x00, 0x01]); // QDCOUNT
+            resp.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // ANCOUNT, NSCOUNT, ARCOUNT
+            resp.extend_from_slice(&query[12..end]);
         }
+        None => {
+            resp.extend_from_slice(&[0x00; 8]); // All counts zero
+        }
+    }
+
+    Some(resp)
+}
+
+/// Build a DNS response for a "server failure" (SERVFAIL).
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
+/// * `authoritative` - Whether this server is authoritative for the domain.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+pub fn build_servfail_response(query: &[u8], config: &ServerConfig, authoritative: bool) -> Option<Vec<u8>> {
+    build_error_response(query, config, authoritative, 2, None)
+}
+
+/// Build a DNS response for a refused query (REFUSED).
+///
+/// RA is set only when `config.allow_recursion` is on. When `ede` is given
+/// and the query used EDNS, an Extended DNS Error explaining the refusal is
+/// attached.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
+/// * `ede` - An optional EDE INFO-CODE and extra text.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+pub fn build_refused_response(query: &[u8], config: &ServerConfig, ede: Option<(u16, &str)>) -> Option<Vec<u8>> {
+    build_error_response(query, config, config.authoritative, 5, ede)
+}
+
+/// Build an empty response echoing the question with the given RCODE.
+///
+/// RA is set only when `config.allow_recursion` is on. A query that used
+/// EDNS gets an OPT record advertising `config.edns_buffer_size`, plus an
+/// Extended DNS Error when `ede` is given.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
+/// * `authoritative` - Whether this server is authoritative for the domain.
+/// * `rcode` - The response code.
+/// * `ede` - An optional EDE INFO-CODE and extra text.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+fn build_error_response(
+    query: &[u8],
+    config: &ServerConfig,
+    authoritative: bool,
+    rcode: u8,
+    ede: Option<(u16, &str)>,
+) -> Option<Vec<u8>> {
+    if query.len() < 12 {
+        return None;
+    }
+
+    let mut resp = Vec::with_capacity(512);
+
+    // Copy transaction ID from query
+    resp.extend_from_slice(&query[0..2]);
+
+    // Set flags
+    // QR = 1 (response)
+    // OPCODE = 0 (standard query)
+    // AA = 0 or 1 depending on authoritative
+    // TC = 0 (not truncated)
+    // RD = copy from query
+    // RA = 1 only if recursion is allowed
+    // Z = 0
+    // RCODE = rcode
+    let rd = recursion_desired(query) as u8; // Extract RD
+    let flags1 = 0x80 | rd; // QR=1, RD=rd
+    let ra = if config.allow_recursion { 0x80 } else { 0x00 };
+    let flags2 = ra | (rcode & 0x0F); // RA, RCODE=rcode
+
+    resp.extend_from_slice(&[
+        if authoritative { flags1 | 0x04 } else { flags1 }, // Set AA if authoritative
+        flags2,
+    ]);
+
+    // Copy QDCOUNT from query
+    resp.extend_from_slice(&query[4..6]);
+
+    // Set ANCOUNT and NSCOUNT to 0
+    resp.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+
+    // Set ARCOUNT to 1 if EDNS, 0 otherwise
+    let has_edns = has_opt_record(query);
+    resp.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
+
+    // Copy question section from query
+    let pos = skip_name(query, 12)? + 4;
+    if pos > query.len() {
+        return None;
+    }
+    resp.extend_from_slice(&query[12..pos]);
 
-        resp.extend_from_slice(&[0x00, 0x00]); // RDATA length
+    if has_edns {
+        append_opt_record(&mut resp, query, config.edns_buffer_size, &config.edns_echo_options);
+        if let Some((info_code, extra_text)) = ede {
+            append_extended_error(&mut resp, info_code, extra_text);
+        }
     }
 
     Some(resp)
@@ -1074,27 +4350,48 @@ pub fn build_not_implemented_response(query: &[u8], authoritative: bool) -> Opti
 /// # Arguments
 /// * `query` - The DNS query.
 /// * `authoritative` - Whether this server is authoritative for the domain.
+/// * `config` - Server configuration.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+pub fn build_nxdomain_response(query: &[u8], authoritative: bool, config: &ServerConfig) -> Option<Vec<u8>> {
+    build_negative_response(query, authoritative, 3, config)
+}
+
+/// Build a NODATA response: the name exists but has no records of the
+/// queried type (RFC 2308 section 2.2).
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `authoritative` - Whether this server is authoritative for the domain.
+/// * `config` - Server configuration.
+///
+/// # Returns
+/// An `Option` containing the response if successful.
+pub fn build_nodata_response(query: &[u8], authoritative: bool, config: &ServerConfig) -> Option<Vec<u8>> {
+    build_negative_response(query, authoritative, 0, config)
+}
+
+/// Build an empty response carrying the enclosing zone's SOA and NS records
+/// in the authority section.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `authoritative` - Whether this server is authoritative for the domain.
+/// * `rcode` - The response code: 3 for NXDOMAIN, 0 for NODATA.
+/// * `config` - Server configuration.
 ///
 /// # Returns
 /// An `Option` containing the response if successful.
-pub fn build_nxdomain_response(query: &[u8], authoritative: bool) -> Option<Vec<u8>> {
+fn build_negative_response(query: &[u8], authoritative: bool, rcode: u8, config: &ServerConfig) -> Option<Vec<u8>> {
     let mut resp = Vec::with_capacity(512);
     resp.extend_from_slice(&query[0..2]); // Transaction ID
 
     // Extract domain from query for authority section reference
-    let domain = match extract_domain(query) {
-        Some(d) => d,
-        None => return None,
-    };
-
-    // Get zones we're authoritative for
-    let config = match ServerConfig::from_env() {
-        Ok(c) => c,
-        Err(_) => return None,
-    };
+    let domain = extract_domain(query)?;
 
     // Get authoritative zones
-    let zones = get_authoritative_zones(&config.db_path);
+    let zones = get_zones(config);
     let zone = find_closest_parent_zone(&domain, &zones);
 
     // Set flags
@@ -1105,10 +4402,10 @@ pub fn build_nxdomain_response(query: &[u8], authoritative: bool) -> Option<Vec<
     // RD = copy from query
     // RA = 1 (recursion available)
     // Z = 0
-    // RCODE = 3 (name error)
-    let rd = query[2] & 0x01; // Extract RD
+    // RCODE = rcode (3 for name error, 0 for no data)
+    let rd = recursion_desired(query) as u8; // Extract RD
     let flags1 = 0x80 | rd; // QR=1, RD=rd
-    let flags2 = 0x83; // RA=1, RCODE=3 (name error)
+    let flags2 = 0x80 | (rcode & 0x0F); // RA=1, RCODE=rcode
 
     resp.extend_from_slice(&[
         if authoritative { flags1 | 0x04 } else { flags1 }, // Set AA if authoritative
@@ -1121,8 +4418,8 @@ pub fn build_nxdomain_response(query: &[u8], authoritative: bool) -> Option<Vec<
     // Set ANCOUNT to 0
     resp.extend_from_slice(&[0x00, 0x00]);
 
-    // Set NSCOUNT to 1 if we have a zone, 0 otherwise
-    resp.extend_from_slice(&[0x00, if zone.is_some() { 0x01 } else { 0x00 }]);
+    // NSCOUNT placeholder, set once the authority section is written
+    resp.extend_from_slice(&[0x00, 0x00]);
 
     // Check for EDNS
     let has_edns = has_opt_record(query);
@@ -1131,28 +4428,16 @@ pub fn build_nxdomain_response(query: &[u8], authoritative: bool) -> Option<Vec<
     resp.extend_from_slice(&[0x00, if has_edns { 0x01 } else { 0x00 }]);
 
     // Copy question section from query
-    let qname_end = match query[12..].iter().position(|&b| b == 0) {
-        Some(pos) => pos + 13,
-        None => return None,
-    };
-    resp.extend_from_slice(&query[12..qname_end + 4]);
+    let question_end = question_end(query)?;
+    resp.extend_from_slice(&query[12..question_end]);
 
     // Add authority section if we have a zone
+    let mut nscount: u16 = 0;
     if let Some(zone) = zone {
-        // Add SOA record
-        let zone_name = encode_dns_name(&zone.name);
-        resp.extend_from_slice(&zone_name);
-
-        // Type SOA (0x0006)
-        resp.extend_from_slice(&[0x00, 0x06]);
-
-        // Class IN (0x0001)
-        resp.extend_from_slice(&[0x00, 0x01]);
-
-        // TTL
-        resp.extend_from_slice(&(DEFAULT_TTL as u32).to_be_bytes());
+        let mut names = NameCompressor::new(config.compress_names);
+        names.seed_question(&resp);
 
-        // RDATA
+        // Add SOA record
         if let Some(soa) = zone.soa_record {
             // Parse SOA record
             let parts: Vec<&str> = soa.split_whitespace().collect();
@@ -1160,31 +4445,45 @@ pub fn build_nxdomain_response(query: &[u8], authoritative: bool) -> Option<Vec<
                 let mname = parts[0];
                 let rname = parts[1];
 
-                // Encode MNAME and RNAME
-                let mname_wire = encode_dns_name(mname);
-                let rname_wire = encode_dns_name(rname);
+                names.write_name(&mut resp, &zone.name).ok()?;
 
-                // RDLENGTH
-                let rdlength = mname_wire.len() + rname_wire.len() + 20; // 5 32-bit integers
-                resp.extend_from_slice(&(rdlength as u16).to_be_bytes());
+                // Type SOA (0x0006)
+                resp.extend_from_slice(&[0x00, 0x06]);
+
+                // Class IN (0x0001)
+                resp.extend_from_slice(&[0x00, 0x01]);
+
+                // TTL
+                resp.extend_from_slice(&(DEFAULT_TTL as u32).to_be_bytes());
+
+                // RDLENGTH placeholder
+                let rdlength_pos = resp.len();
+                resp.extend_from_slice(&[0x00, 0x00]);
 
                 // RDATA
-                resp.extend_from_slice(&mname_wire);
-                resp.extend_from_slice(&rname_wire);
-
-                // SOA integers with reasonable defaults
-                resp.extend_from_slice(&1u32.to_be_bytes()); // SERIAL
-                resp.extend_from_slice(&10800u32.to_be_bytes()); // REFRESH
-                resp.extend_from_slice(&3600u32.to_be_bytes()); // RETRY
-                resp.extend_from_slice(&604800u32.to_be_bytes()); // EXPIRE
-                resp.extend_from_slice(&86400u32.to_be_bytes()); // MINIMUM
+                names.write_name(&mut resp, mname).ok()?;
+                names.write_name(&mut resp, rname).ok()?;
+
+                // SERIAL, REFRESH, RETRY, EXPIRE and MINIMUM from the zone's SOA
+                let timers = parts[2..7].iter()
+                    .map(|field| field.parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap_or_else(|_| {
+                        debug!("Invalid SOA for {}, using default timers: {}", zone.name, soa);
+                        DEFAULT_SOA_TIMERS.to_vec()
+                    });
+                for timer in timers {
+                    resp.extend_from_slice(&timer.to_be_bytes());
+                }
+                set_rdlength(&mut resp, rdlength_pos);
+                nscount += 1;
             }
         }
 
         // Add NS records
         for ns in &zone.ns_records {
             // Name of the zone
-            resp.extend_from_slice(&zone_name);
+            names.write_name(&mut resp, &zone.name).ok()?;
 
             // Type NS (0x0002)
             resp.extend_from_slice(&[0x00, 0x02]);
@@ -1195,35 +4494,130 @@ pub fn build_nxdomain_response(query: &[u8], authoritative: bool) -> Option<Vec<
             // TTL
             resp.extend_from_slice(&(DEFAULT_TTL as u32).to_be_bytes());
 
-            // RDLENGTH and RDATA (NS name)
-            let ns_data = encode_dns_name(ns);
-            resp.extend_from_slice(&(ns_data.len() as u16).to_be_bytes());
-            resp.extend_from_slice(&ns_data);
+            // RDLENGTH placeholder and RDATA (NS name)
+            let rdlength_pos = resp.len();
+            resp.extend_from_slice(&[0x00, 0x00]);
+            names.write_name(&mut resp, ns).ok()?;
+            set_rdlength(&mut resp, rdlength_pos);
+            nscount += 1;
         }
     }
+    resp[8..10].copy_from_slice(&nscount.to_be_bytes());
 
-    // Handle EDNS in NXDOMAIN response
+    // Handle EDNS in the negative response
     if has_edns {
-        let opt_payload_size = extract_edns_payload_size(query).unwrap_or(4096);
+        append_opt_record(&mut resp, query, config.edns_buffer_size, &config.edns_echo_options);
+    }
 
-        // Copy DO bit if present in request
-        let do_bit = extract_do_bit(query);
+    Some(resp)
+}
 
-        // Add OPT record for EDNS
-        resp.extend_from_slice(&[0x00]); // Root domain
-        resp.extend_from_slice(&[0x00, 0x29]); // TYPE OPT
-        resp.extend_from_slice(&opt_payload_size.to_be_bytes()); // UDP payload size from request
-        resp.extend_from_slice(&[0x00]); // Extended RCODE
-        resp.extend_from_slice(&[0x00]); // EDNS version
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    /// An Ed25519 signing key for `signer`, loaded the way the server loads one.
+    fn signing_key(signer: &str) -> SigningKey {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let public_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap().public_key().as_ref().to_vec();
+        let path = std::env::temp_dir().join(format!("nx9-key-{}-{}.der", signer, std::process::id()));
+        std::fs::write(&path, pkcs8.as_ref()).unwrap();
+        let dnskey = format!(
+            "{}. IN DNSKEY 256 3 15 {}",
+            signer,
+            base64::engine::general_purpose::STANDARD.encode(public_key)
+        );
+        SigningKey::load(&path.to_string_lossy(), &[dnskey]).unwrap()
+    }
 
-        if do_bit {
-            resp.extend_from_slice(&[0x80, 0x00]); // Flags with DO bit set
-        } else {
-            resp.extend_from_slice(&[0x00, 0x00]); // Flags with DO bit clear
-        }
+    /// A query with the DO bit set.
+    fn do_query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+        query.extend_from_slice(&encode_dns_name(name));
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&[0x00, 0x01]);
+        query.extend_from_slice(&[0x00, 0x00, 0x29, 0x04, 0xD0, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00]);
+        query
+    }
 
-        resp.extend_from_slice(&[0x00, 0x00]); // RDATA length
+    /// The EDE INFO-CODEs carried in a response.
+    fn ede_codes(response: &[u8]) -> Vec<u16> {
+        extract_edns_options(response).into_iter()
+            .filter(|(code, data)| *code == 15 && data.len() >= 2)
+            .map(|(_, data)| u16::from_be_bytes([data[0], data[1]]))
+            .collect()
     }
 
-    Some(resp)
-}
\ No newline at end of file
+    #[test]
+    fn signing_failure_answers_servfail_with_dnssec_bogus() {
+        let config = ServerConfig::from_env().unwrap();
+        let key = signing_key("bogus.test");
+        let query = do_query("www.bogus.test", 1);
+
+        // An answer count with no answer behind it cannot be signed
+        let mut response = build_nodata_response(&query, true, &config).unwrap();
+        response[7] = 1;
+
+        let answer = signed_or_servfail(&response, &query, "www.bogus.test", &key, &config).unwrap();
+        assert_eq!(answer[3] & 0x0F, 2);
+        assert_eq!(answer[6..8], [0, 0]);
+        assert_eq!(ede_codes(&answer), vec![EDE_DNSSEC_BOGUS]);
+    }
+
+    #[test]
+    fn zero_signature_validity_answers_servfail_with_signature_expired() {
+        let mut config = ServerConfig::from_env().unwrap();
+        config.signature_validity = 0;
+        let key = signing_key("expired.test");
+        let query = do_query("www.expired.test", 1);
+        let response = build_dns_response(&query, "192.0.2.1", 300, &config).unwrap();
+
+        let answer = signed_or_servfail(&response, &query, "www.expired.test", &key, &config).unwrap();
+        assert_eq!(answer[3] & 0x0F, 2);
+        assert_eq!(answer[6..8], [0, 0]);
+        assert_eq!(ede_codes(&answer), vec![EDE_SIGNATURE_EXPIRED]);
+    }
+
+    #[test]
+    fn rrsig_without_key_tag_signer_or_signature_is_rejected() {
+        // Ten fields end at the inception time
+        let short = "bzo.in. 3600 IN RRSIG DNSKEY 8 2 3600 20250601000000 20240501000000";
+        assert!(matches!(encode_rrsig_rr(short, 3600), Err(DnsError::Dnssec(_))));
+
+        let missing_signature = format!("{} 24550 bzo.in.", short);
+        assert!(matches!(encode_rrsig_rr(&missing_signature, 3600), Err(DnsError::Dnssec(_))));
+    }
+
+    #[test]
+    fn signable_answer_gets_rrsig() {
+        let config = ServerConfig::from_env().unwrap();
+        let key = signing_key("signed.test");
+        let query = do_query("www.signed.test", 1);
+        let response = build_dns_response(&query, "192.0.2.1", 300, &config).unwrap();
+
+        let answer = signed_or_servfail(&response, &query, "www.signed.test", &key, &config).unwrap();
+        assert_eq!(answer[3] & 0x0F, 0);
+        assert_eq!(answer[6..8], [0, 2]); // The A record and its RRSIG
+    }
+
+    #[test]
+    fn upstream_response_must_match_the_question_sent() {
+        let query = do_query("www.example.test", 1);
+        let sent_id = [0xAB, 0xCD];
+        let answer_to = |name: &str, qtype: u16| {
+            let mut response = do_query(name, qtype);
+            response[0..2].copy_from_slice(&sent_id);
+            response[2] |= 0x80;
+            response
+        };
+
+        let restored = restore_client_id(answer_to("WWW.Example.Test", 1), sent_id, &query).unwrap();
+        assert_eq!(restored[0..2], query[0..2]);
+
+        assert!(restore_client_id(answer_to("evil.example.test", 1), sent_id, &query).is_err());
+        assert!(restore_client_id(answer_to("www.example.test", 28), sent_id, &query).is_err());
+        assert!(restore_client_id(answer_to("www.example.test", 1), [0xAB, 0xCE], &query).is_err());
+    }
+}
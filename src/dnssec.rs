@@ -0,0 +1,385 @@
+//! DNSSEC helpers.
+//!
+//! This module provides key tag computation for DNSKEY records, checks that
+//! the configured DS records reference the DNSKEYs this server actually serves,
+//! builds NSEC records for authenticated denial of existence, and signs RRsets
+//! online (RFC 4034) with an ECDSA P-256 or Ed25519 key.
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::fs;
+use base64::Engine;
+use log::{info, warn};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+use crate::errors::DnsError;
+use crate::utils::encode_dns_name;
+
+/// DNSSEC algorithm number for ECDSA P-256 with SHA-256 (RFC 6605).
+pub const ALGORITHM_ECDSAP256SHA256: u8 = 13;
+
+/// DNSSEC algorithm number for Ed25519 (RFC 8080).
+pub const ALGORITHM_ED25519: u8 = 15;
+
+/// The private half of a signing key.
+#[derive(Debug)]
+enum KeyPairKind {
+    /// ECDSA P-256 with SHA-256.
+    EcdsaP256(EcdsaKeyPair),
+
+    /// Ed25519.
+    Ed25519(Ed25519KeyPair),
+}
+
+/// A private key used to sign answers, with the DNSKEY it corresponds to.
+#[derive(Debug)]
+pub struct SigningKey {
+    /// The zone the key belongs to: the DNSKEY owner and RRSIG signer name.
+    pub signer: String,
+
+    /// The DNSSEC algorithm number.
+    pub algorithm: u8,
+
+    /// The key tag of the matching DNSKEY.
+    pub key_tag: u16,
+
+    /// The key pair.
+    key_pair: KeyPairKind,
+}
+
+impl SigningKey {
+    /// Load a PKCS#8 private key and find the served DNSKEY it belongs to.
+    ///
+    /// # Arguments
+    /// * `path` - Path to the private key, PEM (`PRIVATE KEY`) or DER encoded.
+    /// * `dnskey_records` - The configured DNSKEY records.
+    ///
+    /// # Returns
+    /// A `Result` containing the key, or `DnsError::Config` if it cannot be
+    /// read, uses an unsupported algorithm or matches no DNSKEY.
+    pub fn load(path: &str, dnskey_records: &[String]) -> Result<Self, DnsError> {
+        let bytes = fs::read(path)
+            .map_err(|e| DnsError::Config(format!("Failed to read DNSSEC private key {}: {}", path, e)))?;
+        let pkcs8 = match rustls_pemfile::pkcs8_private_keys(&mut bytes.as_slice()) {
+            Ok(mut keys) if !keys.is_empty() => keys.remove(0),
+            _ => bytes,
+        };
+
+        let key_pair = if let Ok(pair) = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &SystemRandom::new()) {
+            KeyPairKind::EcdsaP256(pair)
+        } else if let Ok(pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8) {
+            KeyPairKind::Ed25519(pair)
+        } else {
+            return Err(DnsError::Dnssec(format!("{} is not a PKCS#8 ECDSA P-256 or Ed25519 key", path)));
+        };
+        let (algorithm, public_key) = match &key_pair {
+            // DNSKEY holds the bare point, without the uncompressed-point prefix
+            KeyPairKind::EcdsaP256(pair) => (ALGORITHM_ECDSAP256SHA256, pair.public_key().as_ref()[1..].to_vec()),
+            KeyPairKind::Ed25519(pair) => (ALGORITHM_ED25519, pair.public_key().as_ref().to_vec()),
+        };
+
+        for record in dnskey_records {
+            let Ok(rdata) = dnskey_rdata(record) else {
+                continue;
+            };
+            if rdata[3] == algorithm && rdata[4..] == public_key[..] {
+                let signer = record.split_whitespace().next().unwrap_or_default()
+                    .trim_end_matches('.')
+                    .to_ascii_lowercase();
+                let key_tag = key_tag(&rdata);
+                info!("Loaded DNSSEC signing key for {} (algorithm {}, key tag {})", signer, algorithm, key_tag);
+                return Ok(Self { signer, algorithm, key_tag, key_pair });
+            }
+        }
+        Err(DnsError::Dnssec(format!("DNSSEC private key {} matches no configured DNSKEY", path)))
+    }
+
+    /// Check whether a name is inside the zone this key signs.
+    ///
+    /// # Arguments
+    /// * `name` - The domain name.
+    ///
+    /// # Returns
+    /// `true` if the name is the signer or below it.
+    pub fn covers(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        name == self.signer || name.ends_with(&format!(".{}", self.signer))
+    }
+
+    /// Sign a message.
+    ///
+    /// # Arguments
+    /// * `message` - The data to sign.
+    ///
+    /// # Returns
+    /// A `Result` containing the signature in DNSSEC wire format.
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        match &self.key_pair {
+            KeyPairKind::EcdsaP256(pair) => pair.sign(&SystemRandom::new(), message)
+                .map(|signature| signature.as_ref().to_vec())
+                .map_err(|_| DnsError::Dnssec("ECDSA signing failed".into())),
+            KeyPairKind::Ed25519(pair) => Ok(pair.sign(message).as_ref().to_vec()),
+        }
+    }
+}
+
+/// Compare two domain names in DNSSEC canonical order (RFC 4034 section 6.1).
+///
+/// Names are compared label by label from the root down, each label as
+/// lowercase bytes, so a name sorts directly before the names below it.
+///
+/// # Arguments
+/// * `a` - The first name.
+/// * `b` - The second name.
+///
+/// # Returns
+/// The ordering of `a` relative to `b`.
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| -> Vec<Vec<u8>> {
+        name.trim_end_matches('.')
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .rev()
+            .map(|label| label.to_ascii_lowercase().into_bytes())
+            .collect()
+    };
+    labels(a).cmp(&labels(b))
+}
+
+/// Encode the RDATA of an NSEC record (RFC 4034 section 4).
+///
+/// # Arguments
+/// * `next` - The next owner name in the zone's canonical order.
+/// * `types` - The record types present at the NSEC owner.
+///
+/// # Returns
+/// The NSEC RDATA: the uncompressed next name followed by the type bitmap.
+pub fn nsec_rdata(next: &str, types: &[u16]) -> Vec<u8> {
+    let mut types = types.to_vec();
+    types.sort_unstable();
+    types.dedup();
+
+    let mut rdata = encode_dns_name(&next.to_ascii_lowercase());
+    let mut windows: Vec<(u8, Vec<u8>)> = Vec::new();
+    for rtype in types {
+        let (window, bit) = ((rtype >> 8) as u8, (rtype & 0xFF) as usize);
+        if windows.last().map(|(w, _)| *w) != Some(window) {
+            windows.push((window, Vec::new()));
+        }
+        let Some((_, bitmap)) = windows.last_mut() else {
+            continue;
+        };
+        if bitmap.len() <= bit / 8 {
+            bitmap.resize(bit / 8 + 1, 0);
+        }
+        bitmap[bit / 8] |= 0x80 >> (bit % 8);
+    }
+    for (window, bitmap) in windows {
+        rdata.push(window);
+        rdata.push(bitmap.len() as u8);
+        rdata.extend_from_slice(&bitmap);
+    }
+    rdata
+}
+
+/// Sign an RRset, producing the RDATA of its RRSIG record (RFC 4034 section 3).
+///
+/// The RRset is put in canonical form first (RFC 4034 section 6): the owner
+/// is lowercased and the records are sorted by RDATA with duplicates removed.
+/// Names inside `rdatas` must already be uncompressed and lowercased.
+///
+/// # Arguments
+/// * `key` - The signing key.
+/// * `owner` - The owner name of the RRset.
+/// * `rtype` - The type of the RRset.
+/// * `ttl` - The original TTL of the RRset.
+/// * `rdatas` - The RDATA of each record in the set.
+/// * `inception` - Start of the signature validity period, in seconds since the epoch.
+/// * `expiration` - End of the signature validity period, in seconds since the epoch.
+///
+/// # Returns
+/// A `Result` containing the RRSIG RDATA, or an error if signing failed.
+pub fn sign_rrset(
+    key: &SigningKey,
+    owner: &str,
+    rtype: u16,
+    ttl: u32,
+    rdatas: &[Vec<u8>],
+    inception: u32,
+    expiration: u32,
+) -> Result<Vec<u8>, DnsError> {
+    let owner = owner.trim_end_matches('.').to_ascii_lowercase();
+    let owner_wire = encode_dns_name(&owner);
+    let labels = owner.split('.').filter(|label| !label.is_empty() && *label != "*").count() as u8;
+
+    let mut rrsig = Vec::with_capacity(128);
+    rrsig.extend_from_slice(&rtype.to_be_bytes());
+    rrsig.push(key.algorithm);
+    rrsig.push(labels);
+    rrsig.extend_from_slice(&ttl.to_be_bytes());
+    rrsig.extend_from_slice(&expiration.to_be_bytes());
+    rrsig.extend_from_slice(&inception.to_be_bytes());
+    rrsig.extend_from_slice(&key.key_tag.to_be_bytes());
+    rrsig.extend_from_slice(&encode_dns_name(&key.signer));
+
+    let mut sorted: Vec<&Vec<u8>> = rdatas.iter().collect();
+    sorted.sort();
+    sorted.dedup();
+
+    let mut signed = rrsig.clone();
+    for rdata in sorted {
+        signed.extend_from_slice(&owner_wire);
+        signed.extend_from_slice(&rtype.to_be_bytes());
+        signed.extend_from_slice(&[0x00, 0x01]); // Class IN
+        signed.extend_from_slice(&ttl.to_be_bytes());
+        signed.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        signed.extend_from_slice(rdata);
+    }
+
+    rrsig.extend_from_slice(&key.sign(&signed)?);
+    Ok(rrsig)
+}
+
+/// Compute the key tag of a DNSKEY record (RFC 4034 Appendix B).
+///
+/// # Arguments
+/// * `dnskey_rdata` - The DNSKEY RDATA in wire format (flags, protocol, algorithm, key).
+///
+/// # Returns
+/// The 16-bit key tag.
+pub fn key_tag(dnskey_rdata: &[u8]) -> u16 {
+    // Algorithm 1 (RSA/MD5) uses the most significant 16 bits of the
+    // least significant 24 bits of the modulus
+    if dnskey_rdata.len() >= 4 && dnskey_rdata[3] == 1 {
+        let n = dnskey_rdata.len();
+        return u16::from_be_bytes([dnskey_rdata[n - 3], dnskey_rdata[n - 2]]);
+    }
+
+    let mut ac: u32 = 0;
+    for (i, &byte) in dnskey_rdata.iter().enumerate() {
+        if i & 1 == 1 {
+            ac += byte as u32;
+        } else {
+            ac += (byte as u32) << 8;
+        }
+    }
+    ac += (ac >> 16) & 0xFFFF;
+    (ac & 0xFFFF) as u16
+}
+
+/// Encode a DNSKEY record in presentation format to its wire RDATA.
+///
+/// # Arguments
+/// * `dnskey_record` - The DNSKEY record string, e.g. `example.com. IN DNSKEY 257 3 8 AwEAA...`.
+///
+/// # Returns
+/// A `Result` containing the RDATA or an error.
+pub fn dnskey_rdata(dnskey_record: &str) -> Result<Vec<u8>, DnsError> {
+    let parts: Vec<&str> = dnskey_record.split_whitespace().collect();
+    if parts.len() < 7 {
+        return Err(DnsError::Dnssec(format!("Invalid DNSKEY record format: {}", dnskey_record)));
+    }
+
+    let flags = parts[3].parse::<u16>()
+        .map_err(|_| DnsError::Dnssec(format!("Invalid flags: {}", parts[3])))?;
+    let protocol = parts[4].parse::<u8>()
+        .map_err(|_| DnsError::Dnssec(format!("Invalid protocol: {}", parts[4])))?;
+    let algorithm = parts[5].parse::<u8>()
+        .map_err(|_| DnsError::Dnssec(format!("Invalid algorithm: {}", parts[5])))?;
+    let key_data = base64::engine::general_purpose::STANDARD
+        .decode(parts[6..].join(""))
+        .map_err(|e| DnsError::Base64(e.to_string()))?;
+
+    let mut rdata = Vec::with_capacity(4 + key_data.len());
+    rdata.extend_from_slice(&flags.to_be_bytes());
+    rdata.push(protocol);
+    rdata.push(algorithm);
+    rdata.extend_from_slice(&key_data);
+    Ok(rdata)
+}
+
+/// Extract the key tag referenced by a DS record in presentation format.
+///
+/// # Arguments
+/// * `ds_record` - The DS record string, e.g. `example.com. IN DS 24550 8 2 1F21...`.
+///
+/// # Returns
+/// A `Result` containing the key tag or an error.
+pub fn ds_key_tag(ds_record: &str) -> Result<u16, DnsError> {
+    let parts: Vec<&str> = ds_record.split_whitespace().collect();
+    if parts.len() < 7 {
+        return Err(DnsError::Dnssec(format!("Invalid DS record format: {}", ds_record)));
+    }
+    parts[3].parse::<u16>()
+        .map_err(|_| DnsError::Dnssec(format!("Invalid key tag: {}", parts[3])))
+}
+
+/// Check that every configured DS record references a served DNSKEY.
+///
+/// Mismatches are logged as warnings; they usually mean the parent zone holds
+/// a DS for a key that has been rolled or was never loaded.
+///
+/// # Arguments
+/// * `ds_records` - The configured DS records.
+/// * `dnskey_records` - The configured DNSKEY records.
+///
+/// # Returns
+/// A boolean indicating whether all DS records matched a DNSKEY.
+pub fn validate_ds_key_tags(ds_records: &[String], dnskey_records: &[String]) -> bool {
+    let mut key_tags = Vec::new();
+    for dnskey in dnskey_records {
+        match dnskey_rdata(dnskey) {
+            Ok(rdata) => key_tags.push(key_tag(&rdata)),
+            Err(e) => warn!("Unable to compute key tag for DNSKEY: {}", e),
+        }
+    }
+
+    let mut all_match = true;
+    for ds in ds_records {
+        match ds_key_tag(ds) {
+            Ok(tag) if key_tags.contains(&tag) => info!("DS key tag {} matches a served DNSKEY", tag),
+            Ok(tag) => {
+                warn!("DS key tag {} does not match any served DNSKEY (served: {:?})", tag, key_tags);
+                all_match = false;
+            }
+            Err(e) => {
+                warn!("Unable to read DS key tag: {}", e);
+                all_match = false;
+            }
+        }
+    }
+
+    all_match
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The DNSKEY from RFC 4034 section 5.4, key tag 60485.
+    const DSKEY_EXAMPLE: &str = "dskey.example.com. IN DNSKEY 256 3 5 \
+        AQOeiiR0GOMYkDshWoSKz9Xz fwJr1AYtsmx3TGkJaNXVbfi/ 2pHm822aJ5iI9BMzNXxeYCmZ \
+        DRD99WYwYqUSdjMmmAphXdvx egXd/M5+X7OrzKBaMbCVdFLU Uh6DhweJBjEVv5f2wwjM9Xzc \
+        nOf+EPbtG9DMBmADjFDc2w/r ljwvFw==";
+
+    /// The DNSKEY from RFC 4034 section 2.3, key tag 2642.
+    const EXAMPLE_COM: &str = "example.com. IN DNSKEY 256 3 5 \
+        AQPSKmynfzW4kyBv015MUG2DeIQ3 Cbl+BBZH4b/0PY1kxkmvHjcZc8no kfzj31GajIQKY+5CptLr3buXA10h \
+        WqTkF7H6RfoRqXQeogmMHfpftf6z Mv1LyBUgia7za6ZEzOJBOztyvhjL 742iU/TpPSEDhm2SNKLijfUppn1U \
+        aNvv4w==";
+
+    #[test]
+    fn key_tags_match_rfc_4034_examples() {
+        assert_eq!(key_tag(&dnskey_rdata(DSKEY_EXAMPLE).unwrap()), 60485);
+        assert_eq!(key_tag(&dnskey_rdata(EXAMPLE_COM).unwrap()), 2642);
+    }
+
+    #[test]
+    fn ds_key_tags_are_checked_against_dnskeys() {
+        let dnskeys = [DSKEY_EXAMPLE.to_string()];
+        let ds = "dskey.example.com. IN DS 60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118";
+        assert!(validate_ds_key_tags(&[ds.to_string()], &dnskeys));
+        assert!(!validate_ds_key_tags(&[ds.replace("60485", "60486")], &dnskeys));
+    }
+}
@@ -0,0 +1,309 @@
+//! DNS over HTTPS (RFC 8484).
+//!
+//! This module serves `application/dns-message` queries on `/dns-query`,
+//! over HTTP/1.1 and HTTP/2, with TLS when a certificate is configured.
+//! Queries arrive either as the body of a POST or base64url-encoded in the
+//! `dns` parameter of a GET, and are answered through `generate_dns_response`.
+#![allow(dead_code)]
+
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use base64::Engine;
+use hyper::body::HttpBody;
+use hyper::header::{ALLOW, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, info, warn};
+use tokio::net::TcpListener;
+use tokio::task;
+use tokio_rustls::{rustls, TlsAcceptor};
+
+use crate::config::{ServerConfig, SharedConfig};
+use crate::dns::{negative_ttl, nodata_ttl, Transport};
+use crate::errors::DnsError;
+use crate::handlers::answer_query_from;
+use crate::metrics::record_query;
+use crate::utils::skip_name;
+
+/// Path DoH queries are served on.
+pub const DOH_PATH: &str = "/dns-query";
+
+/// Media type of DNS wire-format messages.
+pub const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Largest query accepted, the same as the DNS-over-TCP limit.
+const MAX_DOH_QUERY_SIZE: usize = 65535;
+
+/// How long a client may take to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run the DoH server on `config.doh_bind`.
+///
+/// # Arguments
+/// * `shared` - The live server configuration; the listen address and TLS
+///   certificate are fixed at startup.
+///
+/// # Returns
+/// A `Result` that is only returned if the listener fails.
+pub async fn run_doh_server(shared: SharedConfig) -> Result<(), DnsError> {
+    let config = shared.current();
+    let Some(addr) = config.doh_bind else {
+        return Ok(());
+    };
+    let tls = match (&config.doh_tls_cert, &config.doh_tls_key) {
+        (Some(cert), Some(key)) => Some(load_tls_acceptor(cert, key)?),
+        _ => None,
+    };
+
+    let listener = TcpListener::bind(addr).await?;
+    info!(
+        "DoH server listening on {}://{}{}",
+        if tls.is_some() { "https" } else { "http" },
+        addr,
+        DOH_PATH,
+    );
+
+    loop {
+        let (stream, src) = listener.accept().await?;
+        let tls = tls.clone();
+        let shared = shared.clone();
+        task::spawn(async move {
+            let service = service_fn(move |req| {
+                let config = shared.current();
+                async move { Ok::<_, Infallible>(handle_doh_request(req, src, &config).await) }
+            });
+            let served = match tls {
+                Some(acceptor) => match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => Http::new().serve_connection(stream, service).await,
+                    Ok(Err(e)) => {
+                        debug!("DoH TLS handshake with {} failed: {}", src, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("DoH TLS handshake with {} timed out", src);
+                        return;
+                    }
+                },
+                None => Http::new().serve_connection(stream, service).await,
+            };
+            if let Err(e) = served {
+                debug!("DoH connection from {} failed: {}", src, e);
+            }
+        });
+    }
+}
+
+/// Build a TLS acceptor from PEM certificate and key files.
+///
+/// # Arguments
+/// * `cert_path` - Path to the PEM certificate chain.
+/// * `key_path` - Path to the PEM private key (PKCS#8, PKCS#1 or SEC1).
+///
+/// # Returns
+/// A `Result` containing the acceptor, offering HTTP/2 and HTTP/1.1 via ALPN.
+fn load_tls_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, DnsError> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(DnsError::Config(format!("No certificates found in {}", cert_path)));
+    }
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| DnsError::Config(format!("No private key found in {}", key_path)))?;
+
+    let mut tls = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| DnsError::Config(format!("Invalid DoH certificate or key: {}", e)))?;
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(tls)))
+}
+
+/// Handle one DoH request.
+///
+/// # Arguments
+/// * `req` - The HTTP request.
+/// * `src` - The client address.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// The HTTP response: the DNS answer, or an error status.
+pub async fn handle_doh_request(req: Request<Body>, src: SocketAddr, config: &ServerConfig) -> Response<Body> {
+    if req.uri().path() != DOH_PATH {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+
+    let query = match *req.method() {
+        Method::GET => {
+            let param = req.uri().query()
+                .and_then(|params| params.split('&').find_map(|param| param.strip_prefix("dns=")));
+            match param.map(|param| base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(param.trim_end_matches('='))) {
+                Some(Ok(query)) => query,
+                _ => return status_response(StatusCode::BAD_REQUEST),
+            }
+        }
+        Method::POST => {
+            let content_type = req.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+            if !content_type.eq_ignore_ascii_case(DNS_MESSAGE) {
+                return status_response(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+            match read_body(req.into_body()).await {
+                Some(query) => query,
+                None => return status_response(StatusCode::PAYLOAD_TOO_LARGE),
+            }
+        }
+        _ => {
+            let mut response = status_response(StatusCode::METHOD_NOT_ALLOWED);
+            response.headers_mut().insert(ALLOW, "GET, POST".parse().unwrap());
+            return response;
+        }
+    };
+
+    match answer_doh_query(&query, src, config).await {
+        Ok(answer) => {
+            let max_age = cache_max_age(&answer);
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, DNS_MESSAGE)
+                .header(CONTENT_LENGTH, answer.len())
+                .header(CACHE_CONTROL, format!("max-age={}", max_age))
+                .body(Body::from(answer))
+                .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        Err(e) => {
+            debug!("Rejected DoH query from {}: {}", src, e);
+            status_response(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+/// Read a request body, giving up once it exceeds the largest DNS message.
+///
+/// # Arguments
+/// * `body` - The request body.
+///
+/// # Returns
+/// An `Option` containing the body, or `None` if it is too large or the read fails.
+async fn read_body(mut body: Body) -> Option<Vec<u8>> {
+    let mut query = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.ok()?;
+        if query.len() + chunk.len() > MAX_DOH_QUERY_SIZE {
+            return None;
+        }
+        query.extend_from_slice(&chunk);
+    }
+    Some(query)
+}
+
+/// Answer a DNS query received over DoH.
+///
+/// Applies the same checks as the UDP and TCP handlers; responses are never
+/// truncated.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `src` - The client address.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response, or an error if the query cannot be parsed.
+async fn answer_doh_query(query: &[u8], src: SocketAddr, config: &ServerConfig) -> Result<Vec<u8>, DnsError> {
+    if query.len() < 12 {
+        return Err(DnsError::Protocol("Query shorter than a DNS header".into()));
+    }
+    record_query(query, Transport::Https);
+
+    answer_query_from(query, src, Transport::Https, config).await?
+        .ok_or_else(|| DnsError::Protocol("Query ignored".into()))
+}
+
+/// Work out how long HTTP caches may keep a response (RFC 8484 section 5.1).
+///
+/// # Arguments
+/// * `response` - The DNS response.
+///
+/// # Returns
+/// The smallest TTL in the answer section, or the negative TTL of an
+/// answer without records; 0 if neither can be found.
+fn cache_max_age(response: &[u8]) -> u64 {
+    if let Some(ttl) = negative_ttl(response).or_else(|| nodata_ttl(response)) {
+        return ttl;
+    }
+    if response.len() < 12 {
+        return 0;
+    }
+
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = match skip_name(response, pos) {
+            Some(p) => p + 4,
+            None => return 0,
+        };
+    }
+
+    let mut min_ttl = None;
+    for _ in 0..ancount {
+        pos = match skip_name(response, pos) {
+            Some(p) => p,
+            None => break,
+        };
+        if pos + 10 > response.len() {
+            break;
+        }
+        let ttl = u32::from_be_bytes([response[pos + 4], response[pos + 5], response[pos + 6], response[pos + 7]]) as u64;
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        min_ttl = Some(min_ttl.map_or(ttl, |min: u64| min.min(ttl)));
+        pos += 10 + rdlength;
+    }
+
+    min_ttl.unwrap_or(0)
+}
+
+/// Build an empty response with a status code.
+///
+/// # Arguments
+/// * `status` - The HTTP status.
+///
+/// # Returns
+/// The response.
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Start the DoH server if `config.doh_bind` is set.
+///
+/// # Arguments
+/// * `shared` - The live server configuration.
+///
+/// # Returns
+/// An `Option` containing the DoH task, or `None` if DoH is disabled.
+pub fn spawn_doh_server(shared: &SharedConfig) -> Option<task::JoinHandle<()>> {
+    shared.current().doh_bind?;
+    let shared = shared.clone();
+    Some(task::spawn(async move {
+        if let Err(e) = run_doh_server(shared).await {
+            warn!("DoH server stopped: {}", e);
+        }
+    }))
+}
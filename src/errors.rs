@@ -2,7 +2,6 @@
 //!
 //! This module defines the error types used throughout the DNS server implementation.
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
 use thiserror::Error;
 
@@ -16,6 +15,10 @@ pub enum DnsError {
     /// Database errors from rusqlite.
     #[error("Database error: {0}")]
     Db(#[from] rusqlite::Error),
+
+    /// Errors obtaining a pooled database connection.
+    #[error("Database pool error: {0}")]
+    Pool(#[from] r2d2::Error),
     
     /// Errors related to DNS protocol parsing or formatting.
     #[error("Invalid DNS packet: {0}")]
@@ -32,8 +35,86 @@ pub enum DnsError {
     /// Base64 decoding errors.
     #[error("Base64 error: {0}")]
     Base64(String),
+
+    /// Hex decoding errors, e.g. a malformed DS digest or TLSA association data.
+    #[error("Hex decode error: {0}")]
+    Hex(String),
+
+    /// Malformed DNSSEC data or a failure to sign with it.
+    #[error("DNSSEC error: {0}")]
+    Dnssec(String),
     
+    /// The in-memory cache could not be used.
+    #[error("Cache unavailable: {0}")]
+    Cache(String),
+
+    /// A record was rejected by validation before being stored.
+    #[error("Invalid record: {0}")]
+    Validation(#[from] RecordValidationError),
+
+    /// An operation, such as a forwarded query, did not finish in time.
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    /// The server is not authoritative for the name and will not answer for it.
+    #[error("Not authoritative for {0}")]
+    NotAuthoritative(String),
+
     /// Shutdown signal received.
     #[error("Shutdown signal received")]
     Shutdown,
+}
+
+impl From<hex::FromHexError> for DnsError {
+    fn from(e: hex::FromHexError) -> Self {
+        DnsError::Hex(e.to_string())
+    }
+}
+
+/// Reasons a record is rejected before it is written to the database.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RecordValidationError {
+    /// The record type is not one the database stores.
+    #[error("unsupported record type {0}")]
+    InvalidType(String),
+
+    /// The owner name, TTL or a field of the record value is malformed.
+    #[error("invalid {field}: {reason}")]
+    InvalidValue {
+        /// The field that failed validation, e.g. `preference` for an MX record.
+        field: &'static str,
+        /// Why the field was rejected.
+        reason: String,
+    },
+
+    /// An identical record already exists.
+    #[error("{domain} {record_type} {value} already exists")]
+    DuplicateConflict {
+        /// Owner name of the existing record.
+        domain: String,
+        /// Type of the existing record.
+        record_type: String,
+        /// Value of the existing record.
+        value: String,
+    },
+
+    /// A CNAME would share its owner name with other records (RFC 1034).
+    #[error("a CNAME at {domain} cannot coexist with other records")]
+    CnameConflict {
+        /// The owner name with the conflicting records.
+        domain: String,
+    },
+}
+
+impl RecordValidationError {
+    /// The HTTP status the admin API should answer with for this error.
+    ///
+    /// # Returns
+    /// 400 for malformed records, 409 for conflicts with stored records.
+    pub fn http_status(&self) -> u16 {
+        match self {
+            Self::InvalidType(_) | Self::InvalidValue { .. } => 400,
+            Self::DuplicateConflict { .. } | Self::CnameConflict { .. } => 409,
+        }
+    }
 }
\ No newline at end of file
@@ -2,44 +2,327 @@
 //!
 //! This module provides functions for handling DNS requests over UDP and TCP.
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
-use std::net::SocketAddr;
+use std::net::{Ipv6Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use log::{debug, error, info, warn};
+use socket2::{Domain, Socket, Type};
 use tokio::{
     io::AsyncReadExt,
     net::{TcpListener, TcpStream, UdpSocket},
-    task,
+    sync::Semaphore,
+    task::{self, JoinSet},
+    time::timeout,
 };
 
 use crate::errors::DnsError;
-use crate::config::ServerConfig;
-use crate::utils::extract_domain;
+use crate::config::{RrlAction, ServerConfig, SharedConfig};
+use crate::utils::{count_opt_records, edns_options_valid, expand_question, extract_domain, extract_query_class, extract_query_type, ip_in_subnet, random_below};
+use crate::db::{find_closest_parent_zone, get_zones};
+use crate::metrics::{record_query, record_response};
+use crate::rrl;
 use crate::dns::{
-    build_not_implemented_response, build_nxdomain_response, generate_dns_response,
-    send_tcp_response,
+    build_formerr_response, build_not_implemented_response, build_nxdomain_response, build_refused_response,
+    generate_dns_response, build_servfail_response, send_tcp_response, transfer_zone, validate_response,
+    QueryContext, Transport, EDE_NOT_AUTHORITATIVE,
 };
 
-/// Run the UDP DNS server.
+/// Sleep for a random delay bounded by `config.response_jitter_ms`.
+///
+/// Smooths bursts of clients that all re-query when a popular record expires.
+///
+/// # Arguments
+/// * `config` - The server configuration.
+async fn apply_response_jitter(config: &ServerConfig) {
+    if config.response_jitter_ms > 0 {
+        let delay = random_below(config.response_jitter_ms + 1);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+}
+
+/// Validate a response before sending it when `config.validate_responses` is set.
+///
+/// A response that fails validation is logged and replaced with SERVFAIL.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `response` - The response about to be sent.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response to send.
+pub fn checked_response(query: &[u8], response: Vec<u8>, config: &ServerConfig) -> Result<Vec<u8>, DnsError> {
+    if !config.validate_responses {
+        return Ok(response);
+    }
+
+    match validate_response(query, &response) {
+        Ok(()) => Ok(response),
+        Err(e) => {
+            error!("Refusing to send invalid response: {}", e);
+            build_servfail_response(query, config, config.authoritative)
+                .ok_or(DnsError::Protocol("SERVFAIL".into()))
+        }
+    }
+}
+
+/// Build the response for a query that could not be resolved.
+///
+/// Transient failures, such as an unavailable database or forwarders that
+/// did not answer, get SERVFAIL so clients retry rather than cache a
+/// negative answer, as do stored records that cannot be encoded. NXDOMAIN
+/// is kept for names inside our zones that we answer for ourselves. For
+/// other names the server has no authority to deny their existence, and
+/// answers REFUSED when it does not forward or resolution reported
+/// `DnsError::NotAuthoritative`.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `error` - Why resolution failed.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response to send.
+pub fn unresolved_response(query: &[u8], domain: &str, error: &DnsError, config: &ServerConfig) -> Result<Vec<u8>, DnsError> {
+    let in_zone = find_closest_parent_zone(domain, &get_zones(config)).is_some();
+    // Names in our zones are forwarded too unless we are authoritative for them
+    let forwarded = config.forwarding_enabled() && !(in_zone && config.authoritative);
+
+    let refused = || {
+        let ede = config.refused_ede
+            .then_some((EDE_NOT_AUTHORITATIVE, "not authoritative for this name"));
+        build_refused_response(query, config, ede)
+            .ok_or(DnsError::Protocol("REFUSED".into()))
+    };
+
+    match error {
+        DnsError::Db(_) | DnsError::Pool(_) | DnsError::Io(_) | DnsError::Cache(_) | DnsError::Config(_)
+        | DnsError::Hex(_) | DnsError::Dnssec(_) => {
+            warn!("Answering SERVFAIL for {}: {}", domain, error);
+        }
+        DnsError::Timeout(_) => debug!("Answering SERVFAIL for {}: {}", domain, error),
+        DnsError::NotAuthoritative(_) => return refused(),
+        _ if forwarded => debug!("Answering SERVFAIL for {}: {}", domain, error),
+        _ if in_zone => {
+            return build_nxdomain_response(query, config.authoritative, config)
+                .ok_or(DnsError::Protocol("NXDOMAIN".into()));
+        }
+        _ => return refused(),
+    }
+    build_servfail_response(query, config, false)
+        .ok_or(DnsError::Protocol("SERVFAIL".into()))
+}
+
+/// Serve an AXFR request, refusing clients outside `config.axfr_allow` and
+/// names that are not the apex of one of our zones.
 ///
 /// # Arguments
+/// * `stream` - The TCP stream.
+/// * `query` - The AXFR query.
+/// * `domain` - The zone name from the query.
+/// * `addr` - The client address.
 /// * `config` - The server configuration.
 ///
 /// # Returns
 /// A `Result` indicating success or failure.
-pub async fn run_udp_server(config: ServerConfig) -> Result<(), DnsError> {
-    let socket = UdpSocket::bind(config.bind_addr).await?;
-    info!("UDP DNS server listening on {}", config.bind_addr);
-    let socket = Arc::new(socket);
-    let mut buf = vec![0u8; config.max_packet_size];
+async fn handle_axfr(
+    stream: &mut TcpStream,
+    query: &[u8],
+    domain: &str,
+    addr: SocketAddr,
+    config: &ServerConfig,
+) -> Result<(), DnsError> {
+    let allowed = config.axfr_allow.iter()
+        .any(|(network, prefix_len)| ip_in_subnet(&addr.ip(), network, *prefix_len));
+    let zone = get_zones(config).into_iter()
+        .find(|zone| zone.name.eq_ignore_ascii_case(domain.trim_end_matches('.')));
+
+    match zone {
+        Some(zone) if allowed => {
+            info!("AXFR of {} to {}", zone.name, addr);
+            transfer_zone(stream, query, &zone, config).await
+        }
+        _ => {
+            warn!("Refused AXFR of {} to {}", domain, addr);
+            if let Some(response) = build_refused_response(query, config, None) {
+                send_tcp_response(stream, &response).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Log a query at warn level if its handling exceeded `config.slow_query_ms`.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `domain` - The domain name from the query.
+/// * `ctx` - The query context holding the phase timings.
+/// * `started` - When handling of the query began.
+/// * `config` - The server configuration.
+pub fn log_slow_query(
+    query: &[u8],
+    domain: &str,
+    ctx: &QueryContext,
+    started: Instant,
+    config: &ServerConfig,
+) {
+    if config.slow_query_ms == 0 {
+        return;
+    }
+
+    let elapsed = started.elapsed();
+    if elapsed.as_millis() as u64 > config.slow_query_ms {
+        warn!(
+            "Slow query: {} type {} from {} took {}ms (cache {}ms, db {}ms, forward {}ms)",
+            domain,
+            extract_query_type(query).unwrap_or(0),
+            ctx.src,
+            elapsed.as_millis(),
+            ctx.timings.cache.as_millis(),
+            ctx.timings.db.as_millis(),
+            ctx.timings.forward.as_millis(),
+        );
+    }
+}
+
+/// Answer a DNS query without a network listener.
+///
+/// This is the entry point for embedding the server or testing its
+/// responses: the query is handled as if it came from `127.0.0.1` over TCP,
+/// so the answer is never truncated. Use [`answer_query_from`] to choose the
+/// client address and transport.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response, or an error if the query is too
+/// malformed to answer or would be ignored.
+pub async fn answer_query(query: &[u8], config: &ServerConfig) -> Result<Vec<u8>, DnsError> {
+    let src = SocketAddr::from(([127, 0, 0, 1], 0));
+    answer_query_from(query, src, Transport::Tcp, config).await?
+        .ok_or_else(|| DnsError::Protocol("Query ignored".into()))
+}
+
+/// Answer a DNS query from a given client over a given transport.
+///
+/// Runs the checks shared by every listener (OPCODE, OPT records, the QU
+/// bit) before resolving the query, falling back to an error response when
+/// it cannot be resolved. Zone transfers are refused, as they need a
+/// streaming TCP connection; rate limiting and sending are left to the caller.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `src` - The client address.
+/// * `transport` - The transport the query arrived on.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing the response, `None` if the query should be
+/// ignored, or an error if it is too malformed to answer.
+pub async fn answer_query_from(
+    query: &[u8],
+    src: SocketAddr,
+    transport: Transport,
+    config: &ServerConfig,
+) -> Result<Option<Vec<u8>>, DnsError> {
+    let started = Instant::now();
+    if query.len() < 12 {
+        return Err(DnsError::Protocol("Query shorter than a DNS header".into()));
+    }
+
+    // Answer a compressed QNAME as if written in full, since every builder
+    // copies the question into the response verbatim
+    let expanded = expand_question(query);
+    let query = expanded.as_deref().unwrap_or(query);
+
+    let opcode = (query[2] & 0x78) >> 3;
+    if opcode != 0 {
+        return Ok(build_not_implemented_response(query, config.authoritative));
+    }
+
+    // More than one OPT record, or malformed options, is a format error (RFC 6891)
+    if count_opt_records(query) > 1 || (config.strict_edns_options && !edns_options_valid(query)) {
+        debug!("{:?} query from {} has a malformed OPT record", transport, src);
+        return Ok(build_formerr_response(query));
+    }
+
+    if let Some((_, true)) = extract_query_class(query) {
+        if !config.answer_qu_queries {
+            debug!("Ignoring {:?} query with QU bit set from {}", transport, src);
+            return Ok(None);
+        }
+    }
+
+    let domain = extract_domain(query)
+        .ok_or_else(|| DnsError::Protocol("Failed to extract domain from query".into()))?;
+    debug!("{:?} query for {} from {}", transport, domain, src);
+    info!("Processing query for domain: {}", domain);
+
+    // Zone transfers are only served over TCP (RFC 5936)
+    if extract_query_type(query) == Some(252) {
+        return Ok(build_refused_response(query, config, None));
+    }
+
+    let mut ctx = QueryContext::new(src, transport);
+    let response = match generate_dns_response(query, domain.clone(), config, &mut ctx).await {
+        Ok(resp) => resp,
+        Err(e) => unresolved_response(query, &domain, &e, config)?,
+    };
+    log_slow_query(query, &domain, &ctx, started, config);
+    let response = checked_response(query, response, config)?;
+    record_response(&response, started);
+    Ok(Some(response))
+}
+
+/// Run the UDP DNS server.
+///
+/// With `config.enable_ipv6` an IPv6 socket is bound next to an IPv4
+/// `udp_bind` (see [`ipv6_listen_addr`]), and both are served.
+///
+/// # Arguments
+/// * `shared` - The live server configuration; the listen address and
+///   receive buffer size are fixed at startup.
+///
+/// # Returns
+/// A `Result` indicating success or failure.
+pub async fn run_udp_server(shared: SharedConfig) -> Result<(), DnsError> {
+    let config = shared.current();
+    let mut sockets = vec![UdpSocket::bind(config.udp_bind).await?];
+    if let Some(addr) = ipv6_listen_addr(config.udp_bind, &config) {
+        sockets.push(UdpSocket::from_std(ipv6_only_socket(addr, Type::DGRAM)?.into())?);
+    }
+
+    let mut listeners = JoinSet::new();
+    for socket in sockets {
+        info!("UDP DNS server listening on {}", socket.local_addr()?);
+        listeners.spawn(serve_udp_socket(Arc::new(socket), shared.clone(), config.max_recv_size));
+    }
+    first_listener_exit(listeners, "UDP").await
+}
+
+/// Receive queries on one UDP socket and answer each in its own task.
+///
+/// # Arguments
+/// * `socket` - The bound socket.
+/// * `shared` - The live server configuration.
+/// * `max_recv_size` - Size of the receive buffer.
+///
+/// # Returns
+/// Never returns; receive errors are logged.
+async fn serve_udp_socket(socket: Arc<UdpSocket>, shared: SharedConfig, max_recv_size: usize) -> Result<(), DnsError> {
+    let mut buf = vec![0u8; max_recv_size];
 
     loop {
         match socket.recv_from(&mut buf).await {
             Ok((amt, src)) => {
                 let query = buf[..amt].to_vec();
                 let socket = socket.clone();
-                let config = config.clone();
+                let config = shared.current();
                 task::spawn(async move {
                     if let Err(e) = handle_udp_query(query, src, socket, config).await {
                         warn!("UDP query error: {}", e);
@@ -51,6 +334,77 @@ pub async fn run_udp_server(config: ServerConfig) -> Result<(), DnsError> {
     }
 }
 
+/// Work out the IPv6 address to listen on alongside an IPv4 bind address.
+///
+/// The wildcard address pairs with `[::]` and loopback with `[::1]`, on the
+/// same port. Other IPv4 addresses have no obvious IPv6 counterpart, so
+/// nothing extra is bound for them; bind an IPv6 address explicitly instead.
+///
+/// # Arguments
+/// * `addr` - The configured bind address.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the IPv6 address, or `None` if `config.enable_ipv6`
+/// is off or there is no counterpart.
+pub fn ipv6_listen_addr(addr: SocketAddr, config: &ServerConfig) -> Option<SocketAddr> {
+    let SocketAddr::V4(v4) = addr else {
+        return None;
+    };
+    if !config.enable_ipv6 {
+        return None;
+    }
+    let ip = if v4.ip().is_unspecified() {
+        Ipv6Addr::UNSPECIFIED
+    } else if v4.ip().is_loopback() {
+        Ipv6Addr::LOCALHOST
+    } else {
+        warn!("DNS_ENABLE_IPV6 has no IPv6 counterpart for {}; bind an IPv6 address explicitly", addr);
+        return None;
+    };
+    Some(SocketAddr::new(ip.into(), addr.port()))
+}
+
+/// Bind an IPv6 socket that does not also accept IPv4, so it can share its
+/// port with an IPv4 socket whatever the system's dual-stack default.
+///
+/// # Arguments
+/// * `addr` - The IPv6 address to bind.
+/// * `kind` - `Type::DGRAM` for UDP or `Type::STREAM` for TCP.
+///
+/// # Returns
+/// A `Result` containing the bound, non-blocking socket; TCP sockets are
+/// already listening.
+fn ipv6_only_socket(addr: SocketAddr, kind: Type) -> Result<Socket, DnsError> {
+    let socket = Socket::new(Domain::IPV6, kind, None)?;
+    socket.set_only_v6(true)?;
+    socket.set_nonblocking(true)?;
+    if kind == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(&addr.into())?;
+    if kind == Type::STREAM {
+        socket.listen(1024)?;
+    }
+    Ok(socket)
+}
+
+/// Wait for the first of a server's listeners to stop.
+///
+/// # Arguments
+/// * `listeners` - The listener tasks.
+/// * `transport` - The transport name, for the error message.
+///
+/// # Returns
+/// The result of the listener that stopped first.
+async fn first_listener_exit(mut listeners: JoinSet<Result<(), DnsError>>, transport: &str) -> Result<(), DnsError> {
+    match listeners.join_next().await {
+        Some(Ok(result)) => result,
+        Some(Err(e)) => Err(DnsError::Protocol(format!("{} listener failed: {}", transport, e))),
+        None => Ok(()),
+    }
+}
+
 /// Handle a UDP DNS query.
 ///
 /// # Arguments
@@ -65,63 +419,88 @@ pub async fn handle_udp_query(
     query: Vec<u8>,
     src: SocketAddr,
     socket: Arc<UdpSocket>,
-    config: ServerConfig,
+    config: Arc<ServerConfig>,
 ) -> Result<(), DnsError> {
     if query.len() < 12 {
         debug!("Received malformed query from {}", src);
         return Ok(());
     }
+    record_query(&query, Transport::Udp);
 
-    let opcode = (query[2] & 0x78) >> 3;
-    if opcode != 0 {
-        if let Some(response) = build_not_implemented_response(&query, config.authoritative) {
-            socket.send_to(&response, src).await?;
+    // UDP sources can be spoofed, so limit how much we send to each
+    if !rrl::allow(src.ip(), &extract_domain(&query).unwrap_or_default(), &config) {
+        debug!("Rate limiting UDP query from {}", src);
+        if config.rrl_action == RrlAction::Truncate {
+            socket.send_to(&rrl::slip_response(&query, &config), src).await?;
         }
         return Ok(());
     }
 
-    let domain = match extract_domain(&query) {
-        Some(d) => d,
-        None => {
-            info!("Failed to extract domain from query");
-            return Ok(());
-        }
-    };
-
-    debug!("UDP query for {} from {}", domain, src);
-    info!("Processing query for domain: {}", domain);
-
-    let response = match generate_dns_response(&query, domain.clone(), &config).await {
-        Ok(resp) => resp,
-        Err(_) => {
-            build_nxdomain_response(&query, config.authoritative)
-                .ok_or(DnsError::Protocol("NXDOMAIN".into()))?
-        }
+    let Some(response) = answer_query_from(&query, src, Transport::Udp, &config).await? else {
+        return Ok(());
     };
 
+    apply_response_jitter(&config).await;
     socket.send_to(&response, src).await?;
     Ok(())
 }
 
 /// Run the TCP DNS server.
 ///
+/// With `config.enable_ipv6` an IPv6 listener is bound next to an IPv4
+/// `tcp_bind` (see [`ipv6_listen_addr`]), and both are served. The
+/// connection limit applies across all listeners.
+///
 /// # Arguments
-/// * `config` - The server configuration.
+/// * `shared` - The live server configuration; the listen address and
+///   connection limit are fixed at startup.
 ///
 /// # Returns
 /// A `Result` indicating success or failure.
-pub async fn run_tcp_server(config: ServerConfig) -> Result<(), DnsError> {
-    let listener = TcpListener::bind(config.bind_addr).await?;
-    info!("TCP DNS server listening on {}", config.bind_addr);
+pub async fn run_tcp_server(shared: SharedConfig) -> Result<(), DnsError> {
+    let config = shared.current();
+    let mut sockets = vec![TcpListener::bind(config.tcp_bind).await?];
+    if let Some(addr) = ipv6_listen_addr(config.tcp_bind, &config) {
+        sockets.push(TcpListener::from_std(ipv6_only_socket(addr, Type::STREAM)?.into())?);
+    }
+    let connections = Arc::new(Semaphore::new(config.max_tcp_connections));
+
+    let mut listeners = JoinSet::new();
+    for listener in sockets {
+        info!("TCP DNS server listening on {}", listener.local_addr()?);
+        listeners.spawn(serve_tcp_listener(listener, shared.clone(), connections.clone()));
+    }
+    first_listener_exit(listeners, "TCP").await
+}
 
+/// Accept connections on one TCP listener and serve each in its own task.
+///
+/// # Arguments
+/// * `listener` - The bound listener.
+/// * `shared` - The live server configuration.
+/// * `connections` - Permits for the open connection limit.
+///
+/// # Returns
+/// Never returns; accept errors are logged.
+async fn serve_tcp_listener(listener: TcpListener, shared: SharedConfig, connections: Arc<Semaphore>) -> Result<(), DnsError> {
     loop {
         match listener.accept().await {
             Ok((stream, addr)) => {
-                let config = config.clone();
+                // Close connections beyond the limit straight away rather than queueing them
+                let permit = match connections.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        warn!("Closing TCP connection from {}: connection limit reached", addr);
+                        drop(stream);
+                        continue;
+                    }
+                };
+                let config = shared.current();
                 task::spawn(async move {
                     if let Err(e) = handle_tcp_connection(stream, addr, config).await {
                         warn!("TCP connection error: {}", e);
                     }
+                    drop(permit);
                 });
             }
             Err(e) => error!("TCP accept error: {}", e),
@@ -129,8 +508,27 @@ pub async fn run_tcp_server(config: ServerConfig) -> Result<(), DnsError> {
     }
 }
 
+/// Fill `buf` from a TCP client within `config.tcp_read_timeout`.
+///
+/// # Arguments
+/// * `stream` - The TCP stream.
+/// * `buf` - The buffer to fill.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A `Result` containing `false` if the client did not send enough data in time.
+async fn read_tcp_message(stream: &mut TcpStream, buf: &mut [u8], config: &ServerConfig) -> Result<bool, DnsError> {
+    match timeout(config.tcp_read_timeout, stream.read_exact(buf)).await {
+        Ok(read) => read.map(|_| true).map_err(DnsError::from),
+        Err(_) => Ok(false),
+    }
+}
+
 /// Handle a TCP DNS connection.
 ///
+/// Clients that stall before sending a complete query are disconnected
+/// after `config.tcp_read_timeout`.
+///
 /// # Arguments
 /// * `stream` - The TCP stream.
 /// * `addr` - The client address.
@@ -141,50 +539,42 @@ pub async fn run_tcp_server(config: ServerConfig) -> Result<(), DnsError> {
 pub async fn handle_tcp_connection(
     mut stream: TcpStream,
     addr: SocketAddr,
-    config: ServerConfig,
+    config: Arc<ServerConfig>,
 ) -> Result<(), DnsError> {
     // Read the 2-byte length prefix
     let mut len_buf = [0u8; 2];
-    stream.read_exact(&mut len_buf).await?;
+    if !read_tcp_message(&mut stream, &mut len_buf, &config).await? {
+        debug!("Closing TCP connection from {}: no query within {:?}", addr, config.tcp_read_timeout);
+        return Ok(());
+    }
     let len = u16::from_be_bytes(len_buf) as usize;
 
     // Read the DNS query
     let mut query = vec![0u8; len];
-    stream.read_exact(&mut query).await?;
+    if !read_tcp_message(&mut stream, &mut query, &config).await? {
+        debug!("Closing TCP connection from {}: query not received within {:?}", addr, config.tcp_read_timeout);
+        return Ok(());
+    }
 
     if query.len() < 12 {
         debug!("Received malformed TCP query from {}", addr);
         return Ok(());
     }
+    record_query(&query, Transport::Tcp);
 
-    let opcode = (query[2] & 0x78) >> 3;
-    if opcode != 0 {
-        if let Some(response) = build_not_implemented_response(&query, config.authoritative) {
-            send_tcp_response(&mut stream, &response).await?;
+    // Zone transfers stream their answer as several messages
+    if extract_query_type(&query) == Some(252) {
+        if let Some(domain) = extract_domain(&query) {
+            return handle_axfr(&mut stream, &query, &domain, addr, &config).await;
         }
-        return Ok(());
     }
 
-    let domain = match extract_domain(&query) {
-        Some(d) => d,
-        None => {
-            info!("Failed to extract domain from TCP query");
-            return Ok(());
-        }
-    };
-
-    debug!("TCP query for {} from {}", domain, addr);
-    info!("Processing TCP query for domain: {}", domain);
-
-    let response = match generate_dns_response(&query, domain.clone(), &config).await {
-        Ok(resp) => resp,
-        Err(_) => {
-            build_nxdomain_response(&query, config.authoritative)
-                .ok_or(DnsError::Protocol("NXDOMAIN".into()))?
-        }
+    let Some(response) = answer_query_from(&query, addr, Transport::Tcp, &config).await? else {
+        return Ok(());
     };
 
     // Send the response (local/cache answer)
+    apply_response_jitter(&config).await;
     send_tcp_response(&mut stream, &response).await?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,147 @@
+//! Backend health checks.
+//!
+//! This module probes the backends named in `ServerConfig::health_checks`
+//! and tracks which addresses are unhealthy, so their A/AAAA records can be
+//! left out of answers until they recover.
+#![allow(dead_code)]
+
+use std::collections::HashSet;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+use log::{debug, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::config::{HealthCheck, ServerConfig};
+
+/// Upper bound on a single probe.
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Addresses whose last probe failed.
+static UNHEALTHY: OnceLock<RwLock<HashSet<IpAddr>>> = OnceLock::new();
+
+/// Get the set of unhealthy addresses.
+fn unhealthy() -> &'static RwLock<HashSet<IpAddr>> {
+    UNHEALTHY.get_or_init(|| RwLock::new(HashSet::new()))
+}
+
+/// Check whether an address may be served.
+///
+/// Addresses without a health check are always healthy.
+///
+/// # Arguments
+/// * `addr` - The address to check.
+///
+/// # Returns
+/// `false` only if the address's last probe failed.
+pub fn is_healthy(addr: IpAddr) -> bool {
+    unhealthy().read().map(|set| !set.contains(&addr)).unwrap_or(true)
+}
+
+/// Record the outcome of a probe.
+///
+/// # Arguments
+/// * `addr` - The probed address.
+/// * `healthy` - Whether the probe succeeded.
+pub fn set_health(addr: IpAddr, healthy: bool) {
+    let Ok(mut set) = unhealthy().write() else {
+        return;
+    };
+    let changed = if healthy { set.remove(&addr) } else { set.insert(addr) };
+    if changed {
+        if healthy {
+            info!("Backend {} is healthy again", addr);
+        } else {
+            warn!("Backend {} is unhealthy, withholding its records", addr);
+        }
+    }
+}
+
+/// Drop unhealthy addresses from an answer's (value, ttl) set.
+///
+/// If every address is unhealthy the set is left alone, since an answer
+/// pointing at a failing backend beats no answer at all.
+///
+/// # Arguments
+/// * `addresses` - The address records to filter in place.
+pub fn retain_healthy(addresses: &mut Vec<(String, u64)>) {
+    let healthy = |value: &str| value.parse().map(is_healthy).unwrap_or(true);
+    if addresses.iter().any(|(value, _)| healthy(value)) {
+        addresses.retain(|(value, _)| healthy(value));
+    }
+}
+
+/// Run one probe.
+///
+/// # Arguments
+/// * `check` - The probe to run.
+///
+/// # Returns
+/// A `Result` that is `Ok` if the backend passed.
+pub async fn probe(check: &HealthCheck) -> io::Result<()> {
+    tokio::time::timeout(HEALTH_CHECK_TIMEOUT, async {
+        match check {
+            HealthCheck::Tcp(addr) => TcpStream::connect(addr).await.map(drop),
+            HealthCheck::Http { addr, path } => {
+                let mut stream = TcpStream::connect(addr).await?;
+                let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr.ip());
+                stream.write_all(request.as_bytes()).await?;
+
+                // Only the status line matters: "HTTP/1.x NNN ..."
+                let mut head = [0u8; 12];
+                stream.read_exact(&mut head).await?;
+                match &head[9..10] {
+                    b"2" | b"3" => Ok(()),
+                    _ => Err(io::Error::other(format!("HTTP status {}", String::from_utf8_lossy(&head[9..12])))),
+                }
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "health check timed out")))
+}
+
+/// Probe every configured backend once, concurrently, and record the results.
+///
+/// # Arguments
+/// * `checks` - The probes to run.
+pub async fn run_checks(checks: &[HealthCheck]) {
+    let mut probes = JoinSet::new();
+    for check in checks.iter().cloned() {
+        probes.spawn(async move {
+            let result = probe(&check).await;
+            (check, result)
+        });
+    }
+    while let Some(Ok((check, result))) = probes.join_next().await {
+        if let Err(e) = &result {
+            debug!("Health check {:?} failed: {}", check, e);
+        }
+        set_health(check.target(), result.is_ok());
+    }
+}
+
+/// Start probing the configured backends in the background.
+///
+/// # Arguments
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the task handle, or `None` if no checks are configured.
+pub fn spawn_health_checks(config: &ServerConfig) -> Option<JoinHandle<()>> {
+    if config.health_checks.is_empty() {
+        return None;
+    }
+    let checks = config.health_checks.clone();
+    let period = config.health_check_interval;
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            run_checks(&checks).await;
+        }
+    }))
+}
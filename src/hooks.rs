@@ -0,0 +1,227 @@
+//! Query hooks for the DNS server.
+//!
+//! This module defines the middleware interface used to inspect, rewrite,
+//! answer or refuse queries around normal resolution, plus the built-in
+//! hooks configured from the environment.
+#![allow(dead_code)]
+
+use std::fmt::Debug;
+use std::net::{IpAddr, SocketAddr};
+use async_trait::async_trait;
+use log::debug;
+
+use crate::dns::Transport;
+use crate::errors::DnsError;
+use crate::utils::{encode_dns_name, extract_domain, extract_query_class, extract_query_type, find_opt_record, ip_in_subnet, recursion_desired, skip_name};
+
+/// The question of a query, as seen and rewritten by hooks.
+#[derive(Debug, Clone)]
+pub struct ParsedQuery {
+    /// The query packet as received.
+    pub raw: Vec<u8>,
+
+    /// The queried name.
+    pub name: String,
+
+    /// The query type.
+    pub qtype: u16,
+
+    /// The query class.
+    pub qclass: u16,
+
+    /// Whether the client set Recursion Desired.
+    pub rd: bool,
+
+    /// The client address the query came from.
+    pub src: SocketAddr,
+
+    /// The transport the query arrived on.
+    pub transport: Transport,
+}
+
+impl ParsedQuery {
+    /// Parse the question of a query.
+    ///
+    /// # Arguments
+    /// * `query` - The DNS query.
+    /// * `src` - The client address.
+    /// * `transport` - The transport the query arrived on.
+    ///
+    /// # Returns
+    /// A `Result` containing the parsed query or an error.
+    pub fn parse(query: &[u8], src: SocketAddr, transport: Transport) -> Result<Self, DnsError> {
+        let name = extract_domain(query)
+            .ok_or_else(|| DnsError::Protocol("Invalid question format".into()))?;
+        Ok(Self {
+            raw: query.to_vec(),
+            name,
+            qtype: extract_query_type(query).unwrap_or(1),
+            qclass: extract_query_class(query).map(|(class, _)| class).unwrap_or(1),
+            rd: recursion_desired(query),
+            src,
+            transport,
+        })
+    }
+
+    /// Whether a hook changed the question.
+    ///
+    /// # Returns
+    /// A boolean indicating whether the name, type or class differs from the raw query.
+    pub fn is_rewritten(&self) -> bool {
+        extract_domain(&self.raw).as_deref() != Some(self.name.as_str())
+            || extract_query_type(&self.raw) != Some(self.qtype)
+            || extract_query_class(&self.raw).map(|(class, _)| class) != Some(self.qclass)
+    }
+
+    /// Build a query packet carrying the (possibly rewritten) question.
+    ///
+    /// The header and any OPT record are copied from the raw query.
+    ///
+    /// # Returns
+    /// A `Result` containing the query packet or an error.
+    pub fn to_query(&self) -> Result<Vec<u8>, DnsError> {
+        let mut query = Vec::with_capacity(self.raw.len());
+        query.extend_from_slice(&self.raw[..4]);
+        query.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        query.extend_from_slice(&encode_dns_name(&self.name));
+        query.extend_from_slice(&self.qtype.to_be_bytes());
+        query.extend_from_slice(&self.qclass.to_be_bytes());
+
+        if let Some(start) = find_opt_record(&self.raw) {
+            let pos = skip_name(&self.raw, start)
+                .filter(|pos| pos + 10 <= self.raw.len())
+                .ok_or_else(|| DnsError::Protocol("Invalid OPT record".into()))?;
+            let rdlength = u16::from_be_bytes([self.raw[pos + 8], self.raw[pos + 9]]) as usize;
+            let end = (pos + 10 + rdlength).min(self.raw.len());
+            query.extend_from_slice(&self.raw[start..end]);
+            query[11] = 1; // ARCOUNT
+        }
+
+        Ok(query)
+    }
+}
+
+/// A response on its way back to the client, as seen by hooks.
+#[derive(Debug, Clone)]
+pub struct ResponseBuilder {
+    /// The query being answered, after any rewriting.
+    pub query: ParsedQuery,
+
+    /// The response packet.
+    pub response: Vec<u8>,
+}
+
+impl ResponseBuilder {
+    /// The response code of the response.
+    ///
+    /// # Returns
+    /// The RCODE from the header.
+    pub fn rcode(&self) -> u8 {
+        self.response.get(3).map(|b| b & 0x0F).unwrap_or(0)
+    }
+
+    /// Replace the response code of the response.
+    ///
+    /// # Arguments
+    /// * `rcode` - The new RCODE.
+    pub fn set_rcode(&mut self, rcode: u8) {
+        if let Some(b) = self.response.get_mut(3) {
+            *b = (*b & 0xF0) | (rcode & 0x0F);
+        }
+    }
+
+    /// The number of records in the answer section.
+    ///
+    /// # Returns
+    /// The ANCOUNT from the header.
+    pub fn answer_count(&self) -> u16 {
+        match self.response.get(6..8) {
+            Some(count) => u16::from_be_bytes([count[0], count[1]]),
+            None => 0,
+        }
+    }
+}
+
+/// What resolution should do after a hook has seen the query.
+#[derive(Debug, Clone)]
+pub enum HookAction {
+    /// Carry on with the (possibly rewritten) query.
+    Continue,
+
+    /// Send this response without resolving the query.
+    ShortCircuit(Vec<u8>),
+
+    /// Answer REFUSED.
+    Refuse,
+
+    /// Answer NXDOMAIN.
+    Nxdomain,
+}
+
+/// Middleware invoked around resolution of every query.
+///
+/// Hooks run in configuration order. `pre` may rewrite the question, answer
+/// the query itself or refuse it; the first hook that does not return
+/// `Continue` ends the chain. `post` sees every response produced by
+/// resolution and may modify it.
+#[async_trait]
+pub trait QueryHook: Debug + Send + Sync {
+    /// Inspect or rewrite a query before it is resolved.
+    ///
+    /// # Arguments
+    /// * `query` - The query; changes to its question are resolved instead of the original.
+    ///
+    /// # Returns
+    /// The action to take.
+    async fn pre(&self, _query: &mut ParsedQuery) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Inspect or modify a response before it is sent.
+    ///
+    /// # Arguments
+    /// * `resp` - The response.
+    async fn post(&self, _resp: &mut ResponseBuilder) {}
+}
+
+/// Refuses queries from denied client subnets.
+#[derive(Debug, Clone)]
+pub struct AclHook {
+    /// Client subnets whose queries are refused.
+    pub deny: Vec<(IpAddr, u8)>,
+}
+
+#[async_trait]
+impl QueryHook for AclHook {
+    async fn pre(&self, query: &mut ParsedQuery) -> HookAction {
+        let ip = query.src.ip();
+        if self.deny.iter().any(|(network, prefix_len)| ip_in_subnet(&ip, network, *prefix_len)) {
+            debug!("ACL refused query for {} from {}", query.name, query.src);
+            return HookAction::Refuse;
+        }
+        HookAction::Continue
+    }
+}
+
+/// Answers NXDOMAIN for blocked names and everything below them, in the
+/// manner of a response policy zone (RPZ).
+#[derive(Debug, Clone)]
+pub struct RpzHook {
+    /// Blocked names, lowercase without a trailing dot.
+    pub blocked: Vec<String>,
+}
+
+#[async_trait]
+impl QueryHook for RpzHook {
+    async fn pre(&self, query: &mut ParsedQuery) -> HookAction {
+        let name = query.name.trim_end_matches('.').to_ascii_lowercase();
+        let blocked = self.blocked.iter().any(|b| {
+            name == *b || name.strip_suffix(b.as_str()).is_some_and(|prefix| prefix.ends_with('.'))
+        });
+        if blocked {
+            debug!("RPZ blocked query for {} from {}", query.name, query.src);
+            return HookAction::Nxdomain;
+        }
+        HookAction::Continue
+    }
+}
@@ -5,19 +5,27 @@
 //! and can forward queries to upstream DNS servers.
 
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
 // Define modules
 pub mod errors;
 pub mod config;
 pub mod cache;
+pub mod cookies;
 pub mod db;
 pub mod dns;
+pub mod dnssec;
+pub mod doh;
 pub mod handlers;
+pub mod health;
+pub mod hooks;
+pub mod metrics;
+pub mod probe;
+pub mod rrl;
 pub mod utils;
-mod error;
+pub mod zonefile;
 
 // Re-export commonly used items
 pub use errors::DnsError;
 pub use config::ServerConfig;
-pub use cache::DnsCache;
\ No newline at end of file
+pub use cache::DnsCache;
+pub use handlers::answer_query;
\ No newline at end of file
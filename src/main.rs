@@ -5,17 +5,21 @@
 //!
 //! Author: Sunil Purushottam Thakare
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
-use log::info;
+use log::{error, info, warn};
 use tokio::{signal, task};
 
 use nx9_dns_server::{
     cache::{CACHE, CACHE_CLEANUP_INTERVAL},
-    config::ServerConfig,
-    db::init_db,
+    config::{ServerConfig, SharedConfig},
+    db::{export_zone, import_zone_file, init_db},
+    dnssec::validate_ds_key_tags,
+    doh::spawn_doh_server,
     errors::DnsError,
     handlers::{run_tcp_server, run_udp_server},
+    health::spawn_health_checks,
+    metrics::spawn_metrics_server,
+    probe::spawn_probe_server,
 };
 
 #[tokio::main]
@@ -27,13 +31,41 @@ async fn main() -> Result<(), DnsError> {
 
     // Load configuration from environment variables
     let config = ServerConfig::from_env()?;
+
+    // Warn early if the DS records don't match the keys we serve
+    if !config.dnskey_records.is_empty() {
+        validate_ds_key_tags(&config.ds_records, &config.dnskey_records);
+    }
     
     // Initialize cache with NS records from config
-    let cache = CACHE.get_or_init(|| nx9_dns_server::cache::DnsCache::new(config.ns_records.clone()));
+    let cache = CACHE.get_or_init(|| {
+        nx9_dns_server::cache::DnsCache::new(config.ns_records.clone()).with_max_entries(config.cache_max_entries)
+    });
 
     // Initialize the database
     init_db(&config.db_path, &config.default_domain, &config.default_ip)?;
 
+    // Run a one-off command instead of the server if one is given
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => {}
+        ["import-zone", path] => {
+            let (zone, count) = import_zone_file(&config.db_path, path, config.serial_scheme)?;
+            info!("Imported {} records into zone {} from {}", count, zone, path);
+            return Ok(());
+        }
+        ["export", zone] => {
+            print!("{}", export_zone(&config.db_path, zone)?);
+            return Ok(());
+        }
+        _ => {
+            return Err(DnsError::Config(format!(
+                "Unknown command {:?}; usage: nx9-dns-server [import-zone <file> | export <zone>]",
+                args.join(" "),
+            )));
+        }
+    }
+
     // Set up cache cleanup task
     let cache_cleanup = task::spawn({
         let cache = cache.clone();
@@ -41,11 +73,29 @@ async fn main() -> Result<(), DnsError> {
             let mut interval = tokio::time::interval(CACHE_CLEANUP_INTERVAL);
             loop {
                 interval.tick().await;
-                cache.cleanup();
+                if let Err(e) = cache.cleanup() {
+                    warn!("Cache cleanup skipped: {}", e);
+                }
             }
         }
     });
 
+    // Probe backends whose records are gated on health
+    let health_checks = spawn_health_checks(&config);
+
+    // Serve Prometheus metrics when enabled
+    let metrics_server = spawn_metrics_server(&config);
+
+    // Serve the liveness/readiness probe when enabled
+    let probe_server = spawn_probe_server(&config);
+
+    // Queries read the configuration through this, so SIGHUP can swap it
+    let shared = SharedConfig::new(config.clone());
+    let reloader = task::spawn(reload_on_hangup(shared.clone()));
+
+    // Serve DNS over HTTPS when enabled
+    let doh_server = spawn_doh_server(&shared);
+
     // Set up shutdown signal handler
     let shutdown_signal = async {
         signal::ctrl_c().await.expect("Failed to listen for shutdown signal");
@@ -53,17 +103,85 @@ async fn main() -> Result<(), DnsError> {
     };
 
     // Start UDP and TCP servers
-    let udp_server = run_udp_server(config.clone());
-    let tcp_server = run_tcp_server(config.clone());
+    let udp_server = run_udp_server(shared.clone());
+    let tcp_server = run_tcp_server(shared.clone());
 
     // Wait for either a shutdown signal or server error
     tokio::select! {
         _ = shutdown_signal => {
             info!("Initiating graceful shutdown...");
             cache_cleanup.abort();
+            reloader.abort();
+            if let Some(health_checks) = health_checks {
+                health_checks.abort();
+            }
+            if let Some(metrics_server) = metrics_server {
+                metrics_server.abort();
+            }
+            if let Some(doh_server) = doh_server {
+                doh_server.abort();
+            }
+            if let Some(probe_server) = probe_server {
+                probe_server.abort();
+            }
             Ok(())
         },
         res = udp_server => res,
         res = tcp_server => res,
     }
-}
\ No newline at end of file
+}
+
+/// Reload the configuration whenever the process receives SIGHUP.
+///
+/// The new configuration replaces the old one for queries that arrive
+/// afterwards, and the cache is re-seeded with its NS records (and cleared
+/// when `reload_flush_cache` is set). A configuration that fails to load is
+/// logged and the current one is kept. Listen addresses, the TCP connection
+/// limit and the metrics, health and DoH endpoints still need a restart.
+///
+/// # Arguments
+/// * `shared` - The live server configuration.
+#[cfg(unix)]
+async fn reload_on_hangup(shared: SharedConfig) {
+    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Configuration reload on SIGHUP disabled: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+        let config = match ServerConfig::from_env() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Configuration reload failed, keeping the current configuration: {}", e);
+                continue;
+            }
+        };
+        if !config.dnskey_records.is_empty() {
+            validate_ds_key_tags(&config.ds_records, &config.dnskey_records);
+        }
+        if let Some(cache) = CACHE.get() {
+            let reseeded = cache.reseed(config.ns_records.clone())
+                .and_then(|_| if config.reload_flush_cache { cache.clear() } else { Ok(()) });
+            if let Err(e) = reseeded {
+                warn!("Cache not updated on reload: {}", e);
+            }
+        }
+        shared.replace(config);
+        info!("Configuration reloaded");
+    }
+}
+
+/// Reload the configuration whenever the process receives SIGHUP.
+///
+/// There is no SIGHUP outside Unix, so the configuration is never reloaded.
+///
+/// # Arguments
+/// * `shared` - The live server configuration.
+#[cfg(not(unix))]
+async fn reload_on_hangup(_shared: SharedConfig) {
+    std::future::pending::<()>().await
+}
@@ -0,0 +1,221 @@
+//! Prometheus metrics.
+//!
+//! This module records query counts, cache and forwarder outcomes and
+//! response latency, and serves them in the Prometheus text format on
+//! `ServerConfig::metrics_bind`. Nothing is recorded unless the endpoint is
+//! enabled.
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use metrics::{describe_counter, describe_histogram, histogram, increment_counter};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{self, JoinHandle};
+
+use crate::config::ServerConfig;
+use crate::dns::{query_type_name, Transport};
+use crate::errors::DnsError;
+use crate::utils::extract_query_type;
+
+/// Queries received, by transport.
+pub const QUERIES_TOTAL: &str = "dns_queries_total";
+
+/// Queries received, by query type.
+pub const QUERIES_BY_TYPE_TOTAL: &str = "dns_queries_by_type_total";
+
+/// Cache lookups that produced an answer.
+pub const CACHE_HITS_TOTAL: &str = "dns_cache_hits_total";
+
+/// Cache lookups that fell through to the next source.
+pub const CACHE_MISSES_TOTAL: &str = "dns_cache_misses_total";
+
+/// Responses sent with RCODE NXDOMAIN.
+pub const NXDOMAIN_TOTAL: &str = "dns_nxdomain_total";
+
+/// Queries answered by an upstream forwarder.
+pub const FORWARD_SUCCESSES_TOTAL: &str = "dns_forward_successes_total";
+
+/// Queries no upstream forwarder answered.
+pub const FORWARD_FAILURES_TOTAL: &str = "dns_forward_failures_total";
+
+/// Time from receiving a query to having its response ready.
+pub const RESPONSE_DURATION_SECONDS: &str = "dns_response_duration_seconds";
+
+/// Histogram buckets for response latency, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// How long a scrape may take to send its request.
+const SCRAPE_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle for rendering the installed recorder.
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the Prometheus recorder.
+///
+/// Until this is called the recording functions are no-ops.
+///
+/// # Returns
+/// A `Result` indicating whether the recorder was installed.
+pub fn install() -> Result<(), DnsError> {
+    if HANDLE.get().is_some() {
+        return Ok(());
+    }
+
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(RESPONSE_DURATION_SECONDS.into()), LATENCY_BUCKETS)
+        .and_then(|builder| builder.install_recorder())
+        .map_err(|e| DnsError::Config(format!("Failed to install metrics recorder: {}", e)))?;
+
+    describe_counter!(QUERIES_TOTAL, "DNS queries received, by transport.");
+    describe_counter!(QUERIES_BY_TYPE_TOTAL, "DNS queries received, by query type.");
+    describe_counter!(CACHE_HITS_TOTAL, "Cache lookups that produced an answer.");
+    describe_counter!(CACHE_MISSES_TOTAL, "Cache lookups that fell through to the next source.");
+    describe_counter!(NXDOMAIN_TOTAL, "Responses sent with RCODE NXDOMAIN.");
+    describe_counter!(FORWARD_SUCCESSES_TOTAL, "Queries answered by an upstream forwarder.");
+    describe_counter!(FORWARD_FAILURES_TOTAL, "Queries no upstream forwarder answered.");
+    describe_histogram!(RESPONSE_DURATION_SECONDS, "Time taken to answer a query, in seconds.");
+
+    let _ = HANDLE.set(handle);
+    Ok(())
+}
+
+/// Render the current metrics in the Prometheus text format.
+///
+/// # Returns
+/// An `Option` containing the exposition, or `None` if the recorder is not installed.
+pub fn render() -> Option<String> {
+    HANDLE.get().map(PrometheusHandle::render)
+}
+
+/// Count a received query.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `transport` - The transport the query arrived on.
+pub fn record_query(query: &[u8], transport: Transport) {
+    let transport = match transport {
+        Transport::Udp => "udp",
+        Transport::Tcp => "tcp",
+        Transport::Https => "https",
+    };
+    increment_counter!(QUERIES_TOTAL, "transport" => transport);
+
+    let qtype = match extract_query_type(query).map(query_type_name) {
+        Some("") | None => "OTHER",
+        Some(name) => name,
+    };
+    increment_counter!(QUERIES_BY_TYPE_TOTAL, "qtype" => qtype);
+}
+
+/// Record the outcome of answering a query.
+///
+/// # Arguments
+/// * `response` - The response about to be sent.
+/// * `started` - When handling of the query began.
+pub fn record_response(response: &[u8], started: Instant) {
+    if response.len() >= 4 && response[3] & 0x0F == 3 {
+        increment_counter!(NXDOMAIN_TOTAL);
+    }
+    histogram!(RESPONSE_DURATION_SECONDS, started.elapsed().as_secs_f64());
+}
+
+/// Record a cache lookup.
+///
+/// # Arguments
+/// * `hit` - Whether the cache produced an answer.
+pub fn record_cache(hit: bool) {
+    if hit {
+        increment_counter!(CACHE_HITS_TOTAL);
+    } else {
+        increment_counter!(CACHE_MISSES_TOTAL);
+    }
+}
+
+/// Record the outcome of forwarding a query upstream.
+///
+/// # Arguments
+/// * `answered` - Whether any forwarder answered.
+pub fn record_forward(answered: bool) {
+    if answered {
+        increment_counter!(FORWARD_SUCCESSES_TOTAL);
+    } else {
+        increment_counter!(FORWARD_FAILURES_TOTAL);
+    }
+}
+
+/// Serve `/metrics` over HTTP.
+///
+/// # Arguments
+/// * `addr` - The address to listen on.
+///
+/// # Returns
+/// A `Result` that is only returned if the listener fails.
+pub async fn run_metrics_server(addr: SocketAddr) -> Result<(), DnsError> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Metrics endpoint listening on http://{}/metrics", addr);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        task::spawn(async move {
+            if let Err(e) = handle_scrape(stream).await {
+                debug!("Metrics request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Answer one HTTP request on the metrics endpoint.
+///
+/// # Arguments
+/// * `stream` - The client connection.
+///
+/// # Returns
+/// A `Result` indicating whether the response was sent.
+async fn handle_scrape(mut stream: TcpStream) -> Result<(), DnsError> {
+    let mut buf = vec![0u8; 1024];
+    let len = tokio::time::timeout(SCRAPE_READ_TIMEOUT, stream.read(&mut buf))
+        .await
+        .map_err(|_| DnsError::Protocol("Metrics request timed out".into()))??;
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render().unwrap_or_default()),
+        _ => ("404 Not Found", "Not Found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Start serving metrics if `config.metrics_bind` is set.
+///
+/// # Arguments
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the metrics task, or `None` if the endpoint is disabled.
+pub fn spawn_metrics_server(config: &ServerConfig) -> Option<JoinHandle<()>> {
+    let addr = config.metrics_bind?;
+    if let Err(e) = install() {
+        warn!("Metrics disabled: {}", e);
+        return None;
+    }
+
+    Some(task::spawn(async move {
+        if let Err(e) = run_metrics_server(addr).await {
+            warn!("Metrics endpoint stopped: {}", e);
+        }
+    }))
+}
@@ -0,0 +1,116 @@
+//! Liveness and readiness probe.
+//!
+//! This module serves a minimal HTTP endpoint on `ServerConfig::health_bind`
+//! for container orchestrators. Any request is answered `200 OK` while the
+//! database can be opened and the cache is initialized, and
+//! `503 Service Unavailable` otherwise. Unlike the metrics endpoint it
+//! records nothing; it only reports whether the server can answer queries.
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use log::{debug, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{self, JoinHandle};
+
+use crate::cache;
+use crate::config::ServerConfig;
+use crate::db::pooled_connection;
+use crate::errors::DnsError;
+
+/// Upper bound on reading a probe request.
+const PROBE_READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Check whether the server is ready to answer queries.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+///
+/// # Returns
+/// A `Result` that is `Ok` if the database answers a trivial query and the
+/// cache is initialized, or the first error found.
+pub fn check_ready(db_path: &str) -> Result<(), DnsError> {
+    cache::global()?;
+    let conn = pooled_connection(db_path)?;
+    conn.query_row("SELECT 1", [], |_| Ok(()))?;
+    Ok(())
+}
+
+/// Serve the probe over HTTP.
+///
+/// # Arguments
+/// * `addr` - The address to listen on.
+/// * `db_path` - Path to the SQLite database file.
+///
+/// # Returns
+/// A `Result` that is only returned if the listener fails.
+pub async fn run_probe_server(addr: SocketAddr, db_path: String) -> Result<(), DnsError> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Health endpoint listening on http://{}/", addr);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let db_path = db_path.clone();
+        task::spawn(async move {
+            if let Err(e) = handle_probe(stream, db_path).await {
+                debug!("Health request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Answer one HTTP request on the probe endpoint.
+///
+/// # Arguments
+/// * `stream` - The client connection.
+/// * `db_path` - Path to the SQLite database file.
+///
+/// # Returns
+/// A `Result` indicating whether the response was sent.
+async fn handle_probe(mut stream: TcpStream, db_path: String) -> Result<(), DnsError> {
+    let mut buf = vec![0u8; 1024];
+    tokio::time::timeout(PROBE_READ_TIMEOUT, stream.read(&mut buf))
+        .await
+        .map_err(|_| DnsError::Protocol("Health request timed out".into()))??;
+
+    // SQLite calls block, so keep them off the async workers
+    let ready = task::spawn_blocking(move || check_ready(&db_path))
+        .await
+        .map_err(|e| DnsError::Protocol(format!("Health check panicked: {}", e)))?;
+    let (status, body) = match ready {
+        Ok(()) => ("200 OK", "OK\n".to_string()),
+        Err(e) => {
+            debug!("Health check failed: {}", e);
+            ("503 Service Unavailable", format!("UNAVAILABLE: {}\n", e))
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Start serving the probe if `config.health_bind` is set.
+///
+/// # Arguments
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// An `Option` containing the probe task, or `None` if the endpoint is disabled.
+pub fn spawn_probe_server(config: &ServerConfig) -> Option<JoinHandle<()>> {
+    let addr = config.health_bind?;
+    let db_path = config.db_path.clone();
+
+    Some(task::spawn(async move {
+        if let Err(e) = run_probe_server(addr, db_path).await {
+            warn!("Health endpoint stopped: {}", e);
+        }
+    }))
+}
@@ -0,0 +1,96 @@
+//! Response Rate Limiting.
+//!
+//! This module limits how many UDP responses each client receives, to blunt
+//! reflection and amplification attacks that spoof the victim's address.
+//! Every client (or client and queried name) gets a token bucket that refills
+//! at `ServerConfig::rrl_rate` per second up to `ServerConfig::rrl_burst`.
+//! Over the limit, queries are dropped or answered with an empty truncated
+//! response so that a genuine client retries over TCP, which cannot be spoofed.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::config::ServerConfig;
+use crate::dns::truncate_response;
+
+/// Maximum number of buckets tracked before idle ones are pruned.
+const MAX_TRACKED_BUCKETS: usize = 100_000;
+
+/// Token buckets, keyed by client address and (optionally) queried name.
+static BUCKETS: OnceLock<Mutex<HashMap<(IpAddr, String), Bucket>>> = OnceLock::new();
+
+/// A client's token bucket.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Responses the client may still receive right now.
+    tokens: f64,
+
+    /// When `tokens` was last brought up to date.
+    updated: Instant,
+}
+
+/// Check whether a client may be answered, taking a token if so.
+///
+/// # Arguments
+/// * `client` - The client's source address.
+/// * `domain` - The queried name; only used with `config.rrl_per_name`.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// `true` if the query is within the limit or rate limiting is disabled.
+pub fn allow(client: IpAddr, domain: &str, config: &ServerConfig) -> bool {
+    if config.rrl_rate == 0 {
+        return true;
+    }
+    let rate = config.rrl_rate as f64;
+    let burst = config.rrl_burst.max(1) as f64;
+    let name = if config.rrl_per_name { domain.to_ascii_lowercase() } else { String::new() };
+
+    let buckets = BUCKETS.get_or_init(|| Mutex::new(HashMap::new()));
+    let Ok(mut buckets) = buckets.lock() else {
+        return true;
+    };
+    let now = Instant::now();
+
+    if buckets.len() >= MAX_TRACKED_BUCKETS && !buckets.contains_key(&(client, name.clone())) {
+        // A bucket that has had time to refill is no different from a new one
+        buckets.retain(|_, bucket| now.duration_since(bucket.updated).as_secs_f64() * rate < burst);
+        if buckets.len() >= MAX_TRACKED_BUCKETS {
+            if let Some(key) = buckets.keys().next().cloned() {
+                buckets.remove(&key);
+            }
+        }
+    }
+
+    let bucket = buckets.entry((client, name)).or_insert(Bucket { tokens: burst, updated: now });
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+    bucket.updated = now;
+
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        true
+    } else {
+        false
+    }
+}
+
+/// Build the empty truncated response sent to a client over its limit.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+/// * `config` - The server configuration.
+///
+/// # Returns
+/// A response with the query's question, no records and TC set.
+pub fn slip_response(query: &[u8], config: &ServerConfig) -> Vec<u8> {
+    let mut response = query.to_vec();
+    if response.len() >= 4 {
+        response[2] = 0x80 | (query[2] & 0x79); // QR, OPCODE and RD from the query
+        response[3] = 0x00;
+    }
+    truncate_response(&response, query, config)
+}
@@ -2,64 +2,43 @@
 //!
 //! This module provides helper functions for parsing and encoding DNS data.
 #![allow(dead_code)]
-#[allow(unused_variables)]
 
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str;
 use chrono::{NaiveDateTime, TimeZone, Utc};
+use rand::Rng;
 
 use crate::errors::DnsError;
 
+/// Maximum compression pointers followed while reading one name.
+const MAX_POINTER_JUMPS: usize = 64;
+
 /// Extract the domain name from a DNS query packet.
 ///
+/// The name is lowercased since DNS names compare case-insensitively
+/// (RFC 4343); the question echoed in responses keeps the client's casing.
+/// Compression pointers are followed (RFC 1035 section 4.1.4) by
+/// [`read_name`], so a pointer loop makes the name invalid rather than
+/// looping.
+///
 /// # Arguments
 /// * `query` - The DNS query packet.
 ///
 /// # Returns
-/// An `Option` containing the domain name if successfully extracted.
+/// An `Option` containing the lowercased domain name if successfully extracted.
 pub fn extract_domain(query: &[u8]) -> Option<String> {
     if query.len() < 12 {
         return None; // DNS header is 12 bytes
     }
 
-    let mut pos = 12; // Start after header
-    let mut domain = String::new();
-
-    // Extract QNAME (domain)
-    loop {
-        if pos >= query.len() {
-            return None;
-        }
-        
-        let len = query[pos] as usize;
-        if len == 0 {
-            break; // End of QNAME
-        }
-        pos += 1;
-
-        if pos + len > query.len() {
-            return None; // Invalid length
-        }
-
-        if !domain.is_empty() {
-            domain.push('.');
-        }
-
-        let label = match str::from_utf8(&query[pos..pos + len]) {
-            Ok(l) => l,
-            Err(_) => return None, // Invalid UTF-8
-        };
-        domain.push_str(label);
-        pos += len;
-    }
-
-    // Skip QTYPE and QCLASS (4 bytes)
-    pos += 4;
-
-    // Verify we have enough data for at least QTYPE/QCLASS
-    if pos > query.len() {
+    // Verify we have enough data for QTYPE and QCLASS (4 bytes)
+    let (mut domain, name_end) = read_name(query, 12)?;
+    if name_end + 4 > query.len() {
         return None;
     }
 
+    domain.make_ascii_lowercase();
     Some(domain)
 }
 
@@ -75,33 +54,276 @@ pub fn extract_query_type(query: &[u8]) -> Option<u16> {
         return None; // DNS header is 12 bytes
     }
 
-    let mut pos = 12; // Start after header
+    // Get QTYPE (2 bytes after QNAME)
+    let pos = skip_name(query, 12)?;
+    let qtype = query.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([qtype[0], qtype[1]]))
+}
 
-    // Skip QNAME
-    loop {
-        if pos >= query.len() {
+/// Extract the query class from a DNS query packet.
+///
+/// The top bit of QCLASS is the mDNS "unicast response" (QU) bit and is
+/// masked off the returned class.
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// An `Option` containing the query class and whether the QU bit was set.
+pub fn extract_query_class(query: &[u8]) -> Option<(u16, bool)> {
+    if query.len() < 12 {
+        return None;
+    }
+
+    // QCLASS follows QNAME and QTYPE
+    let pos = skip_name(query, 12)? + 2;
+    if pos + 1 < query.len() {
+        let qclass = ((query[pos] as u16) << 8) | query[pos + 1] as u16;
+        Some((qclass & 0x7FFF, qclass & 0x8000 != 0))
+    } else {
+        None
+    }
+}
+
+/// Parse a CIDR subnet such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// A bare address is treated as a host route (/32 or /128).
+///
+/// # Arguments
+/// * `cidr` - The subnet string.
+///
+/// # Returns
+/// An `Option` containing the network address and prefix length.
+pub fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match cidr.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (cidr.trim(), None),
+    };
+    let network: IpAddr = addr.parse().ok()?;
+    let max = if network.is_ipv4() { 32 } else { 128 };
+    let prefix_len = match prefix {
+        Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max)?,
+        None => max,
+    };
+    Some((network, prefix_len))
+}
+
+/// Check whether an address falls inside a subnet.
+///
+/// IPv4-mapped IPv6 addresses are compared as IPv4.
+///
+/// # Arguments
+/// * `ip` - The address to check.
+/// * `network` - The network address of the subnet.
+/// * `prefix_len` - The prefix length of the subnet.
+///
+/// # Returns
+/// A boolean indicating whether the address is in the subnet.
+pub fn ip_in_subnet(ip: &IpAddr, network: &IpAddr, prefix_len: u8) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+        IpAddr::V4(_) => *ip,
+    };
+    match (ip, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len.min(32) as u32).unwrap_or(0);
+            u32::from(a) & mask == u32::from(*n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len.min(128) as u32).unwrap_or(0);
+            u128::from(a) & mask == u128::from(*n) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Check whether an address belongs to a private or otherwise non-public range.
+///
+/// Covers RFC 1918, loopback, link-local, carrier-grade NAT, unspecified and
+/// IPv6 unique-local addresses, plus IPv4-mapped forms of these.
+///
+/// # Arguments
+/// * `ip` - The address to check.
+///
+/// # Returns
+/// A boolean indicating whether the address is non-public.
+pub fn is_private_address(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || (octets[0] == 100 && (octets[1] & 0xC0) == 64) // 100.64.0.0/10
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_private_address(&IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || (first & 0xFE00) == 0xFC00 // fc00::/7 unique local
+                || (first & 0xFFC0) == 0xFE80 // fe80::/10 link local
+        }
+    }
+}
+
+/// Embed an IPv4 address in a NAT64 prefix (RFC 6052) for DNS64.
+///
+/// # Arguments
+/// * `prefix` - The /96 NAT64 prefix, e.g. `64:ff9b::`.
+/// * `v4` - The IPv4 address to embed.
+///
+/// # Returns
+/// The synthesized IPv6 address with the IPv4 address in its low 32 bits.
+pub fn synthesize_dns64(prefix: &Ipv6Addr, v4: &Ipv4Addr) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[12..].copy_from_slice(&v4.octets());
+    Ipv6Addr::from(octets)
+}
+
+/// Recover the address a reverse-mapping name stands for.
+///
+/// `1.2.0.192.in-addr.arpa` gives `192.0.2.1`, and an `ip6.arpa` name of 32
+/// nibble labels gives the IPv6 address (RFC 3596 section 2.5).
+///
+/// # Arguments
+/// * `name` - The reverse name, without a trailing dot.
+///
+/// # Returns
+/// An `Option` containing the address, or `None` if the name is not a
+/// complete, well-formed reverse name.
+pub fn parse_reverse_name(name: &str) -> Option<IpAddr> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if let Some(labels) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets: Vec<u8> = labels.split('.')
+            .map(|label| if label.len() <= 3 && label.bytes().all(|b| b.is_ascii_digit()) { label.parse().ok() } else { None })
+            .collect::<Option<_>>()?;
+        if octets.len() != 4 {
             return None;
         }
+        octets.reverse();
+        return Some(IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])));
+    }
 
-        let len = query[pos] as usize;
-        if len == 0 {
-            pos += 1;
-            break; // End of QNAME
+    let labels = name.strip_suffix(".ip6.arpa")?;
+    let nibbles: Vec<u8> = labels.split('.')
+        .map(|label| if label.len() == 1 { u8::from_str_radix(label, 16).ok() } else { None })
+        .collect::<Option<_>>()?;
+    if nibbles.len() != 32 {
+        return None;
+    }
+    let mut octets = [0u8; 16];
+    for (i, pair) in nibbles.rchunks(2).enumerate() {
+        octets[i] = (pair[1] << 4) | pair[0];
+    }
+    Some(IpAddr::V6(Ipv6Addr::from(octets)))
+}
+
+/// Convert a domain name to its ASCII (punycode) form.
+///
+/// Records must be stored under the punycode form of internationalized names
+/// (e.g. `xn--bcher-kva.example`), which is what appears on the wire.
+///
+/// # Arguments
+/// * `name` - The domain name, possibly containing Unicode labels.
+///
+/// # Returns
+/// A `Result` containing the punycode domain name or an error.
+pub fn to_punycode(name: &str) -> Result<String, DnsError> {
+    idna::domain_to_ascii(name)
+        .map_err(|e| DnsError::Config(format!("Invalid internationalized domain name {}: {}", name, e)))
+}
+
+/// Convert a punycode domain name to its Unicode form.
+///
+/// # Arguments
+/// * `name` - The domain name, possibly containing `xn--` labels.
+///
+/// # Returns
+/// The Unicode domain name. Labels that fail to decode are left as-is.
+pub fn from_punycode(name: &str) -> String {
+    idna::domain_to_unicode(name).0
+}
+
+/// Maximum length of a label in a domain name (RFC 1035 section 2.3.4).
+pub const MAX_LABEL_LENGTH: usize = 63;
+
+/// Maximum length of a domain name in wire format (RFC 1035 section 2.3.4).
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// Check that a domain name can be stored and encoded without loss.
+///
+/// Every label must be 1 to 63 bytes of letters, digits, `-` or `_` (a
+/// leading `*` label is allowed for wildcards), and the whole name at most
+/// 255 bytes in wire format. The root name (empty or `.`) is valid.
+///
+/// # Arguments
+/// * `name` - The domain name, with or without a trailing dot.
+///
+/// # Returns
+/// A `Result` that is `DnsError::Config` describing the first problem found.
+pub fn validate_dns_name(name: &str) -> Result<(), DnsError> {
+    validate_name_length(name)?;
+    let trimmed = name.strip_suffix('.').unwrap_or(name);
+    if trimmed.is_empty() {
+        return Ok(());
+    }
+
+    for (i, label) in trimmed.split('.').enumerate() {
+        let wildcard = i == 0 && label == "*";
+        if !wildcard && !label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+            return Err(DnsError::Config(format!(
+                "invalid domain name {:?}: label {:?} may only contain letters, digits, '-' and '_'",
+                name, label
+            )));
         }
+    }
+    Ok(())
+}
 
-        pos += len + 1;
+/// Check that a domain name fits in DNS wire format.
+///
+/// Every label must be 1 to 63 bytes and the whole name at most 255 bytes
+/// in wire format; the label contents are not checked. Names being encoded
+/// for a response use this rather than [`validate_dns_name`], as stored
+/// data such as an SOA RNAME may legally hold other characters.
+///
+/// # Arguments
+/// * `name` - The domain name, with or without a trailing dot.
+///
+/// # Returns
+/// A `Result` that is `DnsError::Config` describing the first problem found.
+pub fn validate_name_length(name: &str) -> Result<(), DnsError> {
+    let trimmed = name.strip_suffix('.').unwrap_or(name);
+    if trimmed.is_empty() {
+        return Ok(());
     }
 
-    // Get QTYPE (2 bytes after QNAME)
-    if pos + 1 < query.len() {
-        Some(((query[pos] as u16) << 8) | query[pos + 1] as u16)
-    } else {
-        None
+    let invalid = |reason: String| Err(DnsError::Config(format!("invalid domain name {:?}: {}", name, reason)));
+    let mut wire_len = 1;
+    for label in trimmed.split('.') {
+        if label.is_empty() {
+            return invalid("empty label".into());
+        }
+        if label.len() > MAX_LABEL_LENGTH {
+            return invalid(format!("label {:?} is longer than {} bytes", label, MAX_LABEL_LENGTH));
+        }
+        wire_len += label.len() + 1;
     }
+    if wire_len > MAX_NAME_LENGTH {
+        return invalid(format!("{} bytes in wire format, more than {}", wire_len, MAX_NAME_LENGTH));
+    }
+    Ok(())
 }
 
 /// Encode a domain name in DNS wire format.
 ///
+/// Empty and over-long labels are skipped, so names from outside the
+/// server should be checked with [`validate_dns_name`] first.
+///
 /// # Arguments
 /// * `name` - The domain name to encode.
 ///
@@ -110,8 +332,8 @@ pub fn extract_query_type(query: &[u8]) -> Option<u16> {
 pub fn encode_dns_name(name: &str) -> Vec<u8> {
     let mut out = Vec::new();
     for part in name.trim_end_matches('.').split('.') {
-        if part.len() > 63 {
-            continue; // Skip invalid labels
+        if part.is_empty() || part.len() > 63 {
+            continue; // Skip empty (root) and invalid labels
         }
         out.push(part.len() as u8);
         out.extend_from_slice(part.as_bytes());
@@ -120,6 +342,177 @@ pub fn encode_dns_name(name: &str) -> Vec<u8> {
     out
 }
 
+/// Maximum length of a DNS character-string (RFC 1035 section 3.3).
+pub const MAX_CHARACTER_STRING: usize = 255;
+
+/// Split a stored TXT value into the character-strings of its RDATA.
+///
+/// A value starting with a quote is read as a sequence of quoted strings
+/// (with `\"` and `\\` escapes) and bare words separated by whitespace,
+/// as in a zone file. Any other value is a single string, spaces included.
+/// Strings longer than 255 bytes are split into 255-byte chunks, on
+/// character boundaries.
+///
+/// # Arguments
+/// * `value` - The TXT value as stored in the database.
+///
+/// # Returns
+/// The character-strings, at least one and each at most 255 bytes.
+pub fn txt_character_strings(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let mut strings = Vec::new();
+
+    if value.starts_with('"') {
+        let mut chars = value.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '"' {
+                chars.next();
+                let mut current = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => current.extend(chars.next()),
+                        c => current.push(c),
+                    }
+                }
+                strings.push(current);
+            } else {
+                let mut current = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '"' {
+                        break;
+                    }
+                    current.push(c);
+                    chars.next();
+                }
+                strings.push(current);
+            }
+        }
+    } else {
+        strings.push(value.to_string());
+    }
+
+    let mut chunks = Vec::with_capacity(strings.len());
+    for string in strings {
+        let mut rest = string.as_str();
+        while rest.len() > MAX_CHARACTER_STRING {
+            let mut split = MAX_CHARACTER_STRING;
+            while !rest.is_char_boundary(split) {
+                split -= 1;
+            }
+            chunks.push(rest[..split].to_string());
+            rest = &rest[split..];
+        }
+        chunks.push(rest.to_string());
+    }
+    chunks
+}
+
+/// Writes domain names into a message, compressing them against names
+/// already written (RFC 1035 section 4.1.4).
+///
+/// Only names written through the compressor (and the question name, once
+/// seeded) are used as pointer targets, so pointers always refer to earlier
+/// offsets in the message.
+#[derive(Debug, Clone, Default)]
+pub struct NameCompressor {
+    /// Offsets of name suffixes already in the message, keyed by lowercase suffix.
+    offsets: HashMap<String, u16>,
+
+    /// Whether compression is applied; when off names are written in full.
+    enabled: bool,
+}
+
+impl NameCompressor {
+    /// Create a compressor.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to compress names.
+    ///
+    /// # Returns
+    /// A new `NameCompressor` with no known names.
+    pub fn new(enabled: bool) -> Self {
+        Self { offsets: HashMap::new(), enabled }
+    }
+
+    /// Register the question name of a message as a pointer target.
+    ///
+    /// # Arguments
+    /// * `packet` - The message, with its question starting at offset 12.
+    pub fn seed_question(&mut self, packet: &[u8]) {
+        let mut labels = Vec::new();
+        let mut pos = 12;
+        while pos < packet.len() && packet[pos] != 0 && packet[pos] & 0xC0 == 0 {
+            let len = packet[pos] as usize;
+            if pos + 1 + len > packet.len() {
+                return;
+            }
+            labels.push((pos, String::from_utf8_lossy(&packet[pos + 1..pos + 1 + len]).to_ascii_lowercase()));
+            pos += len + 1;
+        }
+        for i in 0..labels.len() {
+            let suffix = labels[i..].iter().map(|(_, l)| l.as_str()).collect::<Vec<_>>().join(".");
+            self.offsets.entry(suffix).or_insert(labels[i].0 as u16);
+        }
+    }
+
+    /// Append a domain name to a message.
+    ///
+    /// # Arguments
+    /// * `packet` - The message being built.
+    /// * `name` - The domain name to write.
+    ///
+    /// # Returns
+    /// A `Result` that is `DnsError::Config` if the name fails
+    /// [`validate_name_length`], in which case nothing is written.
+    pub fn write_name(&mut self, packet: &mut Vec<u8>, name: &str) -> Result<(), DnsError> {
+        validate_name_length(name)?;
+        if !self.enabled {
+            packet.extend_from_slice(&encode_dns_name(name));
+            return Ok(());
+        }
+
+        let labels: Vec<&str> = name.trim_end_matches('.')
+            .split('.')
+            .filter(|l| !l.is_empty())
+            .collect();
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".").to_ascii_lowercase();
+            if let Some(&offset) = self.offsets.get(&suffix) {
+                packet.extend_from_slice(&(0xC000 | offset).to_be_bytes());
+                return Ok(());
+            }
+            // Pointers can only address the first 16 KiB of a message
+            if packet.len() < 0x4000 {
+                self.offsets.insert(suffix, packet.len() as u16);
+            }
+            packet.push(labels[i].len() as u8);
+            packet.extend_from_slice(labels[i].as_bytes());
+        }
+        packet.push(0);
+        Ok(())
+    }
+}
+
+/// Generate a random number below an upper bound.
+///
+/// All randomness in the server goes through this helper so that there is a
+/// single place to swap the RNG.
+///
+/// # Arguments
+/// * `upper` - Exclusive upper bound.
+///
+/// # Returns
+/// A random number in `0..upper`, or 0 if `upper` is 0.
+pub fn random_below(upper: u64) -> u64 {
+    if upper == 0 {
+        return 0;
+    }
+    rand::thread_rng().gen_range(0..upper)
+}
+
 /// Parse a signature time in YYYYMMDDHHMMSS format to seconds since epoch.
 ///
 /// # Arguments
@@ -133,6 +526,17 @@ pub fn parse_sig_time(s: &str) -> Result<u32, DnsError> {
     Ok(Utc.from_utc_datetime(&dt).timestamp() as u32)
 }
 
+/// Check whether a DNS query has the Recursion Desired (RD) bit set.
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// `true` if RD is set; `false` if it is clear or the header is truncated.
+pub fn recursion_desired(query: &[u8]) -> bool {
+    query.get(2).is_some_and(|flags| flags & 0x01 != 0)
+}
+
 /// Check if a DNS query packet has an OPT record (EDNS).
 ///
 /// # Arguments
@@ -141,287 +545,509 @@ pub fn parse_sig_time(s: &str) -> Result<u32, DnsError> {
 /// # Returns
 /// A boolean indicating whether the query has an OPT record.
 pub fn has_opt_record(query: &[u8]) -> bool {
-    if query.len() < 12 {
-        return false;
-    }
+    opt_fixed_fields(query).is_some()
+}
 
-    // Get ARCOUNT (number of additional records)
-    let arcount = ((query[10] as u16) << 8) | query[11] as u16;
-    if arcount == 0 {
-        return false;
-    }
+/// Extract the EDNS payload size from a DNS query packet.
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// An `Option` containing the EDNS payload size if found.
+pub fn extract_edns_payload_size(query: &[u8]) -> Option<u16> {
+    // The UDP payload size is carried in the OPT record's CLASS field
+    let fields = opt_fixed_fields(query)?;
+    Some(u16::from_be_bytes([fields[2], fields[3]]))
+}
 
-    // Skip header
-    let mut pos = 12;
+/// Extract the DO (DNSSEC OK) bit from a DNS query packet.
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// A boolean indicating whether the DO bit is set.
+pub fn extract_do_bit(query: &[u8]) -> bool {
+    // DO is the top bit of the flags, after the extended RCODE and version bytes
+    opt_fixed_fields(query).is_some_and(|fields| fields[6] & 0x80 != 0)
+}
 
-    // Skip question section
-    // First skip QNAME
+/// Skip over an encoded domain name in a DNS packet.
+///
+/// # Arguments
+/// * `packet` - The DNS packet.
+/// * `pos` - Offset of the first byte of the name.
+///
+/// # Returns
+/// An `Option` containing the offset just past the name, or `None` if the
+/// name runs past the end of the packet.
+pub fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
     loop {
-        if pos >= query.len() {
-            return false;
+        let len = *packet.get(pos)? as usize;
+        if (len & 0xC0) == 0xC0 {
+            // Compression pointer terminates the name
+            return if pos + 2 <= packet.len() { Some(pos + 2) } else { None };
         }
-        let len = query[pos] as usize;
         if len == 0 {
-            pos += 1;
-            break;
+            return Some(pos + 1);
         }
         pos += len + 1;
     }
+}
 
-    // Skip QTYPE and QCLASS
-    pos += 4;
-
-    // Skip answer and authority sections
-    let ancount = ((query[6] as u16) << 8) | query[7] as u16;
-    let nscount = ((query[8] as u16) << 8) | query[9] as u16;
-
-    for _ in 0..(ancount + nscount) {
-        // Skip name
-        if pos >= query.len() {
-            return false;
-        }
-
-        // Handle compression pointers
-        if (query[pos] & 0xC0) == 0xC0 {
-            pos += 2; // Skip compression pointer
-        } else {
-            // Skip labels
-            loop {
-                if pos >= query.len() {
-                    return false;
-                }
-                let len = query[pos] as usize;
-                if len == 0 {
-                    pos += 1;
-                    break;
+/// Read a possibly compressed domain name from a DNS packet.
+///
+/// Names longer than 255 bytes in wire format, reserved label types and
+/// pointer loops make the name malformed.
+///
+/// # Arguments
+/// * `packet` - The DNS packet.
+/// * `pos` - Offset of the first byte of the name.
+///
+/// # Returns
+/// An `Option` containing the dotted name and the offset just past the name
+/// as it appears at `pos`, or `None` if the name is malformed.
+pub fn read_name(packet: &[u8], pos: usize) -> Option<(String, usize)> {
+    let end = skip_name(packet, pos)?;
+    let mut labels: Vec<String> = Vec::new();
+    let mut pos = pos;
+    let mut jumps = 0;
+    let mut wire_len = 1;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len & 0xC0 {
+            0xC0 => {
+                // Follow the pointer, bounding the number of jumps to reject loops
+                jumps += 1;
+                if jumps > MAX_POINTER_JUMPS {
+                    return None;
                 }
-                pos += len + 1;
+                pos = ((len & 0x3F) << 8) | *packet.get(pos + 1)? as usize;
+                continue;
             }
+            0x00 => {}
+            _ => return None, // Reserved label types
         }
-
-        // Skip TYPE, CLASS, TTL, RDLENGTH, RDATA
-        if pos + 10 > query.len() {
-            return false;
-        }
-        let rdlength = ((query[pos + 8] as usize) << 8) | query[pos + 9] as usize;
-        pos += 10 + rdlength;
-    }
-
-    // Check additional records for OPT
-    for _ in 0..arcount {
-        if pos >= query.len() {
-            return false;
+        if len == 0 {
+            break;
         }
-
-        // OPT record has empty (root) name
-        if query[pos] == 0 {
-            // Check if TYPE is OPT (41)
-            if pos + 2 < query.len() && query[pos + 1] == 0 && query[pos + 2] == 41 {
-                return true;
-            }
+        wire_len += len + 1;
+        if wire_len > MAX_NAME_LENGTH {
+            return None;
         }
-
-        // Skip this record
-        if pos + 10 >= query.len() { break; }
-        let rdlength = ((query[pos + 8] as usize) << 8) | query[pos + 9] as usize;
-        pos += 10 + rdlength;
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += len + 1;
     }
-
-    false // No OPT record found
+    Some((labels.join("."), end))
 }
 
-/// Extract the EDNS payload size from a DNS query packet.
+/// Find the end of the question section of a single-question packet.
 ///
 /// # Arguments
-/// * `query` - The DNS query packet.
+/// * `packet` - The DNS packet.
 ///
 /// # Returns
-/// An `Option` containing the EDNS payload size if found.
-pub fn extract_edns_payload_size(query: &[u8]) -> Option<u16> {
-    if query.len() < 12 {
-        return None;
-    }
+/// An `Option` containing the offset just past QTYPE and QCLASS, or `None`
+/// if the question runs past the end of the packet.
+pub fn question_end(packet: &[u8]) -> Option<usize> {
+    skip_name(packet, 12).map(|pos| pos + 4).filter(|&end| end <= packet.len())
+}
 
-    // Get ARCOUNT (number of additional records)
-    let arcount = ((query[10] as u16) << 8) | query[11] as u16;
-    if arcount == 0 {
-        return None;
-    }
+/// Rewrite a query whose QNAME uses compression with the name written in full.
+///
+/// Response builders copy the question verbatim, and a pointer copied that
+/// way would refer to whatever happens to sit at that offset in the response.
+///
+/// # Arguments
+/// * `query` - The DNS query.
+///
+/// # Returns
+/// An `Option` containing the rewritten query, or `None` if the QNAME is
+/// not compressed or is malformed.
+pub fn expand_question(query: &[u8]) -> Option<Vec<u8>> {
+    let end = question_end(query)?;
+    // Rejects pointer loops and out-of-range labels, so the walk below stays in bounds
+    read_name(query, 12)?;
 
-    // Skip header
+    let mut name = Vec::new();
     let mut pos = 12;
-
-    // Skip question section
-    // First skip QNAME
+    let mut compressed = false;
     loop {
-        if pos >= query.len() {
-            return None;
-        }
         let len = query[pos] as usize;
+        if len & 0xC0 == 0xC0 {
+            compressed = true;
+            pos = ((len & 0x3F) << 8) | query[pos + 1] as usize;
+            continue;
+        }
+        name.extend_from_slice(&query[pos..=pos + len]);
         if len == 0 {
-            pos += 1;
             break;
         }
         pos += len + 1;
     }
+    if !compressed {
+        return None;
+    }
 
-    // Skip QTYPE and QCLASS
-    pos += 4;
+    let mut expanded = Vec::with_capacity(query.len() + name.len());
+    expanded.extend_from_slice(&query[..12]);
+    expanded.extend_from_slice(&name);
+    expanded.extend_from_slice(&query[end - 4..]);
+    Some(expanded)
+}
 
-    // Skip answer and authority sections
-    let ancount = ((query[6] as u16) << 8) | query[7] as u16;
-    let nscount = ((query[8] as u16) << 8) | query[9] as u16;
+/// Locate the additional section of a DNS packet.
+///
+/// # Arguments
+/// * `query` - The DNS packet.
+///
+/// # Returns
+/// An `Option` containing the offset of the first additional record and ARCOUNT.
+fn additional_section(query: &[u8]) -> Option<(usize, u16)> {
+    if query.len() < 12 {
+        return None;
+    }
 
-    for _ in 0..(ancount + nscount) {
-        // Skip name
-        if pos >= query.len() {
-            return None;
-        }
+    let qdcount = u16::from_be_bytes([query[4], query[5]]);
+    let ancount = u16::from_be_bytes([query[6], query[7]]);
+    let nscount = u16::from_be_bytes([query[8], query[9]]);
+    let arcount = u16::from_be_bytes([query[10], query[11]]);
 
-        // Handle compression pointers
-        if (query[pos] & 0xC0) == 0xC0 {
-            pos += 2; // Skip compression pointer
-        } else {
-            // Skip labels
-            loop {
-                if pos >= query.len() {
-                    return None;
-                }
-                let len = query[pos] as usize;
-                if len == 0 {
-                    pos += 1;
-                    break;
-                }
-                pos += len + 1;
-            }
-        }
+    let mut pos = 12;
+
+    // Skip question section (QNAME, QTYPE, QCLASS)
+    for _ in 0..qdcount {
+        pos = skip_name(query, pos)? + 4;
+    }
 
-        // Skip TYPE, CLASS, TTL, RDLENGTH, RDATA
+    // Skip answer and authority sections
+    for _ in 0..(ancount as u32 + nscount as u32) {
+        pos = skip_name(query, pos)?;
         if pos + 10 > query.len() {
             return None;
         }
-        let rdlength = ((query[pos + 8] as usize) << 8) | query[pos + 9] as usize;
+        let rdlength = u16::from_be_bytes([query[pos + 8], query[pos + 9]]) as usize;
         pos += 10 + rdlength;
     }
 
-    // Check additional records for OPT
+    Some((pos, arcount))
+}
+
+/// Find the offsets of all OPT pseudo-records in the additional section.
+///
+/// # Arguments
+/// * `query` - The DNS packet.
+///
+/// # Returns
+/// The offsets of each OPT record's owner name, in packet order.
+fn opt_record_offsets(query: &[u8]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let (mut pos, arcount) = match additional_section(query) {
+        Some(section) => section,
+        None => return offsets,
+    };
+
+    // Scan additional records for TYPE OPT (41)
     for _ in 0..arcount {
-        if pos >= query.len() {
-            return None;
+        let start = pos;
+        pos = match skip_name(query, pos) {
+            Some(p) => p,
+            None => break,
+        };
+        if pos + 10 > query.len() {
+            break;
         }
-
-        // OPT record has empty (root) name
-        if query[pos] == 0 {
-            // Check if TYPE is OPT (41)
-            if pos + 5 < query.len() && query[pos + 1] == 0 && query[pos + 2] == 41 {
-                // Extract UDP payload size (CLASS field in OPT record)
-                return Some(((query[pos + 3] as u16) << 8) | query[pos + 4] as u16);
-            }
+        if u16::from_be_bytes([query[pos], query[pos + 1]]) == 41 {
+            offsets.push(start);
         }
-
-        // Skip this record
-        if pos + 10 >= query.len() { break; }
-        let rdlength = ((query[pos + 8] as usize) << 8) | query[pos + 9] as usize;
+        let rdlength = u16::from_be_bytes([query[pos + 8], query[pos + 9]]) as usize;
         pos += 10 + rdlength;
     }
 
-    None // No OPT record found
+    offsets
 }
 
-/// Extract the DO (DNSSEC OK) bit from a DNS query packet.
+/// Find the OPT pseudo-record in the additional section of a DNS packet.
 ///
 /// # Arguments
 /// * `query` - The DNS query packet.
 ///
 /// # Returns
-/// A boolean indicating whether the DO bit is set.
-pub fn extract_do_bit(query: &[u8]) -> bool {
-    if query.len() < 12 {
-        return false;
+/// An `Option` containing the offset of the OPT record's owner name.
+pub fn find_opt_record(query: &[u8]) -> Option<usize> {
+    opt_record_offsets(query).first().copied()
+}
+
+/// Count the OPT pseudo-records in a DNS packet.
+///
+/// More than one OPT record makes the message malformed (RFC 6891).
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// The number of OPT records in the additional section.
+pub fn count_opt_records(query: &[u8]) -> usize {
+    opt_record_offsets(query).len()
+}
+
+/// Locate the fixed fields of the OPT record in a DNS packet.
+///
+/// # Arguments
+/// * `query` - The DNS packet.
+///
+/// # Returns
+/// An `Option` containing the TYPE, CLASS, TTL and RDLENGTH of the first OPT record.
+fn opt_fixed_fields(query: &[u8]) -> Option<&[u8]> {
+    let pos = skip_name(query, find_opt_record(query)?)?;
+    query.get(pos..pos + 10)
+}
+
+/// Extract the EDNS options carried in a DNS packet's OPT record.
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// A vector of (option code, option data) pairs, empty if there is no OPT record.
+pub fn extract_edns_options(query: &[u8]) -> Vec<(u16, Vec<u8>)> {
+    let mut options = Vec::new();
+
+    let pos = match find_opt_record(query).and_then(|start| skip_name(query, start)) {
+        Some(pos) => pos,
+        None => return options,
+    };
+
+    if pos + 10 > query.len() {
+        return options;
+    }
+    let rdlength = u16::from_be_bytes([query[pos + 8], query[pos + 9]]) as usize;
+    let rdata_start = pos + 10;
+    let rdata_end = (rdata_start + rdlength).min(query.len());
+
+    let mut pos = rdata_start;
+    while pos + 4 <= rdata_end {
+        let code = u16::from_be_bytes([query[pos], query[pos + 1]]);
+        let len = u16::from_be_bytes([query[pos + 2], query[pos + 3]]) as usize;
+        pos += 4;
+        if pos + len > rdata_end {
+            break;
+        }
+        options.push((code, query[pos..pos + len].to_vec()));
+        pos += len;
     }
 
-    // Get ARCOUNT (number of additional records)
-    let arcount = ((query[10] as u16) << 8) | query[11] as u16;
-    if arcount == 0 {
+    options
+}
+
+/// Check that the options in a DNS packet's OPT record exactly fill its RDATA.
+///
+/// Each option must fit within the declared RDLENGTH, the RDATA must fit in
+/// the packet, and the option lengths must add up to RDLENGTH.
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// `true` if the OPT record is well formed or there is no OPT record.
+pub fn edns_options_valid(query: &[u8]) -> bool {
+    let pos = match find_opt_record(query).and_then(|start| skip_name(query, start)) {
+        Some(pos) => pos,
+        None => return true,
+    };
+    if pos + 10 > query.len() {
         return false;
     }
 
-    // Skip header
-    let mut pos = 12;
+    let rdlength = u16::from_be_bytes([query[pos + 8], query[pos + 9]]) as usize;
+    let rdata_end = pos + 10 + rdlength;
+    if rdata_end > query.len() {
+        return false;
+    }
 
-    // Skip question section
-    // First skip QNAME
-    loop {
-        if pos >= query.len() {
+    let mut pos = pos + 10;
+    while pos < rdata_end {
+        if pos + 4 > rdata_end {
             return false;
         }
-        let len = query[pos] as usize;
-        if len == 0 {
-            pos += 1;
-            break;
-        }
-        pos += len + 1;
+        let len = u16::from_be_bytes([query[pos + 2], query[pos + 3]]) as usize;
+        pos += 4 + len;
     }
 
-    // Skip QTYPE and QCLASS
-    pos += 4;
+    pos == rdata_end
+}
 
-    // Skip answer and authority sections
-    let ancount = ((query[6] as u16) << 8) | query[7] as u16;
-    let nscount = ((query[8] as u16) << 8) | query[9] as u16;
+/// Encode the data of an EDNS Client Subnet option (RFC 7871).
+///
+/// The address is cut to the source prefix length, with the bits past the
+/// prefix cleared, and the scope prefix length is left at 0.
+///
+/// # Arguments
+/// * `addr` - The client address.
+/// * `source_prefix` - How many leading bits of the address to reveal.
+///
+/// # Returns
+/// The option data, without the option code and length.
+pub fn encode_client_subnet(addr: IpAddr, source_prefix: u8) -> Vec<u8> {
+    let (family, octets, max_prefix): (u16, Vec<u8>, u8) = match addr {
+        IpAddr::V4(v4) => (1, v4.octets().to_vec(), 32),
+        IpAddr::V6(v6) => (2, v6.octets().to_vec(), 128),
+    };
+    let source_prefix = source_prefix.min(max_prefix);
+    let len = (source_prefix as usize).div_ceil(8);
 
-    for _ in 0..(ancount + nscount) {
-        // Skip name
-        if pos >= query.len() {
-            return false;
+    let mut data = Vec::with_capacity(4 + len);
+    data.extend_from_slice(&family.to_be_bytes());
+    data.push(source_prefix);
+    data.push(0);
+    data.extend_from_slice(&octets[..len]);
+    if source_prefix % 8 != 0 {
+        if let Some(last) = data.last_mut() {
+            *last &= 0xFF << (8 - source_prefix % 8);
         }
+    }
+    data
+}
 
-        // Handle compression pointers
-        if (query[pos] & 0xC0) == 0xC0 {
-            pos += 2; // Skip compression pointer
-        } else {
-            // Skip labels
-            loop {
-                if pos >= query.len() {
-                    return false;
-                }
-                let len = query[pos] as usize;
-                if len == 0 {
-                    pos += 1;
-                    break;
-                }
-                pos += len + 1;
-            }
-        }
+/// Extract the client subnet from an EDNS Client Subnet option (RFC 7871).
+///
+/// # Arguments
+/// * `query` - The DNS query packet.
+///
+/// # Returns
+/// An `Option` containing the client address and source prefix length.
+pub fn extract_client_subnet(query: &[u8]) -> Option<(IpAddr, u8)> {
+    let (_, data) = extract_edns_options(query).into_iter().find(|(code, _)| *code == 8)?;
+    if data.len() < 4 {
+        return None;
+    }
+    let family = u16::from_be_bytes([data[0], data[1]]);
+    let source_prefix = data[2];
+    let address = &data[4..];
 
-        // Skip TYPE, CLASS, TTL, RDLENGTH, RDATA
-        if pos + 10 > query.len() {
-            return false;
+    match family {
+        1 if address.len() <= 4 => {
+            let mut octets = [0u8; 4];
+            octets[..address.len()].copy_from_slice(address);
+            Some((IpAddr::V4(Ipv4Addr::from(octets)), source_prefix.min(32)))
         }
-        let rdlength = ((query[pos + 8] as usize) << 8) | query[pos + 9] as usize;
-        pos += 10 + rdlength;
+        2 if address.len() <= 16 => {
+            let mut octets = [0u8; 16];
+            octets[..address.len()].copy_from_slice(address);
+            Some((IpAddr::V6(Ipv6Addr::from(octets)), source_prefix.min(128)))
+        }
+        _ => None,
     }
+}
 
-    // Check additional records for OPT
-    for _ in 0..arcount {
-        if pos >= query.len() {
-            return false;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // OPT record has empty (root) name
-        if query[pos] == 0 {
-            // Check if TYPE is OPT (41)
-            if pos + 7 < query.len() && query[pos + 1] == 0 && query[pos + 2] == 41 {
-                // Check DO bit (bit 15 of TTL field, which is used for flags in OPT)
-                return (query[pos + 6] & 0x80) != 0;
-            }
-        }
+    /// A query header with QDCOUNT 1 followed by `question`.
+    fn query_with(question: &[u8]) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        query.extend_from_slice(question);
+        query
+    }
 
-        // Skip this record
-        if pos + 10 >= query.len() { break; }
-        let rdlength = ((query[pos + 8] as usize) << 8) | query[pos + 9] as usize;
-        pos += 10 + rdlength;
+    #[test]
+    fn extract_domain_follows_compressed_qname() {
+        // "WWW" then a pointer to "Example.test" stored after QTYPE and QCLASS
+        let mut question = vec![3, b'W', b'W', b'W', 0xC0, 22, 0x00, 0x01, 0x00, 0x01];
+        question.extend_from_slice(&encode_dns_name("Example.test"));
+        let query = query_with(&question);
+
+        assert_eq!(extract_domain(&query).as_deref(), Some("www.example.test"));
+        assert_eq!(extract_query_type(&query), Some(1));
     }
 
-    false // No OPT record found or no DO bit set
-}
\ No newline at end of file
+    #[test]
+    fn extract_domain_rejects_pointer_loops() {
+        // A pointer to itself
+        let query = query_with(&[0xC0, 12, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(extract_domain(&query), None);
+
+        // Two labels pointing back at each other
+        let query = query_with(&[1, b'a', 0xC0, 20, 0x00, 0x01, 0x00, 0x01, 1, b'b', 0xC0, 12]);
+        assert_eq!(extract_domain(&query), None);
+    }
+
+    #[test]
+    fn compressed_question_is_expanded_in_place() {
+        let mut question = vec![3, b'W', b'W', b'W', 0xC0, 22, 0x00, 0x01, 0x00, 0x01];
+        question.extend_from_slice(&encode_dns_name("Example.test"));
+        let query = query_with(&question);
+
+        let expanded = expand_question(&query).unwrap();
+        let mut expected = query_with(&encode_dns_name("WWW.Example.test"));
+        expected.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        expected.extend_from_slice(&encode_dns_name("Example.test"));
+        assert_eq!(expanded, expected);
+        assert_eq!(question_end(&expanded), Some(12 + 18 + 4));
+
+        // Uncompressed and looping names are left alone
+        assert_eq!(expand_question(&query_with(&encode_dns_name("example.test"))), None);
+        assert_eq!(expand_question(&query_with(&[0xC0, 12, 0x00, 0x01, 0x00, 0x01])), None);
+    }
+
+    #[test]
+    fn question_end_rejects_truncated_questions() {
+        let mut query = query_with(&encode_dns_name("example.test"));
+        assert_eq!(question_end(&query), None);
+        query.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(question_end(&query), Some(query.len()));
+        assert_eq!(question_end(&query_with(&[0xC0])), None);
+    }
+
+    #[test]
+    fn edns_fields_are_read_after_compressed_names() {
+        // A query carrying one answer whose owner is a pointer to the QNAME
+        let mut query = query_with(&encode_dns_name("example.test"));
+        query.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        query[7] = 1; // ANCOUNT
+        query[11] = 1; // ARCOUNT
+        query.extend_from_slice(&[0xC0, 12, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x04, 192, 0, 2, 1]);
+        query.extend_from_slice(&[0x00, 0x00, 0x29, 0x04, 0xD0, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00]);
+
+        assert!(has_opt_record(&query));
+        assert_eq!(extract_edns_payload_size(&query), Some(1232));
+        assert!(extract_do_bit(&query));
+    }
+
+    #[test]
+    fn name_length_check_ignores_label_contents() {
+        assert!(validate_name_length("john\\.doe.example.test").is_ok());
+        assert!(validate_name_length("under score.example.test.").is_ok());
+        assert!(validate_dns_name("john\\.doe.example.test").is_err());
+    }
+
+    #[test]
+    fn name_length_check_rejects_oversized_names() {
+        let long_label = "a".repeat(MAX_LABEL_LENGTH + 1);
+        assert!(validate_name_length(&format!("{}.example.test", long_label)).is_err());
+        assert!(validate_name_length("www..example.test").is_err());
+
+        let long_name = vec!["a".repeat(MAX_LABEL_LENGTH); 4].join(".");
+        assert!(validate_name_length(&long_name).is_err());
+        assert!(validate_name_length(&long_name[2..]).is_ok());
+    }
+
+    #[test]
+    fn write_name_encodes_names_outside_the_strict_charset() {
+        for enabled in [false, true] {
+            let mut packet = Vec::new();
+            NameCompressor::new(enabled).write_name(&mut packet, "john\\.doe.example.test").unwrap();
+            assert_eq!(packet, encode_dns_name("john\\.doe.example.test"));
+        }
+    }
+
+    #[test]
+    fn names_may_start_labels_with_underscores() {
+        for name in ["_dmarc.example.test", "_sip._tcp.example.test", "default._domainkey.example.test"] {
+            assert!(validate_dns_name(name).is_ok(), "{}", name);
+        }
+    }
+}
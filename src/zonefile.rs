@@ -0,0 +1,455 @@
+//! Zone file support for the DNS server.
+//!
+//! This module converts zones stored in the database to standard master
+//! file (RFC 1035 presentation) format, and parses master files back into
+//! records for import.
+#![allow(dead_code)]
+
+use std::fmt::Write;
+
+use crate::config::DEFAULT_TTL;
+use crate::db::{validate_record, zone_records_page};
+use crate::errors::DnsError;
+use crate::utils::{txt_character_strings, validate_dns_name};
+
+/// Number of records read from the database per page while exporting.
+const EXPORT_PAGE_SIZE: usize = 1000;
+
+/// Export a zone from the database as a master file.
+///
+/// The output starts with `$ORIGIN` and `$TTL`, followed by the apex SOA and
+/// every other record in the zone. Owners are written relative to the
+/// origin and names inside record data are fully qualified.
+///
+/// # Arguments
+/// * `db_path` - Path to the SQLite database file.
+/// * `zone` - The zone apex.
+///
+/// # Returns
+/// A `Result` containing the zone file text or a `DnsError`.
+pub fn export(db_path: &str, zone: &str) -> Result<String, DnsError> {
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+
+    let mut soa = None;
+    let mut records = Vec::new();
+    let mut last: Option<(String, String, String)> = None;
+    loop {
+        let page = zone_records_page(db_path, &zone, last.as_ref(), EXPORT_PAGE_SIZE)?;
+        let Some((domain, _, rtype, value)) = page.last().cloned() else {
+            break;
+        };
+        last = Some((domain, rtype, value));
+
+        for record in page {
+            if record.2.eq_ignore_ascii_case("SOA") && record.0.eq_ignore_ascii_case(&zone) {
+                soa = Some(record);
+            } else {
+                records.push(record);
+            }
+        }
+    }
+
+    let soa = soa.ok_or_else(|| DnsError::Protocol(format!("Zone {} has no SOA", zone)))?;
+    let default_ttl = if soa.1 > 0 { soa.1 } else { DEFAULT_TTL };
+
+    let mut out = String::new();
+    writeln!(out, "$ORIGIN {}.", zone).ok();
+    writeln!(out, "$TTL {}", default_ttl).ok();
+    for (domain, ttl, rtype, value) in std::iter::once(soa).chain(records) {
+        let rtype = rtype.to_ascii_uppercase();
+        writeln!(
+            out,
+            "{}\t{}\tIN\t{}\t{}",
+            relative_owner(&domain, &zone),
+            ttl,
+            rtype,
+            presentation_value(&rtype, &value),
+        ).ok();
+    }
+
+    Ok(out)
+}
+
+/// Write an owner name relative to the zone origin.
+///
+/// # Arguments
+/// * `domain` - The owner name as stored in the database.
+/// * `zone` - The zone apex.
+///
+/// # Returns
+/// `@` for the apex, the leading labels for names inside the zone, or the
+/// fully qualified name otherwise.
+fn relative_owner(domain: &str, zone: &str) -> String {
+    let domain = domain.trim_end_matches('.');
+    if domain.eq_ignore_ascii_case(zone) {
+        return "@".to_string();
+    }
+
+    let lower = domain.to_ascii_lowercase();
+    match lower.strip_suffix(zone).and_then(|prefix| prefix.strip_suffix('.')) {
+        Some(_) => domain[..domain.len() - zone.len() - 1].to_string(),
+        None => format!("{}.", domain),
+    }
+}
+
+/// Fully qualify a domain name by appending the root label.
+///
+/// # Arguments
+/// * `name` - The domain name.
+///
+/// # Returns
+/// The name with a trailing dot.
+fn qualify(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+/// Render a stored record value in presentation format.
+///
+/// Names in NS, CNAME, PTR, MX, SRV and SOA data are fully qualified so they
+/// are not read relative to `$ORIGIN`; TXT data is written as quoted
+/// character-strings of at most 255 bytes.
+///
+/// # Arguments
+/// * `rtype` - The record type, in upper case.
+/// * `value` - The record value as stored in the database.
+///
+/// # Returns
+/// The record data as it appears in a zone file.
+fn presentation_value(rtype: &str, value: &str) -> String {
+    let value = value.trim();
+    let mut fields: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+
+    // Positions of the domain-name fields in each type's data
+    let name_fields: &[usize] = match rtype {
+        "NS" | "CNAME" | "PTR" => &[0],
+        "MX" => &[1],
+        "SRV" => &[3],
+        "SOA" => &[0, 1],
+        "TXT" => {
+            return txt_character_strings(value)
+                .iter()
+                .map(|string| format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+        _ => return value.to_string(),
+    };
+
+    for &index in name_fields {
+        if let Some(field) = fields.get_mut(index) {
+            *field = qualify(field);
+        }
+    }
+    fields.join(" ")
+}
+
+/// Parse a master file into records in the database's form.
+///
+/// Supports `$ORIGIN` and `$TTL`, `@` for the origin, relative and absolute
+/// names, owners inherited from the previous record, TTLs with unit
+/// suffixes (`1h30m`), comments and parenthesized continuation lines.
+/// Records without a TTL take `$TTL`, or else the previous record's TTL.
+///
+/// # Arguments
+/// * `text` - The zone file contents.
+/// * `origin` - The initial origin, used until a `$ORIGIN` directive.
+///
+/// # Returns
+/// A `Result` containing the (domain, ttl, record_type, value) tuples, or
+/// `DnsError::Config` naming the offending line.
+pub fn parse(text: &str, origin: Option<&str>) -> Result<Vec<(String, u64, String, String)>, DnsError> {
+    let mut origin = origin.map(|o| o.trim_end_matches('.').to_ascii_lowercase());
+    let mut default_ttl: Option<u64> = None;
+    let mut last_ttl: Option<u64> = None;
+    let mut last_owner: Option<String> = None;
+    let mut records = Vec::new();
+
+    for (line_no, line) in logical_lines(text)? {
+        let error = |reason: String| DnsError::Config(format!("line {}: {}", line_no, reason));
+        let inherits_owner = line.starts_with(char::is_whitespace);
+        let tokens = tokenize(&line).map_err(error)?;
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+
+        // Directives
+        if first.starts_with('$') {
+            match (first.to_ascii_uppercase().as_str(), tokens.get(1)) {
+                ("$ORIGIN", Some(name)) => {
+                    origin = Some(absolute_name(name, origin.as_deref()).map_err(error)?);
+                }
+                ("$TTL", Some(ttl)) => default_ttl = Some(parse_ttl(ttl).map_err(error)?),
+                (directive, _) => return Err(error(format!("unsupported or incomplete directive {}", directive))),
+            }
+            continue;
+        }
+
+        let mut fields = tokens.iter().map(String::as_str).peekable();
+        let owner = if inherits_owner {
+            last_owner.clone().ok_or_else(|| error("no previous owner to inherit".into()))?
+        } else {
+            absolute_name(fields.next().unwrap_or_default(), origin.as_deref()).map_err(error)?
+        };
+
+        // TTL and class may appear in either order before the type
+        let mut ttl = None;
+        let mut rtype = None;
+        for field in fields.by_ref() {
+            if field.eq_ignore_ascii_case("IN") {
+                continue;
+            }
+            if ["CH", "HS", "CS"].iter().any(|class| field.eq_ignore_ascii_case(class)) {
+                return Err(error(format!("unsupported class {}", field)));
+            }
+            if ttl.is_none() && field.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(field).map_err(error)?);
+                continue;
+            }
+            rtype = Some(field.to_ascii_uppercase());
+            break;
+        }
+        let rtype = rtype.ok_or_else(|| error("missing record type".into()))?;
+        let rdata: Vec<&str> = fields.collect();
+        if rdata.is_empty() {
+            return Err(error(format!("missing {} data", rtype)));
+        }
+
+        let ttl = ttl.or(default_ttl).or(last_ttl)
+            .ok_or_else(|| error("no TTL given and no $TTL in effect".into()))?;
+        let value = record_value(&rtype, &rdata, origin.as_deref()).map_err(error)?;
+        validate_record(&owner, &rtype, &value, ttl as i64).map_err(|e| error(e.to_string()))?;
+
+        last_owner = Some(owner.clone());
+        last_ttl = Some(ttl);
+        records.push((owner, ttl, rtype, value));
+    }
+
+    Ok(records)
+}
+
+/// Split a zone file into logical lines.
+///
+/// Comments are removed and lines inside parentheses are joined, keeping
+/// the number of the line each logical line starts on.
+///
+/// # Arguments
+/// * `text` - The zone file contents.
+///
+/// # Returns
+/// A `Result` containing (line number, line) pairs, or `DnsError::Config`
+/// for unbalanced parentheses or quotes.
+fn logical_lines(text: &str) -> Result<Vec<(usize, String)>, DnsError> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line_no = index + 1;
+        if depth == 0 {
+            start = line_no;
+        } else {
+            current.push(' ');
+        }
+
+        let mut in_quotes = false;
+        let mut escaped = false;
+        for c in raw.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = !in_quotes,
+                ';' if !in_quotes => break,
+                '(' if !in_quotes => {
+                    depth += 1;
+                    current.push(' ');
+                    continue;
+                }
+                ')' if !in_quotes => {
+                    depth = depth.checked_sub(1)
+                        .ok_or_else(|| DnsError::Config(format!("line {}: unbalanced ')'", line_no)))?;
+                    current.push(' ');
+                    continue;
+                }
+                _ => {}
+            }
+            current.push(c);
+        }
+        if in_quotes {
+            return Err(DnsError::Config(format!("line {}: unterminated quoted string", line_no)));
+        }
+
+        if depth == 0 {
+            if !current.trim().is_empty() {
+                lines.push((start, std::mem::take(&mut current)));
+            }
+            current.clear();
+        }
+    }
+
+    if depth > 0 {
+        return Err(DnsError::Config(format!("line {}: unbalanced '('", start)));
+    }
+    Ok(lines)
+}
+
+/// Split a logical line into fields, keeping quoted strings whole.
+///
+/// # Arguments
+/// * `line` - The logical line.
+///
+/// # Returns
+/// A `Result` containing the fields, quoted ones with their quotes.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut token = String::new();
+        if c == '"' {
+            token.push(chars.next().unwrap_or('"'));
+            loop {
+                match chars.next() {
+                    Some('\\') => {
+                        token.push('\\');
+                        token.push(chars.next().ok_or("dangling escape")?);
+                    }
+                    Some('"') => {
+                        token.push('"');
+                        break;
+                    }
+                    Some(c) => token.push(c),
+                    None => return Err("unterminated quoted string".into()),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Resolve a name from a zone file against the origin.
+///
+/// # Arguments
+/// * `name` - The name as written: `@`, relative, or absolute with a trailing dot.
+/// * `origin` - The current origin.
+///
+/// # Returns
+/// A `Result` containing the fully qualified name in lower case, without the
+/// trailing dot, or why it is not a valid domain name.
+fn absolute_name(name: &str, origin: Option<&str>) -> Result<String, String> {
+    let absolute = if let Some(absolute) = name.strip_suffix('.') {
+        absolute.to_ascii_lowercase()
+    } else {
+        let origin = origin.ok_or_else(|| format!("relative name {} without $ORIGIN", name))?;
+        if name == "@" {
+            origin.to_string()
+        } else if origin.is_empty() {
+            name.to_ascii_lowercase()
+        } else {
+            format!("{}.{}", name.to_ascii_lowercase(), origin)
+        }
+    };
+    match validate_dns_name(&absolute) {
+        Err(DnsError::Config(reason)) => Err(reason),
+        Err(other) => Err(other.to_string()),
+        Ok(()) => Ok(absolute),
+    }
+}
+
+/// Parse a TTL, accepting BIND unit suffixes such as `1h30m` or `2d`.
+///
+/// # Arguments
+/// * `text` - The TTL as written.
+///
+/// # Returns
+/// A `Result` containing the TTL in seconds.
+fn parse_ttl(text: &str) -> Result<u64, String> {
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Ok(seconds);
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(format!("invalid TTL {}", text)),
+        };
+        let value: u64 = number.parse().map_err(|_| format!("invalid TTL {}", text))?;
+        total = total.saturating_add(value.saturating_mul(unit));
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("invalid TTL {}", text));
+    }
+    Ok(total)
+}
+
+/// Convert a record's data fields to the value stored in the database.
+///
+/// Names are resolved against the origin and stored without the trailing
+/// dot, SOA timers are converted to seconds and TXT data is stored as
+/// quoted character-strings.
+///
+/// # Arguments
+/// * `rtype` - The record type, in upper case.
+/// * `rdata` - The data fields.
+/// * `origin` - The current origin.
+///
+/// # Returns
+/// A `Result` containing the stored value.
+fn record_value(rtype: &str, rdata: &[&str], origin: Option<&str>) -> Result<String, String> {
+    let mut fields: Vec<String> = rdata.iter().map(|field| field.to_string()).collect();
+
+    // Positions of the domain-name fields in each type's data
+    let name_fields: &[usize] = match rtype {
+        "NS" | "CNAME" | "PTR" => &[0],
+        "MX" => &[1],
+        "SRV" => &[3],
+        "SOA" => &[0, 1],
+        "TXT" => {
+            return Ok(fields.iter()
+                .map(|field| if field.starts_with('"') { field.clone() } else { format!("\"{}\"", field) })
+                .collect::<Vec<_>>()
+                .join(" "));
+        }
+        _ => &[],
+    };
+
+    for &index in name_fields {
+        if let Some(field) = fields.get_mut(index) {
+            *field = absolute_name(field, origin)?;
+        }
+    }
+    if rtype == "SOA" {
+        for field in fields.iter_mut().skip(3) {
+            *field = parse_ttl(field)?.to_string();
+        }
+    }
+    Ok(fields.join(" "))
+}
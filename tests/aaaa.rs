@@ -0,0 +1,26 @@
+mod common;
+
+use std::net::Ipv6Addr;
+
+use common::{config, counts, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::skip_name;
+
+#[tokio::test]
+async fn stored_aaaa_is_answered_with_16_byte_rdata() {
+    let config = config(&zone_db("aaaa-wire", &[
+        ("dual.example.test", "A", "192.0.2.70", 300),
+        ("dual.example.test", "AAAA", "2001:db8::70", 300),
+    ]));
+
+    let response = resolve(&query("dual.example.test", 28), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 1);
+
+    let answer = skip_name(&response, 12).unwrap() + 4;
+    let fixed = skip_name(&response, answer).unwrap();
+    assert_eq!(response[fixed..fixed + 4], [0, 28, 0, 1]);
+    assert_eq!(response[fixed + 8..fixed + 10], [0, 16]);
+    let expected: Ipv6Addr = "2001:db8::70".parse().unwrap();
+    assert_eq!(response[fixed + 10..fixed + 26], expected.octets());
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+
+use common::{config, counts, query, zone_db};
+use nx9_dns_server::config::{AnswerOrder, ServerConfig};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+use nx9_dns_server::handlers::answer_query_from;
+use nx9_dns_server::utils::{read_name, skip_name};
+
+/// A sticky configuration serving four addresses for `pool.example.test`.
+fn sticky_config(name: &str) -> ServerConfig {
+    let mut config = config(&zone_db(name, &[
+        ("pool.example.test", "A", "192.0.2.1", 300),
+        ("pool.example.test", "A", "192.0.2.2", 300),
+        ("pool.example.test", "A", "192.0.2.3", 300),
+        ("pool.example.test", "A", "192.0.2.4", 300),
+    ]));
+    config.answer_order = AnswerOrder::Sticky;
+    config
+}
+
+/// The first address answered to a client at `client`.
+async fn first_address(client: IpAddr, config: &ServerConfig) -> String {
+    let src = SocketAddr::new(client, 5300);
+    let response = answer_query_from(&query("pool.example.test", 1), src, Transport::Udp, config).await.unwrap().unwrap();
+    let addresses: Vec<String> = extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect();
+    assert_eq!(addresses.len(), 4);
+    addresses[0].clone()
+}
+
+#[tokio::test]
+async fn sticky_order_gives_a_client_the_same_first_address() {
+    let config = sticky_config("sticky-same-client");
+    let client: IpAddr = "198.51.100.7".parse().unwrap();
+
+    let first = first_address(client, &config).await;
+    for _ in 0..10 {
+        assert_eq!(first_address(client, &config).await, first);
+    }
+}
+
+#[tokio::test]
+async fn sticky_order_spreads_clients_across_addresses() {
+    let config = sticky_config("sticky-spread");
+
+    let mut seen = HashSet::new();
+    for host in 1..=64u8 {
+        seen.insert(first_address(IpAddr::from([198, 51, 100, host]), &config).await);
+    }
+    assert!(seen.len() > 1, "every client got {:?}", seen);
+}
+
+/// A configuration serving three NS records at the zone apex.
+fn ns_config(name: &str, order: AnswerOrder) -> ServerConfig {
+    let mut config = config(&zone_db(name, &[
+        ("example.test", "NS", "ns2.example.test", 3600),
+        ("example.test", "NS", "ns3.example.test", 3600),
+    ]));
+    config.answer_order = order;
+    config
+}
+
+/// The NS targets answered to a client at `client`, in answer order.
+async fn name_servers(client: IpAddr, config: &ServerConfig) -> Vec<String> {
+    let src = SocketAddr::new(client, 5300);
+    let response = answer_query_from(&query("example.test", 2), src, Transport::Udp, config).await.unwrap().unwrap();
+    let mut pos = skip_name(&response, 12).unwrap() + 4;
+    let mut targets = Vec::new();
+    for _ in 0..counts(&response)[1] {
+        pos = skip_name(&response, pos).unwrap();
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        targets.push(read_name(&response, pos + 10).unwrap().0);
+        pos += 10 + rdlength;
+    }
+    targets
+}
+
+#[tokio::test]
+async fn sticky_order_applies_to_ns_answers() {
+    let config = ns_config("sticky-ns", AnswerOrder::Sticky);
+    let client: IpAddr = "198.51.100.7".parse().unwrap();
+
+    let order = name_servers(client, &config).await;
+    assert_eq!(order.len(), 3);
+    for _ in 0..10 {
+        assert_eq!(name_servers(client, &config).await, order);
+    }
+
+    let mut firsts = HashSet::new();
+    for host in 1..=64u8 {
+        firsts.insert(name_servers(IpAddr::from([198, 51, 100, host]), &config).await[0].clone());
+    }
+    assert!(firsts.len() > 1, "every client got {:?}", firsts);
+}
+
+#[tokio::test]
+async fn round_robin_order_rotates_ns_answers() {
+    let config = ns_config("round-robin-ns", AnswerOrder::RoundRobin);
+    let client: IpAddr = "198.51.100.7".parse().unwrap();
+
+    let mut firsts = HashSet::new();
+    for _ in 0..12 {
+        let order = name_servers(client, &config).await;
+        assert_eq!(order.len(), 3);
+        firsts.insert(order[0].clone());
+    }
+    assert!(firsts.len() > 1, "NS answers never rotated: {:?}", firsts);
+}
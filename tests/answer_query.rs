@@ -0,0 +1,44 @@
+mod common;
+
+use common::{config, counts, edns_query, query, rcode, zone_db};
+use nx9_dns_server::dns::extract_answer_records;
+use nx9_dns_server::handlers::answer_query;
+
+#[tokio::test]
+async fn query_bytes_are_answered_without_sockets() {
+    let config = config(&zone_db("answer-query", &[("www.example.test", "A", "192.0.2.120", 300)]));
+    let query = query("www.example.test", 1);
+
+    let response = answer_query(&query, &config).await.unwrap();
+    assert_eq!(&response[..2], &query[..2]);
+    assert_eq!(rcode(&response), 0);
+    let addresses: Vec<String> = extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect();
+    assert_eq!(addresses, vec!["192.0.2.120"]);
+}
+
+#[tokio::test]
+async fn unknown_name_gets_nxdomain() {
+    let config = config(&zone_db("answer-query-nxdomain", &[]));
+
+    let response = answer_query(&query("missing.example.test", 1), &config).await.unwrap();
+    assert_eq!(rcode(&response), 3);
+    assert_eq!(counts(&response)[1], 0);
+}
+
+#[tokio::test]
+async fn large_answer_is_not_truncated() {
+    let addresses: Vec<String> = (1..=60).map(|i| format!("192.0.2.{}", i)).collect();
+    let records: Vec<_> = addresses.iter().map(|ip| ("big.example.test", "A", ip.as_str(), 300)).collect();
+    let config = config(&zone_db("answer-query-large", &records));
+
+    let response = answer_query(&edns_query("big.example.test", 1, 512, false), &config).await.unwrap();
+    assert_eq!(response[2] & 0x02, 0, "TC set");
+    assert_eq!(extract_answer_records(&response).len(), 60);
+}
+
+#[tokio::test]
+async fn query_shorter_than_a_header_is_an_error() {
+    let config = config(&zone_db("answer-query-short", &[]));
+
+    assert!(answer_query(&[0x12, 0x34, 0x01], &config).await.is_err());
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use tokio::io::AsyncWrite;
+
+use common::{config, query, zone_db, ZONE};
+use nx9_dns_server::db::get_zones;
+use nx9_dns_server::dns::{transfer_zone, AXFR_MESSAGE_SIZE};
+
+/// The system allocator, tracking the bytes currently allocated and their peak.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let now = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(now, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+/// A stream that checks each length-prefixed message and then discards it.
+#[derive(Default)]
+struct MessageSink {
+    pending: Vec<u8>,
+    messages: usize,
+    records: usize,
+    largest: usize,
+}
+
+impl AsyncWrite for MessageSink {
+    fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.pending.extend_from_slice(buf);
+        while self.pending.len() >= 2 {
+            let len = u16::from_be_bytes([self.pending[0], self.pending[1]]) as usize;
+            if self.pending.len() < 2 + len {
+                break;
+            }
+            let message: Vec<u8> = self.pending.drain(..2 + len).skip(2).collect();
+            self.messages += 1;
+            self.records += u16::from_be_bytes([message[6], message[7]]) as usize;
+            self.largest = self.largest.max(len);
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn large_zone_transfer_streams_in_bounded_memory() {
+    const HOSTS: usize = 20_000;
+    let names: Vec<String> = (0..HOSTS).map(|i| format!("host{}.example.test", i)).collect();
+    let addresses: Vec<String> = (0..HOSTS).map(|i| format!("10.{}.{}.{}", i >> 16, (i >> 8) & 0xFF, i & 0xFF)).collect();
+    let records: Vec<_> = names.iter().zip(&addresses)
+        .map(|(name, ip)| (name.as_str(), "A", ip.as_str(), 300))
+        .collect();
+    let config = config(&zone_db("axfr-large", &records));
+    let zone = get_zones(&config).into_iter().find(|z| z.name == ZONE).unwrap();
+    drop(records);
+
+    let mut sink = MessageSink::default();
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    transfer_zone(&mut sink, &query(ZONE, 252), &zone, &config).await.unwrap();
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+
+    // Both SOAs, the apex NS, ns1's address and every host
+    assert_eq!(sink.records, HOSTS + 4);
+    assert!(sink.messages > 1);
+    assert!(sink.largest <= AXFR_MESSAGE_SIZE);
+    // The zone's records alone would take well over a megabyte held at once
+    assert!(peak < 512 * 1024, "peak allocation {} bytes", peak);
+}
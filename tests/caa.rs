@@ -0,0 +1,47 @@
+mod common;
+
+use common::{answer_types_and_ttls, config, counts, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::{read_name, skip_name};
+
+#[tokio::test]
+async fn deep_subdomain_inherits_apex_caa_under_its_own_name() {
+    let config = config(&zone_db("caa-inherit", &[("example.test", "CAA", "0 issue \"ca.example.net\"", 600)]));
+
+    let response = resolve(&query("a.b.c.example.test", 257), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(answer_types_and_ttls(&response), vec![(257, 600)]);
+
+    let answer = skip_name(&response, 12).unwrap() + 4;
+    assert_eq!(read_name(&response, answer).unwrap().0, "a.b.c.example.test");
+    assert!(response.windows(14).any(|w| w == b"ca.example.net"));
+}
+
+#[tokio::test]
+async fn caa_below_the_apex_takes_precedence() {
+    let config = config(&zone_db("caa-closest", &[
+        ("example.test", "CAA", "0 issue \"ca.example.net\"", 600),
+        ("c.example.test", "CAA", "0 issue \"other-ca.example.net\"", 600),
+    ]));
+
+    let response = resolve(&query("a.b.c.example.test", 257), Transport::Udp, &config).await;
+    assert_eq!(counts(&response)[1], 1);
+    assert!(response.windows(20).any(|w| w == b"other-ca.example.net"));
+}
+
+#[tokio::test]
+async fn every_caa_record_at_a_name_is_answered() {
+    let config = config(&zone_db("caa-rrset", &[
+        ("example.test", "CAA", "0 issue \"ca.example.net\"", 600),
+        ("example.test", "CAA", "0 iodef \"mailto:security@example.test\"", 600),
+    ]));
+
+    let response = resolve(&query("example.test", 257), Transport::Udp, &config).await;
+    assert_eq!(answer_types_and_ttls(&response), vec![(257, 600), (257, 600)]);
+    assert!(response.windows(14).any(|w| w == b"ca.example.net"));
+    assert!(response.windows(28).any(|w| w == b"mailto:security@example.test"));
+
+    // An inherited RRset is answered whole too
+    let inherited = resolve(&query("www.example.test", 257), Transport::Udp, &config).await;
+    assert_eq!(answer_types_and_ttls(&inherited), vec![(257, 600), (257, 600)]);
+}
@@ -0,0 +1,61 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use nx9_dns_server::cache::DnsCache;
+
+/// Store an A record for `domain`, then wait so access times differ.
+fn store(cache: &DnsCache, domain: &str) {
+    cache.set(domain.to_string(), 1, "192.0.2.70".to_string(), 300).unwrap();
+    sleep(Duration::from_millis(5));
+}
+
+/// Whether `domain` is still cached.
+fn cached(cache: &DnsCache, domain: &str) -> bool {
+    cache.get(domain, 1).unwrap().is_some()
+}
+
+#[test]
+fn inserting_over_the_cap_evicts_the_oldest_entries() {
+    let cache = DnsCache::new(Vec::new()).with_max_entries(3);
+    for i in 0..5 {
+        store(&cache, &format!("host{}.example.net", i));
+    }
+
+    assert_eq!(cache.entries.lock().unwrap().len(), 3);
+    assert!(!cached(&cache, "host0.example.net"));
+    assert!(!cached(&cache, "host1.example.net"));
+    assert!(cached(&cache, "host2.example.net"));
+    assert!(cached(&cache, "host3.example.net"));
+    assert!(cached(&cache, "host4.example.net"));
+}
+
+#[test]
+fn recently_read_entry_survives_eviction() {
+    let cache = DnsCache::new(Vec::new()).with_max_entries(3);
+    store(&cache, "a.example.net");
+    store(&cache, "b.example.net");
+    store(&cache, "c.example.net");
+
+    // Reading "a" makes "b" the least recently used
+    assert!(cached(&cache, "a.example.net"));
+    sleep(Duration::from_millis(5));
+    store(&cache, "d.example.net");
+
+    assert!(cached(&cache, "a.example.net"));
+    assert!(!cached(&cache, "b.example.net"));
+    assert!(cached(&cache, "c.example.net"));
+    assert!(cached(&cache, "d.example.net"));
+}
+
+#[test]
+fn negative_markers_are_capped_too() {
+    let cache = DnsCache::new(Vec::new()).with_max_entries(2);
+    for i in 0..4 {
+        cache.set_negative(format!("gone{}.example.net", i), 300).unwrap();
+        sleep(Duration::from_millis(5));
+    }
+
+    assert_eq!(cache.nxdomain.lock().unwrap().len(), 2);
+    assert!(cache.get_negative("gone0.example.net", 1).unwrap().is_none());
+    assert!(cache.get_negative("gone3.example.net", 1).unwrap().is_some());
+}
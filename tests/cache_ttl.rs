@@ -0,0 +1,38 @@
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use common::{answer_types_and_ttls, cache, config, query, resolve, zone_db};
+use nx9_dns_server::cache::DnsCache;
+use nx9_dns_server::dns::Transport;
+
+/// Move a cached entry's insertion time `secs` seconds into the past.
+fn age_entry(cache: &DnsCache, domain: &str, rtype: u16, secs: u64) {
+    let mut entries = cache.entries.lock().unwrap();
+    let entry = entries.get_mut(&(domain.to_string(), rtype)).expect("cached entry");
+    entry.inserted = SystemTime::now() - Duration::from_secs(secs);
+}
+
+#[test]
+fn cached_ttl_counts_down_to_expiry() {
+    let cache = DnsCache::new(Vec::new());
+    cache.set("aged.example.net".to_string(), 1, "192.0.2.60".to_string(), 600).unwrap();
+    assert_eq!(cache.get("aged.example.net", 1).unwrap(), Some(("192.0.2.60".to_string(), 600)));
+
+    age_entry(&cache, "aged.example.net", 1, 500);
+    assert_eq!(cache.get("aged.example.net", 1).unwrap(), Some(("192.0.2.60".to_string(), 100)));
+
+    age_entry(&cache, "aged.example.net", 1, 601);
+    assert_eq!(cache.get("aged.example.net", 1).unwrap(), None);
+}
+
+#[tokio::test]
+async fn answer_from_cache_carries_the_remaining_ttl() {
+    let mut config = config(&zone_db("cache-ttl-countdown", &[]));
+    config.prefetch_threshold_percent = 0;
+    cache().set("countdown.example.net".to_string(), 1, "192.0.2.61".to_string(), 600).unwrap();
+    age_entry(cache(), "countdown.example.net", 1, 500);
+
+    let response = resolve(&query("countdown.example.net", 1), Transport::Udp, &config).await;
+    assert_eq!(answer_types_and_ttls(&response), vec![(1, 100)]);
+}
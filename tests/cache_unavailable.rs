@@ -0,0 +1,42 @@
+//! Queries answered while the global cache is unavailable.
+//!
+//! This binary never creates the cache, so every cache access fails with
+//! `DnsError::Cache`; it must not use `common::config`, which creates it.
+
+mod common;
+
+use common::{counts, query, rcode, resolve, zone_db, ZONE};
+use nx9_dns_server::cache::{global, CACHE};
+use nx9_dns_server::config::ServerConfig;
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+use nx9_dns_server::errors::DnsError;
+
+/// An authoritative configuration serving `db_path` without creating the cache.
+fn uncached_config(db_path: &str) -> ServerConfig {
+    let mut config = ServerConfig::from_env().unwrap();
+    config.db_path = db_path.to_string();
+    config.default_domain = ZONE.to_string();
+    config.authoritative = true;
+    config.forwarders = Vec::new();
+    config.response_jitter_ms = 0;
+    config.negative_cache_max_ttl = 3600;
+    config
+}
+
+#[tokio::test]
+async fn query_resolves_from_the_database_when_the_cache_fails() {
+    let config = uncached_config(&zone_db("cache-unavailable", &[("www.example.test", "A", "192.0.2.50", 300)]));
+    assert!(CACHE.get().is_none());
+    assert!(matches!(global(), Err(DnsError::Cache(_))));
+
+    for _ in 0..2 {
+        let response = resolve(&query("www.example.test", 1), Transport::Udp, &config).await;
+        assert_eq!(rcode(&response), 0);
+        let addresses: Vec<String> = extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect();
+        assert_eq!(addresses, vec!["192.0.2.50"]);
+    }
+
+    let response = resolve(&query("missing.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+    assert_eq!(counts(&response)[1], 0);
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::{config, fake_forwarder, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+#[tokio::test]
+async fn cname_to_external_name_is_completed_through_the_forwarder() {
+    let forwarder = fake_forwarder(vec![("www.external.test", "A", "203.0.113.5")]).await;
+    let mut config = config(&zone_db("cname-external", &[("alias.example.test", "CNAME", "www.external.test", 300)]));
+    config.forwarders = vec![forwarder];
+    config.allow_recursion = true;
+
+    let response = resolve(&query("alias.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    let answers: Vec<(String, u16, String)> = extract_answer_records(&response).into_iter()
+        .map(|(owner, rtype, _, value)| (owner, rtype, value))
+        .collect();
+    assert_eq!(answers, vec![
+        ("alias.example.test".to_string(), 5, "www.external.test".to_string()),
+        ("www.external.test".to_string(), 1, "203.0.113.5".to_string()),
+    ]);
+}
+
+#[tokio::test]
+async fn cname_to_external_name_is_not_forwarded_without_rd() {
+    let forwarder = fake_forwarder(vec![("www.external-nord.test", "A", "203.0.113.6")]).await;
+    let mut config = config(&zone_db("cname-external-nord", &[("alias.example.test", "CNAME", "www.external-nord.test", 300)]));
+    config.forwarders = vec![forwarder];
+    config.allow_recursion = true;
+
+    let mut query = query("alias.example.test", 1);
+    query[2] &= !0x01;
+    let response = resolve(&query, Transport::Udp, &config).await;
+    let types: Vec<u16> = extract_answer_records(&response).into_iter().map(|(_, rtype, _, _)| rtype).collect();
+    assert_eq!(types, vec![5]);
+}
@@ -0,0 +1,156 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use rusqlite::{params, Connection};
+use tokio::net::UdpSocket;
+
+use nx9_dns_server::cache::{DnsCache, CACHE};
+use nx9_dns_server::config::ServerConfig;
+use nx9_dns_server::db::init_db;
+use nx9_dns_server::dns::{encode_record, Transport};
+use nx9_dns_server::handlers::answer_query_from;
+use nx9_dns_server::utils::{encode_dns_name, extract_edns_options, skip_name};
+
+/// Zone used by the test databases.
+pub const ZONE: &str = "example.test";
+
+/// Create an empty database holding the records given as (domain, type, value, ttl).
+///
+/// Each test passes its own `name` so tests running in parallel never share a
+/// file. Records are inserted as stored, without validation.
+pub fn test_db(name: &str, records: &[(&str, &str, &str, i64)]) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!("nx9-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_string_lossy().into_owned();
+    init_db(&path, ZONE, "").expect("create test database");
+
+    let mut conn = Connection::open(&path).expect("open test database");
+    let tx = conn.transaction().expect("begin test transaction");
+    for (domain, rtype, value, ttl) in records {
+        tx.execute(
+            "INSERT INTO dns_records (domain, record_type, value, ttl) VALUES (?1, ?2, ?3, ?4)",
+            params![domain, rtype, value, ttl],
+        ).expect("insert test record");
+    }
+    tx.commit().expect("commit test records");
+    path
+}
+
+/// Create a database holding the SOA and NS set of [`ZONE`] plus `records`.
+pub fn zone_db(name: &str, records: &[(&str, &str, &str, i64)]) -> String {
+    let mut all = vec![
+        (ZONE, "SOA", "ns1.example.test hostmaster.example.test 1 10800 3600 604800 300", 3600),
+        (ZONE, "NS", "ns1.example.test", 3600),
+        ("ns1.example.test", "A", "192.0.2.53", 3600),
+    ];
+    all.extend_from_slice(records);
+    test_db(name, &all)
+}
+
+/// The global cache, created on first use.
+pub fn cache() -> &'static DnsCache {
+    CACHE.get_or_init(|| DnsCache::new(Vec::new()))
+}
+
+/// An authoritative configuration serving `db_path` that never forwards.
+///
+/// The global cache is created so answers can be cached.
+pub fn config(db_path: &str) -> ServerConfig {
+    cache();
+    let mut config = ServerConfig::from_env().expect("default configuration");
+    config.db_path = db_path.to_string();
+    config.default_domain = ZONE.to_string();
+    config.authoritative = true;
+    config.forwarders = Vec::new();
+    config.response_jitter_ms = 0;
+    config
+}
+
+/// Build a query without an OPT record.
+pub fn query(name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    query.extend_from_slice(&encode_dns_name(name));
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&[0x00, 0x01]);
+    query
+}
+
+/// Build a query carrying an OPT record with the given payload size and DO bit.
+pub fn edns_query(name: &str, qtype: u16, payload_size: u16, do_bit: bool) -> Vec<u8> {
+    let mut query = query(name, qtype);
+    query[11] = 1; // ARCOUNT
+    query.extend_from_slice(&[0x00, 0x00, 0x29]);
+    query.extend_from_slice(&payload_size.to_be_bytes());
+    query.extend_from_slice(&[0x00, 0x00, if do_bit { 0x80 } else { 0x00 }, 0x00, 0x00, 0x00]);
+    query
+}
+
+/// Answer `query` as if it arrived from 127.0.0.1 over `transport`.
+pub async fn resolve(query: &[u8], transport: Transport, config: &ServerConfig) -> Vec<u8> {
+    let src = SocketAddr::from(([127, 0, 0, 1], 5300));
+    answer_query_from(query, src, transport, config).await
+        .expect("query answered")
+        .expect("query not ignored")
+}
+
+/// The RCODE of a response.
+pub fn rcode(response: &[u8]) -> u8 {
+    response[3] & 0x0F
+}
+
+/// The QDCOUNT, ANCOUNT, NSCOUNT and ARCOUNT of a response.
+pub fn counts(response: &[u8]) -> [u16; 4] {
+    [4, 6, 8, 10].map(|i| u16::from_be_bytes([response[i], response[i + 1]]))
+}
+
+/// The Extended DNS Error (INFO-CODE, EXTRA-TEXT) options carried in a response.
+pub fn extended_errors(response: &[u8]) -> Vec<(u16, String)> {
+    extract_edns_options(response).into_iter()
+        .filter(|(code, data)| *code == 15 && data.len() >= 2)
+        .map(|(_, data)| (u16::from_be_bytes([data[0], data[1]]), String::from_utf8_lossy(&data[2..]).into_owned()))
+        .collect()
+}
+
+/// The (type, TTL) of every record in the answer section, whatever its type.
+pub fn answer_types_and_ttls(response: &[u8]) -> Vec<(u16, u32)> {
+    let mut pos = skip_name(response, 12).expect("question name") + 4;
+    let mut records = Vec::new();
+    for _ in 0..counts(response)[1] {
+        pos = skip_name(response, pos).expect("owner name");
+        let fixed = &response[pos..pos + 10];
+        records.push((
+            u16::from_be_bytes([fixed[0], fixed[1]]),
+            u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+        ));
+        pos += 10 + u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+    }
+    records
+}
+
+/// Start a forwarder that answers every query with `records` appended to the
+/// question, and return its address.
+pub async fn fake_forwarder(records: Vec<(&'static str, &'static str, &'static str)>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind fake forwarder");
+    let addr = socket.local_addr().expect("fake forwarder address");
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+            let mut response = buf[..len].to_vec();
+            response[2] |= 0x80;
+            response[3] = 0x80;
+            response[6..12].copy_from_slice(&[0, records.len() as u8, 0, 0, 0, 0]);
+            // Drop any OPT record from the echoed question
+            let question_end = skip_name(&response, 12).expect("question") + 4;
+            response.truncate(question_end);
+            for (owner, rtype, value) in &records {
+                response.extend_from_slice(&encode_record(owner, rtype, 300, value).expect("encode record"));
+            }
+            let _ = socket.send_to(&response, src).await;
+        }
+    });
+    addr
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{config, counts, rcode, zone_db};
+use nx9_dns_server::dns::extract_answer_records;
+use nx9_dns_server::handlers::answer_query;
+use nx9_dns_server::utils::{encode_dns_name, read_name};
+
+/// A query whose QNAME is a pointer to `name`, stored after QTYPE and QCLASS.
+fn compressed_query(name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    query.extend_from_slice(&[0xC0, 18]);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&[0x00, 0x01]);
+    query.extend_from_slice(&encode_dns_name(name));
+    query
+}
+
+/// Check the response's question spells `name` out in full and return the response.
+async fn answer(name: &str, qtype: u16, db: &str) -> Vec<u8> {
+    let response = answer_query(&compressed_query(name, qtype), &config(db)).await.unwrap();
+    assert_eq!(counts(&response)[0], 1);
+    assert_ne!(response[12] & 0xC0, 0xC0, "question still compressed");
+    let (qname, end) = read_name(&response, 12).unwrap();
+    assert_eq!(qname, name);
+    assert_eq!(&response[end..end + 2], &qtype.to_be_bytes());
+    response
+}
+
+#[tokio::test]
+async fn compressed_queries_are_answered() {
+    let db = zone_db("compressed-query", &[
+        ("www.example.test", "A", "192.0.2.130", 300),
+        ("example.test", "CAA", "0 issue \"ca.example.net\"", 300),
+    ]);
+
+    let response = answer("www.example.test", 1, &db).await;
+    assert_eq!(rcode(&response), 0);
+    let addresses: Vec<String> = extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect();
+    assert_eq!(addresses, vec!["192.0.2.130"]);
+
+    let response = answer("example.test", 257, &db).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 1);
+
+    let response = answer("nope.example.test", 1, &db).await;
+    assert_eq!(rcode(&response), 3);
+    assert_eq!(counts(&response)[1], 0);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{config, counts, query, rcode, resolve, zone_db};
+use nx9_dns_server::config::MinimalResponses;
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::{read_name, skip_name};
+
+/// Every (owner, type, rdata-name) in the answer, authority and additional
+/// sections, reading NS rdata as a name.
+fn parse_records(response: &[u8]) -> Vec<(String, u16, Option<String>)> {
+    let [_, an, ns, ar] = counts(response);
+    let mut pos = skip_name(response, 12).unwrap() + 4;
+    let mut records = Vec::new();
+    for _ in 0..an + ns + ar {
+        let (owner, fixed) = read_name(response, pos).unwrap();
+        let rtype = u16::from_be_bytes([response[fixed], response[fixed + 1]]);
+        let rdlength = u16::from_be_bytes([response[fixed + 8], response[fixed + 9]]) as usize;
+        let target = (rtype == 2).then(|| read_name(response, fixed + 10).unwrap().0);
+        records.push((owner, rtype, target));
+        pos = fixed + 10 + rdlength;
+    }
+    assert_eq!(pos, response.len());
+    records
+}
+
+/// How often the uncompressed labels of the zone name appear in a packet.
+fn zone_name_copies(response: &[u8]) -> usize {
+    let zone = b"\x07example\x04test\x00";
+    response.windows(zone.len()).filter(|window| window == zone).count()
+}
+
+#[tokio::test]
+async fn repeated_names_across_sections_are_compressed_and_reparse() {
+    let db = zone_db("compress-sections", &[("www.example.test", "A", "192.0.2.90", 300)]);
+    let mut config = config(&db);
+    config.minimal_responses = MinimalResponses::No;
+    config.compress_names = true;
+
+    let response = resolve(&query("www.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    // Only the question spells the zone name out
+    assert_eq!(zone_name_copies(&response), 1);
+
+    let records = parse_records(&response);
+    assert!(records.contains(&("www.example.test".to_string(), 1, None)));
+    assert!(records.contains(&("example.test".to_string(), 2, Some("ns1.example.test".to_string()))));
+    assert!(records.contains(&("ns1.example.test".to_string(), 1, None)));
+
+    // The same answer without compression parses to the same records
+    config.compress_names = false;
+    let uncompressed = resolve(&query("www.example.test", 1), Transport::Udp, &config).await;
+    assert!(uncompressed.len() > response.len());
+    assert_eq!(parse_records(&uncompressed), records);
+}
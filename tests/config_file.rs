@@ -0,0 +1,55 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use nx9_dns_server::config::{ResolutionSource, ServerConfig};
+
+/// Path of the example config shipped with the repository.
+fn example_config() -> String {
+    format!("{}/config.example.toml", env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn example_config_file_loads_its_values() {
+    let config = ServerConfig::from_file(&example_config()).unwrap();
+
+    assert_eq!(config.bind_addr, "0.0.0.0:53".parse::<SocketAddr>().unwrap());
+    assert_eq!(config.db_path, "/var/nx9-dns-server/dns.db");
+    assert!(config.authoritative);
+    assert_eq!(config.default_domain, "yourdomain.tld");
+    assert_eq!(config.forwarders, vec![
+        "8.8.8.8:53".parse::<SocketAddr>().unwrap(),
+        "1.1.1.1:53".parse().unwrap(),
+        "9.9.9.9:53".parse().unwrap(),
+    ]);
+    assert_eq!(config.forward_timeout, Duration::from_millis(2000));
+    assert_eq!(config.resolution_order, vec![
+        ResolutionSource::Cache,
+        ResolutionSource::Db,
+        ResolutionSource::Static,
+        ResolutionSource::Forward,
+    ]);
+    assert_eq!(config.ns_records.len(), 2);
+    assert_eq!(config.ds_records, vec![
+        "yourdomain.tld. IN DS 24550 8 2 1F21CA282945434EE0662805430599CB2A6C479D9F934087150901CE2DA580A0",
+    ]);
+    assert!(config.static_records.contains_key("_dmarc.yourdomain.tld"));
+    assert_eq!(config.axfr_allow, vec![("192.0.2.0".parse().unwrap(), 24)]);
+    assert_eq!(config.negative_cache_max_ttl, 3600);
+}
+
+#[test]
+fn environment_overrides_config_file_values() {
+    std::env::set_var("DNS_DEFAULT_IP", "198.51.100.10");
+    let config = ServerConfig::from_file(&example_config()).unwrap();
+
+    assert_eq!(config.default_ip, "198.51.100.10");
+    assert_eq!(config.default_domain, "yourdomain.tld");
+}
+
+#[test]
+fn unknown_config_file_setting_is_rejected() {
+    let path = std::env::temp_dir().join(format!("nx9-unknown-setting-{}.toml", std::process::id()));
+    std::fs::write(&path, "bind_address = \"0.0.0.0:53\"\n").unwrap();
+
+    assert!(ServerConfig::from_file(&path.to_string_lossy()).is_err());
+}
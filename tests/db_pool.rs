@@ -0,0 +1,18 @@
+mod common;
+
+use common::test_db;
+use nx9_dns_server::db::try_lookup_records;
+
+#[cfg(unix)]
+#[test]
+fn lookups_reuse_pooled_connections() {
+    let db = test_db("db-pool", &[("pooled.example.test", "A", "192.0.2.100", 300)]);
+    let expected = vec![("192.0.2.100".to_string(), 300, "A".to_string())];
+    assert_eq!(try_lookup_records(&db, "pooled.example.test").unwrap(), expected);
+
+    // With the file unlinked, only a connection opened earlier can still read it
+    std::fs::remove_file(&db).unwrap();
+    for _ in 0..100 {
+        assert_eq!(try_lookup_records(&db, "pooled.example.test").unwrap(), expected);
+    }
+}
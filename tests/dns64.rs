@@ -0,0 +1,29 @@
+mod common;
+
+use common::{config, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+#[tokio::test]
+async fn a_only_name_gets_synthesized_aaaa() {
+    let mut config = config(&zone_db("dns64", &[("v4only.example.test", "A", "192.0.2.33", 300)]));
+    config.dns64_enabled = true;
+    config.dns64_prefix = "64:ff9b::".parse().unwrap();
+
+    let response = resolve(&query("v4only.example.test", 28), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].1, 28);
+    // 192.0.2.33 is c000:0221 in the low 32 bits
+    assert_eq!(answers[0].3, "64:ff9b::c000:221");
+}
+
+#[tokio::test]
+async fn a_only_name_has_no_aaaa_without_dns64() {
+    let mut config = config(&zone_db("dns64-off", &[("v4only.example.test", "A", "192.0.2.33", 300)]));
+    config.dns64_enabled = false;
+
+    let response = resolve(&query("v4only.example.test", 28), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert!(extract_answer_records(&response).is_empty());
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{config, counts, query, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::skip_name;
+
+/// The (type, RDATA) of every record in the answer section.
+fn answer_rdatas(response: &[u8]) -> Vec<(u16, Vec<u8>)> {
+    let mut pos = skip_name(response, 12).unwrap() + 4;
+    let mut records = Vec::new();
+    for _ in 0..counts(response)[1] {
+        pos = skip_name(response, pos).unwrap();
+        let rtype = u16::from_be_bytes([response[pos], response[pos + 1]]);
+        let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+        records.push((rtype, response[pos + 10..pos + 10 + rdlength].to_vec()));
+        pos += 10 + rdlength;
+    }
+    records
+}
+
+#[tokio::test]
+async fn every_configured_dnskey_is_answered() {
+    let mut config = config(&zone_db("dnskey-pair", &[]));
+    config.dnskey_records = vec![
+        "example.test. IN DNSKEY 257 3 15 AQID".to_string(),
+        "example.test. IN DNSKEY 256 3 15 BAUG".to_string(),
+    ];
+
+    let response = resolve(&query("example.test", 48), Transport::Udp, &config).await;
+
+    assert_eq!(counts(&response)[1], 2);
+    assert_eq!(answer_rdatas(&response), vec![
+        (48, vec![0x01, 0x01, 3, 15, 1, 2, 3]),
+        (48, vec![0x01, 0x00, 3, 15, 4, 5, 6]),
+    ]);
+}
+
+#[tokio::test]
+async fn every_configured_ds_is_answered() {
+    let mut config = config(&zone_db("ds-pair", &[]));
+    config.ds_records = vec![
+        "example.test. IN DS 60485 15 2 0A0B".to_string(),
+        "example.test. IN DS 2642 15 2 0C0D".to_string(),
+    ];
+
+    let response = resolve(&query("example.test", 43), Transport::Udp, &config).await;
+
+    assert_eq!(answer_rdatas(&response), vec![
+        (43, vec![0xEC, 0x45, 15, 2, 0x0A, 0x0B]),
+        (43, vec![0x0A, 0x52, 15, 2, 0x0C, 0x0D]),
+    ]);
+}
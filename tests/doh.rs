@@ -0,0 +1,68 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use base64::Engine;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Request, Response, StatusCode};
+
+use common::{config, query, rcode, zone_db};
+use nx9_dns_server::config::ServerConfig;
+use nx9_dns_server::dns::extract_answer_records;
+use nx9_dns_server::doh::{handle_doh_request, DNS_MESSAGE, DOH_PATH};
+
+/// A configuration serving `www.example.test` with a 300 second TTL.
+fn doh_config(name: &str) -> ServerConfig {
+    config(&zone_db(name, &[("www.example.test", "A", "192.0.2.90", 300)]))
+}
+
+/// Send `req` to the DoH handler from a loopback client.
+async fn send(req: Request<Body>, config: &ServerConfig) -> Response<Body> {
+    handle_doh_request(req, SocketAddr::from(([127, 0, 0, 1], 44300)), config).await
+}
+
+/// Check a successful DoH response and return the DNS message it carries.
+async fn dns_message(response: Response<Body>) -> Vec<u8> {
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], DNS_MESSAGE);
+    assert_eq!(response.headers()[CACHE_CONTROL], "max-age=300");
+    hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()
+}
+
+#[tokio::test]
+async fn post_of_a_raw_query_is_answered() {
+    let config = doh_config("doh-post");
+    let query = query("www.example.test", 1);
+    let req = Request::post(DOH_PATH)
+        .header(CONTENT_TYPE, DNS_MESSAGE)
+        .body(Body::from(query.clone()))
+        .unwrap();
+
+    let answer = dns_message(send(req, &config).await).await;
+    assert_eq!(&answer[..2], &query[..2]);
+    assert_eq!(rcode(&answer), 0);
+    let addresses: Vec<String> = extract_answer_records(&answer).into_iter().map(|(_, _, _, value)| value).collect();
+    assert_eq!(addresses, vec!["192.0.2.90"]);
+}
+
+#[tokio::test]
+async fn get_with_base64url_query_is_answered() {
+    let config = doh_config("doh-get");
+    let query = query("www.example.test", 1);
+    let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&query);
+    let req = Request::get(format!("{}?dns={}", DOH_PATH, encoded)).body(Body::empty()).unwrap();
+
+    let answer = dns_message(send(req, &config).await).await;
+    assert_eq!(extract_answer_records(&answer).len(), 1);
+}
+
+#[tokio::test]
+async fn post_with_another_content_type_is_rejected() {
+    let config = doh_config("doh-content-type");
+    let req = Request::post(DOH_PATH)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Body::from(query("www.example.test", 1)))
+        .unwrap();
+
+    assert_eq!(send(req, &config).await.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
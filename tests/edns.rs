@@ -0,0 +1,64 @@
+mod common;
+
+use common::{config, counts, edns_query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{append_edns_option, Transport};
+use nx9_dns_server::utils::{extract_edns_options, find_opt_record};
+
+#[tokio::test]
+async fn listed_edns_options_are_echoed_and_others_dropped() {
+    let mut config = config(&zone_db("edns-echo", &[("www.example.test", "A", "192.0.2.10", 300)]));
+    config.edns_echo_options = vec![65001];
+
+    let mut query = edns_query("www.example.test", 1, 1232, false);
+    append_edns_option(&mut query, 65001, b"echo");
+    append_edns_option(&mut query, 65002, b"drop");
+    let response = resolve(&query, Transport::Udp, &config).await;
+
+    assert_eq!(extract_edns_options(&response), vec![(65001, b"echo".to_vec())]);
+}
+
+#[tokio::test]
+async fn negative_answers_use_the_configured_edns_settings() {
+    let mut config = config(&zone_db("edns-negative", &[]));
+    config.edns_buffer_size = 1400;
+    config.edns_echo_options = vec![65003];
+
+    let mut query = edns_query("missing.example.test", 1, 1232, false);
+    append_edns_option(&mut query, 65003, b"echo");
+    let response = resolve(&query, Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+
+    // The OPT record's CLASS is the advertised UDP payload size
+    let opt = find_opt_record(&response).expect("OPT record");
+    assert_eq!(u16::from_be_bytes([response[opt + 3], response[opt + 4]]), 1400);
+    assert_eq!(extract_edns_options(&response), vec![(65003, b"echo".to_vec())]);
+}
+
+#[tokio::test]
+async fn query_with_two_opt_records_is_formerr() {
+    let config = config(&zone_db("edns-two-opt", &[("www.example.test", "A", "192.0.2.10", 300)]));
+
+    let mut query = edns_query("www.example.test", 1, 1232, false);
+    let opt = query[query.len() - 11..].to_vec();
+    query.extend_from_slice(&opt);
+    query[11] = 2; // ARCOUNT
+    let response = resolve(&query, Transport::Udp, &config).await;
+
+    assert_eq!(rcode(&response), 1);
+    assert_eq!(counts(&response)[1], 0);
+}
+
+#[tokio::test]
+async fn edns_option_longer_than_rdlength_is_formerr() {
+    let mut config = config(&zone_db("edns-option-length", &[("www.example.test", "A", "192.0.2.10", 300)]));
+    config.strict_edns_options = true;
+
+    // One option claiming 8 bytes of data in an RDATA of 6 bytes
+    let mut query = edns_query("www.example.test", 1, 1232, false);
+    let rdlength = query.len() - 2;
+    query[rdlength..].copy_from_slice(&[0x00, 0x06]);
+    query.extend_from_slice(&[0xFD, 0xE9, 0x00, 0x08, 0x01, 0x02]);
+    let response = resolve(&query, Transport::Udp, &config).await;
+
+    assert_eq!(rcode(&response), 1);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use common::{counts, edns_query};
+use nx9_dns_server::dns::{encode_record, forward_request_udp_with_fallback};
+use nx9_dns_server::utils::{extract_edns_payload_size, has_opt_record, skip_name};
+
+/// Start an upstream that ignores queries advertising more than `max_buffer`
+/// bytes of EDNS buffer (or any EDNS at all, if `None`), and return its
+/// address with the buffer sizes it has been sent.
+async fn small_buffer_upstream(max_buffer: Option<u16>) -> (SocketAddr, Arc<Mutex<Vec<Option<u16>>>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+            let query = &buf[..len];
+            let size = if has_opt_record(query) { extract_edns_payload_size(query) } else { None };
+            log.lock().unwrap().push(size);
+            let answers = match (size, max_buffer) {
+                (None, _) => true,
+                (Some(size), Some(max)) => size <= max,
+                (Some(_), None) => false,
+            };
+            if !answers {
+                continue;
+            }
+            let mut response = query[..skip_name(query, 12).unwrap() + 4].to_vec();
+            response[2] |= 0x80;
+            response[3] = 0x80;
+            response[6..12].copy_from_slice(&[0, 1, 0, 0, 0, 0]);
+            response.extend_from_slice(&encode_record("fallback.example.test", "A", 300, "192.0.2.40").unwrap());
+            let _ = socket.send_to(&response, src).await;
+        }
+    });
+    (addr, seen)
+}
+
+#[tokio::test]
+async fn large_buffer_timeout_falls_back_to_512() {
+    let (upstream, seen) = small_buffer_upstream(Some(512)).await;
+    let query = edns_query("fallback.example.test", 1, 4096, false);
+
+    let response = forward_request_udp_with_fallback(upstream, &query, Duration::from_millis(200)).await.unwrap();
+    assert_eq!(counts(&response)[1], 1);
+    assert_eq!(*seen.lock().unwrap(), vec![Some(4096), Some(512)]);
+
+    // The working size is remembered, so the next query starts there
+    seen.lock().unwrap().clear();
+    forward_request_udp_with_fallback(upstream, &query, Duration::from_millis(200)).await.unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![Some(512)]);
+}
+
+#[tokio::test]
+async fn edns_is_dropped_when_no_buffer_size_is_answered() {
+    let (upstream, seen) = small_buffer_upstream(None).await;
+    let query = edns_query("fallback.example.test", 1, 4096, false);
+
+    let response = forward_request_udp_with_fallback(upstream, &query, Duration::from_millis(200)).await.unwrap();
+    assert_eq!(counts(&response)[1], 1);
+    assert_eq!(*seen.lock().unwrap(), vec![Some(4096), Some(512), None]);
+}
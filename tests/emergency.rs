@@ -0,0 +1,67 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::{config, counts, query, rcode, resolve};
+use nx9_dns_server::config::{ServerConfig, EMERGENCY_TTL};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+/// A configuration whose database cannot be opened, with emergency records for two names.
+fn unreadable_db_config(name: &str) -> ServerConfig {
+    let path: PathBuf = std::env::temp_dir().join(format!("nx9-{}-{}-missing", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut config = config(&path.to_string_lossy());
+    config.emergency_records.insert("v4.emergency.test".into(), "192.0.2.1".into());
+    config.emergency_records.insert("v6.emergency.test".into(), "2001:db8::1".into());
+    config
+}
+
+#[tokio::test]
+async fn unreadable_db_serves_emergency_a_record() {
+    let config = unreadable_db_config("emergency-a");
+    let response = resolve(&query("v4.emergency.test", 1), Transport::Udp, &config).await;
+
+    assert_eq!(rcode(&response), 0);
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers, vec![("v4.emergency.test".to_string(), 1, EMERGENCY_TTL, "192.0.2.1".to_string())]);
+}
+
+#[tokio::test]
+async fn unreadable_db_serves_emergency_aaaa_record() {
+    let config = unreadable_db_config("emergency-aaaa");
+    let response = resolve(&query("v6.emergency.test", 28), Transport::Udp, &config).await;
+
+    assert_eq!(rcode(&response), 0);
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers, vec![("v6.emergency.test".to_string(), 28, EMERGENCY_TTL, "2001:db8::1".to_string())]);
+}
+
+#[tokio::test]
+async fn unreadable_db_answers_other_address_type_with_nodata() {
+    let config = unreadable_db_config("emergency-nodata");
+
+    for (name, qtype) in [("v4.emergency.test", 28), ("v6.emergency.test", 1)] {
+        let response = resolve(&query(name, qtype), Transport::Udp, &config).await;
+        assert_eq!(rcode(&response), 0, "{} type {}", name, qtype);
+        assert_eq!(counts(&response)[1], 0, "{} type {}", name, qtype);
+    }
+}
+
+#[tokio::test]
+async fn unreadable_db_without_emergency_record_fails() {
+    let config = unreadable_db_config("emergency-servfail");
+    let response = resolve(&query("other.emergency.test", 1), Transport::Udp, &config).await;
+
+    assert_eq!(rcode(&response), 2);
+}
+
+#[tokio::test]
+async fn emergency_nodata_follows_the_authoritative_setting() {
+    let mut config = unreadable_db_config("emergency-nodata-aa");
+    config.authoritative = false;
+
+    let response = resolve(&query("v4.emergency.test", 28), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 0);
+    assert_eq!(response[2] & 0x04, 0, "AA set");
+}
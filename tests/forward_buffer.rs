@@ -0,0 +1,74 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+
+use common::{counts, edns_query};
+use nx9_dns_server::dns::{encode_record, forward_request_udp};
+use nx9_dns_server::utils::skip_name;
+
+/// Number of A records in the upstream answer, about 2KB on the wire.
+const LARGE_ANSWER: usize = 120;
+
+/// A response to `query` carrying [`LARGE_ANSWER`] A records.
+fn large_response(query: &[u8]) -> Vec<u8> {
+    let mut response = query[..skip_name(query, 12).unwrap() + 4].to_vec();
+    response[2] |= 0x80;
+    response[3] = 0x80;
+    response[6..12].copy_from_slice(&[0, LARGE_ANSWER as u8, 0, 0, 0, 0]);
+    for i in 0..LARGE_ANSWER {
+        let address = format!("10.0.{}.{}", i / 250, i % 250 + 1);
+        response.extend_from_slice(&encode_record("big.example.test", "A", 300, &address).unwrap());
+    }
+    response
+}
+
+/// Start an upstream answering every query with [`large_response`] over
+/// both UDP and TCP on the same port, and return its address.
+async fn large_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let socket = UdpSocket::bind(addr).await.unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&large_response(&buf[..len]), src).await;
+        }
+    });
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            let response = large_response(&query);
+            stream.write_all(&(response.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&response).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn two_kilobyte_reply_fits_a_4096_byte_edns_buffer() {
+    let upstream = large_upstream().await;
+    let query = edns_query("big.example.test", 1, 4096, false);
+
+    let response = forward_request_udp(upstream, &query, Duration::from_secs(2)).await.unwrap();
+    assert!(response.len() > 2000, "{} bytes", response.len());
+    assert_eq!(counts(&response)[1] as usize, LARGE_ANSWER);
+    assert_eq!(&response[..2], &query[..2]);
+}
+
+#[tokio::test]
+async fn reply_filling_the_buffer_is_retried_over_tcp() {
+    let upstream = large_upstream().await;
+    let query = edns_query("big.example.test", 1, 1232, false);
+
+    let response = forward_request_udp(upstream, &query, Duration::from_secs(2)).await.unwrap();
+    assert_eq!(counts(&response)[1] as usize, LARGE_ANSWER);
+    assert_eq!(&response[..2], &query[..2]);
+}
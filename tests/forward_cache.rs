@@ -0,0 +1,34 @@
+mod common;
+
+use common::{cache, config, fake_forwarder, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+
+#[tokio::test]
+async fn forwarded_answer_caches_only_the_cname_chain() {
+    let forwarder = fake_forwarder(vec![
+        ("www.chain.test", "CNAME", "edge.chain.test"),
+        ("edge.chain.test", "A", "198.51.100.1"),
+        ("unrelated.chain.test", "A", "198.51.100.2"),
+        ("poisoned.example.test", "A", "198.51.100.3"),
+    ]).await;
+    let mut config = config(&zone_db("forward-chain", &[]));
+    config.forwarders = vec![forwarder];
+
+    let response = resolve(&query("www.chain.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+
+    let cache = cache();
+    assert_eq!(cache.get("edge.chain.test", 1).unwrap().map(|(ip, _)| ip), Some("198.51.100.1".to_string()));
+    assert_eq!(cache.get("unrelated.chain.test", 1).unwrap(), None);
+    assert_eq!(cache.get("poisoned.example.test", 1).unwrap(), None);
+}
+
+#[tokio::test]
+async fn a_and_aaaa_are_cached_side_by_side() {
+    let cache = cache();
+    cache.set("both.cache.test".into(), 1, "192.0.2.1".into(), 300).unwrap();
+    cache.set("both.cache.test".into(), 28, "2001:db8::1".into(), 300).unwrap();
+
+    assert_eq!(cache.get("both.cache.test", 1).unwrap().map(|(ip, _)| ip), Some("192.0.2.1".to_string()));
+    assert_eq!(cache.get("both.cache.test", 28).unwrap().map(|(ip, _)| ip), Some("2001:db8::1".to_string()));
+}
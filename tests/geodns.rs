@@ -0,0 +1,71 @@
+mod common;
+
+use std::net::{IpAddr, SocketAddr};
+
+use common::{config, edns_query, query, resolve, zone_db};
+use nx9_dns_server::config::{GeoRecord, ServerConfig};
+use nx9_dns_server::dns::{append_edns_option, extract_answer_records, Transport};
+use nx9_dns_server::handlers::answer_query_from;
+use nx9_dns_server::utils::{encode_client_subnet, extract_edns_options};
+
+/// A configuration serving `geo.example.test` differently to 10.0.0.0/8 and 172.16.0.0/12.
+fn geo_config(name: &str) -> ServerConfig {
+    let mut config = config(&zone_db(name, &[("geo.example.test", "A", "192.0.2.80", 300)]));
+    for (network, prefix_len, value) in [("10.0.0.0", 8, "10.0.0.5"), ("172.16.0.0", 12, "172.16.0.5")] {
+        config.geo_records.push(GeoRecord {
+            name: "geo.example.test".to_string(),
+            network: network.parse().unwrap(),
+            prefix_len,
+            value: value.to_string(),
+        });
+    }
+    config
+}
+
+/// The addresses answered to a client at `client`.
+async fn addresses_for(query: &[u8], client: &str, config: &ServerConfig) -> Vec<String> {
+    let src = SocketAddr::new(client.parse::<IpAddr>().unwrap(), 5300);
+    let response = answer_query_from(query, src, Transport::Udp, config).await.unwrap().unwrap();
+    extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect()
+}
+
+#[tokio::test]
+async fn clients_in_different_subnets_get_their_configured_addresses() {
+    let config = geo_config("geo-subnets");
+    let query = query("geo.example.test", 1);
+
+    assert_eq!(addresses_for(&query, "10.1.2.3", &config).await, vec!["10.0.0.5"]);
+    assert_eq!(addresses_for(&query, "172.16.9.9", &config).await, vec!["172.16.0.5"]);
+    assert_eq!(addresses_for(&query, "192.0.2.200", &config).await, vec!["192.0.2.80"]);
+}
+
+/// A query for `name` carrying an ECS option for 10.1.2.0/24.
+fn ecs_query(name: &str) -> Vec<u8> {
+    let mut query = edns_query(name, 1, 1232, false);
+    append_edns_option(&mut query, 8, &encode_client_subnet("10.1.2.0".parse().unwrap(), 24));
+    query
+}
+
+/// The SCOPE PREFIX-LENGTH of the ECS option echoed in a response.
+fn ecs_scope(response: &[u8]) -> Option<u8> {
+    extract_edns_options(response).into_iter()
+        .find(|(code, data)| *code == 8 && data.len() >= 4)
+        .map(|(_, data)| data[3])
+}
+
+#[tokio::test]
+async fn ecs_scope_is_zero_for_subnet_independent_answers() {
+    let config = config(&zone_db("geo-scope-zero", &[("plain.example.test", "A", "192.0.2.81", 300)]));
+
+    let response = resolve(&ecs_query("plain.example.test"), Transport::Udp, &config).await;
+    assert_eq!(ecs_scope(&response), Some(0));
+}
+
+#[tokio::test]
+async fn ecs_scope_is_the_source_prefix_for_geo_answers() {
+    let config = geo_config("geo-scope-match");
+
+    let response = resolve(&ecs_query("geo.example.test"), Transport::Udp, &config).await;
+    assert_eq!(extract_answer_records(&response)[0].3, "10.0.0.5");
+    assert_eq!(ecs_scope(&response), Some(24));
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+
+use common::{config, query, resolve, zone_db};
+use nx9_dns_server::config::{HealthCheck, ServerConfig};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+use nx9_dns_server::health::{is_healthy, run_checks};
+
+/// The addresses answered for `pool.example.test`.
+async fn pool_addresses(config: &ServerConfig) -> BTreeSet<String> {
+    let response = resolve(&query("pool.example.test", 1), Transport::Udp, config).await;
+    extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect()
+}
+
+#[tokio::test]
+async fn unhealthy_backend_is_withheld_until_it_recovers() {
+    let config = config(&zone_db("health-failover", &[
+        ("pool.example.test", "A", "127.0.0.10", 300),
+        ("pool.example.test", "A", "127.0.0.11", 300),
+    ]));
+    let up = TcpListener::bind("127.0.0.10:0").await.unwrap();
+    let up_addr = up.local_addr().unwrap();
+    // Nothing listens here until the backend recovers
+    let down_addr: SocketAddr = std::net::TcpListener::bind("127.0.0.11:0").unwrap().local_addr().unwrap();
+    let checks = vec![HealthCheck::Tcp(up_addr), HealthCheck::Tcp(down_addr)];
+
+    run_checks(&checks).await;
+    assert!(!is_healthy(down_addr.ip()));
+    assert_eq!(pool_addresses(&config).await, BTreeSet::from(["127.0.0.10".to_string()]));
+
+    let _recovered = TcpListener::bind(down_addr).await.unwrap();
+    run_checks(&checks).await;
+    assert!(is_healthy(down_addr.ip()));
+    assert_eq!(pool_addresses(&config).await, BTreeSet::from(["127.0.0.10".to_string(), "127.0.0.11".to_string()]));
+}
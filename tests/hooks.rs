@@ -0,0 +1,37 @@
+mod common;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use common::{config, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+use nx9_dns_server::hooks::{HookAction, ParsedQuery, QueryHook};
+use nx9_dns_server::utils::extract_domain;
+
+/// Resolves `old.example.test` as `new.example.test`.
+#[derive(Debug)]
+struct RenameHook;
+
+#[async_trait]
+impl QueryHook for RenameHook {
+    async fn pre(&self, query: &mut ParsedQuery) -> HookAction {
+        if query.name == "old.example.test" {
+            query.name = "new.example.test".to_string();
+        }
+        HookAction::Continue
+    }
+}
+
+#[tokio::test]
+async fn hook_rewrites_the_queried_name() {
+    let mut config = config(&zone_db("hooks-rewrite", &[("new.example.test", "A", "192.0.2.110", 300)]));
+    config.hooks.push(Arc::new(RenameHook));
+
+    let response = resolve(&query("old.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    // The client sees its own question, answered with the rewritten name's data
+    assert_eq!(extract_domain(&response).as_deref(), Some("old.example.test"));
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers, vec![("old.example.test".to_string(), 1, 300, "192.0.2.110".to_string())]);
+}
@@ -0,0 +1,15 @@
+mod common;
+
+use common::{config, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+#[tokio::test]
+async fn unicode_query_resolves_record_stored_as_punycode() {
+    let config = config(&zone_db("idn-punycode", &[("xn--bcher-kva.example.test", "A", "192.0.2.40", 300)]));
+
+    let response = resolve(&query("bücher.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].3, "192.0.2.40");
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use common::{config, query, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::{extract_do_bit, extract_domain, extract_edns_payload_size};
+
+#[tokio::test]
+async fn cname_target_probe_carries_configured_edns_settings() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut config = config(&zone_db("internal-edns", &[("alias.example.test", "CNAME", "target.probe.test", 300)]));
+    config.forwarders = vec![upstream.local_addr().unwrap()];
+    config.internal_edns_buffer_size = 1400;
+    config.internal_do_bit = true;
+
+    // The probe is only captured; it goes unanswered
+    tokio::spawn(async move {
+        let _ = tokio::time::timeout(
+            Duration::from_secs(1),
+            resolve(&query("alias.example.test", 1), Transport::Udp, &config),
+        ).await;
+    });
+
+    let mut buf = [0u8; 512];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(2), upstream.recv_from(&mut buf)).await
+        .expect("probe sent upstream")
+        .unwrap();
+    let probe = &buf[..len];
+    assert_eq!(extract_domain(probe).as_deref(), Some("target.probe.test"));
+    assert_eq!(extract_edns_payload_size(probe), Some(1400));
+    assert!(extract_do_bit(probe));
+}
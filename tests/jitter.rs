@@ -0,0 +1,44 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use common::{config, query, zone_db};
+use nx9_dns_server::dns::extract_answer_records;
+use nx9_dns_server::handlers::handle_udp_query;
+
+#[tokio::test]
+async fn jittered_responses_stay_within_bound_and_match_their_queries() {
+    let records: Vec<(String, String)> = (1..=8)
+        .map(|i| (format!("host{}.example.test", i), format!("192.0.2.{}", i)))
+        .collect();
+    let rows: Vec<_> = records.iter().map(|(name, ip)| (name.as_str(), "A", ip.as_str(), 300)).collect();
+    let mut config = config(&zone_db("jitter", &rows));
+    config.response_jitter_ms = 150;
+    let config = Arc::new(config);
+
+    let server = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let src = client.local_addr().unwrap();
+
+    let started = Instant::now();
+    for (i, (name, _)) in records.iter().enumerate() {
+        let mut query = query(name, 1);
+        query[1] = i as u8;
+        tokio::spawn(handle_udp_query(query, src, server.clone(), config.clone()));
+    }
+
+    let mut buf = [0u8; 512];
+    for _ in &records {
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf)).await
+            .expect("response within the jitter bound")
+            .unwrap();
+        // Each response answers the query carrying its ID, whatever order they arrive in
+        let (name, ip) = &records[buf[1] as usize];
+        let answers = extract_answer_records(&buf[..len]);
+        assert_eq!(answers, vec![(name.clone(), 1, 300, ip.clone())]);
+    }
+    assert!(started.elapsed() < Duration::from_millis(150 + 500), "took {:?}", started.elapsed());
+}
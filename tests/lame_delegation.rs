@@ -0,0 +1,48 @@
+mod common;
+
+use common::{config, counts, edns_query, query, rcode, resolve, test_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::extract_edns_payload_size;
+
+#[tokio::test]
+async fn zone_with_only_apex_ns_is_lame() {
+    let db = test_db("lame-only-ns", &[
+        ("lame.test", "NS", "ns1.elsewhere.test", 3600),
+        ("lame.test", "NS", "ns2.elsewhere.test", 3600),
+    ]);
+    let config = config(&db);
+
+    for name in ["lame.test", "www.lame.test"] {
+        let response = resolve(&query(name, 1), Transport::Udp, &config).await;
+        assert_eq!(rcode(&response), 2, "{}", name);
+        assert_eq!(counts(&response), [1, 0, 0, 0], "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn zone_with_data_but_no_soa_is_not_lame() {
+    let db = test_db("lame-with-data", &[
+        ("partial.test", "NS", "ns1.elsewhere.test", 3600),
+        ("www.partial.test", "A", "192.0.2.10", 3600),
+    ]);
+    let config = config(&db);
+
+    // The apex holds only NS records, but the zone has other data
+    let response = resolve(&query("partial.test", 1), Transport::Udp, &config).await;
+    assert_ne!(rcode(&response), 2);
+
+    let response = resolve(&query("missing.partial.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+}
+
+#[tokio::test]
+async fn lame_servfail_advertises_our_edns_buffer_size() {
+    let db = test_db("lame-edns", &[("edns-lame.test", "NS", "ns1.elsewhere.test", 3600)]);
+    let mut config = config(&db);
+    config.edns_buffer_size = 1232;
+
+    let response = resolve(&edns_query("www.edns-lame.test", 1, 4096, false), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 2);
+    assert_eq!(counts(&response), [1, 0, 0, 1]);
+    assert_eq!(extract_edns_payload_size(&response), Some(1232));
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::{answer_types_and_ttls, config, query, rcode, resolve, zone_db};
+use nx9_dns_server::config::MaintenanceMode;
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+#[tokio::test]
+async fn maintenance_mode_overrides_a_but_not_soa() {
+    let mut config = config(&zone_db("maintenance", &[("www.example.test", "A", "192.0.2.120", 300)]));
+    let query_a = query("www.example.test", 1);
+
+    let response = resolve(&query_a, Transport::Udp, &config).await;
+    assert_eq!(extract_answer_records(&response)[0].3, "192.0.2.120");
+
+    config.maintenance_mode = Some(MaintenanceMode { pattern: None, target: "198.51.100.99".parse().unwrap() });
+    let response = resolve(&query_a, Transport::Udp, &config).await;
+    assert_eq!(extract_answer_records(&response)[0].3, "198.51.100.99");
+
+    let response = resolve(&query("example.test", 6), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(answer_types_and_ttls(&response).first().map(|(rtype, _)| *rtype), Some(6));
+
+    config.maintenance_mode = None;
+    let response = resolve(&query_a, Transport::Udp, &config).await;
+    assert_eq!(extract_answer_records(&response)[0].3, "192.0.2.120");
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{config, counts, query, resolve, zone_db};
+use nx9_dns_server::config::{MinimalResponses, ServerConfig};
+use nx9_dns_server::dns::Transport;
+
+/// A configuration serving `www.example.test` with the given minimal-responses mode.
+fn minimal_config(name: &str, mode: MinimalResponses) -> ServerConfig {
+    let mut config = config(&zone_db(name, &[("www.example.test", "A", "192.0.2.10", 300)]));
+    config.minimal_responses = mode;
+    config
+}
+
+/// The same query for `www.example.test` without the RD bit.
+fn iterative_query() -> Vec<u8> {
+    let mut query = query("www.example.test", 1);
+    query[2] &= !0x01;
+    query
+}
+
+#[tokio::test]
+async fn minimal_mode_answers_without_authority_or_additional() {
+    let config = minimal_config("minimal-yes", MinimalResponses::Yes);
+
+    let response = resolve(&query("www.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(counts(&response), [1, 1, 0, 0]);
+}
+
+#[tokio::test]
+async fn full_mode_adds_ns_and_glue() {
+    let config = minimal_config("minimal-no", MinimalResponses::No);
+
+    let response = resolve(&query("www.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(counts(&response), [1, 1, 1, 1]);
+}
+
+#[tokio::test]
+async fn no_auth_recursive_mode_adds_ns_and_glue_only_without_rd() {
+    let config = minimal_config("minimal-no-auth-recursive", MinimalResponses::NoAuthRecursive);
+
+    let recursive = resolve(&query("www.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(counts(&recursive), [1, 1, 0, 0]);
+
+    let iterative = resolve(&iterative_query(), Transport::Udp, &config).await;
+    assert_eq!(counts(&iterative), [1, 1, 1, 1]);
+}
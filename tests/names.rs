@@ -0,0 +1,38 @@
+mod common;
+
+use common::{config, counts, rcode, query, resolve, test_db, zone_db};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+#[tokio::test]
+async fn soa_with_escaped_dot_in_rname_is_served() {
+    let db = test_db("names-soa-rname", &[
+        ("rname.test", "SOA", "ns1.rname.test john\\.doe.rname.test 1 10800 3600 604800 300", 3600),
+        ("rname.test", "NS", "ns1.rname.test", 3600),
+    ]);
+    let config = config(&db);
+
+    let response = resolve(&query("rname.test", 6), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+}
+
+#[tokio::test]
+async fn mixed_case_query_matches_lowercase_records() {
+    let db = zone_db("names-mixed-case", &[
+        ("www.example.test", "A", "192.0.2.30", 300),
+        ("example.test", "MX", "10 mail.example.test", 300),
+    ]);
+    let config = config(&db);
+
+    let a_query = query("WWW.Example.TEST", 1);
+    let response = resolve(&a_query, Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    let addresses: Vec<String> = extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect();
+    assert_eq!(addresses, vec!["192.0.2.30"]);
+    // The question is echoed with the client's casing
+    assert_eq!(&response[12..a_query.len()], &a_query[12..]);
+
+    let response = resolve(&query("eXaMpLe.TeSt", 15), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 1);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::{cache, config, counts, query, rcode, resolve, test_db, zone_db};
+use nx9_dns_server::cache::NegativeKind;
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::skip_name;
+
+/// A zone whose SOA MINIMUM is well above `DEFAULT_TTL`.
+fn long_minimum_db(name: &str, zone: &str) -> String {
+    let soa = format!("ns1.{0} hostmaster.{0} 1 10800 3600 604800 7200", zone);
+    test_db(name, &[
+        (zone, "SOA", soa.as_str(), 3600),
+        (zone, "NS", "ns1.elsewhere.test", 3600),
+    ])
+}
+
+#[tokio::test]
+async fn negative_ttl_follows_soa_minimum_up_to_configured_cap() {
+    let db = long_minimum_db("negative-minimum", "neg-minimum.test");
+    let mut config = config(&db);
+    config.negative_cache_max_ttl = 86400;
+
+    let response = resolve(&query("missing.neg-minimum.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+
+    let (kind, ttl) = cache().get_negative("missing.neg-minimum.test", 1).unwrap().expect("negative entry");
+    assert_eq!(kind, NegativeKind::NxDomain);
+    assert!(ttl > 7000 && ttl <= 7200, "ttl {}", ttl);
+}
+
+#[tokio::test]
+async fn negative_ttl_is_capped_by_negative_cache_max_ttl() {
+    let db = long_minimum_db("negative-cap", "neg-cap.test");
+    let mut config = config(&db);
+    config.negative_cache_max_ttl = 900;
+
+    let response = resolve(&query("missing.neg-cap.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+
+    let (_, ttl) = cache().get_negative("missing.neg-cap.test", 1).unwrap().expect("negative entry");
+    assert!(ttl > 800 && ttl <= 900, "ttl {}", ttl);
+}
+
+#[tokio::test]
+async fn nxdomain_entry_suppresses_every_type() {
+    let db = zone_db("negative-nxdomain-kind", &[("gone.example.test", "A", "192.0.2.20", 300)]);
+    let mut config = config(&db);
+    config.negative_cache_max_ttl = 3600;
+    cache().set_negative("gone.example.test".to_string(), 300).unwrap();
+
+    for qtype in [1, 28, 16] {
+        let response = resolve(&query("gone.example.test", qtype), Transport::Udp, &config).await;
+        assert_eq!(rcode(&response), 3, "type {}", qtype);
+    }
+}
+
+#[tokio::test]
+async fn nodata_entry_suppresses_only_its_own_type() {
+    let db = zone_db("negative-nodata-kind", &[
+        ("dual.example.test", "A", "192.0.2.21", 300),
+        ("dual.example.test", "AAAA", "2001:db8::21", 300),
+    ]);
+    let mut config = config(&db);
+    config.negative_cache_max_ttl = 3600;
+    cache().set_nodata("dual.example.test".to_string(), 28, 300).unwrap();
+
+    let aaaa = resolve(&query("dual.example.test", 28), Transport::Udp, &config).await;
+    assert_eq!(rcode(&aaaa), 0);
+    assert_eq!(counts(&aaaa)[1], 0);
+
+    let a = resolve(&query("dual.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&a), 0);
+    assert_eq!(counts(&a)[1], 1);
+}
+
+#[tokio::test]
+async fn nxdomain_authority_carries_the_zone_soa_timers() {
+    let db = test_db("negative-soa-timers", &[
+        ("soa-timers.test", "SOA", "ns1.soa-timers.test hostmaster.soa-timers.test 2024050501 7200 1800 1209600 900", 3600),
+        ("soa-timers.test", "NS", "ns1.soa-timers.test", 3600),
+    ]);
+    let config = config(&db);
+
+    let response = resolve(&query("missing.soa-timers.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+    assert!(counts(&response)[2] > 0);
+
+    // The first authority record is the SOA; its timers follow MNAME and RNAME
+    let authority = skip_name(&response, 12).unwrap() + 4;
+    let rdata = skip_name(&response, authority).unwrap() + 10;
+    assert_eq!(response[rdata - 10..rdata - 8], [0, 6]);
+    let timers = skip_name(&response, skip_name(&response, rdata).unwrap()).unwrap();
+    let field = |i: usize| u32::from_be_bytes(response[timers + 4 * i..timers + 4 * i + 4].try_into().unwrap());
+    assert_eq!(field(0), 2024050501);
+    assert_eq!(field(4), 900);
+}
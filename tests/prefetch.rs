@@ -0,0 +1,30 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{cache, config, query, resolve, zone_db};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+#[tokio::test]
+async fn popular_entry_near_expiry_is_refreshed_while_still_served() {
+    let mut config = config(&zone_db("prefetch", &[("popular.example.test", "A", "192.0.2.61", 300)]));
+    config.prefetch_threshold_percent = 60;
+    config.prefetch_min_hits = 1;
+
+    // A stale address that the database no longer holds, with under 60% of its TTL left
+    cache().set("popular.example.test".into(), 1, "192.0.2.60".into(), 2).unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let response = resolve(&query("popular.example.test", 1), Transport::Udp, &config).await;
+    assert_eq!(extract_answer_records(&response)[0].3, "192.0.2.60");
+
+    let mut refreshed = None;
+    for _ in 0..50 {
+        refreshed = cache().get("popular.example.test", 1).unwrap().map(|(ip, _)| ip);
+        if refreshed.as_deref() == Some("192.0.2.61") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(refreshed.as_deref(), Some("192.0.2.61"));
+}
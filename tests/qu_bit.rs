@@ -0,0 +1,37 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use common::{config, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+use nx9_dns_server::handlers::answer_query_from;
+
+/// A query for `name` with the QU bit set in QCLASS.
+fn qu_query(name: &str) -> Vec<u8> {
+    let mut query = query(name, 1);
+    let qclass = query.len() - 2;
+    query[qclass] |= 0x80;
+    query
+}
+
+#[tokio::test]
+async fn qu_query_gets_a_unicast_answer() {
+    let mut config = config(&zone_db("qu-answer", &[("printer.example.test", "A", "192.0.2.30", 300)]));
+    config.answer_qu_queries = true;
+
+    let response = resolve(&qu_query("printer.example.test"), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(response[0..2], [0x12, 0x34]);
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers, vec![("printer.example.test".to_string(), 1, 300, "192.0.2.30".to_string())]);
+}
+
+#[tokio::test]
+async fn qu_query_is_ignored_when_disabled() {
+    let mut config = config(&zone_db("qu-ignored", &[("printer.example.test", "A", "192.0.2.30", 300)]));
+    config.answer_qu_queries = false;
+
+    let src = SocketAddr::from(([127, 0, 0, 1], 5300));
+    let response = answer_query_from(&qu_query("printer.example.test"), src, Transport::Udp, &config).await;
+    assert!(matches!(response, Ok(None)));
+}
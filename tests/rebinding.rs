@@ -0,0 +1,28 @@
+mod common;
+
+use common::{config, counts, fake_forwarder, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+
+#[tokio::test]
+async fn forwarded_loopback_answer_for_public_name_is_filtered() {
+    let forwarder = fake_forwarder(vec![("public.rebind.test", "A", "127.0.0.1")]).await;
+    let mut config = config(&zone_db("rebinding-filtered", &[]));
+    config.forwarders = vec![forwarder];
+    config.rebinding_protection = true;
+
+    let response = resolve(&query("public.rebind.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+    assert_eq!(counts(&response)[1], 0);
+}
+
+#[tokio::test]
+async fn forwarded_loopback_answer_passes_without_protection() {
+    let forwarder = fake_forwarder(vec![("open.rebind.test", "A", "127.0.0.1")]).await;
+    let mut config = config(&zone_db("rebinding-off", &[]));
+    config.forwarders = vec![forwarder];
+    config.rebinding_protection = false;
+
+    let response = resolve(&query("open.rebind.test", 1), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 1);
+}
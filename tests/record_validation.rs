@@ -0,0 +1,61 @@
+mod common;
+
+use common::zone_db;
+use nx9_dns_server::config::SerialScheme;
+use nx9_dns_server::db::{insert_record, validate_record};
+use nx9_dns_server::errors::{DnsError, RecordValidationError};
+
+/// The validation error an insert into `db` fails with.
+fn insert_error(db: &str, domain: &str, record_type: &str, value: &str) -> RecordValidationError {
+    match insert_record(db, domain, record_type, value, 300, SerialScheme::Increment, None) {
+        Err(DnsError::Validation(e)) => e,
+        other => panic!("expected a validation error, got {:?}", other),
+    }
+}
+
+#[test]
+fn unknown_record_type_is_invalid_type() {
+    assert_eq!(
+        validate_record("host.example.test", "bogus", "192.0.2.1", 300),
+        Err(RecordValidationError::InvalidType("BOGUS".to_string())),
+    );
+}
+
+#[test]
+fn malformed_fields_name_the_field() {
+    let field = |domain, record_type, value, ttl| match validate_record(domain, record_type, value, ttl) {
+        Err(RecordValidationError::InvalidValue { field, .. }) => field,
+        other => panic!("expected an invalid value, got {:?}", other),
+    };
+
+    assert_eq!(field("host.example.test", "A", "192.0.2.300", 300), "address");
+    assert_eq!(field("host.example.test", "MX", "high mail.example.test", 300), "preference");
+    assert_eq!(field("host.example.test", "A", "192.0.2.1", -1), "ttl");
+    assert_eq!(field("bad..example.test", "A", "192.0.2.1", 300), "domain");
+}
+
+#[test]
+fn identical_record_is_a_duplicate_conflict() {
+    let db = zone_db("validation-duplicate", &[("host.example.test", "A", "192.0.2.1", 300)]);
+
+    let error = insert_error(&db, "host.example.test", "A", "192.0.2.1");
+    assert!(matches!(error, RecordValidationError::DuplicateConflict { .. }));
+    assert_eq!(error.http_status(), 409);
+}
+
+#[test]
+fn cname_beside_other_records_is_a_cname_conflict() {
+    let db = zone_db("validation-cname", &[
+        ("host.example.test", "A", "192.0.2.1", 300),
+        ("alias.example.test", "CNAME", "host.example.test", 300),
+    ]);
+
+    assert_eq!(
+        insert_error(&db, "host.example.test", "CNAME", "other.example.test"),
+        RecordValidationError::CnameConflict { domain: "host.example.test".to_string() },
+    );
+    assert_eq!(
+        insert_error(&db, "alias.example.test", "A", "192.0.2.2"),
+        RecordValidationError::CnameConflict { domain: "alias.example.test".to_string() },
+    );
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::{config, edns_query, extended_errors, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{Transport, EDE_NOT_AUTHORITATIVE};
+
+#[tokio::test]
+async fn disabled_recursion_is_refused_with_explanatory_ede() {
+    let mut config = config(&zone_db("recursion-disabled", &[]));
+    config.allow_recursion = false;
+    config.refused_ede = true;
+
+    let response = resolve(&edns_query("www.elsewhere.test", 1, 1232, false), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 5);
+    assert_eq!(
+        extended_errors(&response),
+        vec![(EDE_NOT_AUTHORITATIVE, "recursion is disabled on this server".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn refused_ede_can_be_turned_off() {
+    let mut config = config(&zone_db("recursion-no-ede", &[]));
+    config.allow_recursion = false;
+    config.refused_ede = false;
+
+    let response = resolve(&edns_query("www.elsewhere.test", 1, 1232, false), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 5);
+    assert!(extended_errors(&response).is_empty());
+}
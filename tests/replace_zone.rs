@@ -0,0 +1,62 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use common::{config, query, resolve, zone_db, ZONE};
+use nx9_dns_server::config::SerialScheme;
+use nx9_dns_server::db::replace_zone;
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+/// A full zone whose `pool.example.test` RRset holds 20 addresses in `prefix`.
+fn zone_set(prefix: &str) -> Vec<(String, u64, String, String)> {
+    let mut records = vec![
+        (ZONE.to_string(), 3600, "SOA".to_string(), "ns1.example.test hostmaster.example.test 1 10800 3600 604800 300".to_string()),
+        (ZONE.to_string(), 3600, "NS".to_string(), "ns1.example.test".to_string()),
+        ("ns1.example.test".to_string(), 3600, "A".to_string(), "192.0.2.53".to_string()),
+    ];
+    for host in 1..=20 {
+        records.push(("pool.example.test".to_string(), 300, "A".to_string(), format!("{}.{}", prefix, host)));
+    }
+    records
+}
+
+/// The addresses of a zone set, as a query would return them.
+fn addresses(records: &[(String, u64, String, String)]) -> BTreeSet<String> {
+    records.iter()
+        .filter(|(domain, ..)| domain == "pool.example.test")
+        .map(|(.., value)| value.clone())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn concurrent_query_sees_the_old_or_new_zone_never_a_mix() {
+    let old = zone_set("192.0.2");
+    let new = zone_set("198.51.100");
+    let db = zone_db("replace-concurrent", &[]);
+    replace_zone(&db, ZONE, &old, SerialScheme::Increment).unwrap();
+    let config = config(&db);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (db, old, new, done) = (db.clone(), old.clone(), new.clone(), done.clone());
+        tokio::task::spawn_blocking(move || {
+            for round in 0..50 {
+                let records = if round % 2 == 0 { &new } else { &old };
+                replace_zone(&db, ZONE, records, SerialScheme::Increment).unwrap();
+            }
+            done.store(true, Ordering::SeqCst);
+        })
+    };
+
+    let (old_set, new_set) = (addresses(&old), addresses(&new));
+    let mut queries = 0;
+    while !done.load(Ordering::SeqCst) || queries == 0 {
+        let response = resolve(&query("pool.example.test", 1), Transport::Udp, &config).await;
+        let seen: BTreeSet<String> = extract_answer_records(&response).into_iter().map(|(_, _, _, value)| value).collect();
+        assert!(seen == old_set || seen == new_set, "mixed answer: {:?}", seen);
+        queries += 1;
+    }
+    writer.await.unwrap();
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::{config, query, resolve, zone_db};
+use nx9_dns_server::config::{ResolutionSource, ServerConfig};
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+/// A configuration with `static.example.test` both in the database and as a static record.
+fn overlapping_config(name: &str, order: Vec<ResolutionSource>) -> ServerConfig {
+    let mut config = config(&zone_db(name, &[("static.example.test", "A", "192.0.2.50", 300)]));
+    config.static_records.insert(
+        "static.example.test".to_string(),
+        vec![("198.51.100.50".to_string(), 300, "A".to_string())],
+    );
+    config.resolution_order = order;
+    config
+}
+
+#[tokio::test]
+async fn static_source_ahead_of_db_wins() {
+    let config = overlapping_config("order-static-first", vec![ResolutionSource::Static, ResolutionSource::Db]);
+    let response = resolve(&query("static.example.test", 1), Transport::Udp, &config).await;
+
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].3, "198.51.100.50");
+}
+
+#[tokio::test]
+async fn db_source_ahead_of_static_wins() {
+    let config = overlapping_config("order-db-first", vec![ResolutionSource::Db, ResolutionSource::Static]);
+    let response = resolve(&query("static.example.test", 1), Transport::Udp, &config).await;
+
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].3, "192.0.2.50");
+}
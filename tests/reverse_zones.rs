@@ -0,0 +1,23 @@
+mod common;
+
+use common::{config, counts, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::{read_name, skip_name};
+
+#[tokio::test]
+async fn missing_ptr_in_reverse_zone_is_nxdomain_with_its_soa() {
+    let mut config = config(&zone_db("reverse-soa", &[]));
+    config.reverse_zones = vec!["2.0.192.in-addr.arpa".to_string()];
+
+    let response = resolve(&query("77.2.0.192.in-addr.arpa", 12), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 3);
+    assert_ne!(response[2] & 0x04, 0, "AA not set");
+    assert_eq!(counts(&response)[1], 0);
+    assert!(counts(&response)[2] > 0);
+
+    // The authority section starts with the reverse zone's SOA
+    let authority = skip_name(&response, 12).unwrap() + 4;
+    let (owner, end) = read_name(&response, authority).unwrap();
+    assert_eq!(owner, "2.0.192.in-addr.arpa");
+    assert_eq!(response[end..end + 2], [0, 6]);
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use common::{config, counts, query, zone_db};
+use nx9_dns_server::config::{RrlAction, ServerConfig};
+use nx9_dns_server::handlers::handle_udp_query;
+use nx9_dns_server::rrl::allow;
+
+/// A configuration allowing one response a second after a burst of three.
+fn limited_config(name: &str, action: RrlAction) -> ServerConfig {
+    let mut config = config(&zone_db(name, &[("www.example.test", "A", "192.0.2.100", 300)]));
+    config.rrl_rate = 1;
+    config.rrl_burst = 3;
+    config.rrl_action = action;
+    config
+}
+
+/// Send `count` queries from a client bound to `client_ip` through the UDP
+/// handler and return the responses received.
+async fn fire(client_ip: &str, count: usize, config: ServerConfig) -> Vec<Vec<u8>> {
+    let config = Arc::new(config);
+    let server = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let client = UdpSocket::bind((client_ip, 0)).await.unwrap();
+    let src = client.local_addr().unwrap();
+    for _ in 0..count {
+        handle_udp_query(query("www.example.test", 1), src, server.clone(), config.clone()).await.unwrap();
+    }
+
+    let mut responses = Vec::new();
+    let mut buf = [0u8; 512];
+    while let Ok(Ok((len, _))) = tokio::time::timeout(Duration::from_millis(200), client.recv_from(&mut buf)).await {
+        responses.push(buf[..len].to_vec());
+    }
+    responses
+}
+
+#[test]
+fn client_is_throttled_after_its_burst() {
+    let config = limited_config("rrl-allow", RrlAction::Truncate);
+    let client: IpAddr = "198.51.100.30".parse().unwrap();
+
+    let allowed = (0..10).filter(|_| allow(client, "www.example.test", &config)).count();
+    assert_eq!(allowed, 3);
+    // Other clients have buckets of their own
+    assert!(allow("198.51.100.31".parse().unwrap(), "www.example.test", &config));
+}
+
+#[test]
+fn per_name_buckets_limit_each_name_separately() {
+    let mut config = limited_config("rrl-per-name", RrlAction::Truncate);
+    config.rrl_per_name = true;
+    let client: IpAddr = "198.51.100.32".parse().unwrap();
+
+    assert_eq!((0..5).filter(|_| allow(client, "a.example.test", &config)).count(), 3);
+    assert_eq!((0..5).filter(|_| allow(client, "b.example.test", &config)).count(), 3);
+}
+
+#[tokio::test]
+async fn queries_over_the_limit_get_truncated_empty_responses() {
+    let responses = fire("127.0.0.30", 5, limited_config("rrl-truncate", RrlAction::Truncate)).await;
+
+    assert_eq!(responses.len(), 5);
+    for response in &responses[..3] {
+        assert_eq!(response[2] & 0x02, 0, "TC set within the burst");
+        assert_eq!(counts(response)[1], 1);
+    }
+    for response in &responses[3..] {
+        assert_ne!(response[2] & 0x02, 0, "TC not set over the limit");
+        assert_eq!(counts(response)[1], 0);
+    }
+}
+
+#[tokio::test]
+async fn queries_over_the_limit_are_dropped() {
+    let responses = fire("127.0.0.31", 5, limited_config("rrl-drop", RrlAction::Drop)).await;
+
+    assert_eq!(responses.len(), 3);
+    assert!(responses.iter().all(|response| counts(response)[1] == 1));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+
+use common::{zone_db, ZONE};
+use nx9_dns_server::config::SerialScheme;
+use nx9_dns_server::db::{current_serial, insert_record, next_serial, serial_newer};
+use nx9_dns_server::errors::{DnsError, RecordValidationError};
+
+#[test]
+fn unix_time_serial_is_the_timestamp() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 5, 12, 0, 0).unwrap();
+    let serial = next_serial(1, SerialScheme::UnixTime, now);
+    assert_eq!(serial, 1709640000);
+    assert!(serial_newer(serial, 1));
+
+    // A second change within the same second still moves forward
+    let again = next_serial(serial, SerialScheme::UnixTime, now);
+    assert_eq!(again, serial + 1);
+    assert!(serial_newer(again, serial));
+}
+
+#[test]
+fn date_counter_serial_is_yyyymmddnn() {
+    let day = Utc.with_ymd_and_hms(2024, 3, 5, 8, 0, 0).unwrap();
+    let first = next_serial(2024030403, SerialScheme::DateCounter, day);
+    assert_eq!(first, 2024030500);
+    let second = next_serial(first, SerialScheme::DateCounter, day);
+    assert_eq!(second, 2024030501);
+    assert!(serial_newer(second, first));
+
+    let next_day = Utc.with_ymd_and_hms(2024, 3, 6, 8, 0, 0).unwrap();
+    let third = next_serial(second, SerialScheme::DateCounter, next_day);
+    assert_eq!(third, 2024030600);
+    assert!(serial_newer(third, second));
+}
+
+#[test]
+fn increment_serial_wraps_per_rfc_1982() {
+    let now = Utc::now();
+    assert_eq!(next_serial(41, SerialScheme::Increment, now), 42);
+
+    let wrapped = next_serial(u32::MAX, SerialScheme::Increment, now);
+    assert_eq!(wrapped, 0);
+    assert!(serial_newer(wrapped, u32::MAX));
+    assert!(!serial_newer(u32::MAX, wrapped));
+}
+
+#[test]
+fn scheme_value_behind_the_current_serial_is_not_used() {
+    // A unixtime serial far ahead of the date-based candidate
+    let now = Utc.with_ymd_and_hms(2024, 3, 5, 8, 0, 0).unwrap();
+    let serial = next_serial(3000000000, SerialScheme::DateCounter, now);
+    assert_eq!(serial, 3000000001);
+}
+
+#[test]
+fn explicit_serial_override_must_be_newer() {
+    let db = zone_db("serial-override", &[]);
+
+    let serial = insert_record(&db, "a.example.test", "A", "192.0.2.80", 300, SerialScheme::Increment, Some(2024030500)).unwrap();
+    assert_eq!(serial, Some(2024030500));
+    assert_eq!(current_serial(&db, ZONE).unwrap(), Some(2024030500));
+
+    match insert_record(&db, "b.example.test", "A", "192.0.2.81", 300, SerialScheme::Increment, Some(2024030400)) {
+        Err(DnsError::Validation(RecordValidationError::InvalidValue { field, .. })) => assert_eq!(field, "serial"),
+        other => panic!("expected a serial error, got {:?}", other),
+    }
+    assert_eq!(current_serial(&db, ZONE).unwrap(), Some(2024030500));
+}
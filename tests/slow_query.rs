@@ -0,0 +1,54 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use common::{config, query, test_db};
+use nx9_dns_server::dns::{QueryContext, Transport};
+use nx9_dns_server::handlers::log_slow_query;
+
+/// A logger that keeps every warning so tests can inspect them.
+struct CapturedWarnings(Mutex<Vec<String>>);
+
+impl Log for CapturedWarnings {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static WARNINGS: CapturedWarnings = CapturedWarnings(Mutex::new(Vec::new()));
+
+/// Whether a warning mentioning `text` has been logged.
+fn warned(text: &str) -> bool {
+    WARNINGS.0.lock().unwrap().iter().any(|warning| warning.contains(text))
+}
+
+#[tokio::test]
+async fn slow_resolve_logs_a_warning_and_fast_one_does_not() {
+    let _ = log::set_logger(&WARNINGS);
+    log::set_max_level(LevelFilter::Warn);
+
+    let mut config = config(&test_db("slow-query", &[]));
+    config.slow_query_ms = 20;
+    let ctx = QueryContext::new(SocketAddr::from(([127, 0, 0, 1], 5300)), Transport::Udp);
+
+    let started = Instant::now();
+    log_slow_query(&query("fast.example.test", 1), "fast.example.test", &ctx, started, &config);
+    assert!(!warned("fast.example.test"));
+
+    let started = Instant::now();
+    tokio::time::sleep(Duration::from_millis(40)).await;
+    log_slow_query(&query("slow.example.test", 1), "slow.example.test", &ctx, started, &config);
+    assert!(warned("Slow query: slow.example.test type 1"));
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use common::{config, query, test_db, zone_db};
+use nx9_dns_server::config::SharedConfig;
+use nx9_dns_server::handlers::{handle_tcp_connection, run_tcp_server};
+
+/// Accept one connection and hand it to `handle_tcp_connection` with a short read timeout.
+async fn serve_one(name: &str) -> TcpStream {
+    let mut config = config(&test_db(name, &[]));
+    config.tcp_read_timeout = Duration::from_millis(100);
+    let config = Arc::new(config);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, src) = listener.accept().await.unwrap();
+        let _ = handle_tcp_connection(stream, src, config).await;
+    });
+    TcpStream::connect(addr).await.unwrap()
+}
+
+/// Wait for the server to close the connection, failing if it stays open.
+async fn assert_closed(mut client: TcpStream) {
+    let mut buf = [0u8; 16];
+    let read = timeout(Duration::from_secs(2), client.read(&mut buf)).await
+        .expect("connection closed before the test timeout");
+    assert!(matches!(read, Ok(0) | Err(_)), "unexpected data: {:?}", read);
+}
+
+#[tokio::test]
+async fn idle_connection_is_closed_after_read_timeout() {
+    let client = serve_one("tcp-idle").await;
+    assert_closed(client).await;
+}
+
+#[tokio::test]
+async fn stalled_query_body_is_closed_after_read_timeout() {
+    let mut client = serve_one("tcp-stalled").await;
+    // Announce a 40-byte query but send only part of it
+    client.write_all(&[0x00, 0x28, 0x12, 0x34]).await.unwrap();
+    assert_closed(client).await;
+}
+
+/// Send a length-prefixed query on `client` and wait for the response length.
+async fn exchange_tcp(client: &mut TcpStream) -> u16 {
+    let query = query("example.test", 6);
+    client.write_all(&(query.len() as u16).to_be_bytes()).await.unwrap();
+    client.write_all(&query).await.unwrap();
+    let mut len = [0u8; 2];
+    timeout(Duration::from_secs(2), client.read_exact(&mut len)).await
+        .expect("response before the test timeout")
+        .unwrap();
+    u16::from_be_bytes(len)
+}
+
+#[tokio::test]
+async fn connections_beyond_the_limit_are_closed() {
+    let mut config = config(&zone_db("tcp-limit", &[]));
+    config.max_tcp_connections = 2;
+    config.enable_ipv6 = false;
+    config.tcp_bind = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let addr = config.tcp_bind;
+    tokio::spawn(run_tcp_server(SharedConfig::new(config)));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Two idle connections hold both permits while the server waits for their queries
+    let first = TcpStream::connect(addr).await.unwrap();
+    let mut second = TcpStream::connect(addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let excess = TcpStream::connect(addr).await.unwrap();
+    assert_closed(excess).await;
+
+    // Closing a connection frees its permit for the next client
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut next = TcpStream::connect(addr).await.unwrap();
+    assert!(exchange_tcp(&mut next).await > 0);
+    assert!(exchange_tcp(&mut second).await > 0);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{config, counts, edns_query, rcode, resolve, zone_db};
+use nx9_dns_server::cookies::COOKIE_OPTION;
+use nx9_dns_server::dns::{append_edns_option, extract_answer_records, Transport};
+use nx9_dns_server::utils::{extract_edns_options, extract_edns_payload_size};
+
+/// A zone with an A RRset of `size` addresses; 60 are far more than 512 bytes.
+fn large_rrset_db(name: &str, size: u8) -> String {
+    let addresses: Vec<String> = (1..=size).map(|i| format!("192.0.2.{}", i)).collect();
+    let records: Vec<_> = addresses.iter()
+        .map(|ip| ("big.example.test", "A", ip.as_str(), 3600))
+        .collect();
+    zone_db(name, &records)
+}
+
+#[tokio::test]
+async fn large_rrset_over_udp_is_truncated_to_question_and_opt() {
+    let config = config(&large_rrset_db("truncate-udp", 60));
+    let query = edns_query("big.example.test", 1, 512, false);
+
+    let response = resolve(&query, Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_ne!(response[2] & 0x02, 0, "TC not set");
+    assert_eq!(counts(&response), [1, 0, 0, 1]);
+    assert!(response.len() <= 512);
+    assert_eq!(extract_edns_payload_size(&response), Some(config.edns_buffer_size));
+    // Header and question match the query, followed only by the OPT record
+    let question_end = query.len() - 11;
+    assert_eq!(response[12..question_end], query[12..question_end]);
+    assert_eq!(response.len(), question_end + 11);
+}
+
+#[tokio::test]
+async fn large_rrset_over_tcp_is_answered_in_full() {
+    let config = config(&large_rrset_db("truncate-tcp", 60));
+    let query = edns_query("big.example.test", 1, 512, false);
+
+    let response = resolve(&query, Transport::Tcp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(response[2] & 0x02, 0, "TC set");
+    assert_eq!(extract_answer_records(&response).len(), 60);
+}
+
+#[tokio::test]
+async fn default_buffer_is_1232_and_larger_answers_truncate() {
+    let config = config(&large_rrset_db("truncate-default", 100));
+    assert_eq!(config.edns_buffer_size, 1232);
+
+    let response = resolve(&edns_query("big.example.test", 1, 4096, false), Transport::Udp, &config).await;
+    assert_ne!(response[2] & 0x02, 0, "TC not set");
+    assert!(response.len() <= 1232);
+    assert_eq!(extract_edns_payload_size(&response), Some(1232));
+}
+
+#[tokio::test]
+async fn large_rrset_over_https_is_answered_in_full() {
+    let config = config(&large_rrset_db("truncate-https", 60));
+    let query = edns_query("big.example.test", 1, 512, false);
+
+    let response = resolve(&query, Transport::Https, &config).await;
+    assert_eq!(response[2] & 0x02, 0, "TC set");
+    assert_eq!(extract_answer_records(&response).len(), 60);
+}
+
+#[tokio::test]
+async fn valid_server_cookie_lifts_the_1232_byte_cap() {
+    let mut config = config(&large_rrset_db("truncate-cookie", 100));
+    config.dns_cookies = true;
+
+    // The first query only has a client cookie, so the answer is capped
+    let mut query = edns_query("big.example.test", 1, 4096, false);
+    append_edns_option(&mut query, COOKIE_OPTION, &[1, 2, 3, 4, 5, 6, 7, 8]);
+    let response = resolve(&query, Transport::Udp, &config).await;
+    assert_ne!(response[2] & 0x02, 0, "TC not set");
+    assert!(response.len() <= 1232);
+    let (_, cookie) = extract_edns_options(&response).into_iter()
+        .find(|(code, _)| *code == COOKIE_OPTION)
+        .expect("server cookie");
+
+    // Returning the server cookie proves the path, so the full buffer is used
+    let mut query = edns_query("big.example.test", 1, 4096, false);
+    append_edns_option(&mut query, COOKIE_OPTION, &cookie);
+    let response = resolve(&query, Transport::Udp, &config).await;
+    assert_eq!(response[2] & 0x02, 0, "TC set");
+    assert!(response.len() > 1232);
+    assert_eq!(extract_answer_records(&response).len(), 100);
+}
+
+#[tokio::test]
+async fn larger_configured_buffer_overrides_the_1232_byte_cap() {
+    let mut config = config(&large_rrset_db("truncate-override", 100));
+    config.edns_buffer_size = 4096;
+
+    let response = resolve(&edns_query("big.example.test", 1, 4096, false), Transport::Udp, &config).await;
+    assert_eq!(response[2] & 0x02, 0, "TC set");
+    assert_eq!(extract_answer_records(&response).len(), 100);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{answer_types_and_ttls, config, query, rcode, resolve, zone_db};
+use nx9_dns_server::config::ServerConfig;
+use nx9_dns_server::dns::{extract_answer_records, Transport};
+
+/// A configuration serving records with TTLs around the bounds of 60 and 86400 seconds.
+fn bounded_config(name: &str) -> ServerConfig {
+    let db = zone_db(name, &[
+        ("below-min.example.test", "A", "192.0.2.1", 30),
+        ("at-min.example.test", "A", "192.0.2.2", 60),
+        ("at-max.example.test", "A", "192.0.2.3", 86400),
+        ("above-max.example.test", "A", "192.0.2.4", 604800),
+    ]);
+    let mut config = config(&db);
+    config.min_ttl = 60;
+    config.max_ttl = 86400;
+    config
+}
+
+/// The TTL of the single answer to an A query for `name`.
+async fn answer_ttl(name: &str, config: &ServerConfig) -> u64 {
+    let response = resolve(&query(name, 1), Transport::Udp, config).await;
+    assert_eq!(rcode(&response), 0, "{}", name);
+    let answers = extract_answer_records(&response);
+    assert_eq!(answers.len(), 1, "{}", name);
+    answers[0].2
+}
+
+#[tokio::test]
+async fn ttls_are_raised_to_the_minimum() {
+    let config = bounded_config("ttl-min");
+    assert_eq!(answer_ttl("below-min.example.test", &config).await, 60);
+    assert_eq!(answer_ttl("at-min.example.test", &config).await, 60);
+}
+
+#[tokio::test]
+async fn ttls_are_lowered_to_the_maximum() {
+    let config = bounded_config("ttl-max");
+    assert_eq!(answer_ttl("at-max.example.test", &config).await, 86400);
+    assert_eq!(answer_ttl("above-max.example.test", &config).await, 86400);
+}
+
+#[tokio::test]
+async fn whoami_answers_keep_ttl_zero() {
+    let mut config = bounded_config("ttl-whoami");
+    config.whoami_name = Some("whoami.example.test".into());
+    config.whoami_address = Some("192.0.2.99".parse().unwrap());
+
+    for qtype in [1, 16] {
+        let response = resolve(&query("whoami.example.test", qtype), Transport::Udp, &config).await;
+        assert_eq!(answer_types_and_ttls(&response), vec![(qtype, 0)]);
+    }
+}
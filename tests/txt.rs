@@ -0,0 +1,43 @@
+mod common;
+
+use common::{config, counts, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::skip_name;
+
+/// The character-strings of the single TXT record in the answer section.
+fn txt_strings(response: &[u8]) -> Vec<Vec<u8>> {
+    assert_eq!(counts(response)[1], 1);
+    let pos = skip_name(response, skip_name(response, 12).unwrap() + 4).unwrap();
+    let rdlength = u16::from_be_bytes([response[pos + 8], response[pos + 9]]) as usize;
+    let mut rdata = &response[pos + 10..pos + 10 + rdlength];
+    let mut strings = Vec::new();
+    while let Some((&len, rest)) = rdata.split_first() {
+        strings.push(rest[..len as usize].to_vec());
+        rdata = &rest[len as usize..];
+    }
+    strings
+}
+
+#[tokio::test]
+async fn long_dkim_key_is_split_into_two_character_strings() {
+    let key = format!("v=DKIM1; k=rsa; p={}", "A".repeat(382));
+    assert_eq!(key.len(), 400);
+    let db = zone_db("txt-dkim", &[("mail._domainkey.example.test", "TXT", key.as_str(), 300)]);
+    let config = config(&db);
+
+    let response = resolve(&query("mail._domainkey.example.test", 16), Transport::Tcp, &config).await;
+    assert_eq!(rcode(&response), 0);
+
+    let strings = txt_strings(&response);
+    assert_eq!(strings.iter().map(Vec::len).collect::<Vec<_>>(), vec![255, 145]);
+    assert_eq!(strings.concat(), key.as_bytes());
+}
+
+#[tokio::test]
+async fn quoted_strings_are_kept_as_separate_character_strings() {
+    let db = zone_db("txt-quoted", &[("multi.example.test", "TXT", "\"first part\" \"second \\\"part\\\"\"", 300)]);
+    let config = config(&db);
+
+    let response = resolve(&query("multi.example.test", 16), Transport::Udp, &config).await;
+    assert_eq!(txt_strings(&response), vec![b"first part".to_vec(), b"second \"part\"".to_vec()]);
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use common::{config, counts, edns_query, query, rcode, zone_db};
+use nx9_dns_server::config::{ServerConfig, SharedConfig};
+use nx9_dns_server::dns::{append_edns_option, extract_answer_records};
+use nx9_dns_server::handlers::run_udp_server;
+
+/// Start the UDP server for `config` on a free loopback port and return its address.
+async fn start_udp_server(mut config: ServerConfig, ip: &str) -> SocketAddr {
+    let free = std::net::UdpSocket::bind((ip, 0)).unwrap().local_addr().unwrap();
+    config.udp_bind = free;
+    tokio::spawn(run_udp_server(SharedConfig::new(config)));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    free
+}
+
+/// Send `query` to `server` and wait for the response.
+async fn exchange(server: SocketAddr, query: &[u8]) -> Vec<u8> {
+    let client = UdpSocket::bind(if server.is_ipv6() { "[::1]:0" } else { "127.0.0.1:0" }).await.unwrap();
+    client.send_to(query, server).await.unwrap();
+    let mut buf = vec![0u8; 65535];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf)).await
+        .expect("response from server")
+        .unwrap();
+    buf.truncate(len);
+    buf
+}
+
+#[tokio::test]
+async fn large_query_is_received_whole_while_response_is_bounded() {
+    let addresses: Vec<String> = (1..=60).map(|i| format!("192.0.2.{}", i)).collect();
+    let records: Vec<_> = addresses.iter().map(|ip| ("big.example.test", "A", ip.as_str(), 300)).collect();
+    let mut config = config(&zone_db("udp-recv-bounded", &records));
+    config.max_recv_size = 4096;
+    config.max_udp_response_size = 600;
+    config.strict_edns_options = true;
+    let server = start_udp_server(config, "127.0.0.1").await;
+
+    // Padding makes the query far larger than the response limit
+    let mut query = edns_query("big.example.test", 1, 4096, false);
+    append_edns_option(&mut query, 12, &[0u8; 2000]);
+    let response = exchange(server, &query).await;
+
+    // A query cut short would have a malformed OPT record and get FORMERR
+    assert_eq!(rcode(&response), 0);
+    assert_ne!(response[2] & 0x02, 0, "TC not set");
+    assert!(response.len() <= 600);
+    assert_eq!(counts(&response)[0], 1);
+}
+
+#[tokio::test]
+async fn whoami_name_answers_the_bound_address() {
+    let mut config = config(&zone_db("udp-whoami", &[]));
+    config.whoami_name = Some("whoami.example.com".to_string());
+    let server = start_udp_server(config, "127.0.0.1").await;
+
+    let response = exchange(server, &query("whoami.example.com", 1)).await;
+    assert_eq!(rcode(&response), 0);
+    let answers: Vec<(u64, String)> = extract_answer_records(&response).into_iter().map(|(_, _, ttl, value)| (ttl, value)).collect();
+    assert_eq!(answers, vec![(0, server.ip().to_string())]);
+
+    // The TXT answer echoes the client's own address
+    let response = exchange(server, &query("whoami.example.com", 16)).await;
+    assert_eq!(counts(&response)[1], 1);
+    assert!(response.ends_with(b"\x09127.0.0.1"));
+
+    let response = exchange(server, &query("whoami.example.com", 28)).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 0);
+}
+
+#[tokio::test]
+async fn loopback_server_also_answers_over_ipv6() {
+    let mut config = config(&zone_db("udp-ipv6", &[("www.example.test", "A", "192.0.2.110", 300)]));
+    config.enable_ipv6 = true;
+    let server = start_udp_server(config, "127.0.0.1").await;
+    let server_v6 = SocketAddr::new("::1".parse().unwrap(), server.port());
+
+    for addr in [server, server_v6] {
+        let response = exchange(addr, &query("www.example.test", 1)).await;
+        assert_eq!(rcode(&response), 0, "over {}", addr);
+        assert_eq!(counts(&response)[1], 1, "over {}", addr);
+    }
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use common::{config, counts, query, rcode, resolve, zone_db};
+use nx9_dns_server::config::SerialScheme;
+use nx9_dns_server::db::insert_record;
+use nx9_dns_server::dns::Transport;
+
+#[tokio::test]
+async fn underscore_names_are_stored_and_answered() {
+    let db = zone_db("underscore", &[]);
+    insert_record(&db, "_dmarc.example.test", "TXT", "\"v=DMARC1; p=none\"", 300, SerialScheme::Increment, None).unwrap();
+    insert_record(&db, "_sip._tcp.example.test", "SRV", "10 60 5060 sip.example.test", 300, SerialScheme::Increment, None).unwrap();
+    let config = config(&db);
+
+    let response = resolve(&query("_dmarc.example.test", 16), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 1);
+    assert!(response.windows(16).any(|w| w == b"v=DMARC1; p=none"));
+
+    let response = resolve(&query("_sip._tcp.example.test", 33), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 1);
+}
+
+#[tokio::test]
+async fn every_srv_record_at_a_name_is_answered() {
+    let config = config(&zone_db("underscore-srv-rrset", &[
+        ("_xmpp._tcp.example.test", "SRV", "10 60 5222 xmpp1.example.test", 300),
+        ("_xmpp._tcp.example.test", "SRV", "20 40 5222 xmpp2.example.test", 300),
+    ]));
+
+    let response = resolve(&query("_xmpp._tcp.example.test", 33), Transport::Udp, &config).await;
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 2);
+}
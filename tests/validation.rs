@@ -0,0 +1,42 @@
+mod common;
+
+use common::{config, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::{validate_response, Transport};
+use nx9_dns_server::handlers::checked_response;
+
+#[tokio::test]
+async fn validator_rejects_corrupt_responses() {
+    let config = config(&zone_db("validate-corrupt", &[("www.example.test", "A", "192.0.2.10", 300)]));
+    let query = query("www.example.test", 1);
+    let response = resolve(&query, Transport::Udp, &config).await;
+    assert!(validate_response(&query, &response).is_ok());
+
+    let mut wrong_id = response.clone();
+    wrong_id[1] ^= 0xFF;
+    let mut qr_clear = response.clone();
+    qr_clear[2] &= 0x7F;
+    let mut other_question = response.clone();
+    other_question[13] = b'x';
+    let mut extra_count = response.clone();
+    extra_count[7] += 1;
+    let mut trailing = response.clone();
+    trailing.push(0);
+    let short_rdata = response[..response.len() - 1].to_vec();
+
+    for corrupt in [wrong_id, qr_clear, other_question, extra_count, trailing, short_rdata] {
+        assert!(validate_response(&query, &corrupt).is_err(), "{:02x?}", corrupt);
+    }
+}
+
+#[tokio::test]
+async fn corrupt_response_is_replaced_with_servfail() {
+    let mut config = config(&zone_db("validate-servfail", &[]));
+    config.validate_responses = true;
+    let query = query("www.example.test", 1);
+
+    let mut corrupt = resolve(&query, Transport::Udp, &config).await;
+    corrupt[7] += 1;
+    let sent = checked_response(&query, corrupt, &config).unwrap();
+    assert_eq!(rcode(&sent), 2);
+    assert!(validate_response(&query, &sent).is_ok());
+}
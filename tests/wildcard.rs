@@ -0,0 +1,19 @@
+mod common;
+
+use common::{config, counts, query, rcode, resolve, zone_db};
+use nx9_dns_server::dns::Transport;
+use nx9_dns_server::utils::{read_name, skip_name};
+
+#[tokio::test]
+async fn wildcard_answer_is_owned_by_the_queried_name() {
+    let config = config(&zone_db("wildcard-owner", &[("*.wild.example.test", "A", "192.0.2.20", 300)]));
+    let response = resolve(&query("Host.Wild.Example.Test", 1), Transport::Udp, &config).await;
+
+    assert_eq!(rcode(&response), 0);
+    assert_eq!(counts(&response)[1], 1);
+    let answer = skip_name(&response, 12).unwrap() + 4;
+    let (owner, _) = read_name(&response, answer).unwrap();
+    assert_eq!(owner, "Host.Wild.Example.Test");
+    // The owner points back at the question rather than spelling out the wildcard
+    assert_eq!(&response[answer..answer + 2], &[0xC0, 0x0C]);
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::{zone_db, ZONE};
+use nx9_dns_server::config::SerialScheme;
+use nx9_dns_server::db::{current_serial, insert_record, zone_summary};
+
+#[test]
+fn zone_summary_reflects_an_update_that_bumps_the_serial() {
+    let db = zone_db("zone-info-update", &[("www.example.test", "A", "192.0.2.1", 300)]);
+
+    let before = zone_summary(&db, ZONE).unwrap().expect("zone summary");
+    assert_eq!(before.name, ZONE);
+    assert_eq!(before.serial, 1);
+    assert_eq!(before.record_count, 4);
+    assert_eq!(before.default_ttl, 3600);
+    assert_eq!((before.refresh, before.retry, before.expire, before.minimum), (10800, 3600, 604800, 300));
+
+    let serial = insert_record(&db, "mail.example.test", "A", "192.0.2.2", 300, SerialScheme::Increment, None).unwrap();
+    assert_eq!(serial, Some(2));
+
+    let after = zone_summary(&db, "Example.Test.").unwrap().expect("zone summary");
+    assert_eq!(after.serial, 2);
+    assert_eq!(current_serial(&db, ZONE).unwrap(), Some(2));
+    assert_eq!(after.record_count, 5);
+    assert_eq!(after.mname, before.mname);
+}
+
+#[test]
+fn zone_without_soa_has_no_summary() {
+    let db = zone_db("zone-info-missing", &[]);
+
+    assert!(zone_summary(&db, "other.test").unwrap().is_none());
+    assert_eq!(current_serial(&db, "other.test").unwrap(), None);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::test_db;
+use nx9_dns_server::config::SerialScheme;
+use nx9_dns_server::db::{export_zone, import_zone_file};
+use nx9_dns_server::zonefile::parse;
+
+const ZONE_FILE: &str = r#"$ORIGIN roundtrip.test.
+$TTL 3600
+@       IN SOA  ns1 hostmaster 2024010101 7200 3600 1209600 300
+@       IN NS   ns1
+@       IN NS   ns2.elsewhere.test.
+@       IN MX   10 mail
+ns1     IN A    192.0.2.1
+www 600 IN A    192.0.2.2
+www     IN AAAA 2001:db8::2
+mail    IN A    192.0.2.3
+ftp     IN CNAME www
+_sip._tcp IN SRV 10 60 5060 sip
+@       IN TXT  "v=spf1 mx -all"
+_dmarc  IN TXT  "v=DMARC1; p=reject"
+"#;
+
+/// The records of a zone file, sorted for comparison.
+fn sorted_records(text: &str) -> Vec<(String, u64, String, String)> {
+    let mut records = parse(text, None).unwrap();
+    records.sort();
+    records
+}
+
+#[test]
+fn imported_zone_exports_to_an_equivalent_file() {
+    let db = test_db("zonefile-roundtrip", &[]);
+    let path = std::env::temp_dir().join(format!("nx9-roundtrip-{}.zone", std::process::id()));
+    std::fs::write(&path, ZONE_FILE).unwrap();
+
+    let (zone, count) = import_zone_file(&db, &path.to_string_lossy(), SerialScheme::Increment).unwrap();
+    assert_eq!(zone, "roundtrip.test");
+    assert_eq!(count, 12);
+
+    let exported = export_zone(&db, &zone).unwrap();
+    assert_eq!(sorted_records(&exported), sorted_records(ZONE_FILE));
+}